@@ -0,0 +1,141 @@
+//! Configs built in code, for embedding migux as a library. The result goes
+//! through the same defaults and validation as a loaded `migux.conf`.
+
+use crate::{
+    GlobalConfig, HttpConfig, LocationConfig, MiguxConfig, ServerConfig, StreamConfig,
+    UpstreamConfig, validation::ConfigReport,
+};
+
+/// Builder for a [`MiguxConfig`], started with [`MiguxConfig::builder`].
+/// Sections are named like their `migux.conf` counterparts
+/// (`[server.<name>]`, `[location.<name>]`, `[upstream.<name>]`,
+/// `[stream.<name>]`); adding a name twice replaces the earlier section.
+#[derive(Debug)]
+pub struct MiguxConfigBuilder {
+    cfg: MiguxConfig,
+}
+
+impl MiguxConfigBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            cfg: MiguxConfig {
+                global: GlobalConfig::default(),
+                http: HttpConfig::default(),
+                upstream: Default::default(),
+                servers: Default::default(),
+                location: Default::default(),
+                stream: Default::default(),
+                source_path: None,
+            },
+        }
+    }
+
+    pub fn global(mut self, global: GlobalConfig) -> Self {
+        self.cfg.global = global;
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.cfg.http = http;
+        self
+    }
+
+    pub fn server(mut self, name: impl Into<String>, server: ServerConfig) -> Self {
+        self.cfg.servers.insert(name.into(), server);
+        self
+    }
+
+    /// A location; `location.server` names the server it belongs to.
+    pub fn location(mut self, name: impl Into<String>, location: LocationConfig) -> Self {
+        self.cfg.location.insert(name.into(), location);
+        self
+    }
+
+    pub fn upstream(mut self, name: impl Into<String>, upstream: UpstreamConfig) -> Self {
+        self.cfg.upstream.insert(name.into(), upstream);
+        self
+    }
+
+    pub fn stream(mut self, name: impl Into<String>, stream: StreamConfig) -> Self {
+        self.cfg.stream.insert(name.into(), stream);
+        self
+    }
+
+    /// Apply defaults and validate. Errors come back as the report (which
+    /// also lists the warnings); a config with only warnings is returned.
+    pub fn build(self) -> Result<MiguxConfig, ConfigReport> {
+        let mut cfg = self.cfg;
+        cfg.apply_defaults();
+        let report = cfg.validate();
+        if report.has_errors() {
+            return Err(report);
+        }
+        Ok(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        LocationConfig, LocationType, MiguxConfig, ServerConfig, UpstreamConfig, UpstreamServers,
+    };
+
+    #[test]
+    fn built_config_gets_defaults_like_a_loaded_one() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cfg = MiguxConfig::builder()
+            .server(
+                "main",
+                ServerConfig {
+                    listen: "8081".into(),
+                    root: root.path().to_string_lossy().into_owned(),
+                    ..Default::default()
+                },
+            )
+            .upstream(
+                "app",
+                UpstreamConfig {
+                    server: UpstreamServers::One("127.0.0.1:3000".into()),
+                    ..Default::default()
+                },
+            )
+            .location(
+                "api",
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/api".into(),
+                    r#type: LocationType::Proxy,
+                    upstream: Some("app".into()),
+                    ..Default::default()
+                },
+            )
+            .build()
+            .expect("valid config");
+
+        let server = cfg.server("main").expect("server");
+        assert_eq!(server.listen, "0.0.0.0:8081");
+        assert_eq!(server.index, "index.html");
+        assert!(cfg.location("api").is_some());
+        assert!(cfg.source_path().is_none());
+    }
+
+    #[test]
+    fn invalid_config_returns_the_report() {
+        let report = MiguxConfig::builder()
+            .server("main", ServerConfig::default())
+            .location(
+                "api",
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/api".into(),
+                    r#type: LocationType::Proxy,
+                    upstream: Some("missing".into()),
+                    ..Default::default()
+                },
+            )
+            .build()
+            .expect_err("unknown upstream");
+        assert!(report.has_errors());
+        assert!(report.to_string().contains("missing"), "{report}");
+    }
+}
@@ -1,15 +1,59 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+// =======================================================
+// LOG FORMAT (salida de tracing)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    #[default]
+    #[serde(rename = "compact")]
+    Compact,
+    /// One JSON object per event, for log aggregation.
+    #[serde(rename = "json")]
+    Json,
+}
+
+// =======================================================
+// OVERLOAD ACTION (worker_connections agotadas)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverloadAction {
+    /// New connections wait for a slot (up to `max_queue_wait_secs`).
+    #[default]
+    #[serde(rename = "queue")]
+    Queue,
+    /// New connections get `503` with `Retry-After` and are closed at once.
+    #[serde(rename = "reject")]
+    Reject,
+}
 
 // =======================================================
 // GLOBAL CONFIG + DEFAULTS
 // =======================================================
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct GlobalConfig {
     pub worker_processes: u8,
     pub worker_connections: u16,
+    /// What a new connection gets while all `worker_connections` are busy.
+    pub overload_action: OverloadAction,
+    /// With `overload_action = "queue"`, how long a connection may wait for
+    /// a slot before it is shed like a rejected one (0 = no limit).
+    pub max_queue_wait_secs: u64,
+    /// Default tracing filter (`info`, `warn,migux=debug`, ...) when
+    /// `RUST_LOG` is not set.
     pub log_level: String,
+    pub log_format: LogFormat,
+    /// File that also receives `warn` and `error` lines (`off`/`-`, the
+    /// default, for stdout only).
     pub error_log: String,
+    /// TCP Fast Open on listeners and upstream connects (Linux; default off).
+    pub tcp_fastopen: bool,
+    /// Most `[server.*]` sections a config may define (0 = no limit).
+    pub max_servers: usize,
+    /// Most `[location.*]` sections a config may define (0 = no limit).
+    pub max_locations: usize,
 }
 
 impl Default for GlobalConfig {
@@ -17,8 +61,14 @@ impl Default for GlobalConfig {
         Self {
             worker_processes: 1,
             worker_connections: 1024,
+            overload_action: OverloadAction::Queue,
+            max_queue_wait_secs: 0,
             log_level: "info".into(),
-            error_log: "/var/log/migux/error.log".into(),
+            log_format: LogFormat::Compact,
+            error_log: "off".into(),
+            tcp_fastopen: false,
+            max_servers: 0,
+            max_locations: 0,
         }
     }
 }
@@ -32,14 +82,41 @@ impl GlobalConfig {
         self.worker_connections
     }
 
+    pub fn overload_action(&self) -> OverloadAction {
+        self.overload_action
+    }
+
+    /// `max_queue_wait_secs`, `None` when unlimited.
+    pub fn max_queue_wait_secs(&self) -> Option<u64> {
+        Some(self.max_queue_wait_secs).filter(|secs| *secs > 0)
+    }
+
     pub fn log_level(&self) -> &str {
         &self.log_level
     }
 
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
     pub fn error_log(&self) -> &str {
         &self.error_log
     }
 
+    pub fn tcp_fastopen(&self) -> bool {
+        self.tcp_fastopen
+    }
+
+    /// `max_servers`, `None` when unlimited.
+    pub fn max_servers(&self) -> Option<usize> {
+        Some(self.max_servers).filter(|max| *max > 0)
+    }
+
+    /// `max_locations`, `None` when unlimited.
+    pub fn max_locations(&self) -> Option<usize> {
+        Some(self.max_locations).filter(|max| *max > 0)
+    }
+
     pub(crate) fn apply_defaults_from(&mut self, defaults: &GlobalConfig) {
         if self.worker_processes == 0 {
             self.worker_processes = defaults.worker_processes;
@@ -0,0 +1,136 @@
+// =======================================================
+// PROXY HEADERS (proxy_set_header / proxy_hide_header)
+// =======================================================
+/// Variables usable in `proxy_set_header` values.
+pub const PROXY_HEADER_VARIABLES: &[&str] =
+    &["host", "remote_addr", "request_id", "request_uri", "scheme"];
+
+/// Headers that carry framing or connection state; the proxy owns them.
+const RESERVED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Parsed `proxy_set_header` entry: `"<Name>: <value>"`.
+///
+/// The value may reference `$host`, `$remote_addr`, `$request_id`,
+/// `$request_uri` and `$scheme`. An empty value removes the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl SetHeader {
+    /// Parse and check a single entry.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let Some((name, value)) = raw.split_once(':') else {
+            return Err(format!(
+                "proxy_set_header '{raw}' must be '<Name>: <value>'"
+            ));
+        };
+        let name = name.trim();
+        if !is_header_name(name) {
+            return Err(format!(
+                "proxy_set_header '{raw}' has an invalid header name"
+            ));
+        }
+        if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(format!(
+                "proxy_set_header cannot override '{name}' (managed by the proxy)"
+            ));
+        }
+        let value = value.trim();
+        for var in variables(value) {
+            if !PROXY_HEADER_VARIABLES.contains(&var) {
+                return Err(format!(
+                    "proxy_set_header '{raw}' uses unknown variable '${var}'"
+                ));
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Expand `$variables` in the value through `lookup`.
+    pub fn render(&self, mut lookup: impl FnMut(&str) -> String) -> String {
+        let mut out = String::with_capacity(self.value.len());
+        let mut rest = self.value.as_str();
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            let len = variable_len(after);
+            if len == 0 {
+                out.push('$');
+            } else {
+                out.push_str(&lookup(&after[..len]));
+            }
+            rest = &after[len..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// HTTP token check (RFC 9110 `tchar`).
+pub fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn variables(value: &str) -> impl Iterator<Item = &str> {
+    value.split('$').skip(1).filter_map(|part| {
+        let len = variable_len(part);
+        (len > 0).then(|| &part[..len])
+    })
+}
+
+fn variable_len(s: &str) -> usize {
+    s.bytes()
+        .take_while(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || *b == b'_')
+        .count()
+}
+
+// =======================================================
+// UPSTREAM REDIRECTS (proxy_redirect)
+// =======================================================
+/// Parsed `proxy_redirect` entry (`"off"` is handled by the location: it
+/// means no entries at all).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyRedirect {
+    /// `default`: `http(s)://<upstream address>` is replaced by the prefix
+    /// stripped from the request path (the location's public path).
+    Default,
+    /// `"<from> <to>"`: a `Location` starting with `from` gets `to` instead.
+    Map { from: String, to: String },
+}
+
+impl ProxyRedirect {
+    /// Parse and check a single entry.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        if raw.eq_ignore_ascii_case("default") {
+            return Ok(Self::Default);
+        }
+        let mut parts = raw.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(from), Some(to), None) => Ok(Self::Map {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(format!(
+                "proxy_redirect '{raw}' must be 'default', 'off' or '<from> <to>'"
+            )),
+        }
+    }
+}
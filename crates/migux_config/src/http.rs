@@ -1,42 +1,176 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+use crate::StringList;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum CacheEvictionPolicy {
     Lru,
 }
 
+// =======================================================
+// FORWARDED HEADERS (X-Forwarded-* / RFC 7239)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-*` and `X-Real-IP` only.
+    #[default]
+    #[serde(rename = "xff")]
+    Xff,
+    /// `Forwarded: for=..;proto=..;host=..` only.
+    #[serde(rename = "rfc7239")]
+    Rfc7239,
+    /// Both `Forwarded` and the `X-Forwarded-*` headers.
+    #[serde(rename = "both")]
+    Both,
+}
+
+impl ForwardedHeader {
+    pub fn emits_xff(self) -> bool {
+        matches!(self, Self::Xff | Self::Both)
+    }
+
+    pub fn emits_rfc7239(self) -> bool {
+        matches!(self, Self::Rfc7239 | Self::Both)
+    }
+}
+
+// =======================================================
+// ENCODED SLASHES (%2F en el path de la peticion)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodedSlashes {
+    /// `%2F` stays encoded: it routes as part of a segment and reaches
+    /// upstreams as sent.
+    #[default]
+    #[serde(rename = "preserve")]
+    Preserve,
+    /// `%2F` routes like `/`; upstreams still receive it encoded.
+    #[serde(rename = "decode")]
+    Decode,
+    /// Paths containing `%2F` get 404.
+    #[serde(rename = "reject")]
+    Reject,
+}
+
 // =======================================================
 // HTTP CONFIG + DEFAULTS
 // =======================================================
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct HttpConfig {
     pub sendfile: bool,
     pub keepalive_timeout_secs: u64,
+    /// Requests served on one client connection before it is closed.
+    pub keepalive_max_requests: u64,
+    /// Add `X-Migux-Conn-Requests` (requests so far on this connection) to
+    /// HTTP/1 responses, for debugging keep-alive and pipelining (default off).
+    pub debug_connection_header: bool,
     pub access_log: String,
+    /// Log 1 in N requests (1 = log everything).
+    pub access_log_sample_rate: u64,
+    /// Paths (prefixes) whose requests are not logged.
+    pub access_log_skip_paths: Option<StringList>,
+    /// Statuses (`204` or classes like `2xx`) that are not logged.
+    pub access_log_skip_statuses: Option<StringList>,
+    /// Path (e.g. `/healthz`) answered with `200 OK` by migux itself,
+    /// before routing, for load balancers. Unset: no such path.
+    pub health_check_path: Option<String>,
+    /// Write access log lines for `health_check_path` requests (default off).
+    pub health_check_log: bool,
 
     // Timeouts (seconds)
     pub client_read_timeout_secs: u64,
+    /// Abort a static body transfer when the client accepts no bytes for
+    /// this long.
+    pub client_write_timeout_secs: u64,
     pub proxy_connect_timeout_secs: u64,
     pub proxy_read_timeout_secs: u64,
     pub proxy_write_timeout_secs: u64,
 
+    // Read buffer sizes (bytes)
+    /// Bytes requested per read from an upstream.
+    pub proxy_buffer_size: usize,
+    /// Bytes requested per read from a client.
+    pub client_buffer_size: usize,
+
     // Upstream pool limits
     pub proxy_pool_max_per_addr: usize,
     pub proxy_pool_idle_timeout_secs: u64,
 
+    // Upstream failover
+    /// Retry GET/HEAD on the next upstream when one answers 5xx (nothing sent yet).
+    pub proxy_retry_5xx_get: bool,
+    /// Base delay in milliseconds before trying the next upstream after a
+    /// failed attempt, doubled for each further failover (0: retry at once).
+    pub proxy_retry_backoff_ms: u64,
+    /// Randomize each backoff delay between half and all of its value.
+    pub proxy_retry_backoff_jitter: bool,
+    /// Overall time in seconds for trying upstream candidates, backoff
+    /// included; once spent, no further candidate is tried and the client
+    /// gets a 504 (0: no limit).
+    pub proxy_request_timeout_secs: u64,
+    /// Reject (502) keep-alive upstream responses with neither Content-Length
+    /// nor chunked encoding instead of streaming them to EOF.
+    pub proxy_strict_framing: bool,
+    /// Interim (1xx) responses accepted from an upstream before its final
+    /// response; one more is a protocol error (502).
+    pub proxy_max_interim_responses: u32,
+    /// Seconds allowed for the final response once an upstream has sent a
+    /// 1xx; past that the request fails as an upstream timeout (504).
+    pub proxy_interim_timeout_secs: u64,
+    /// Put the failure category and the upstream addresses tried in 502/504
+    /// bodies. Exposes internal topology: staging/debugging only.
+    pub proxy_error_detail: bool,
+
+    // Forwarding headers
+    /// Which forwarding headers are sent upstream (`xff`, `rfc7239`, `both`).
+    pub proxy_forwarded_header: ForwardedHeader,
+    /// Keep the client's `Forwarded` header and append our element to it
+    /// (set when migux sits behind another proxy); otherwise it is dropped.
+    pub proxy_trust_forwarded: bool,
+
     // Limits (bytes)
     pub max_request_headers_bytes: u64,
+    /// Longest single header line (name and value) accepted; a longer one
+    /// gets 431. `0` turns the check off.
+    pub max_header_value_bytes: u64,
+    pub max_request_uri_bytes: u64,
     pub max_request_body_bytes: u64,
     pub max_upstream_response_headers_bytes: u64,
     pub max_upstream_response_body_bytes: u64,
+    /// Accept (drain or forward) a body on GET/HEAD instead of answering 400.
+    pub allow_get_body: bool,
+    /// Dispatch TRACE/TRACK like any other method instead of answering 405.
+    pub allow_trace: bool,
+    /// Let a POST carry its effective method in `X-HTTP-Method-Override`
+    /// (or a `_method` query parameter) for clients limited to GET/POST.
+    pub method_override: bool,
+    /// Send proxied overridden requests upstream as the original POST
+    /// (override header intact) instead of the overridden method.
+    pub method_override_forward_original: bool,
+    /// How `%2F` in request paths is treated for routing.
+    pub encoded_slashes: EncodedSlashes,
+
+    /// Directory for spooled bodies (files fetched by `origin_pull`).
+    /// Defaults to the system temp dir.
+    pub temp_dir: Option<String>,
+
+    /// `charset` parameter added to text content types of static files
+    /// (`utf-8` by default); `off` sends the bare type.
+    pub charset: String,
 
     // Caché control
     /// Directory used for disk-backed static cache (optional).
     pub cache_dir: Option<String>,
     /// Default TTL in seconds for cached static objects (optional).
     pub cache_default_ttl_secs: Option<u32>,
+    /// TTL in seconds per content type, overriding `cache_default_ttl_secs`.
+    /// Keys are exact types (`text/css`), `type/*` or `*`; the most specific
+    /// match wins.
+    #[serde(default)]
+    pub cache_type_ttls: BTreeMap<String, u64>,
     /// Maximum size in bytes for a cached static object (optional).
     pub cache_max_object_bytes: Option<u64>,
     /// Global cache size cap in bytes (optional).
@@ -49,6 +183,12 @@ pub struct HttpConfig {
     pub cache_max_ttl_secs: Option<u64>,
     /// Evict entries if not accessed for this many seconds (optional).
     pub cache_inactive_secs: Option<u64>,
+    /// Serve expired entries for this many seconds while refreshing in the background (optional).
+    pub cache_stale_while_revalidate_secs: Option<u64>,
+    /// Serve expired entries for this many seconds when the origin read fails (optional).
+    pub cache_stale_if_error_secs: Option<u64>,
+    /// Gzip disk cache entries; decompressed when read back (default: false).
+    pub cache_compress: bool,
 }
 
 impl Default for HttpConfig {
@@ -56,25 +196,58 @@ impl Default for HttpConfig {
         Self {
             sendfile: true,
             keepalive_timeout_secs: 65,
+            keepalive_max_requests: 1000,
+            debug_connection_header: false,
             access_log: "/var/log/migux/access.log".into(),
+            access_log_sample_rate: 1,
+            access_log_skip_paths: None,
+            access_log_skip_statuses: None,
+            health_check_path: None,
+            health_check_log: false,
             client_read_timeout_secs: 15,
+            client_write_timeout_secs: 30,
             proxy_connect_timeout_secs: 5,
             proxy_read_timeout_secs: 30,
             proxy_write_timeout_secs: 30,
+            proxy_buffer_size: 8 * 1024,
+            client_buffer_size: 4 * 1024,
             proxy_pool_max_per_addr: 32,
             proxy_pool_idle_timeout_secs: 60,
+            proxy_retry_5xx_get: false,
+            proxy_retry_backoff_ms: 0,
+            proxy_retry_backoff_jitter: false,
+            proxy_request_timeout_secs: 0,
+            proxy_strict_framing: false,
+            proxy_max_interim_responses: 8,
+            proxy_interim_timeout_secs: 30,
+            proxy_error_detail: false,
+            proxy_forwarded_header: ForwardedHeader::default(),
+            proxy_trust_forwarded: false,
             max_request_headers_bytes: 64 * 1024,
+            max_header_value_bytes: 8 * 1024,
+            max_request_uri_bytes: 8 * 1024,
             max_request_body_bytes: 10 * 1024 * 1024,
             max_upstream_response_headers_bytes: 64 * 1024,
             max_upstream_response_body_bytes: 10 * 1024 * 1024,
+            allow_get_body: false,
+            allow_trace: false,
+            method_override: false,
+            method_override_forward_original: false,
+            encoded_slashes: EncodedSlashes::default(),
+            temp_dir: None,
+            charset: "utf-8".into(),
             cache_dir: None,
             cache_default_ttl_secs: None,
+            cache_type_ttls: BTreeMap::new(),
             cache_max_object_bytes: None,
             cache_max_total_bytes: None,
             cache_max_entries: None,
             cache_eviction_policy: None,
             cache_max_ttl_secs: None,
             cache_inactive_secs: None,
+            cache_stale_while_revalidate_secs: None,
+            cache_stale_if_error_secs: None,
+            cache_compress: false,
         }
     }
 }
@@ -88,14 +261,64 @@ impl HttpConfig {
         self.keepalive_timeout_secs
     }
 
+    pub fn keepalive_max_requests(&self) -> u64 {
+        self.keepalive_max_requests
+    }
+
+    pub fn debug_connection_header(&self) -> bool {
+        self.debug_connection_header
+    }
+
+    pub fn proxy_buffer_size(&self) -> usize {
+        self.proxy_buffer_size
+    }
+
+    pub fn client_buffer_size(&self) -> usize {
+        self.client_buffer_size
+    }
+
     pub fn access_log(&self) -> &str {
         &self.access_log
     }
 
+    pub fn access_log_sample_rate(&self) -> u64 {
+        self.access_log_sample_rate.max(1)
+    }
+
+    pub fn access_log_skip_paths(&self) -> Vec<String> {
+        self.access_log_skip_paths
+            .as_ref()
+            .map(StringList::items)
+            .unwrap_or_default()
+    }
+
+    pub fn access_log_skip_statuses(&self) -> Vec<String> {
+        self.access_log_skip_statuses
+            .as_ref()
+            .map(StringList::items)
+            .unwrap_or_default()
+    }
+
+    /// Built-in health check path; empty counts as unset.
+    pub fn health_check_path(&self) -> Option<&str> {
+        self.health_check_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+    }
+
+    pub fn health_check_log(&self) -> bool {
+        self.health_check_log
+    }
+
     pub fn client_read_timeout_secs(&self) -> u64 {
         self.client_read_timeout_secs
     }
 
+    pub fn client_write_timeout_secs(&self) -> u64 {
+        self.client_write_timeout_secs
+    }
+
     pub fn proxy_connect_timeout_secs(&self) -> u64 {
         self.proxy_connect_timeout_secs
     }
@@ -116,10 +339,58 @@ impl HttpConfig {
         self.proxy_pool_idle_timeout_secs
     }
 
+    pub fn proxy_retry_5xx_get(&self) -> bool {
+        self.proxy_retry_5xx_get
+    }
+
+    pub fn proxy_retry_backoff_ms(&self) -> u64 {
+        self.proxy_retry_backoff_ms
+    }
+
+    pub fn proxy_retry_backoff_jitter(&self) -> bool {
+        self.proxy_retry_backoff_jitter
+    }
+
+    pub fn proxy_request_timeout_secs(&self) -> u64 {
+        self.proxy_request_timeout_secs
+    }
+
+    pub fn proxy_strict_framing(&self) -> bool {
+        self.proxy_strict_framing
+    }
+
+    pub fn proxy_max_interim_responses(&self) -> u32 {
+        self.proxy_max_interim_responses
+    }
+
+    pub fn proxy_interim_timeout_secs(&self) -> u64 {
+        self.proxy_interim_timeout_secs
+    }
+
+    pub fn proxy_error_detail(&self) -> bool {
+        self.proxy_error_detail
+    }
+
+    pub fn proxy_forwarded_header(&self) -> ForwardedHeader {
+        self.proxy_forwarded_header
+    }
+
+    pub fn proxy_trust_forwarded(&self) -> bool {
+        self.proxy_trust_forwarded
+    }
+
     pub fn max_request_headers_bytes(&self) -> u64 {
         self.max_request_headers_bytes
     }
 
+    pub fn max_header_value_bytes(&self) -> u64 {
+        self.max_header_value_bytes
+    }
+
+    pub fn max_request_uri_bytes(&self) -> u64 {
+        self.max_request_uri_bytes
+    }
+
     pub fn max_request_body_bytes(&self) -> u64 {
         self.max_request_body_bytes
     }
@@ -132,6 +403,37 @@ impl HttpConfig {
         self.max_upstream_response_body_bytes
     }
 
+    pub fn allow_get_body(&self) -> bool {
+        self.allow_get_body
+    }
+
+    pub fn allow_trace(&self) -> bool {
+        self.allow_trace
+    }
+
+    pub fn method_override(&self) -> bool {
+        self.method_override
+    }
+
+    pub fn method_override_forward_original(&self) -> bool {
+        self.method_override_forward_original
+    }
+
+    pub fn encoded_slashes(&self) -> EncodedSlashes {
+        self.encoded_slashes
+    }
+
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir
+            .as_deref()
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    pub fn charset(&self) -> &str {
+        &self.charset
+    }
+
     pub fn cache_dir(&self) -> Option<&str> {
         self.cache_dir.as_deref()
     }
@@ -140,6 +442,25 @@ impl HttpConfig {
         self.cache_default_ttl_secs
     }
 
+    /// `cache_type_ttls` entry for `content_type` (parameters ignored):
+    /// exact type, then `type/*`, then `*`.
+    pub fn cache_type_ttl_secs(&self, content_type: &str) -> Option<u64> {
+        if self.cache_type_ttls.is_empty() {
+            return None;
+        }
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        let wildcard = essence.split_once('/').map(|(kind, _)| format!("{kind}/*"));
+        [Some(essence.to_string()), wildcard, Some("*".to_string())]
+            .into_iter()
+            .flatten()
+            .find_map(|pattern| {
+                self.cache_type_ttls
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(&pattern))
+                    .map(|(_, ttl)| *ttl)
+            })
+    }
+
     pub fn cache_max_object_bytes(&self) -> Option<u64> {
         self.cache_max_object_bytes
     }
@@ -166,6 +487,18 @@ impl HttpConfig {
         self.cache_inactive_secs
     }
 
+    pub fn cache_stale_while_revalidate_secs(&self) -> Option<u64> {
+        self.cache_stale_while_revalidate_secs
+    }
+
+    pub fn cache_stale_if_error_secs(&self) -> Option<u64> {
+        self.cache_stale_if_error_secs
+    }
+
+    pub fn cache_compress(&self) -> bool {
+        self.cache_compress
+    }
+
     pub(crate) fn apply_cache_defaults(&mut self) {
         if self.cache_dir.is_some() {
             if self.cache_default_ttl_secs.is_none() {
@@ -181,12 +514,30 @@ impl HttpConfig {
         if self.access_log.is_empty() {
             self.access_log = defaults.access_log.clone();
         }
+        if self.charset.is_empty() {
+            self.charset = defaults.charset.clone();
+        }
+        if self.access_log_sample_rate == 0 {
+            self.access_log_sample_rate = defaults.access_log_sample_rate;
+        }
         if self.keepalive_timeout_secs == 0 {
             self.keepalive_timeout_secs = defaults.keepalive_timeout_secs;
         }
+        if self.keepalive_max_requests == 0 {
+            self.keepalive_max_requests = defaults.keepalive_max_requests;
+        }
+        if self.proxy_buffer_size == 0 {
+            self.proxy_buffer_size = defaults.proxy_buffer_size;
+        }
+        if self.client_buffer_size == 0 {
+            self.client_buffer_size = defaults.client_buffer_size;
+        }
         if self.client_read_timeout_secs == 0 {
             self.client_read_timeout_secs = defaults.client_read_timeout_secs;
         }
+        if self.client_write_timeout_secs == 0 {
+            self.client_write_timeout_secs = defaults.client_write_timeout_secs;
+        }
         if self.proxy_connect_timeout_secs == 0 {
             self.proxy_connect_timeout_secs = defaults.proxy_connect_timeout_secs;
         }
@@ -196,6 +547,12 @@ impl HttpConfig {
         if self.proxy_write_timeout_secs == 0 {
             self.proxy_write_timeout_secs = defaults.proxy_write_timeout_secs;
         }
+        if self.proxy_max_interim_responses == 0 {
+            self.proxy_max_interim_responses = defaults.proxy_max_interim_responses;
+        }
+        if self.proxy_interim_timeout_secs == 0 {
+            self.proxy_interim_timeout_secs = defaults.proxy_interim_timeout_secs;
+        }
         if self.proxy_pool_max_per_addr == 0 {
             self.proxy_pool_max_per_addr = defaults.proxy_pool_max_per_addr;
         }
@@ -205,6 +562,9 @@ impl HttpConfig {
         if self.max_request_headers_bytes == 0 {
             self.max_request_headers_bytes = defaults.max_request_headers_bytes;
         }
+        if self.max_request_uri_bytes == 0 {
+            self.max_request_uri_bytes = defaults.max_request_uri_bytes;
+        }
         if self.max_request_body_bytes == 0 {
             self.max_request_body_bytes = defaults.max_request_body_bytes;
         }
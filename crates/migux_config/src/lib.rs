@@ -1,17 +1,29 @@
+mod builder;
 mod global;
+mod header;
 mod http;
+mod list;
+mod listen;
 mod location;
 mod migux;
+mod rewrite;
 mod server;
+mod stream;
 mod tls;
 mod upstream;
 mod validation;
 
-pub use global::GlobalConfig;
-pub use http::HttpConfig;
-pub use location::{LocationConfig, LocationType};
+pub use builder::MiguxConfigBuilder;
+pub use global::{GlobalConfig, LogFormat, OverloadAction};
+pub use header::{PROXY_HEADER_VARIABLES, ProxyRedirect, SetHeader, is_header_name};
+pub use http::{EncodedSlashes, ForwardedHeader, HttpConfig};
+pub use list::StringList;
+pub use listen::normalize_listen;
+pub use location::{AcceptRanges, ErrorFormat, LocationConfig, LocationType};
 pub use migux::MiguxConfig;
+pub use rewrite::{RewriteFlag, RewriteRule};
 pub use server::ServerConfig;
-pub use tls::TlsConfig;
-pub use upstream::{UpstreamConfig, UpstreamHealthConfig, UpstreamServers};
+pub use stream::StreamConfig;
+pub use tls::{ALPN_PROTOCOLS, TlsConfig};
+pub use upstream::{ForwardedHost, UpstreamConfig, UpstreamHealthConfig, UpstreamServers};
 pub use validation::ConfigReport;
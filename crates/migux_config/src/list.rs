@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize, Serializer};
+
+// =======================================================
+// STRING LIST (one value or a list)
+// =======================================================
+/// Config value that accepts a single string or a list of strings.
+///
+/// INI files deliver lists as raw text (`["a", "b"]`), so `items()` also
+/// unpacks that form.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StringList {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Always a list of the parsed items, however the value was written.
+impl Serialize for StringList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.items())
+    }
+}
+
+impl Default for StringList {
+    fn default() -> Self {
+        StringList::Many(Vec::new())
+    }
+}
+
+impl StringList {
+    /// Return the configured values in order, with empty entries dropped.
+    pub fn items(&self) -> Vec<String> {
+        match self {
+            StringList::One(raw) => parse_list_text(raw),
+            StringList::Many(list) => list
+                .iter()
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items().is_empty()
+    }
+}
+
+impl std::fmt::Display for StringList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.items())
+    }
+}
+
+/// Parse `value` or `["a", "b"]` text into its items.
+///
+/// Quoted items may contain commas (handy for regexes); `\"` and `\\` are
+/// unescaped inside quotes.
+fn parse_list_text(raw: &str) -> Vec<String> {
+    let trimmed = raw.trim();
+    let Some(inner) = trimmed
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    else {
+        return if trimmed.is_empty() {
+            Vec::new()
+        } else {
+            vec![trimmed.to_string()]
+        };
+    };
+
+    let mut items = Vec::new();
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+
+        let mut item = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if matches!(chars.peek(), Some(&n) if n == first || n == '\\') => {
+                        item.push(chars.next().unwrap_or(c));
+                    }
+                    c if c == first => break,
+                    c => item.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                item.push(c);
+            }
+        }
+
+        let item = item.trim();
+        if !item.is_empty() {
+            items.push(item.to_string());
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StringList;
+
+    #[test]
+    fn single_value_is_one_item() {
+        let list = StringList::One("^/a(.*)$ /b$1".into());
+        assert_eq!(list.items(), vec!["^/a(.*)$ /b$1"]);
+    }
+
+    #[test]
+    fn bracketed_text_keeps_commas_inside_quotes() {
+        let list = StringList::One(r#"["^/a{1,3}$ /b", 'x', "say \"hi\"", plain ]"#.into());
+        assert_eq!(
+            list.items(),
+            vec!["^/a{1,3}$ /b", "x", "say \"hi\"", "plain"]
+        );
+    }
+
+    #[test]
+    fn empty_brackets_are_empty() {
+        assert!(StringList::One("[]".into()).is_empty());
+        assert!(StringList::One("[ \"\" ]".into()).is_empty());
+    }
+}
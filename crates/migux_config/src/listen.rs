@@ -0,0 +1,144 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+
+// =======================================================
+// LISTEN ADDRESSES
+// =======================================================
+/// Normalize a `listen` value into a bindable `ip:port`.
+///
+/// - `"8080"` / `":8080"` → `0.0.0.0:8080`
+/// - `"localhost"` → `localhost:<default_port>`, then resolved
+/// - `"example.com:8080"` → resolved to its first address (IPv4 preferred)
+/// - socket addresses (`"127.0.0.1:80"`, `"[::1]:80"`) are kept as-is
+pub fn normalize_listen(raw: &str, default_port: u16) -> Result<String, String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Err("listen address is empty".into());
+    }
+    if let Ok(addr) = raw.parse::<SocketAddr>() {
+        return Ok(addr.to_string());
+    }
+
+    let port_only = raw.strip_prefix(':').unwrap_or(raw);
+    if port_only.bytes().all(|b| b.is_ascii_digit()) {
+        let port = parse_port(port_only, raw)?;
+        return Ok(format!("0.0.0.0:{port}"));
+    }
+
+    let (host, port) = split_host_port(raw)?;
+    let port = match port {
+        Some(port) => parse_port(port, raw)?,
+        None => default_port,
+    };
+    if let Ok(ip) = host.parse::<std::net::IpAddr>() {
+        return Ok(SocketAddr::new(ip, port).to_string());
+    }
+    if !is_hostname(host) {
+        return Err(format!("listen '{raw}' has an invalid host '{host}'"));
+    }
+    resolve(host, port).ok_or_else(|| format!("listen '{raw}': cannot resolve host '{host}'"))
+}
+
+/// Split `host[:port]`, accepting bracketed IPv6 (`[::1]`, `[::1]:80`).
+fn split_host_port(raw: &str) -> Result<(&str, Option<&str>), String> {
+    if let Some(rest) = raw.strip_prefix('[') {
+        let Some((host, after)) = rest.split_once(']') else {
+            return Err(format!("listen '{raw}' has an unclosed '['"));
+        };
+        return match after {
+            "" => Ok((host, None)),
+            _ => match after.strip_prefix(':') {
+                Some(port) => Ok((host, Some(port))),
+                None => Err(format!("listen '{raw}' is not '[ip]:port'")),
+            },
+        };
+    }
+    match raw.rsplit_once(':') {
+        Some((host, _)) if host.contains(':') => {
+            Err(format!("listen '{raw}': IPv6 addresses must be bracketed"))
+        }
+        Some((host, port)) => Ok((host, Some(port))),
+        None => Ok((raw, None)),
+    }
+}
+
+fn parse_port(port: &str, raw: &str) -> Result<u16, String> {
+    port.parse::<u16>()
+        .map_err(|_| format!("listen '{raw}' has an invalid port '{port}'"))
+}
+
+fn is_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+pub(crate) fn resolve(host: &str, port: u16) -> Option<String> {
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs().ok()?.collect();
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or_else(|| addrs.first())
+        .map(SocketAddr::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_listen;
+
+    #[test]
+    fn bare_ports_bind_all_interfaces() {
+        assert_eq!(normalize_listen("8080", 80).unwrap(), "0.0.0.0:8080");
+        assert_eq!(normalize_listen(":8080", 80).unwrap(), "0.0.0.0:8080");
+        assert_eq!(normalize_listen(" 0 ", 80).unwrap(), "0.0.0.0:0");
+    }
+
+    #[test]
+    fn socket_addresses_are_kept() {
+        assert_eq!(
+            normalize_listen("127.0.0.1:9000", 80).unwrap(),
+            "127.0.0.1:9000"
+        );
+        assert_eq!(normalize_listen("[::1]:9000", 80).unwrap(), "[::1]:9000");
+    }
+
+    #[test]
+    fn missing_port_uses_the_default() {
+        assert_eq!(normalize_listen("127.0.0.1", 80).unwrap(), "127.0.0.1:80");
+        assert_eq!(normalize_listen("[::1]", 443).unwrap(), "[::1]:443");
+        let local = normalize_listen("localhost", 80).unwrap();
+        assert!(
+            local == "127.0.0.1:80" || local == "[::1]:80",
+            "got {local}"
+        );
+    }
+
+    #[test]
+    fn hostnames_are_resolved() {
+        let local = normalize_listen("localhost:8080", 80).unwrap();
+        assert!(
+            local == "127.0.0.1:8080" || local == "[::1]:8080",
+            "got {local}"
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for raw in [
+            "",
+            "70000",
+            ":http",
+            "host:port",
+            "::1:80",
+            "[::1",
+            "bad host:80",
+            "-bad",
+        ] {
+            assert!(normalize_listen(raw, 80).is_err(), "{raw:?} accepted");
+        }
+    }
+}
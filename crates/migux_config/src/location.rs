@@ -1,33 +1,138 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::ServerConfig;
+use crate::{ProxyRedirect, RewriteRule, ServerConfig, SetHeader, StringList};
 
 // =======================================================
 // LOCATION TYPE (enum tipado)
 // =======================================================
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub enum LocationType {
     #[default]
     #[serde(rename = "static")]
     Static,
     #[serde(rename = "proxy")]
     Proxy,
+    /// Static files pulled from `upstream` on a miss and stored under the
+    /// (first) root, then served like any static file.
+    #[serde(rename = "origin_pull")]
+    OriginPull,
+    /// Static files served straight out of a `.zip` or `.tar` file
+    /// (`archive`), indexed once and never unpacked. Experimental.
+    #[serde(rename = "archive")]
+    Archive,
+}
+
+// =======================================================
+// ERROR FORMAT (cuerpo de las respuestas de error)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `404 Not Found` as text/plain.
+    #[default]
+    #[serde(rename = "text")]
+    Text,
+    /// `{"error":"Not Found","status":404}` as application/json.
+    #[serde(rename = "json")]
+    Json,
+}
+
+// =======================================================
+// ACCEPT RANGES (byte-range serving de ficheros estaticos)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AcceptRanges {
+    /// Honor single `Range: bytes=...` requests and advertise `Accept-Ranges: bytes`.
+    #[default]
+    #[serde(rename = "bytes")]
+    Bytes,
+    /// No `Accept-Ranges` header; `Range` is ignored and the full body is sent.
+    #[serde(rename = "none")]
+    Disabled,
 }
 
 // =======================================================
 // LOCATION CONFIG + DEFAULTS
 // =======================================================
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct LocationConfig {
     pub server: String,
     pub path: String,
-    pub r#type: LocationType, // static | proxy
+    pub r#type: LocationType, // static | proxy | origin_pull
     pub root: Option<String>, // only static content
+    /// Fallback chain of roots; the first one containing the file wins.
+    pub roots: Option<StringList>,
+    /// `.zip` or `.tar` file served by `type = "archive"` locations.
+    pub archive: Option<String>,
     pub index: Option<String>,
+    /// Charset for text content types (falls back to http.charset; `off`
+    /// sends none).
+    pub charset: Option<String>,
     pub upstream: Option<String>,
     pub strip_prefix: Option<String>,
+    /// Regex path rewrites for proxy locations, applied in order.
+    pub rewrite: Option<StringList>,
     pub cache: Option<bool>,
+    /// `Cache-Control` value sent with files from this location.
+    pub cache_control: Option<String>,
+    /// Derive the cache TTL from `max-age` in a `<file>.httpheaders` sidecar or
+    /// `cache_control`, instead of the configured TTL.
+    pub respect_origin_cache_control: Option<bool>,
+    /// Send `Cache-Control: max-age=0, must-revalidate` instead of
+    /// `cache_control` or a sidecar, so clients revalidate on every use
+    /// (answered with 304 through the ETag / `Last-Modified`).
+    pub force_revalidate: Option<bool>,
+    /// Cache TTL override for this location (falls back to http.cache_default_ttl_secs).
+    /// Signed so validation can reject negative values with a clear message.
+    pub cache_ttl_secs: Option<i64>,
+    /// Body format for error responses (`text` or `json`).
+    pub error_format: Option<ErrorFormat>,
+    /// Byte-range support for static files (`bytes` or `none`).
+    pub accept_ranges: Option<AcceptRanges>,
+    /// Serve paths with a component starting with `.` (`/.env`, `/.git/…`).
+    /// Off by default: such requests answer 404.
+    pub serve_dotfiles: Option<bool>,
+    /// Relative path prefixes (`.well-known`, `.well-known/acme-challenge`)
+    /// served even with `serve_dotfiles` off; default `[".well-known"]` so
+    /// ACME challenges and `security.txt` work out of the box.
+    pub allow_dotfile_prefixes: Option<StringList>,
+    /// Upstream read timeout for this proxy location (falls back to http.proxy_read_timeout_secs).
+    pub proxy_read_timeout_secs: Option<u64>,
+    /// Time allowed from sending the request to receiving the final
+    /// response headers (falls back to the upstream's setting).
+    pub proxy_response_header_timeout_secs: Option<u64>,
+    /// Request body cap for this location (falls back to
+    /// http.max_request_body_bytes).
+    pub max_body_bytes: Option<u64>,
+    /// Extra upstream request headers, `"<Name>: <value>"` with `$variables`.
+    pub proxy_set_header: Option<StringList>,
+    /// Client request headers not forwarded upstream.
+    pub proxy_hide_header: Option<StringList>,
+    /// Rewrite of upstream `Location` headers: `"default"` (when unset),
+    /// `"off"`, or `"<from> <to>"` mappings.
+    pub proxy_redirect: Option<StringList>,
+    /// Compress textual upstream responses (brotli or gzip, per the client's
+    /// `Accept-Encoding`) when the upstream sent them uncompressed.
+    pub proxy_compress: Option<bool>,
+    /// Smallest `Content-Length` worth compressing (default 1024); responses
+    /// without one are always compressed.
+    pub proxy_compress_min_length: Option<u64>,
+    /// Honor `Accept-Encoding: identity;q=0`: a response that cannot be
+    /// sent encoded gets 406 instead of going out uncompressed (default
+    /// false). Small responses are compressed regardless of the minimum.
+    pub proxy_compress_strict: Option<bool>,
+    /// File served instead of the 502/504 when every upstream fails before
+    /// any response bytes were sent (e.g. a maintenance page).
+    pub fallback_static: Option<String>,
+    /// Status for `fallback_static` (default 503).
+    pub fallback_status: Option<u16>,
+
+    /// `rewrite` compiled at load time (invalid rules are reported by validation).
+    #[serde(skip)]
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// `proxy_set_header` parsed at load time (invalid entries are reported by validation).
+    #[serde(skip)]
+    pub set_headers: Vec<SetHeader>,
 }
 
 impl Default for LocationConfig {
@@ -37,10 +142,35 @@ impl Default for LocationConfig {
             path: "/".into(),
             r#type: LocationType::Static,
             root: None,
+            roots: None,
+            archive: None,
             index: None,
+            charset: None,
             upstream: None,
             strip_prefix: None,
+            rewrite: None,
             cache: None,
+            cache_control: None,
+            respect_origin_cache_control: None,
+            force_revalidate: None,
+            cache_ttl_secs: None,
+            error_format: None,
+            accept_ranges: None,
+            serve_dotfiles: None,
+            allow_dotfile_prefixes: None,
+            proxy_read_timeout_secs: None,
+            proxy_response_header_timeout_secs: None,
+            max_body_bytes: None,
+            proxy_set_header: None,
+            proxy_hide_header: None,
+            proxy_redirect: None,
+            proxy_compress: None,
+            proxy_compress_min_length: None,
+            proxy_compress_strict: None,
+            fallback_static: None,
+            fallback_status: None,
+            rewrite_rules: Vec::new(),
+            set_headers: Vec::new(),
         }
     }
 }
@@ -66,6 +196,18 @@ impl LocationConfig {
         self.root.as_deref().unwrap_or(default)
     }
 
+    /// Roots to search in order: `roots` when set, otherwise `root` (or `default`).
+    pub fn roots_or(&self, default: &str) -> Vec<String> {
+        match self.roots.as_ref().map(StringList::items) {
+            Some(roots) if !roots.is_empty() => roots,
+            _ => vec![self.root_or(default).to_string()],
+        }
+    }
+
+    pub fn archive(&self) -> Option<&str> {
+        self.archive.as_deref()
+    }
+
     pub fn index(&self) -> Option<&str> {
         self.index.as_deref()
     }
@@ -74,6 +216,13 @@ impl LocationConfig {
         self.index.as_deref().unwrap_or(default)
     }
 
+    /// Charset parameter for text content types: this location's, else
+    /// `default` (http.charset); `None` when it is `off`.
+    pub fn charset_or<'a>(&'a self, default: &'a str) -> Option<&'a str> {
+        let charset = self.charset.as_deref().unwrap_or(default);
+        (!charset.eq_ignore_ascii_case("off")).then_some(charset)
+    }
+
     pub fn upstream(&self) -> Option<&str> {
         self.upstream.as_deref()
     }
@@ -82,10 +231,151 @@ impl LocationConfig {
         self.strip_prefix.as_deref()
     }
 
+    pub fn rewrite_rules(&self) -> &[RewriteRule] {
+        &self.rewrite_rules
+    }
+
     pub fn cache(&self) -> Option<bool> {
         self.cache
     }
 
+    pub fn cache_control(&self) -> Option<&str> {
+        self.cache_control.as_deref()
+    }
+
+    /// On by default for `origin_pull`, whose stored files carry the
+    /// origin's `Cache-Control` in their sidecar.
+    pub fn respect_origin_cache_control(&self) -> bool {
+        self.respect_origin_cache_control
+            .unwrap_or(matches!(self.r#type, LocationType::OriginPull))
+    }
+
+    pub fn force_revalidate(&self) -> bool {
+        self.force_revalidate.unwrap_or(false)
+    }
+
+    pub fn cache_ttl_secs(&self) -> Option<u64> {
+        self.cache_ttl_secs.and_then(|ttl| u64::try_from(ttl).ok())
+    }
+
+    pub fn error_format(&self) -> ErrorFormat {
+        self.error_format.unwrap_or_default()
+    }
+
+    pub fn accept_ranges(&self) -> AcceptRanges {
+        self.accept_ranges.unwrap_or_default()
+    }
+
+    pub fn serve_dotfiles(&self) -> bool {
+        self.serve_dotfiles.unwrap_or(false)
+    }
+
+    pub fn allow_dotfile_prefixes(&self) -> Vec<String> {
+        self.allow_dotfile_prefixes
+            .as_ref()
+            .map(StringList::items)
+            .unwrap_or_else(|| vec![".well-known".into()])
+    }
+
+    /// Per-location upstream read timeout; `0` counts as unset.
+    pub fn proxy_read_timeout_secs(&self) -> Option<u64> {
+        self.proxy_read_timeout_secs.filter(|secs| *secs > 0)
+    }
+
+    /// Per-location time-to-first-byte budget, as configured (`0` turns
+    /// off an upstream default).
+    pub fn proxy_response_header_timeout_secs(&self) -> Option<u64> {
+        self.proxy_response_header_timeout_secs
+    }
+
+    /// Per-location request body cap; `0` counts as unset.
+    pub fn max_body_bytes(&self) -> Option<u64> {
+        self.max_body_bytes.filter(|bytes| *bytes > 0)
+    }
+
+    pub fn proxy_compress(&self) -> bool {
+        self.proxy_compress.unwrap_or(false)
+    }
+
+    pub fn proxy_compress_min_length(&self) -> u64 {
+        self.proxy_compress_min_length.unwrap_or(1024)
+    }
+
+    pub fn proxy_compress_strict(&self) -> bool {
+        self.proxy_compress_strict.unwrap_or(false)
+    }
+
+    pub fn fallback_static(&self) -> Option<&str> {
+        self.fallback_static
+            .as_deref()
+            .filter(|path| !path.is_empty())
+    }
+
+    pub fn fallback_status(&self) -> u16 {
+        self.fallback_status.unwrap_or(503)
+    }
+
+    pub fn set_headers(&self) -> &[SetHeader] {
+        &self.set_headers
+    }
+
+    /// Lowercased names from `proxy_hide_header`.
+    pub fn proxy_hide_headers(&self) -> Vec<String> {
+        self.proxy_hide_header
+            .as_ref()
+            .map(|list| {
+                list.items()
+                    .iter()
+                    .map(|name| name.to_ascii_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// `proxy_redirect` entries; `["default"]` when unset, none for `"off"`.
+    /// Invalid entries are skipped (validation reports them).
+    pub fn proxy_redirects(&self) -> Vec<ProxyRedirect> {
+        let Some(list) = &self.proxy_redirect else {
+            return vec![ProxyRedirect::Default];
+        };
+        let items = list.items();
+        if items.iter().any(|item| item.eq_ignore_ascii_case("off")) {
+            return Vec::new();
+        }
+        items
+            .iter()
+            .filter_map(|raw| ProxyRedirect::parse(raw).ok())
+            .collect()
+    }
+
+    /// Parse `proxy_set_header` into `set_headers`, skipping invalid entries.
+    pub(crate) fn compile_set_headers(&mut self) {
+        self.set_headers = self
+            .proxy_set_header
+            .as_ref()
+            .map(|list| {
+                list.items()
+                    .iter()
+                    .filter_map(|raw| SetHeader::parse(raw).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    /// Compile `rewrite` into `rewrite_rules`, skipping invalid entries.
+    pub(crate) fn compile_rewrites(&mut self) {
+        self.rewrite_rules = self
+            .rewrite
+            .as_ref()
+            .map(|list| {
+                list.items()
+                    .iter()
+                    .filter_map(|raw| RewriteRule::parse(raw).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
     pub(crate) fn apply_defaults_from_server(&mut self, server: &ServerConfig) {
         if self.root.is_none() {
             self.root = Some(server.root.clone());
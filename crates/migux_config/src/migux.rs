@@ -1,13 +1,14 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 
+use crate::builder::MiguxConfigBuilder;
 use crate::validation::{ConfigReport, validate};
-use crate::{GlobalConfig, HttpConfig, LocationConfig, ServerConfig, UpstreamConfig};
+use crate::{GlobalConfig, HttpConfig, LocationConfig, ServerConfig, StreamConfig, UpstreamConfig};
 
 // =======================================================
 // MIGUX CONFIG — main config
 // =======================================================
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct MiguxConfig {
     #[serde(default)]
     pub global: GlobalConfig,
@@ -15,18 +16,28 @@ pub struct MiguxConfig {
     #[serde(default)]
     pub http: HttpConfig,
 
-    #[serde(default)]
+    #[serde(default, serialize_with = "sorted")]
     pub upstream: HashMap<String, UpstreamConfig>,
 
-    #[serde(default)]
+    #[serde(default, serialize_with = "sorted")]
     #[serde(rename = "server")]
     pub servers: HashMap<String, ServerConfig>,
 
-    #[serde(default)]
+    #[serde(default, serialize_with = "sorted")]
     pub location: HashMap<String, LocationConfig>,
+
+    /// Raw TCP listeners (`[stream.<name>]`).
+    #[serde(default, serialize_with = "sorted")]
+    pub stream: HashMap<String, StreamConfig>,
+
+    /// File this config was loaded from (`None` for built-in defaults).
+    #[serde(skip_deserializing)]
+    pub source_path: Option<String>,
 }
 
 impl Default for MiguxConfig {
+    /// Usable fallback config: a single `main` server on `0.0.0.0:8080`
+    /// serving `./public`.
     fn default() -> Self {
         let mut cfg = Self {
             global: GlobalConfig::default(),
@@ -34,6 +45,8 @@ impl Default for MiguxConfig {
             upstream: HashMap::new(),
             servers: HashMap::new(),
             location: HashMap::new(),
+            stream: HashMap::new(),
+            source_path: None,
         };
         cfg.apply_defaults();
         cfg
@@ -41,6 +54,11 @@ impl Default for MiguxConfig {
 }
 
 impl MiguxConfig {
+    /// Start a config in code instead of loading `migux.conf`.
+    pub fn builder() -> MiguxConfigBuilder {
+        MiguxConfigBuilder::new()
+    }
+
     pub fn has_tls_servers(&self) -> bool {
         self.servers.values().any(|s| s.tls.is_some())
     }
@@ -73,6 +91,14 @@ impl MiguxConfig {
         &self.location
     }
 
+    pub fn streams(&self) -> &HashMap<String, StreamConfig> {
+        &self.stream
+    }
+
+    pub fn source_path(&self) -> Option<&str> {
+        self.source_path.as_deref()
+    }
+
     pub fn location(&self, name: &str) -> Option<&LocationConfig> {
         self.location.get(name)
     }
@@ -89,6 +115,7 @@ impl MiguxConfig {
 
         let mut cfg: MiguxConfig = built.try_deserialize()?;
 
+        cfg.source_path = Some(file_name.to_string());
         cfg.apply_defaults();
         Ok(cfg)
     }
@@ -118,7 +145,7 @@ impl MiguxConfig {
         }
     }
 
-    fn apply_defaults(&mut self) {
+    pub(crate) fn apply_defaults(&mut self) {
         self.http.apply_cache_defaults();
 
         let def_global = GlobalConfig::default();
@@ -127,18 +154,51 @@ impl MiguxConfig {
         let def_http = HttpConfig::default();
         self.http.apply_defaults_from(&def_http);
 
+        // An empty config (no servers, no locations) still serves something useful.
+        if self.servers.is_empty() && self.location.is_empty() && self.stream.is_empty() {
+            self.servers
+                .insert("main".to_string(), ServerConfig::default());
+        }
+
+        for upstream in self.upstream.values_mut() {
+            upstream.resolve_servers();
+        }
+
         let def_server = ServerConfig::default();
         for server in self.servers.values_mut() {
             server.apply_defaults_from(&def_server);
         }
 
+        for stream in self.stream.values_mut() {
+            stream.apply_defaults();
+        }
+
         for location in self.location.values_mut() {
+            location.compile_rewrites();
+            location.compile_set_headers();
             if let Some(server) = self.servers.get(&location.server) {
                 location.apply_defaults_from_server(server);
             }
         }
     }
 
+    /// The effective config (defaults applied, lists parsed) as pretty JSON,
+    /// sections sorted by name so dumps can be diffed. With `redact`, inline
+    /// secrets (`proxy_set_header` values) are replaced by `<redacted>`.
+    pub fn to_json(&self, redact: bool) -> String {
+        let mut value = serde_json::to_value(self).expect("config serializes to JSON");
+        if redact && let Some(locations) = value["location"].as_object_mut() {
+            for location in locations.values_mut() {
+                if let Some(headers) = location["proxy_set_header"].as_array_mut() {
+                    for header in headers {
+                        *header = redact_header(header.as_str().unwrap_or_default()).into();
+                    }
+                }
+            }
+        }
+        serde_json::to_string_pretty(&value).expect("JSON value serializes")
+    }
+
     pub fn print(&self) {
         println!("================ MIGUX CONFIG ================");
         self.print_global();
@@ -146,6 +206,7 @@ impl MiguxConfig {
         self.print_upstreams();
         self.print_servers();
         self.print_locations();
+        self.print_streams();
         println!("==============================================");
     }
 
@@ -156,8 +217,17 @@ impl MiguxConfig {
             "  worker_connections   = {}",
             self.global.worker_connections
         );
+        println!("  overload_action      = {:?}", self.global.overload_action);
+        println!(
+            "  max_queue_wait_secs  = {}",
+            self.global.max_queue_wait_secs
+        );
         println!("  log_level            = {}", self.global.log_level);
+        println!("  log_format           = {:?}", self.global.log_format);
         println!("  error_log            = {}", self.global.error_log);
+        println!("  tcp_fastopen         = {}", self.global.tcp_fastopen);
+        println!("  max_servers          = {}", self.global.max_servers);
+        println!("  max_locations        = {}", self.global.max_locations);
     }
 
     fn print_http(&self) {
@@ -167,11 +237,40 @@ impl MiguxConfig {
             "  keepalive_timeout    = {}",
             self.http.keepalive_timeout_secs
         );
+        println!(
+            "  keepalive_max_requests = {}",
+            self.http.keepalive_max_requests
+        );
+        if self.http.debug_connection_header {
+            println!("  debug_connection_header = true");
+        }
+        println!("  proxy_buffer_size    = {}", self.http.proxy_buffer_size);
+        println!("  client_buffer_size   = {}", self.http.client_buffer_size);
         println!("  access_log           = {}", self.http.access_log);
+        println!(
+            "  access_log_sample_rate = {}",
+            self.http.access_log_sample_rate
+        );
+        println!(
+            "  access_log_skip_paths = {:?}",
+            self.http.access_log_skip_paths()
+        );
+        println!(
+            "  access_log_skip_statuses = {:?}",
+            self.http.access_log_skip_statuses()
+        );
+        if let Some(path) = self.http.health_check_path() {
+            println!("  health_check_path    = {}", path);
+            println!("  health_check_log     = {}", self.http.health_check_log);
+        }
         println!(
             "  client_read_timeout_secs = {}",
             self.http.client_read_timeout_secs
         );
+        println!(
+            "  client_write_timeout_secs = {}",
+            self.http.client_write_timeout_secs
+        );
         println!(
             "  proxy_connect_timeout_secs = {}",
             self.http.proxy_connect_timeout_secs
@@ -192,10 +291,58 @@ impl MiguxConfig {
             "  proxy_pool_idle_timeout_secs = {}",
             self.http.proxy_pool_idle_timeout_secs
         );
+        println!(
+            "  proxy_retry_5xx_get          = {}",
+            self.http.proxy_retry_5xx_get
+        );
+        println!(
+            "  proxy_retry_backoff_ms       = {}",
+            self.http.proxy_retry_backoff_ms
+        );
+        println!(
+            "  proxy_retry_backoff_jitter   = {}",
+            self.http.proxy_retry_backoff_jitter
+        );
+        println!(
+            "  proxy_request_timeout_secs   = {}",
+            self.http.proxy_request_timeout_secs
+        );
+        println!(
+            "  proxy_strict_framing         = {}",
+            self.http.proxy_strict_framing
+        );
+        println!(
+            "  proxy_max_interim_responses  = {}",
+            self.http.proxy_max_interim_responses
+        );
+        println!(
+            "  proxy_interim_timeout_secs   = {}",
+            self.http.proxy_interim_timeout_secs
+        );
+        println!(
+            "  proxy_error_detail           = {}",
+            self.http.proxy_error_detail
+        );
+        println!(
+            "  proxy_forwarded_header       = {:?}",
+            self.http.proxy_forwarded_header
+        );
+        println!(
+            "  proxy_trust_forwarded        = {}",
+            self.http.proxy_trust_forwarded
+        );
         println!(
             "  max_request_headers_bytes = {}",
             self.http.max_request_headers_bytes
         );
+        println!(
+            "  max_header_value_bytes = {}",
+            self.http.max_header_value_bytes
+        );
+        println!(
+            "  max_request_uri_bytes = {}",
+            self.http.max_request_uri_bytes
+        );
         println!(
             "  max_request_body_bytes = {}",
             self.http.max_request_body_bytes
@@ -208,11 +355,25 @@ impl MiguxConfig {
             "  max_upstream_response_body_bytes = {}",
             self.http.max_upstream_response_body_bytes
         );
+        println!("  allow_get_body = {}", self.http.allow_get_body);
+        println!("  allow_trace    = {}", self.http.allow_trace);
+        println!("  method_override = {}", self.http.method_override);
+        println!(
+            "  method_override_forward_original = {}",
+            self.http.method_override_forward_original
+        );
+        println!("  encoded_slashes = {:?}", self.http.encoded_slashes);
+        println!("  temp_dir        = {:?}", self.http.temp_dir);
+        println!("  charset         = {}", self.http.charset);
         println!("  cache_dir       = {:?}", self.http.cache_dir);
         println!(
             "  cache_default_ttl_secs       = {:?}",
             self.http.cache_default_ttl_secs
         );
+        println!(
+            "  cache_type_ttls              = {:?}",
+            self.http.cache_type_ttls
+        );
         println!(
             "  cache_max_object_bytes       = {:?}",
             self.http.cache_max_object_bytes
@@ -237,6 +398,18 @@ impl MiguxConfig {
             "  cache_inactive_secs           = {:?}",
             self.http.cache_inactive_secs
         );
+        println!(
+            "  cache_stale_while_revalidate_secs = {:?}",
+            self.http.cache_stale_while_revalidate_secs
+        );
+        println!(
+            "  cache_stale_if_error_secs     = {:?}",
+            self.http.cache_stale_if_error_secs
+        );
+        println!(
+            "  cache_compress                = {}",
+            self.http.cache_compress
+        );
     }
 
     fn print_upstreams(&self) {
@@ -244,6 +417,34 @@ impl MiguxConfig {
         for (name, up) in &self.upstream {
             println!("  upstream {}:", name);
             println!("    server   = {}", up.server);
+            if let Some(version) = &up.proxy_http_version {
+                println!("    proxy_http_version = {}", version);
+            }
+            if let Some(connection) = &up.proxy_connection {
+                println!("    proxy_connection   = {}", connection);
+            }
+            if let Some(forwarded_host) = &up.forwarded_host {
+                println!("    forwarded_host     = {:?}", forwarded_host);
+            }
+            if up.proxy_canonicalize_headers {
+                println!("    proxy_canonicalize_headers = true");
+            }
+            if let Some(secs) = up.proxy_response_header_timeout_secs {
+                println!("    proxy_response_header_timeout_secs = {}", secs);
+            }
+            if up.resolve {
+                println!("    resolve  = true");
+            }
+            if up.tls {
+                println!("    tls      = true");
+                println!("    tls_verify = {}", up.tls_verify());
+                if let Some(ca_path) = &up.tls_ca_path {
+                    println!("    tls_ca_path = {}", ca_path);
+                }
+                if let Some(server_name) = &up.tls_server_name {
+                    println!("    tls_server_name = {}", server_name);
+                }
+            }
         }
     }
 
@@ -255,12 +456,20 @@ impl MiguxConfig {
             println!("    server_name = {}", srv.server_name);
             println!("    root        = {}", srv.root);
             println!("    index       = {}", srv.index);
+            if srv.default_server {
+                println!("    default_server = true");
+            }
+            println!("    http2       = {}", srv.http2);
+            println!("    http2_cleartext = {}", srv.http2_cleartext);
             if let Some(tls) = &srv.tls {
                 println!("    tls.listen        = {}", tls.listen);
                 println!("    tls.cert_path     = {}", tls.cert_path);
                 println!("    tls.key_path      = {}", tls.key_path);
                 println!("    tls.redirect_http = {}", tls.redirect_http);
                 println!("    tls.http2         = {}", tls.http2);
+                if let Some(alpn) = &tls.alpn {
+                    println!("    tls.alpn          = {}", alpn);
+                }
                 if let Some(max_age) = tls.hsts_max_age_secs {
                     println!("    tls.hsts_max_age_secs = {}", max_age);
                     println!(
@@ -283,9 +492,251 @@ impl MiguxConfig {
                 loc.r#type // enum, lo mostramos con Debug
             );
             println!("    root         = {:?}", loc.root);
+            if let Some(roots) = &loc.roots {
+                println!("    roots        = {}", roots);
+            }
+            if let Some(archive) = &loc.archive {
+                println!("    archive      = {}", archive);
+            }
             println!("    index        = {:?}", loc.index);
+            if let Some(charset) = &loc.charset {
+                println!("    charset      = {}", charset);
+            }
             println!("    upstream     = {:?}", loc.upstream);
             println!("    strip_prefix = {:?}", loc.strip_prefix);
+            if let Some(cache_control) = &loc.cache_control {
+                println!("    cache_control = {}", cache_control);
+            }
+            if let Some(respect) = loc.respect_origin_cache_control {
+                println!("    respect_origin_cache_control = {}", respect);
+            }
+            if let Some(force) = loc.force_revalidate {
+                println!("    force_revalidate = {}", force);
+            }
+            if let Some(ttl) = loc.cache_ttl_secs {
+                println!("    cache_ttl_secs = {}", ttl);
+            }
+            if let Some(rewrite) = &loc.rewrite {
+                println!("    rewrite      = {}", rewrite);
+            }
+            if let Some(set) = &loc.proxy_set_header {
+                println!("    proxy_set_header = {}", set);
+            }
+            if let Some(hide) = &loc.proxy_hide_header {
+                println!("    proxy_hide_header = {}", hide);
+            }
+            if let Some(redirect) = &loc.proxy_redirect {
+                println!("    proxy_redirect = {}", redirect);
+            }
+            if let Some(secs) = loc.proxy_read_timeout_secs {
+                println!("    proxy_read_timeout_secs = {}", secs);
+            }
+            if let Some(secs) = loc.proxy_response_header_timeout_secs {
+                println!("    proxy_response_header_timeout_secs = {}", secs);
+            }
+            if let Some(bytes) = loc.max_body_bytes {
+                println!("    max_body_bytes = {}", bytes);
+            }
+            if let Some(compress) = loc.proxy_compress {
+                println!(
+                    "    proxy_compress = {} (min {} bytes)",
+                    compress,
+                    loc.proxy_compress_min_length()
+                );
+            }
+            if let Some(strict) = loc.proxy_compress_strict {
+                println!("    proxy_compress_strict = {}", strict);
+            }
+            if let Some(path) = &loc.fallback_static {
+                println!("    fallback_static = {} ({})", path, loc.fallback_status());
+            }
+            if let Some(ranges) = loc.accept_ranges {
+                println!("    accept_ranges = {:?}", ranges);
+            }
+            if let Some(serve) = loc.serve_dotfiles {
+                println!("    serve_dotfiles = {}", serve);
+            }
+            if let Some(allow) = &loc.allow_dotfile_prefixes {
+                println!("    allow_dotfile_prefixes = {}", allow);
+            }
+            if let Some(format) = loc.error_format {
+                println!("    error_format = {:?}", format);
+            }
+        }
+    }
+
+    fn print_streams(&self) {
+        if self.stream.is_empty() {
+            return;
+        }
+        println!("\n[stream]");
+        for (name, stream) in &self.stream {
+            println!("  stream {}:", name);
+            println!("    listen       = {}", stream.listen);
+            println!("    upstream     = {}", stream.upstream);
+            if let Some(secs) = stream.proxy_connect_timeout_secs {
+                println!("    proxy_connect_timeout_secs = {}", secs);
+            }
+            println!(
+                "    proxy_idle_timeout_secs = {}",
+                stream.proxy_idle_timeout_secs().unwrap_or(0)
+            );
+        }
+    }
+}
+
+/// Serialize a map with its keys in order.
+fn sorted<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// `Name: value` with the value hidden.
+fn redact_header(header: &str) -> String {
+    match header.split_once(':') {
+        Some((name, _)) => format!("{}: <redacted>", name.trim()),
+        None => "<redacted>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MiguxConfig;
+    use crate::HttpConfig;
+
+    fn load(contents: &str) -> MiguxConfig {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("migux.conf");
+        std::fs::write(&path, contents).expect("write");
+        MiguxConfig::from_file(path.to_str().expect("utf8 path")).expect("config")
+    }
+
+    #[test]
+    fn json_dump_has_resolved_values_and_redacts_secrets() {
+        let cfg = load(
+            r#"
+[global]
+worker_connections = 64
+
+[upstream.app]
+server = ["127.0.0.1:3000", "127.0.0.1:3001"]
+
+[server.main]
+listen = "127.0.0.1:8080"
+root = "/srv/www"
+
+[location.api]
+server = "main"
+path = "/api"
+type = "proxy"
+upstream = "app"
+proxy_set_header = ["Authorization: Bearer s3cr3t", "X-Env: prod"]
+"#,
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&cfg.to_json(false)).expect("valid JSON");
+        assert_eq!(json["global"]["worker_connections"], 64);
+        // Defaults are filled in.
+        assert_eq!(json["global"]["overload_action"], "queue");
+        assert_eq!(
+            json["http"]["keepalive_timeout_secs"],
+            HttpConfig::default().keepalive_timeout_secs
+        );
+        assert_eq!(
+            json["upstream"]["app"]["server"],
+            serde_json::json!(["127.0.0.1:3000", "127.0.0.1:3001"])
+        );
+        assert_eq!(json["server"]["main"]["listen"], "127.0.0.1:8080");
+        assert_eq!(json["location"]["api"]["type"], "proxy");
+        assert_eq!(json["location"]["api"]["root"], "/srv/www");
+        assert_eq!(
+            json["location"]["api"]["proxy_set_header"][0],
+            "Authorization: Bearer s3cr3t"
+        );
+
+        let redacted: serde_json::Value =
+            serde_json::from_str(&cfg.to_json(true)).expect("valid JSON");
+        assert_eq!(
+            redacted["location"]["api"]["proxy_set_header"],
+            serde_json::json!(["Authorization: <redacted>", "X-Env: <redacted>"])
+        );
+        assert!(!cfg.to_json(true).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn section_counts_over_the_global_limits_are_config_errors() {
+        let contents = r#"
+[global]
+max_servers = 1
+max_locations = 2
+
+[server.main]
+listen = "127.0.0.1:8080"
+
+[server.other]
+listen = "127.0.0.1:8081"
+
+[location.a]
+server = "main"
+path = "/a"
+
+[location.b]
+server = "main"
+path = "/b"
+
+[location.c]
+server = "other"
+path = "/c"
+"#;
+        let report = load(contents).validate();
+        assert!(
+            report
+                .errors()
+                .iter()
+                .any(|e| e.contains("2 servers defined") && e.contains("max_servers = 1")),
+            "{:?}",
+            report.errors()
+        );
+        assert!(
+            report
+                .errors()
+                .iter()
+                .any(|e| e.contains("3 locations defined") && e.contains("max_locations = 2")),
+            "{:?}",
+            report.errors()
+        );
+
+        // 0 keeps the default: no limit.
+        let unlimited = contents
+            .replace("max_servers = 1", "max_servers = 0")
+            .replace("max_locations = 2", "max_locations = 0");
+        assert!(!load(&unlimited).validate().has_errors());
+    }
+
+    #[test]
+    fn upstream_with_an_empty_server_list_is_a_config_error() {
+        for server in ["[]", "[ , ]"] {
+            let cfg = load(&format!(
+                r#"
+[upstream.app]
+server = "{server}"
+
+[server.main]
+listen = "127.0.0.1:8080"
+"#
+            ));
+            let report = cfg.validate();
+            assert!(
+                report
+                    .errors()
+                    .iter()
+                    .any(|e| e.contains("upstream 'app'") && e.contains("lists no addresses")),
+                "{server}: {:?}",
+                report.errors()
+            );
         }
     }
 }
@@ -0,0 +1,57 @@
+use regex::Regex;
+
+// =======================================================
+// REWRITE RULES (regex path rewrite for proxy locations)
+// =======================================================
+/// What to do after a rewrite rule matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RewriteFlag {
+    /// Keep applying the following rules to the rewritten path.
+    #[default]
+    Continue,
+    /// Stop processing rules (nginx `last`).
+    Last,
+    /// Stop processing rules (nginx `break`).
+    Break,
+}
+
+/// Compiled `rewrite` rule: `"<regex> <replacement> [last|break]"`.
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub pattern: Regex,
+    /// Replacement with `$1` / `${name}` capture references.
+    pub replacement: String,
+    pub flag: RewriteFlag,
+}
+
+impl RewriteRule {
+    /// Parse and compile a single rule.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut parts = raw.split_whitespace();
+        let (Some(pattern), Some(replacement)) = (parts.next(), parts.next()) else {
+            return Err(format!(
+                "rewrite '{raw}' must be '<regex> <replacement> [last|break]'"
+            ));
+        };
+        let flag = match parts.next() {
+            None => RewriteFlag::Continue,
+            Some("last") => RewriteFlag::Last,
+            Some("break") => RewriteFlag::Break,
+            Some(other) => {
+                return Err(format!(
+                    "rewrite '{raw}' has unknown flag '{other}' (expected last or break)"
+                ));
+            }
+        };
+        if parts.next().is_some() {
+            return Err(format!("rewrite '{raw}' has trailing tokens"));
+        }
+        let pattern = Regex::new(pattern)
+            .map_err(|e| format!("rewrite regex '{pattern}' is invalid: {e}"))?;
+        Ok(Self {
+            pattern,
+            replacement: replacement.to_string(),
+            flag,
+        })
+    }
+}
@@ -1,17 +1,24 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::TlsConfig;
+use crate::{TlsConfig, normalize_listen};
 
 // =======================================================
 // SERVER CONFIG + DEFAULTS
 // =======================================================
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct ServerConfig {
     pub listen: String,
     pub server_name: String,
     pub root: String,
     pub index: String,
+    /// Fallback server for its listen address when no server_name matches.
+    pub default_server: bool,
+    /// Speak HTTP/2: offered via ALPN on the TLS listener (like `tls.http2`)
+    /// and, with `http2_cleartext`, on the plain listener.
+    pub http2: bool,
+    /// Accept prior-knowledge h2c on `listen` (requires `http2`).
+    pub http2_cleartext: bool,
     pub tls: Option<TlsConfig>,
 }
 
@@ -22,6 +29,9 @@ impl Default for ServerConfig {
             server_name: "localhost".into(),
             root: "./public".into(),
             index: "index.html".into(),
+            default_server: false,
+            http2: false,
+            http2_cleartext: false,
             tls: None,
         }
     }
@@ -44,6 +54,19 @@ impl ServerConfig {
         &self.index
     }
 
+    pub fn default_server(&self) -> bool {
+        self.default_server
+    }
+
+    pub fn http2(&self) -> bool {
+        self.http2
+    }
+
+    /// Whether the plain listener should detect HTTP/2 prior knowledge.
+    pub fn h2c(&self) -> bool {
+        self.http2 && self.http2_cleartext
+    }
+
     pub fn tls(&self) -> Option<&TlsConfig> {
         self.tls.as_ref()
     }
@@ -52,6 +75,15 @@ impl ServerConfig {
         if self.listen.is_empty() {
             self.listen = defaults.listen.clone();
         }
+        // Invalid values are left as written; validation reports them.
+        if let Ok(listen) = normalize_listen(&self.listen, 80) {
+            self.listen = listen;
+        }
+        if let Some(tls) = &mut self.tls
+            && let Ok(listen) = normalize_listen(&tls.listen, 443)
+        {
+            tls.listen = listen;
+        }
         if self.server_name.is_empty() {
             self.server_name = defaults.server_name.clone();
         }
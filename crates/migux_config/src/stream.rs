@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use crate::normalize_listen;
+
+// =======================================================
+// STREAM CONFIG (raw TCP proxying)
+// =======================================================
+/// `[stream.<name>]`: every connection accepted on `listen` is relayed byte
+/// for byte to a server of `upstream`, with no HTTP parsing. The upstream's
+/// strategy, health state and `tls` apply as for HTTP locations.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Address accepted on, e.g. `"0.0.0.0:5432"` (a port is required).
+    pub listen: String,
+    /// Name of the `[upstream.<name>]` connections are relayed to.
+    pub upstream: String,
+    /// Connect timeout per upstream server (falls back to
+    /// http.proxy_connect_timeout_secs); the next server is tried after it.
+    pub proxy_connect_timeout_secs: Option<u64>,
+    /// Close both sides after this long without bytes in either direction
+    /// (default 600; `0` waits forever).
+    pub proxy_idle_timeout_secs: Option<u64>,
+}
+
+/// Idle timeout when `proxy_idle_timeout_secs` is unset.
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+
+impl StreamConfig {
+    pub fn listen(&self) -> &str {
+        &self.listen
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub fn proxy_connect_timeout_secs(&self) -> Option<u64> {
+        self.proxy_connect_timeout_secs.filter(|secs| *secs > 0)
+    }
+
+    /// `None` when idle connections are never closed.
+    pub fn proxy_idle_timeout_secs(&self) -> Option<u64> {
+        Some(
+            self.proxy_idle_timeout_secs
+                .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+        )
+        .filter(|secs| *secs > 0)
+    }
+
+    /// `listen` as a bindable address. There is no default port to fall
+    /// back on, so an address without one is an error.
+    pub(crate) fn normalized_listen(&self) -> Result<String, String> {
+        let listen = normalize_listen(&self.listen, 1)?;
+        if normalize_listen(&self.listen, 2)? != listen {
+            return Err(format!("listen '{}' needs a port", self.listen));
+        }
+        Ok(listen)
+    }
+
+    pub(crate) fn apply_defaults(&mut self) {
+        // Invalid values are left as written; validation reports them.
+        if let Ok(listen) = self.normalized_listen() {
+            self.listen = listen;
+        }
+    }
+}
@@ -1,9 +1,14 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::StringList;
+
+/// ALPN protocol ids `tls.alpn` may list.
+pub const ALPN_PROTOCOLS: [&str; 2] = ["h2", "http/1.1"];
 
 // =======================================================
 // TLS CONFIG
 // =======================================================
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 // #[serde(default)]
 /// TLS listener configuration for a server.
 pub struct TlsConfig {
@@ -17,6 +22,11 @@ pub struct TlsConfig {
     pub redirect_http: bool,
     /// Enable HTTP/2 via ALPN on this TLS listener.
     pub http2: bool,
+    /// ALPN protocols offered, in order of preference. Unset offers `h2`
+    /// (when HTTP/2 is enabled) then `http/1.1`; an empty list offers none,
+    /// so an `h2`-only client falls back to HTTP/1.1 instead of failing the
+    /// handshake. `h2` is never offered without HTTP/2.
+    pub alpn: Option<StringList>,
     /// Optional HSTS max-age in seconds (None disables HSTS).
     pub hsts_max_age_secs: Option<u64>,
     /// Enable includeSubDomains in the HSTS header when set to true.
@@ -44,6 +54,10 @@ impl TlsConfig {
         self.http2
     }
 
+    pub fn alpn(&self) -> Option<Vec<String>> {
+        self.alpn.as_ref().map(StringList::items)
+    }
+
     pub fn hsts_max_age_secs(&self) -> Option<u64> {
         self.hsts_max_age_secs
     }
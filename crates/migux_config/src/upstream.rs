@@ -1,4 +1,8 @@
-use serde::Deserialize;
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::listen::resolve;
 
 // =======================================================
 // UPSTREAM CONFIG + DEFAULTS
@@ -10,12 +14,81 @@ pub enum UpstreamServers {
     Many(Vec<String>),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Always the list of addresses (see [`UpstreamServers::addrs`]).
+impl Serialize for UpstreamServers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.addrs())
+    }
+}
+
+impl UpstreamServers {
+    /// Configured addresses. `One` also accepts a list written as text,
+    /// e.g. `"[\"a:1\", \"b:2\"]"`.
+    pub fn addrs(&self) -> Vec<String> {
+        let raw = match self {
+            UpstreamServers::Many(list) => return list.clone(),
+            UpstreamServers::One(raw) => raw.trim(),
+        };
+        match raw.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            Some(inner) => inner
+                .split(',')
+                .map(|part| part.trim().trim_matches('"'))
+                .filter(|part| !part.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => vec![raw.to_string()],
+        }
+    }
+}
+
+// =======================================================
+// FORWARDED HOST (X-Forwarded-Host / X-Forwarded-Port)
+// =======================================================
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardedHost {
+    /// Hostname in `X-Forwarded-Host`, port in `X-Forwarded-Port`.
+    #[default]
+    #[serde(rename = "split")]
+    Split,
+    /// The client's Host, port included, in `X-Forwarded-Host`; no
+    /// `X-Forwarded-Port`.
+    #[serde(rename = "verbatim")]
+    Verbatim,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct UpstreamConfig {
     pub server: UpstreamServers,
     pub strategy: Option<String>,
     pub health: UpstreamHealthConfig,
+    /// HTTP version spoken to this upstream: "1.1" (default) or "1.0".
+    pub proxy_http_version: Option<String>,
+    /// `Connection` sent upstream: "keep-alive" (default, pooled) or "close".
+    pub proxy_connection: Option<String>,
+    /// How the client's Host is forwarded (`split` or `verbatim`).
+    pub forwarded_host: Option<ForwardedHost>,
+    /// Send forwarded request header names in canonical Train-Case
+    /// (`content-type` -> `Content-Type`, `etag` -> `ETag`) instead of as
+    /// the client wrote them.
+    pub proxy_canonicalize_headers: bool,
+    /// Seconds allowed from sending a request to receiving the final
+    /// response headers; past it the attempt fails with 504. `0`/unset: no
+    /// limit beyond the read timeout.
+    pub proxy_response_header_timeout_secs: Option<u64>,
+    /// Resolve server hostnames once at load time, so every spelling of a
+    /// backend shares one connection pool and one health entry.
+    pub resolve: bool,
+    /// Speak HTTPS to the servers.
+    pub tls: bool,
+    /// Verify server certificates (default true). `false` accepts any
+    /// certificate and is only meant for self-signed dev backends.
+    pub tls_verify: Option<bool>,
+    /// PEM bundle of trusted roots, used instead of the system bundle.
+    pub tls_ca_path: Option<String>,
+    /// Name sent as SNI and checked against the certificate; defaults to
+    /// each server's host.
+    pub tls_server_name: Option<String>,
 }
 
 impl Default for UpstreamConfig {
@@ -24,6 +97,16 @@ impl Default for UpstreamConfig {
             server: UpstreamServers::One(String::new()),
             strategy: Some("round_robin".to_string()),
             health: UpstreamHealthConfig::default(),
+            proxy_http_version: None,
+            proxy_connection: None,
+            forwarded_host: None,
+            proxy_canonicalize_headers: false,
+            proxy_response_header_timeout_secs: None,
+            resolve: false,
+            tls: false,
+            tls_verify: None,
+            tls_ca_path: None,
+            tls_server_name: None,
         }
     }
 }
@@ -40,9 +123,75 @@ impl UpstreamConfig {
     pub fn health(&self) -> &UpstreamHealthConfig {
         &self.health
     }
+
+    /// Request-line version for this upstream (`HTTP/1.1` unless set to "1.0").
+    pub fn proxy_http_version(&self) -> &'static str {
+        match self.proxy_http_version.as_deref().map(str::trim) {
+            Some("1.0") => "HTTP/1.0",
+            _ => "HTTP/1.1",
+        }
+    }
+
+    pub fn forwarded_host(&self) -> ForwardedHost {
+        self.forwarded_host.unwrap_or_default()
+    }
+
+    pub fn proxy_canonicalize_headers(&self) -> bool {
+        self.proxy_canonicalize_headers
+    }
+
+    pub fn proxy_response_header_timeout_secs(&self) -> Option<u64> {
+        self.proxy_response_header_timeout_secs
+    }
+
+    pub fn resolve(&self) -> bool {
+        self.resolve
+    }
+
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    pub fn tls_verify(&self) -> bool {
+        self.tls_verify.unwrap_or(true)
+    }
+
+    pub fn tls_ca_path(&self) -> Option<&str> {
+        self.tls_ca_path.as_deref()
+    }
+
+    pub fn tls_server_name(&self) -> Option<&str> {
+        self.tls_server_name.as_deref()
+    }
+
+    /// With `resolve`, replace each server by its resolved `ip:port`.
+    /// Entries that fail to resolve are kept as written (validation flags them).
+    /// Spellings that resolve to the same address collapse into one entry, so
+    /// that address is not picked more often than the others.
+    pub fn resolve_servers(&mut self) {
+        if !self.resolve {
+            return;
+        }
+        let mut resolved: Vec<String> = Vec::new();
+        for addr in self.server.addrs() {
+            let addr = resolve_addr(&addr).unwrap_or(addr);
+            if !resolved.contains(&addr) {
+                resolved.push(addr);
+            }
+        }
+        self.server = UpstreamServers::Many(resolved);
+    }
+
+    /// Whether upstream connections are kept alive (and pooled).
+    pub fn proxy_keep_alive(&self) -> bool {
+        !matches!(
+            self.proxy_connection.as_deref().map(str::trim),
+            Some(value) if value.eq_ignore_ascii_case("close")
+        )
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 /// Health/circuit-breaker configuration for an upstream pool.
 pub struct UpstreamHealthConfig {
@@ -50,12 +199,21 @@ pub struct UpstreamHealthConfig {
     pub fail_threshold: u32,
     /// Cooldown time in seconds before retrying a down node.
     pub cooldown_secs: u64,
+    /// Cap in seconds on the cooldown taken from an upstream 5xx
+    /// `Retry-After`; 0 ignores `Retry-After`.
+    pub max_retry_after_secs: u64,
     /// Enable active TCP health checks.
     pub active: bool,
     /// Interval for active checks in seconds.
     pub interval_secs: u64,
     /// Timeout for active checks in seconds.
     pub timeout_secs: u64,
+    /// Consecutive failed active checks that mark a node down (falls back
+    /// to `fail_threshold`). It then stays down, whatever the cooldown,
+    /// until `healthy_threshold` checks in a row succeed.
+    pub unhealthy_threshold: Option<u32>,
+    /// Consecutive successful active checks that bring a down node back.
+    pub healthy_threshold: u32,
 }
 
 impl Default for UpstreamHealthConfig {
@@ -63,9 +221,12 @@ impl Default for UpstreamHealthConfig {
         Self {
             fail_threshold: 1,
             cooldown_secs: 10,
+            max_retry_after_secs: 300,
             active: false,
             interval_secs: 10,
             timeout_secs: 1,
+            unhealthy_threshold: None,
+            healthy_threshold: 1,
         }
     }
 }
@@ -79,6 +240,10 @@ impl UpstreamHealthConfig {
         self.cooldown_secs
     }
 
+    pub fn max_retry_after_secs(&self) -> u64 {
+        self.max_retry_after_secs
+    }
+
     pub fn active(&self) -> bool {
         self.active
     }
@@ -90,6 +255,24 @@ impl UpstreamHealthConfig {
     pub fn timeout_secs(&self) -> u64 {
         self.timeout_secs
     }
+
+    pub fn unhealthy_threshold(&self) -> u32 {
+        self.unhealthy_threshold.unwrap_or(self.fail_threshold)
+    }
+
+    pub fn healthy_threshold(&self) -> u32 {
+        self.healthy_threshold
+    }
+}
+
+/// Canonical `ip:port` for an upstream `host:port` (IPv4 preferred).
+fn resolve_addr(addr: &str) -> Option<String> {
+    if let Ok(addr) = addr.trim().parse::<SocketAddr>() {
+        return Some(addr.to_string());
+    }
+    let (host, port) = addr.trim().rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    resolve(host, port.parse().ok()?)
 }
 
 impl std::fmt::Display for UpstreamServers {
@@ -100,3 +283,35 @@ impl std::fmt::Display for UpstreamServers {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{UpstreamConfig, UpstreamServers};
+
+    #[test]
+    fn text_lists_are_split() {
+        let one = UpstreamServers::One(r#"["a:1", "b:2",]"#.into());
+        assert_eq!(one.addrs(), ["a:1", "b:2"]);
+        assert_eq!(UpstreamServers::One(" a:1 ".into()).addrs(), ["a:1"]);
+    }
+
+    #[test]
+    fn resolve_canonicalizes_spellings() {
+        let mut upstream = UpstreamConfig {
+            server: UpstreamServers::Many(vec![
+                "localhost:3000".into(),
+                "127.0.0.1:3000".into(),
+                "[::1]:3000".into(),
+                "no-such-host.invalid:3000".into(),
+            ]),
+            resolve: true,
+            ..Default::default()
+        };
+        upstream.resolve_servers();
+        // `localhost` and `127.0.0.1` collapse into one entry.
+        assert_eq!(
+            upstream.server.addrs(),
+            ["127.0.0.1:3000", "[::1]:3000", "no-such-host.invalid:3000"]
+        );
+    }
+}
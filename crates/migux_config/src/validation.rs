@@ -1,6 +1,13 @@
-use std::{collections::HashSet, net::SocketAddr, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+};
 
-use crate::{LocationType, MiguxConfig, UpstreamServers};
+use crate::{
+    ALPN_PROTOCOLS, LocationType, MiguxConfig, OverloadAction, ProxyRedirect, RewriteRule,
+    SetHeader, UpstreamConfig, UpstreamServers, is_header_name, normalize_listen,
+};
 
 /// Validation output for a loaded Migux configuration.
 #[derive(Debug, Default)]
@@ -64,20 +71,232 @@ impl ConfigReport {
     }
 }
 
+impl std::fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.format().trim_end())
+    }
+}
+
+/// Lets a failed [`crate::MiguxConfigBuilder::build`] travel as an error.
+impl std::error::Error for ConfigReport {}
+
 /// Validate a Migux configuration and return a report of issues.
 pub fn validate(cfg: &MiguxConfig) -> ConfigReport {
     let mut report = ConfigReport::default();
 
+    validate_log_level(cfg, &mut report);
+    validate_overload(cfg, &mut report);
+    validate_tcp_fastopen(cfg, &mut report);
+    validate_section_limits(cfg, &mut report);
+    validate_access_log(cfg, &mut report);
+    validate_buffer_sizes(cfg, &mut report);
+    validate_header_limits(cfg, &mut report);
+    validate_charset(cfg, &mut report);
+    validate_temp_dir(cfg, &mut report);
     validate_http_cache(cfg, &mut report);
+    validate_proxy_retry(cfg, &mut report);
     validate_upstreams(cfg, &mut report);
     validate_servers(cfg, &mut report);
     validate_locations(cfg, &mut report);
+    validate_streams(cfg, &mut report);
 
     report
 }
 
+/// `log_level` is an `EnvFilter` string: comma-separated `level` or
+/// `target=level` directives.
+fn validate_log_level(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    const LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
+    for directive in cfg.global.log_level().split(',').map(str::trim) {
+        let level = directive
+            .rsplit_once('=')
+            .map_or(directive, |(_, level)| level);
+        if !LEVELS.iter().any(|known| level.eq_ignore_ascii_case(known)) {
+            report.warn(format!(
+                "global.log_level directive '{directive}' is not a level (trace, debug, info, warn, error, off) or 'target=level'; logging may fall back to 'info'"
+            ));
+        }
+    }
+}
+
+fn validate_tcp_fastopen(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    if cfg.global.tcp_fastopen() && !cfg!(target_os = "linux") {
+        report.warn("global.tcp_fastopen is only supported on Linux; it has no effect here");
+    }
+}
+
+fn validate_overload(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    if cfg.global.overload_action() == OverloadAction::Reject
+        && cfg.global.max_queue_wait_secs().is_some()
+    {
+        report.warn(
+            "global.max_queue_wait_secs is ignored with overload_action = \"reject\" (nothing queues)",
+        );
+    }
+}
+
+fn validate_section_limits(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    if let Some(max) = cfg.global.max_servers()
+        && cfg.servers.len() > max
+    {
+        report.error(format!(
+            "{} servers defined, more than global.max_servers = {max}",
+            cfg.servers.len()
+        ));
+    }
+    if let Some(max) = cfg.global.max_locations()
+        && cfg.location.len() > max
+    {
+        report.error(format!(
+            "{} locations defined, more than global.max_locations = {max}",
+            cfg.location.len()
+        ));
+    }
+}
+
+fn validate_access_log(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    for status in cfg.http.access_log_skip_statuses() {
+        let valid = match status.as_bytes() {
+            [d, b'x' | b'X', b'x' | b'X'] => (b'1'..=b'5').contains(d),
+            _ => status
+                .parse::<u16>()
+                .is_ok_and(|s| (100..=599).contains(&s)),
+        };
+        if !valid {
+            report.error(format!(
+                "http.access_log_skip_statuses entry '{status}' must be a status code or class like '2xx'"
+            ));
+        }
+    }
+
+    for path in cfg.http.access_log_skip_paths() {
+        if !path.starts_with('/') {
+            report.warn(format!(
+                "http.access_log_skip_paths entry '{path}' does not start with '/'; it will never match"
+            ));
+        }
+    }
+
+    if let Some(dir) = match cfg.http.access_log() {
+        "off" | "" | "-" => None,
+        path => Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty()),
+    } && !dir.is_dir()
+    {
+        report.warn(format!(
+            "http.access_log directory '{}' does not exist; access lines will go to the tracing \
+             log instead (set access_log = \"-\" for that, or \"off\")",
+            dir.display()
+        ));
+    }
+
+    if let Some(path) = cfg.http.health_check_path()
+        && !path.starts_with('/')
+    {
+        report.error(format!(
+            "http.health_check_path '{path}' must start with '/'"
+        ));
+    } else if cfg.http.health_check_log && cfg.http.health_check_path().is_none() {
+        report.warn("http.health_check_log is ignored without health_check_path");
+    }
+}
+
+fn validate_header_limits(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let line = cfg.http.max_header_value_bytes();
+    let total = cfg.http.max_request_headers_bytes();
+    if line > 0 && line >= total {
+        report.warn(format!(
+            "http.max_header_value_bytes {line} is not below max_request_headers_bytes {total}; it never applies"
+        ));
+    }
+}
+
+/// Per-read buffer sizes: below 1 KiB costs a syscall per few packets, above
+/// 1 MiB only pins memory per connection.
+fn validate_buffer_sizes(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    const RANGE: std::ops::RangeInclusive<usize> = 1024..=1024 * 1024;
+    for (name, size) in [
+        ("proxy_buffer_size", cfg.http.proxy_buffer_size()),
+        ("client_buffer_size", cfg.http.client_buffer_size()),
+    ] {
+        if !RANGE.contains(&size) {
+            report.error(format!(
+                "http.{name} {size} must be between 1024 and 1048576 bytes"
+            ));
+        }
+    }
+}
+
+fn validate_proxy_retry(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let backoff_ms = cfg.http.proxy_retry_backoff_ms();
+    if cfg.http.proxy_retry_backoff_jitter() && backoff_ms == 0 {
+        report.warn("http.proxy_retry_backoff_jitter is set but proxy_retry_backoff_ms is 0");
+    }
+    let request_timeout_secs = cfg.http.proxy_request_timeout_secs();
+    if request_timeout_secs > 0 && backoff_ms >= request_timeout_secs.saturating_mul(1000) {
+        report.warn(format!(
+            "http.proxy_retry_backoff_ms {backoff_ms} is not below proxy_request_timeout_secs {request_timeout_secs}; no failover attempt will fit"
+        ));
+    }
+}
+
+fn validate_charset(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let charset = cfg.http.charset();
+    if !is_charset(charset) {
+        report.error(format!(
+            "http.charset '{charset}' must be a charset name (e.g. utf-8, iso-8859-1) or 'off'"
+        ));
+    }
+}
+
+/// A `charset` parameter value: an RFC 2978 name such as `utf-8`, or `off`.
+fn is_charset(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:+".contains(&b))
+}
+
+fn validate_temp_dir(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let Some(temp_dir) = cfg.http.temp_dir.as_deref() else {
+        return;
+    };
+    let path = Path::new(temp_dir);
+    if !path.is_dir() {
+        report.error(format!(
+            "http.temp_dir '{temp_dir}' does not exist or is not a directory"
+        ));
+        return;
+    }
+    let probe = path.join(format!(".migux-probe-{}", std::process::id()));
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+        }
+        Err(err) => {
+            report.error(format!("http.temp_dir '{temp_dir}' is not writable: {err}"));
+        }
+    }
+}
+
 fn validate_http_cache(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    validate_cache_type_ttls(cfg, report);
+
     let Some(cache_dir) = cfg.http.cache_dir.as_deref() else {
+        if cfg.http.cache_stale_while_revalidate_secs.is_some()
+            || cfg.http.cache_stale_if_error_secs.is_some()
+        {
+            report.warn(
+                "http.cache_stale_* is set but http.cache_dir is not; stale serving is disabled",
+            );
+        }
+        if !cfg.http.cache_type_ttls.is_empty() {
+            report.warn("http.cache_type_ttls is set but http.cache_dir is not; it has no effect");
+        }
+        if cfg.http.cache_compress {
+            report.warn("http.cache_compress is set but http.cache_dir is not; it has no effect");
+        }
         return;
     };
 
@@ -94,7 +313,7 @@ fn validate_http_cache(cfg: &MiguxConfig, report: &mut ConfigReport) {
         ));
     }
 
-    if cfg.http.cache_default_ttl_secs.unwrap_or(0) == 0 {
+    if cfg.http.cache_default_ttl_secs.unwrap_or(0) == 0 && cfg.http.cache_type_ttls.is_empty() {
         report.warn("http.cache_default_ttl_secs is 0; cache entries will not be stored");
     }
 
@@ -113,12 +332,12 @@ fn validate_http_cache(cfg: &MiguxConfig, report: &mut ConfigReport) {
     if let (Some(total), Some(max_obj)) = (
         cfg.http.cache_max_total_bytes,
         cfg.http.cache_max_object_bytes,
-    ) {
-        if total > 0 && max_obj > total {
-            report.warn(
-                "http.cache_max_object_bytes exceeds cache_max_total_bytes; oversized objects will never be cached",
-            );
-        }
+    ) && total > 0
+        && max_obj > total
+    {
+        report.warn(
+            "http.cache_max_object_bytes exceeds cache_max_total_bytes; oversized objects will never be cached",
+        );
     }
 
     if cfg.http.cache_max_ttl_secs == Some(0) {
@@ -128,6 +347,47 @@ fn validate_http_cache(cfg: &MiguxConfig, report: &mut ConfigReport) {
     if cfg.http.cache_inactive_secs == Some(0) {
         report.warn("http.cache_inactive_secs is 0; inactive eviction is disabled");
     }
+
+    if let (Some(inactive), Some(stale)) = (
+        cfg.http.cache_inactive_secs.filter(|v| *v > 0),
+        cfg.http
+            .cache_stale_while_revalidate_secs
+            .max(cfg.http.cache_stale_if_error_secs),
+    ) && stale > inactive
+    {
+        report.warn(
+            "http.cache_stale_* exceeds cache_inactive_secs; inactive eviction may drop stale entries first",
+        );
+    }
+}
+
+/// Keys are `type/subtype`, `type/*` or `*`, without parameters.
+fn validate_cache_type_ttls(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+    for (pattern, ttl) in &cfg.http.cache_type_ttls {
+        let valid = pattern == "*"
+            || pattern.split_once('/').is_some_and(|(kind, subtype)| {
+                is_token(kind) && (subtype == "*" || is_token(subtype))
+            });
+        if !valid {
+            report.error(format!(
+                "http.cache_type_ttls key '{pattern}' must be a content type, 'type/*' or '*'"
+            ));
+            continue;
+        }
+        if let Some(max_ttl) = cfg.http.cache_max_ttl_secs.filter(|v| *v > 0)
+            && *ttl > max_ttl
+        {
+            report.warn(format!(
+                "http.cache_type_ttls '{pattern}' = {ttl} exceeds http.cache_max_ttl_secs {max_ttl}; it will be clamped"
+            ));
+        }
+    }
 }
 
 fn validate_upstreams(cfg: &MiguxConfig, report: &mut ConfigReport) {
@@ -136,6 +396,13 @@ fn validate_upstreams(cfg: &MiguxConfig, report: &mut ConfigReport) {
             UpstreamServers::One(server) => {
                 if server.trim().is_empty() {
                     report.error(format!("upstream '{name}' has an empty server address"));
+                } else if upstream.server.addrs().is_empty() {
+                    // A list written as text ("[]", "[ , ]") that parses to
+                    // nothing would only fail at request time, with a 502.
+                    report.error(format!(
+                        "upstream '{name}' server '{}' lists no addresses",
+                        server.trim()
+                    ));
                 }
             }
             UpstreamServers::Many(servers) => {
@@ -151,27 +418,160 @@ fn validate_upstreams(cfg: &MiguxConfig, report: &mut ConfigReport) {
                 }
             }
         }
+
+        if upstream.resolve {
+            for addr in upstream.server.addrs() {
+                if addr.parse::<SocketAddr>().is_err() {
+                    report.error(format!(
+                        "upstream '{name}' server '{addr}' could not be resolved"
+                    ));
+                }
+            }
+        }
+
+        validate_upstream_health(name, upstream, report);
+
+        if let Some(strategy) = upstream.strategy()
+            && !matches!(strategy, "single" | "round_robin" | "ewma" | "failover")
+        {
+            report.error(format!(
+                "upstream '{name}' strategy '{strategy}' must be single, round_robin, ewma or failover"
+            ));
+        }
+
+        if let Some(version) = upstream.proxy_http_version.as_deref()
+            && !matches!(version.trim(), "1.0" | "1.1")
+        {
+            report.error(format!(
+                "upstream '{name}' proxy_http_version '{version}' must be 1.0 or 1.1"
+            ));
+        }
+
+        if let Some(connection) = upstream.proxy_connection.as_deref()
+            && !["keep-alive", "close"]
+                .iter()
+                .any(|v| connection.trim().eq_ignore_ascii_case(v))
+        {
+            report.error(format!(
+                "upstream '{name}' proxy_connection '{connection}' must be keep-alive or close"
+            ));
+        }
+
+        validate_upstream_tls(name, upstream, report);
+    }
+}
+
+fn validate_upstream_health(name: &str, upstream: &UpstreamConfig, report: &mut ConfigReport) {
+    let health = &upstream.health;
+    if health.unhealthy_threshold == Some(0) {
+        report.error(format!(
+            "upstream '{name}' health.unhealthy_threshold must be at least 1"
+        ));
+    }
+    if health.healthy_threshold == 0 {
+        report.error(format!(
+            "upstream '{name}' health.healthy_threshold must be at least 1"
+        ));
+    }
+    if !health.active && (health.unhealthy_threshold.is_some() || health.healthy_threshold != 1) {
+        report.warn(format!(
+            "upstream '{name}' health thresholds only apply to active checks; set health.active = true"
+        ));
+    }
+}
+
+fn validate_upstream_tls(name: &str, upstream: &UpstreamConfig, report: &mut ConfigReport) {
+    if !upstream.tls() {
+        if upstream.tls_verify.is_some()
+            || upstream.tls_ca_path.is_some()
+            || upstream.tls_server_name.is_some()
+        {
+            report.warn(format!(
+                "upstream '{name}' sets tls_* options without tls = true; they are ignored"
+            ));
+        }
+        return;
+    }
+
+    if !upstream.tls_verify() {
+        report.warn(format!(
+            "upstream '{name}' has tls_verify = false: server certificates are NOT verified; use only for development"
+        ));
+    }
+
+    if let Some(ca_path) = upstream.tls_ca_path() {
+        match std::fs::read_to_string(ca_path) {
+            Ok(pem) if pem.contains("-----BEGIN CERTIFICATE-----") => {}
+            Ok(_) => report.error(format!(
+                "upstream '{name}' tls_ca_path '{ca_path}' contains no PEM certificates"
+            )),
+            Err(err) => report.error(format!(
+                "upstream '{name}' tls_ca_path '{ca_path}' cannot be read: {err}"
+            )),
+        }
+    }
+
+    // Without a name the handshake carries no SNI, and the certificate must
+    // cover the IP itself; origins behind a CDN usually need both.
+    if upstream.tls_server_name().is_none()
+        && let Some(addr) = upstream
+            .server
+            .addrs()
+            .into_iter()
+            .find(|addr| addr.parse::<std::net::SocketAddr>().is_ok())
+    {
+        report.warn(format!(
+            "upstream '{name}' reaches {addr} by IP over TLS without tls_server_name; no SNI is sent and the certificate must list the IP"
+        ));
+    }
+
+    if let Some(server_name) = upstream.tls_server_name()
+        && server_name.parse::<std::net::IpAddr>().is_err()
+        && (server_name.trim().is_empty() || server_name.contains(['/', ':', ' ']))
+    {
+        report.error(format!(
+            "upstream '{name}' tls_server_name '{server_name}' must be a hostname or IP address"
+        ));
     }
 }
 
 fn validate_servers(cfg: &MiguxConfig, report: &mut ConfigReport) {
     if cfg.servers.is_empty() {
-        report.error("no [server] sections found; at least one server is required");
+        if cfg.stream.is_empty() {
+            report.error("no [server] sections found; at least one server is required");
+        }
         return;
     }
 
     let mut http_listens = HashSet::new();
     let mut tls_listens = HashSet::new();
+    let mut defaults_by_listen: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut h2c_by_listen: HashMap<&str, (Vec<&str>, Vec<&str>)> = HashMap::new();
 
     for (name, server) in &cfg.servers {
+        if server.http2_cleartext && !server.http2 {
+            report.error(format!(
+                "server '{name}' sets http2_cleartext without http2 = true"
+            ));
+        }
+        let (h2c, http1) = h2c_by_listen.entry(server.listen.as_str()).or_default();
+        if server.h2c() {
+            h2c.push(name.as_str());
+        } else {
+            http1.push(name.as_str());
+        }
+        if server.default_server {
+            defaults_by_listen
+                .entry(server.listen.as_str())
+                .or_default()
+                .push(name.as_str());
+        }
+
         if server.listen.trim().is_empty() {
             report.error(format!("server '{name}' has an empty listen address"));
         } else {
-            if server.listen.parse::<SocketAddr>().is_err() {
-                report.warn(format!(
-                    "server '{name}' listen '{listen}' is not a socket address; DNS resolution will be used",
-                    listen = server.listen
-                ));
+            if let Err(e) = normalize_listen(&server.listen, 80) {
+                report.error(format!("server '{name}': {e}"));
             }
             http_listens.insert(server.listen.clone());
         }
@@ -191,6 +591,9 @@ fn validate_servers(cfg: &MiguxConfig, report: &mut ConfigReport) {
                     "server '{name}' enables TLS but tls.listen is empty"
                 ));
             } else {
+                if let Err(e) = normalize_listen(&tls.listen, 443) {
+                    report.error(format!("server '{name}' tls: {e}"));
+                }
                 tls_listens.insert(tls.listen.clone());
             }
 
@@ -213,6 +616,19 @@ fn validate_servers(cfg: &MiguxConfig, report: &mut ConfigReport) {
                 }
             }
 
+            for protocol in tls.alpn().unwrap_or_default() {
+                if !ALPN_PROTOCOLS.contains(&protocol.as_str()) {
+                    report.error(format!(
+                        "server '{name}' tls.alpn '{protocol}' is not one of {}",
+                        ALPN_PROTOCOLS.join(", ")
+                    ));
+                } else if protocol == "h2" && !tls.http2 && !server.http2 {
+                    report.warn(format!(
+                        "server '{name}' lists h2 in tls.alpn without http2 = true; it is not offered"
+                    ));
+                }
+            }
+
             if let Some(max_age) = tls.hsts_max_age_secs {
                 if max_age == 0 {
                     report.warn(format!(
@@ -232,6 +648,29 @@ fn validate_servers(cfg: &MiguxConfig, report: &mut ConfigReport) {
         }
     }
 
+    for (listen, mut names) in defaults_by_listen {
+        if names.len() > 1 {
+            names.sort_unstable();
+            report.error(format!(
+                "listen '{listen}' has more than one default_server: {}",
+                names.join(", ")
+            ));
+        }
+    }
+
+    // h2c detection is per listener, not per server.
+    for (listen, (mut h2c, mut http1)) in h2c_by_listen {
+        if !h2c.is_empty() && !http1.is_empty() {
+            h2c.sort_unstable();
+            http1.sort_unstable();
+            report.warn(format!(
+                "listen '{listen}' accepts h2c (enabled by {}); it also applies to {}",
+                h2c.join(", "),
+                http1.join(", ")
+            ));
+        }
+    }
+
     for listen in tls_listens {
         if http_listens.contains(&listen) {
             report.error(format!(
@@ -241,7 +680,74 @@ fn validate_servers(cfg: &MiguxConfig, report: &mut ConfigReport) {
     }
 }
 
+/// `[stream.*]`: a bindable listen of its own and an existing upstream.
+fn validate_streams(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let taken: HashSet<&str> = cfg
+        .servers
+        .values()
+        .flat_map(|server| {
+            std::iter::once(server.listen.as_str())
+                .chain(server.tls.as_ref().map(|tls| tls.listen.as_str()))
+        })
+        .collect();
+    let mut by_listen: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for (name, stream) in &cfg.stream {
+        match stream.normalized_listen() {
+            Err(e) => report.error(format!("stream '{name}': {e}")),
+            Ok(_) => {
+                if taken.contains(stream.listen.as_str()) {
+                    report.error(format!(
+                        "stream '{name}' listen '{}' conflicts with a server listen",
+                        stream.listen
+                    ));
+                }
+                by_listen
+                    .entry(stream.listen.as_str())
+                    .or_default()
+                    .push(name.as_str());
+            }
+        }
+
+        if stream.upstream.trim().is_empty() {
+            report.error(format!("stream '{name}' has no upstream"));
+        } else if !cfg.upstream.contains_key(&stream.upstream) {
+            report.error(format!(
+                "stream '{name}' references unknown upstream '{}'",
+                stream.upstream
+            ));
+        }
+    }
+
+    for (listen, mut names) in by_listen {
+        if names.len() > 1 {
+            names.sort_unstable();
+            report.error(format!(
+                "listen '{listen}' is used by more than one stream: {}",
+                names.join(", ")
+            ));
+        }
+    }
+}
+
 fn validate_locations(cfg: &MiguxConfig, report: &mut ConfigReport) {
+    let mut by_path: HashMap<(&str, &str), Vec<&str>> = HashMap::new();
+    for (name, location) in &cfg.location {
+        by_path
+            .entry((location.server.as_str(), location.path.as_str()))
+            .or_default()
+            .push(name.as_str());
+    }
+    for ((server, path), mut names) in by_path {
+        if names.len() > 1 {
+            names.sort_unstable();
+            report.error(format!(
+                "server '{server}' has more than one location for path '{path}': {}",
+                names.join(", ")
+            ));
+        }
+    }
+
     for (name, location) in &cfg.location {
         if location.server.trim().is_empty() {
             report.error(format!("location '{name}' has an empty server reference"));
@@ -268,16 +774,95 @@ fn validate_locations(cfg: &MiguxConfig, report: &mut ConfigReport) {
             ));
         }
 
-        if let Some(prefix) = location.strip_prefix.as_deref() {
-            if !location.path.starts_with(prefix) {
+        if let Some(prefix) = location.strip_prefix.as_deref()
+            && !location.path.starts_with(prefix)
+        {
+            report.warn(format!(
+                "location '{name}' strip_prefix '{prefix}' does not match path '{path}'",
+                prefix = prefix,
+                path = location.path
+            ));
+        }
+
+        if let Some(rewrite) = &location.rewrite {
+            for raw in rewrite.items() {
+                if let Err(err) = RewriteRule::parse(&raw) {
+                    report.error(format!("location '{name}' {err}"));
+                }
+            }
+            if matches!(&location.r#type, LocationType::Static) {
+                report.warn(format!(
+                    "location '{name}' defines rewrite but is not proxy; rules are ignored"
+                ));
+            } else if location.strip_prefix.is_some() {
                 report.warn(format!(
-                    "location '{name}' strip_prefix '{prefix}' does not match path '{path}'",
-                    prefix = prefix,
-                    path = location.path
+                    "location '{name}' defines both rewrite and strip_prefix; strip_prefix is ignored"
                 ));
             }
         }
 
+        if let Some(set) = &location.proxy_set_header {
+            for raw in set.items() {
+                if let Err(err) = SetHeader::parse(&raw) {
+                    report.error(format!("location '{name}' {err}"));
+                }
+            }
+        }
+        if let Some(redirect) = &location.proxy_redirect {
+            let items = redirect.items();
+            if items.len() > 1 && items.iter().any(|item| item.eq_ignore_ascii_case("off")) {
+                report.error(format!(
+                    "location '{name}' proxy_redirect 'off' cannot be combined with other entries"
+                ));
+            }
+            for raw in items
+                .iter()
+                .filter(|item| !item.eq_ignore_ascii_case("off"))
+            {
+                if let Err(err) = ProxyRedirect::parse(raw) {
+                    report.error(format!("location '{name}' {err}"));
+                }
+            }
+        }
+        if let Some(hide) = &location.proxy_hide_header {
+            for header in hide.items() {
+                if !is_header_name(&header) {
+                    report.error(format!(
+                        "location '{name}' proxy_hide_header '{header}' is not a valid header name"
+                    ));
+                }
+            }
+        }
+
+        if let Some(charset) = location.charset.as_deref()
+            && !is_charset(charset)
+        {
+            report.error(format!(
+                "location '{name}' charset '{charset}' must be a charset name (e.g. utf-8, iso-8859-1) or 'off'"
+            ));
+        }
+
+        if location.max_body_bytes == Some(0) {
+            report.warn(format!(
+                "location '{name}' max_body_bytes = 0 is ignored; http.max_request_body_bytes applies"
+            ));
+        }
+
+        if let Some(path) = location.fallback_static()
+            && !Path::new(path).is_file()
+        {
+            report.warn(format!(
+                "location '{name}' fallback_static '{path}' is not a file; the built-in 502/504 is sent instead"
+            ));
+        }
+        if let Some(status) = location.fallback_status
+            && !(200..=599).contains(&status)
+        {
+            report.error(format!(
+                "location '{name}' fallback_status {status} must be between 200 and 599"
+            ));
+        }
+
         match &location.r#type {
             LocationType::Static => {
                 if location.upstream.is_some() {
@@ -285,19 +870,60 @@ fn validate_locations(cfg: &MiguxConfig, report: &mut ConfigReport) {
                         "location '{name}' is static but defines an upstream"
                     ));
                 }
-
-                let root = location
-                    .root
-                    .as_deref()
-                    .unwrap_or_else(|| server.root.as_str());
-                if !root.trim().is_empty() && !Path::new(root).exists() {
+                if location.proxy_read_timeout_secs.is_some() {
                     report.warn(format!(
-                        "location '{name}' root '{root}' does not exist",
-                        root = root
+                        "location '{name}' is static; proxy_read_timeout_secs is ignored"
                     ));
                 }
+                if location.proxy_response_header_timeout_secs.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is static; proxy_response_header_timeout_secs is ignored"
+                    ));
+                }
+                if location.proxy_set_header.is_some() || location.proxy_hide_header.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is static; proxy_set_header/proxy_hide_header are ignored"
+                    ));
+                }
+                if location.proxy_redirect.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is static; proxy_redirect is ignored"
+                    ));
+                }
+                if location.fallback_static.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is static; fallback_static is ignored"
+                    ));
+                }
+                if location.proxy_compress.is_some() || location.proxy_compress_strict.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is static; proxy_compress is ignored"
+                    ));
+                }
+
+                for root in location.roots_or(&server.root) {
+                    if !root.trim().is_empty() && !Path::new(&root).exists() {
+                        report.warn(format!(
+                            "location '{name}' root '{root}' does not exist",
+                            root = root
+                        ));
+                    }
+                }
             }
             LocationType::Proxy => {
+                if location.roots.is_some() {
+                    report.warn(format!("location '{name}' is proxy but defines roots"));
+                }
+                if location.accept_ranges.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is proxy; accept_ranges is ignored (ranges are forwarded)"
+                    ));
+                }
+                if location.serve_dotfiles.is_some() || location.allow_dotfile_prefixes.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is proxy; serve_dotfiles/allow_dotfile_prefixes are ignored"
+                    ));
+                }
                 let Some(upstream) = location.upstream.as_deref() else {
                     report.error(format!(
                         "location '{name}' is proxy but no upstream is configured"
@@ -311,12 +937,136 @@ fn validate_locations(cfg: &MiguxConfig, report: &mut ConfigReport) {
                     ));
                 }
             }
+            LocationType::OriginPull => {
+                if location.fallback_static.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is origin_pull; fallback_static is ignored"
+                    ));
+                }
+                if location.proxy_compress.is_some() || location.proxy_compress_strict.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is origin_pull; proxy_compress is ignored (files are stored as sent)"
+                    ));
+                }
+                let roots = location.roots_or(&server.root);
+                if roots.len() > 1 {
+                    report.warn(format!(
+                        "location '{name}' is origin_pull; pulled files are stored in the first root only"
+                    ));
+                }
+                if let Some(root) = roots.first()
+                    && !Path::new(root).is_dir()
+                {
+                    report.error(format!(
+                        "location '{name}' origin_pull root '{root}' is not a directory"
+                    ));
+                }
+                let Some(upstream) = location.upstream.as_deref() else {
+                    report.error(format!(
+                        "location '{name}' is origin_pull but no upstream is configured"
+                    ));
+                    continue;
+                };
+                if !cfg.upstream.contains_key(upstream) {
+                    report.error(format!(
+                        "location '{name}' references unknown upstream '{upstream}'"
+                    ));
+                }
+            }
+            LocationType::Archive => {
+                if location.upstream.is_some()
+                    || location.proxy_set_header.is_some()
+                    || location.proxy_hide_header.is_some()
+                    || location.fallback_static.is_some()
+                    || location.proxy_compress.is_some()
+                    || location.proxy_compress_strict.is_some()
+                {
+                    report.warn(format!(
+                        "location '{name}' is archive; upstream/proxy options are ignored"
+                    ));
+                }
+                if location.root.is_some() || location.roots.is_some() {
+                    report.warn(format!(
+                        "location '{name}' is archive; root/roots are ignored"
+                    ));
+                }
+                match location.archive() {
+                    None => report.error(format!(
+                        "location '{name}' is archive but no archive is configured"
+                    )),
+                    Some(path) if !Path::new(path).is_file() => {
+                        report.error(format!("location '{name}' archive '{path}' is not a file"))
+                    }
+                    Some(path) if !is_archive_path(path) => report.error(format!(
+                        "location '{name}' archive '{path}' must be a .zip or .tar file"
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+        if location.archive.is_some() && !matches!(location.r#type, LocationType::Archive) {
+            report.warn(format!(
+                "location '{name}' sets archive but is not an archive location"
+            ));
+        }
+
+        let serves_files = matches!(
+            &location.r#type,
+            LocationType::Static | LocationType::OriginPull | LocationType::Archive
+        );
+        if location.cache == Some(true) && !serves_files {
+            report.warn(format!("location '{name}' enables cache but is not static"));
+        }
+
+        if location.respect_origin_cache_control == Some(true) && !serves_files {
+            report.warn(format!(
+                "location '{name}' sets respect_origin_cache_control but is not static"
+            ));
+        }
+
+        if location.force_revalidate == Some(true) {
+            if !serves_files {
+                report.warn(format!(
+                    "location '{name}' sets force_revalidate but is not static"
+                ));
+            } else if location.cache_control.is_some() {
+                report.warn(format!(
+                    "location '{name}' cache_control is ignored: force_revalidate sends its own Cache-Control"
+                ));
+            }
         }
 
-        if location.cache == Some(true) {
-            if !matches!(&location.r#type, LocationType::Static) {
-                report.warn(format!("location '{name}' enables cache but is not static"));
+        if let Some(ttl) = location.cache_ttl_secs {
+            if ttl < 0 {
+                report.error(format!(
+                    "location '{name}' cache_ttl_secs must be non-negative (got {ttl})"
+                ));
+            } else if ttl == 0 {
+                report.warn(format!(
+                    "location '{name}' sets cache_ttl_secs=0; responses will not be cached"
+                ));
+            }
+            if let Some(max_ttl) = cfg.http.cache_max_ttl_secs.filter(|v| *v > 0)
+                && ttl > 0
+                && ttl as u64 > max_ttl
+            {
+                report.warn(format!(
+                    "location '{name}' cache_ttl_secs {ttl} exceeds http.cache_max_ttl_secs {max_ttl}; it will be clamped"
+                ));
+            }
+            if !serves_files {
+                report.warn(format!(
+                    "location '{name}' sets cache_ttl_secs but is not static"
+                ));
             }
         }
     }
 }
+
+/// Archive locations read `.zip` and `.tar` files only.
+fn is_archive_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("tar"))
+}
@@ -0,0 +1,51 @@
+//! Location matching over servers with many locations.
+//!
+//! Compares the prefix trie used by `match_location` with the linear
+//! filter + max scan it replaced. Run with `cargo bench -p migux_core`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use migux_config::{LocationConfig, ServerConfig};
+use migux_core::{structs::ServerRuntime, worker::routing::match_location};
+
+fn server_with(count: usize) -> ServerRuntime {
+    let mut locations = vec![LocationConfig {
+        server: "main".into(),
+        path: "/".into(),
+        ..Default::default()
+    }];
+    locations.extend((0..count).map(|i| LocationConfig {
+        server: "main".into(),
+        path: format!("/app{}/section{}", i % 100, i),
+        ..Default::default()
+    }));
+    ServerRuntime::new("main".into(), ServerConfig::default(), locations)
+}
+
+fn linear_scan<'a>(locations: &'a [LocationConfig], path: &str) -> &'a LocationConfig {
+    locations
+        .iter()
+        .filter(|loc| path.starts_with(&loc.path))
+        .max_by_key(|loc| loc.path.len())
+        .unwrap_or(&locations[0])
+}
+
+fn bench_match_location(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_location");
+    for count in [10, 100, 1_000] {
+        let server = server_with(count);
+        let last = count - 1;
+        let path = format!("/app{}/section{last}/assets/app.js", last % 100);
+        group.bench_with_input(BenchmarkId::new("trie", count), &path, |b, path| {
+            b.iter(|| match_location(black_box(&server), black_box(path)))
+        });
+        group.bench_with_input(BenchmarkId::new("linear", count), &path, |b, path| {
+            b.iter(|| linear_scan(black_box(&server.locations), black_box(path)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_match_location);
+criterion_main!(benches);
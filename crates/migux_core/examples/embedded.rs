@@ -0,0 +1,55 @@
+//! migux embedded in another binary, configured in code: static files from
+//! `./public` on 127.0.0.1:8080 and `/api` proxied to 127.0.0.1:3000.
+//! Ctrl+C shuts the listeners down.
+//!
+//! Run with `cargo run -p migux_core --example embedded`.
+
+use migux_config::{
+    LocationConfig, LocationType, MiguxConfig, ServerConfig, UpstreamConfig, UpstreamServers,
+};
+use migux_core::master::Master;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let builder = MiguxConfig::builder()
+        .server(
+            "main",
+            ServerConfig {
+                listen: "127.0.0.1:8080".into(),
+                root: "./public".into(),
+                ..Default::default()
+            },
+        )
+        .upstream(
+            "app",
+            UpstreamConfig {
+                server: UpstreamServers::One("127.0.0.1:3000".into()),
+                ..Default::default()
+            },
+        )
+        .location(
+            "files",
+            LocationConfig {
+                server: "main".into(),
+                path: "/".into(),
+                ..Default::default()
+            },
+        )
+        .location(
+            "api",
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+
+    let handle = Master::from_builder(builder)?.serve().await?;
+    println!("Serving on {:?}", handle.listeners().http);
+
+    tokio::signal::ctrl_c().await?;
+    handle.shutdown().await;
+    Ok(())
+}
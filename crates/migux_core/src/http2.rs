@@ -4,6 +4,15 @@
 //! routing/handler pipeline by bridging each HTTP/2 request into an in-memory
 //! HTTP/1 exchange. It keeps behavior consistent across protocols while
 //! avoiding a full rewrite of the core request path.
+//!
+//! Each HTTP/2 stream gets its own one-request HTTP/1 exchange (a fresh
+//! duplex and handler task, `Connection: close` so the response ends at
+//! EOF). Nothing worth keeping lives in that exchange: the static cache is
+//! process-wide and the `Proxy` (upstream pools, health) is shared by every
+//! connection, so cache hits and pooled upstream connections carry over
+//! between streams. A persistent HTTP/1 worker per connection would either
+//! serialize the multiplexed streams or have to frame responses here; the
+//! per-stream cost is one in-memory duplex and one task.
 
 use std::{net::SocketAddr, sync::Arc};
 
@@ -27,13 +36,15 @@ use migux_proxy::Proxy;
 /// Capacity of the in-memory duplex stream used to bridge HTTP/2 -> HTTP/1.
 const IN_MEMORY_STREAM_CAPACITY: usize = 64 * 1024;
 
-/// Serve a single HTTP/2 connection over an already-accepted TLS stream.
+/// Serve a single HTTP/2 connection over an already-accepted stream: TLS
+/// after ALPN `h2`, or plain TCP for prior-knowledge h2c (`is_tls = false`).
 pub async fn serve_h2_connection<S>(
     stream: S,
     client_addr: SocketAddr,
     servers: Arc<Vec<ServerRuntime>>,
     proxy: Arc<Proxy>,
     cfg: Arc<MiguxConfig>,
+    is_tls: bool,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
@@ -43,7 +54,7 @@ where
         let servers = servers.clone();
         let proxy = proxy.clone();
         let cfg = cfg.clone();
-        async move { handle_h2_request(req, client_addr, servers, proxy, cfg).await }
+        async move { handle_h2_request(req, client_addr, servers, proxy, cfg, is_tls).await }
     });
 
     http2::Builder::new(TokioExecutor::new())
@@ -61,6 +72,7 @@ async fn handle_h2_request(
     servers: Arc<Vec<ServerRuntime>>,
     proxy: Arc<Proxy>,
     cfg: Arc<MiguxConfig>,
+    is_tls: bool,
 ) -> Result<hyper::Response<Full<Bytes>>, hyper::Error> {
     let (parts, body) = req.into_parts();
     let body_bytes = match body.collect().await {
@@ -72,16 +84,17 @@ async fn handle_h2_request(
     };
 
     let req_bytes = build_http1_request(&parts, body_bytes.as_ref());
-    let resp_bytes = match run_http1_pipeline(req_bytes, client_addr, servers, proxy, cfg).await {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            error!(target: "migux::http2", error = ?e, "HTTP/1 pipeline failed");
-            return Ok(simple_h2_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                b"Internal Server Error",
-            ));
-        }
-    };
+    let resp_bytes =
+        match run_http1_pipeline(req_bytes, client_addr, servers, proxy, cfg, is_tls).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!(target: "migux::http2", error = ?e, "HTTP/1 pipeline failed");
+                return Ok(simple_h2_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    b"Internal Server Error",
+                ));
+            }
+        };
 
     match parse_http1_response(&resp_bytes) {
         Ok((status, headers, body)) => {
@@ -134,12 +147,10 @@ fn build_http1_request(parts: &http::request::Parts, body: &[u8]) -> Vec<u8> {
         out.extend_from_slice(b"\r\n");
     }
 
-    if !has_host {
-        if let Some(authority) = parts.uri.authority() {
-            out.extend_from_slice(b"Host: ");
-            out.extend_from_slice(authority.as_str().as_bytes());
-            out.extend_from_slice(b"\r\n");
-        }
+    if !has_host && let Some(authority) = parts.uri.authority() {
+        out.extend_from_slice(b"Host: ");
+        out.extend_from_slice(authority.as_str().as_bytes());
+        out.extend_from_slice(b"\r\n");
     }
 
     out.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
@@ -155,12 +166,20 @@ async fn run_http1_pipeline(
     servers: Arc<Vec<ServerRuntime>>,
     proxy: Arc<Proxy>,
     cfg: Arc<MiguxConfig>,
+    is_tls: bool,
 ) -> anyhow::Result<Vec<u8>> {
     let (mut client_io, server_io) = tokio::io::duplex(IN_MEMORY_STREAM_CAPACITY);
 
     let server_task = tokio::spawn(async move {
-        if let Err(e) =
-            handle_connection(Box::new(server_io), client_addr, servers, proxy, cfg, true).await
+        if let Err(e) = handle_connection(
+            Box::new(server_io),
+            client_addr,
+            servers,
+            proxy,
+            cfg,
+            is_tls,
+        )
+        .await
         {
             error!(target: "migux::http2", error = ?e, "HTTP/1 handler error");
         }
@@ -211,10 +230,10 @@ fn parse_http1_response(bytes: &[u8]) -> anyhow::Result<(StatusCode, HeaderMap,
                 continue;
             }
             "content-length" => {
-                if let Ok(s) = std::str::from_utf8(value) {
-                    if let Ok(len) = s.trim().parse::<usize>() {
-                        content_length = Some(len);
-                    }
+                if let Ok(s) = std::str::from_utf8(value)
+                    && let Ok(len) = s.trim().parse::<usize>()
+                {
+                    content_length = Some(len);
                 }
                 continue;
             }
@@ -232,10 +251,10 @@ fn parse_http1_response(bytes: &[u8]) -> anyhow::Result<(StatusCode, HeaderMap,
     let mut body = bytes[header_len..].to_vec();
     if is_chunked {
         body = decode_chunked(&body)?;
-    } else if let Some(len) = content_length {
-        if body.len() > len {
-            body.truncate(len);
-        }
+    } else if let Some(len) = content_length
+        && body.len() > len
+    {
+        body.truncate(len);
     }
 
     header_map.insert(
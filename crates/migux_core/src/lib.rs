@@ -1,20 +1,27 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use migux_config::{LocationConfig, LocationType, MiguxConfig};
+use migux_config::{LocationConfig, LocationType, MiguxConfig, ServerConfig};
 
 use crate::{
     structs::ServerRuntime,
     types::{ServersByListen, TlsListenConfig, TlsServersByListen},
+    worker::access_log::AccessLog,
 };
 
 pub mod http2;
 pub mod master;
+pub mod stats;
 pub mod structs;
 pub mod types;
 pub mod worker;
 
 /// Build a map of TCP listen address -> servers bound to that address.
+/// The servers share an access log opened from `cfg.http`.
 pub fn build_servers_by_listen(cfg: &MiguxConfig) -> ServersByListen {
+    servers_by_listen(cfg, &Arc::new(AccessLog::from_config(&cfg.http)))
+}
+
+pub(crate) fn servers_by_listen(cfg: &MiguxConfig, access_log: &Arc<AccessLog>) -> ServersByListen {
     let mut map: ServersByListen = HashMap::new();
 
     /*                                                              [server.main]
@@ -26,51 +33,81 @@ pub fn build_servers_by_listen(cfg: &MiguxConfig) -> ServersByListen {
     for (server_name, server_cfg) in &cfg.servers {
         let listen_key = server_cfg.listen.clone(); // "Ej: 0.0.0.0:8080"
 
-        /*
-            * Filter locations that belong
-            * to this server (`location.*` with server = "main")
-
-              [location.main_root]
-               server = "main"
-               path = "/"
-               type = "static"
-               root = "var/www/public"
-               index = "index.html"
-
-        */
-        let mut locations: Vec<LocationConfig> = cfg
-            .location
-            .values()
-            .filter(|loc| loc.server == *server_name)
-            .cloned()
-            .collect();
-
-        // If locations is empty, create a default location for this server
-        if locations.is_empty() {
-            locations.push(LocationConfig {
-                server: server_name.clone(),
-                path: "/".into(),
-                r#type: LocationType::Static,
-                root: Some(server_cfg.root.clone()),
-                index: Some(server_cfg.index.clone()),
-                upstream: None,
-                strip_prefix: None,
-                cache: None,
-            });
-        }
+        let locations = server_locations(cfg, server_name, server_cfg);
 
         // Add the ServerRuntime of each server to the HashMap
-        map.entry(listen_key).or_default().push(ServerRuntime::new(
-            server_name.clone(),
-            server_cfg.clone(),
-            locations,
-        ));
+        map.entry(listen_key).or_default().push(
+            ServerRuntime::new(server_name.clone(), server_cfg.clone(), locations)
+                .with_access_log(access_log.clone()),
+        );
+    }
+    for servers in map.values_mut() {
+        sort_servers(servers);
     }
     map
 }
 
+/// Order servers sharing a listen address: the `default_server` first, the
+/// rest by name, so the fallback server does not depend on HashMap order.
+fn sort_servers(servers: &mut [ServerRuntime]) {
+    servers.sort_by(|a, b| {
+        b.config
+            .default_server
+            .cmp(&a.config.default_server)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+/*
+* Locations that belong to this server (`location.*` with server = "main").
+* A location is only ever routed by the server it names.
+
+  [location.main_root]
+   server = "main"
+   path = "/"
+   type = "static"
+   root = "var/www/public"
+   index = "index.html"
+* */
+fn server_locations(
+    cfg: &MiguxConfig,
+    server_name: &str,
+    server_cfg: &ServerConfig,
+) -> Vec<LocationConfig> {
+    let mut named: Vec<(&String, &LocationConfig)> = cfg
+        .location
+        .iter()
+        .filter(|(_, loc)| loc.server == server_name)
+        .collect();
+    // Section name order keeps `match_location` ties deterministic.
+    named.sort_by(|a, b| a.0.cmp(b.0));
+    let mut locations: Vec<LocationConfig> =
+        named.into_iter().map(|(_, loc)| loc.clone()).collect();
+
+    // If locations is empty, create a default location for this server
+    if locations.is_empty() {
+        locations.push(LocationConfig {
+            server: server_name.to_string(),
+            path: "/".into(),
+            r#type: LocationType::Static,
+            root: Some(server_cfg.root.clone()),
+            index: Some(server_cfg.index.clone()),
+            ..Default::default()
+        });
+    }
+    locations
+}
+
 /// Build a map of TLS listen address -> servers bound to that address.
+/// The servers share an access log opened from `cfg.http`.
 pub fn build_tls_servers_by_listen(cfg: &MiguxConfig) -> TlsServersByListen {
+    tls_servers_by_listen(cfg, &Arc::new(AccessLog::from_config(&cfg.http)))
+}
+
+pub(crate) fn tls_servers_by_listen(
+    cfg: &MiguxConfig,
+    access_log: &Arc<AccessLog>,
+) -> TlsServersByListen {
     let mut map: TlsServersByListen = HashMap::new();
 
     for (server_name, server_cfg) in &cfg.servers {
@@ -80,28 +117,10 @@ pub fn build_tls_servers_by_listen(cfg: &MiguxConfig) -> TlsServersByListen {
 
         let listen_key = tls_cfg.listen.clone();
 
-        let mut locations: Vec<LocationConfig> = cfg
-            .location
-            .values()
-            .filter(|loc| loc.server == *server_name)
-            .cloned()
-            .collect();
-
-        // If locations is empty, create a default location for this server
-        if locations.is_empty() {
-            locations.push(LocationConfig {
-                server: server_name.clone(),
-                path: "/".into(),
-                r#type: LocationType::Static,
-                root: Some(server_cfg.root.clone()),
-                index: Some(server_cfg.index.clone()),
-                upstream: None,
-                strip_prefix: None,
-                cache: None,
-            });
-        }
+        let locations = server_locations(cfg, server_name, server_cfg);
 
-        let runtime = ServerRuntime::new(server_name.clone(), server_cfg.clone(), locations);
+        let runtime = ServerRuntime::new(server_name.clone(), server_cfg.clone(), locations)
+            .with_access_log(access_log.clone());
 
         map.entry(listen_key.clone())
             .and_modify(|entry| {
@@ -109,6 +128,147 @@ pub fn build_tls_servers_by_listen(cfg: &MiguxConfig) -> TlsServersByListen {
             })
             .or_insert_with(|| TlsListenConfig::new(listen_key, tls_cfg.clone(), vec![runtime]));
     }
+    for entry in map.values_mut() {
+        sort_servers(&mut entry.servers);
+    }
 
     map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::build_servers_by_listen;
+    use crate::structs::ServerRuntime;
+    use crate::worker::routing::match_location;
+    use migux_config::{LocationConfig, LocationType, MiguxConfig};
+
+    #[test]
+    fn empty_config_yields_default_server() {
+        let cfg = MiguxConfig::from_file("/nonexistent/migux-empty.conf").expect("empty config");
+        assert!(!cfg.validate().has_errors());
+
+        let by_listen = build_servers_by_listen(&cfg);
+        let servers = by_listen.get("0.0.0.0:8080").expect("default listener");
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].config.root, "./public");
+
+        let location = &servers[0].locations[0];
+        assert_eq!(location.path, "/");
+        assert!(matches!(location.r#type, LocationType::Static));
+    }
+
+    #[test]
+    fn default_config_matches_empty_file() {
+        let cfg = MiguxConfig::default();
+        assert_eq!(cfg.servers.len(), 1);
+        assert_eq!(cfg.servers["main"].listen, "0.0.0.0:8080");
+    }
+
+    fn two_servers_with_locations() -> MiguxConfig {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("migux.conf");
+        std::fs::write(
+            &path,
+            r#"
+[server.a]
+listen = "127.0.0.1:8081"
+root = "/srv/a"
+
+[server.b]
+listen = "127.0.0.1:8082"
+root = "/srv/b"
+
+[location.a_docs]
+server = "a"
+path = "/docs"
+type = "static"
+
+[location.b_admin]
+server = "b"
+path = "/admin"
+type = "static"
+"#,
+        )
+        .expect("write config");
+        MiguxConfig::from_file(path.to_str().expect("utf8 path")).expect("config")
+    }
+
+    #[test]
+    fn locations_stay_with_their_server() {
+        let cfg = two_servers_with_locations();
+        let by_listen = build_servers_by_listen(&cfg);
+
+        let a = &by_listen["127.0.0.1:8081"][0];
+        assert_eq!(a.locations.len(), 1);
+        assert_eq!(a.locations[0].root.as_deref(), Some("/srv/a"));
+        // b's /admin prefix must not be routed by server a.
+        let matched = match_location(a, "/admin/users");
+        assert_eq!(matched.server, "a");
+        assert_eq!(matched.path, "/docs");
+
+        let b = &by_listen["127.0.0.1:8082"][0];
+        let matched = match_location(b, "/admin/users");
+        assert_eq!(matched.server, "b");
+        assert_eq!(matched.root.as_deref(), Some("/srv/b"));
+    }
+
+    #[test]
+    fn server_runtime_drops_foreign_locations() {
+        let cfg = two_servers_with_locations();
+        let all: Vec<LocationConfig> = cfg.location.values().cloned().collect();
+        let runtime = ServerRuntime::new("a".into(), cfg.servers["a"].clone(), all);
+        assert_eq!(runtime.locations.len(), 1);
+        assert_eq!(runtime.locations[0].path, "/docs");
+    }
+
+    #[test]
+    fn duplicate_location_paths_are_rejected() {
+        let mut cfg = MiguxConfig::default();
+        for (name, root) in [("main_b", "/srv/b"), ("main_a", "/srv/a")] {
+            cfg.location.insert(
+                name.to_string(),
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/assets".into(),
+                    root: Some(root.into()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let report = cfg.validate();
+        assert!(
+            report
+                .errors()
+                .iter()
+                .any(|e| e.contains("path '/assets': main_a, main_b")),
+            "{}",
+            report.format()
+        );
+
+        // Ties resolve to the first location by section name.
+        let by_listen = build_servers_by_listen(&cfg);
+        let server = &by_listen["0.0.0.0:8080"][0];
+        for _ in 0..5 {
+            let matched = match_location(server, "/assets/app.js");
+            assert_eq!(matched.root.as_deref(), Some("/srv/a"));
+        }
+    }
+
+    #[test]
+    fn shared_listen_orders_default_server_first_then_by_name() {
+        let mut cfg = MiguxConfig::default();
+        for name in ["c", "b", "a"] {
+            let server = cfg.servers.entry(name.to_string()).or_default();
+            server.listen = "127.0.0.1:9000".into();
+            server.default_server = name == "c";
+        }
+
+        let by_listen = build_servers_by_listen(&cfg);
+        let names: Vec<_> = by_listen["127.0.0.1:9000"]
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(names, ["c", "a", "b"]);
+    }
+}
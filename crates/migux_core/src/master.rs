@@ -1,37 +1,72 @@
 mod accept;
+mod activation;
 mod listeners;
 mod startup;
 mod tls;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use migux_config::MiguxConfig;
+use migux_config::{MiguxConfig, MiguxConfigBuilder};
+use tokio::task::JoinHandle;
 use tracing::{info, instrument};
 
-use crate::{ServersByListen, build_servers_by_listen, build_tls_servers_by_listen};
+use crate::{
+    ServersByListen, servers_by_listen, tls_servers_by_listen, worker::access_log::AccessLog,
+};
 
 pub use crate::structs::CacheStore;
+pub use activation::systemd_listeners;
 
 #[allow(dead_code)]
 pub struct Master {
     cfg: Arc<MiguxConfig>,
     servers_by_listen: Arc<ServersByListen>,
     tls_servers_by_listen: Arc<crate::types::TlsServersByListen>,
+    /// Pre-bound listeners (socket activation), used instead of binding
+    /// their address.
+    inherited: Mutex<Vec<std::net::TcpListener>>,
+    /// Accept loops spawned by `start`, aborted by [`ServerHandle::shutdown`].
+    accept_tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl Master {
     pub fn new(cfg: MiguxConfig) -> Self {
         let cfg = Arc::new(cfg);
-        let servers_by_listen = Arc::new(build_servers_by_listen(&cfg));
-        let tls_servers_by_listen = Arc::new(build_tls_servers_by_listen(&cfg));
+        // One access log (and writer thread) for plain and TLS listeners.
+        let access_log = Arc::new(AccessLog::from_config(&cfg.http));
+        let servers_by_listen = Arc::new(servers_by_listen(&cfg, &access_log));
+        let tls_servers_by_listen = Arc::new(tls_servers_by_listen(&cfg, &access_log));
 
         Self {
             cfg,
             servers_by_listen,
             tls_servers_by_listen,
+            inherited: Mutex::new(Vec::new()),
+            accept_tasks: Mutex::new(Vec::new()),
         }
     }
 
+    /// Master for a config built in code; fails with the validation report
+    /// when the config has errors.
+    pub fn from_builder(builder: MiguxConfigBuilder) -> anyhow::Result<Self> {
+        Ok(Self::new(builder.build()?))
+    }
+
+    /// Serve listen addresses from these already-bound sockets (e.g.
+    /// [`systemd_listeners`]) instead of binding them; lets an unprivileged
+    /// process serve ports below 1024.
+    pub fn with_inherited_listeners(mut self, listeners: Vec<std::net::TcpListener>) -> Self {
+        self.inherited
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(listeners);
+        self
+    }
+
     /// Starts the master process: initializes listeners and spawns accept loops.
     #[instrument(skip(self), fields(
         worker_processes = %self.cfg.global.worker_processes,
@@ -39,14 +74,7 @@ impl Master {
         log_level = %self.cfg.global.log_level,
     ))]
     pub async fn run(self) -> anyhow::Result<()> {
-        self.log_startup();
-
-        let semaphore = self.init_semaphore();
-        let proxy = self.start_proxy();
-
-        self.spawn_http_listeners(semaphore.clone(), proxy.clone())
-            .await?;
-        self.spawn_tls_listeners(semaphore, proxy).await?;
+        self.start().await?;
 
         info!(
             target: "migux::master",
@@ -58,4 +86,83 @@ impl Master {
             tokio::time::sleep(Duration::from_secs(3600)).await;
         }
     }
+
+    /// Bind every listener and spawn its accept loop, then return the bound
+    /// addresses (a `:0` listen gets its ephemeral port filled in). The
+    /// accept loops keep running on the current runtime.
+    pub async fn start(&self) -> anyhow::Result<BoundListeners> {
+        crate::stats::mark_started();
+        self.log_startup();
+
+        let semaphore = self.init_semaphore();
+        self.preload_archives();
+        let proxy = self.start_proxy();
+
+        let http = self
+            .spawn_http_listeners(semaphore.clone(), proxy.clone())
+            .await?;
+        let tls = self
+            .spawn_tls_listeners(semaphore.clone(), proxy.clone())
+            .await?;
+        let stream = self.spawn_stream_listeners(semaphore, proxy).await?;
+        self.drop_unused_inherited();
+
+        if http.is_empty() && tls.is_empty() && stream.is_empty() {
+            anyhow::bail!(
+                "No listeners to bind: configure at least one [server] or [stream] with a listen address"
+            );
+        }
+
+        Ok(BoundListeners { http, tls, stream })
+    }
+
+    /// Like [`Master::start`], for embedding: the returned handle knows the
+    /// bound addresses and stops the listeners. Dropping it without
+    /// `shutdown` leaves the server running.
+    pub async fn serve(self) -> anyhow::Result<ServerHandle> {
+        let listeners = self.start().await?;
+        let tasks =
+            std::mem::take(&mut *self.accept_tasks.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(ServerHandle { listeners, tasks })
+    }
+
+    fn track_accept_task(&self, task: JoinHandle<()>) {
+        self.accept_tasks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(task);
+    }
+}
+
+/// A server started with [`Master::serve`].
+pub struct ServerHandle {
+    listeners: BoundListeners,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub fn listeners(&self) -> &BoundListeners {
+        &self.listeners
+    }
+
+    /// Stop accepting connections; every listener is closed when this
+    /// returns. Connections already accepted finish on their own.
+    pub async fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for task in self.tasks {
+            let _ = task.await;
+        }
+        info!(target: "migux::master", "Listeners shut down");
+    }
+}
+
+/// Addresses the master's listeners are bound to.
+#[derive(Debug, Clone)]
+pub struct BoundListeners {
+    pub http: Vec<SocketAddr>,
+    pub tls: Vec<SocketAddr>,
+    /// `[stream.*]` listeners (raw TCP).
+    pub stream: Vec<SocketAddr>,
 }
@@ -1,13 +1,23 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
 
-use migux_config::MiguxConfig;
+use migux_config::{GlobalConfig, MiguxConfig, OverloadAction};
+use migux_http::responses::send_503_retry_after;
 use migux_proxy::Proxy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest, ReadBuf};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::{Duration, Instant, timeout, timeout_at};
 use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
-use crate::{ServerRuntime, http2::serve_h2_connection, worker::handle_connection};
+use crate::{
+    ServerRuntime, http2::serve_h2_connection, stats::ActiveConnection, worker::handle_connection,
+};
 
 pub(crate) async fn bind_listener(
     listen_addr: &str,
@@ -38,39 +48,114 @@ pub(crate) async fn bind_listener(
                 error = ?e,
                 "Failed to bind listener"
             );
-            Err(e.into())
+            Err(bind_error(listen_addr, e))
         }
     }
 }
 
+/// A bind failure; permission errors (EACCES/EPERM, typically a port below
+/// 1024) say how to get the port without running as root.
+fn bind_error(listen_addr: &str, e: std::io::Error) -> anyhow::Error {
+    if e.kind() != std::io::ErrorKind::PermissionDenied {
+        return e.into();
+    }
+    anyhow::Error::from(e).context(format!(
+        "Permission denied binding {listen_addr}: ports below 1024 need root or \
+         CAP_NET_BIND_SERVICE (e.g. `setcap cap_net_bind_service=+ep /path/to/migux`). \
+         Alternatively pass the socket with systemd socket activation, or listen on a \
+         port above 1024 behind a port-forward"
+    ))
+}
+
 struct AcceptedConn {
     stream: TcpStream,
     addr: SocketAddr,
     permit: OwnedSemaphorePermit,
+    active: ActiveConnection,
 }
 
-async fn accept_with_permit(
+/// `Retry-After` sent with the 503 for shed connections.
+const OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
+/// How long a shed connection may take to accept the 503 and hang up.
+const OVERLOAD_LINGER: Duration = Duration::from_secs(1);
+
+/// Accept the next connection.
+///
+/// In plain `queue` mode (no `max_queue_wait_secs`) its `worker_connections`
+/// slot is taken here, so a saturated server stops accepting and the listen
+/// backlog pushes back. A timed wait or a shed happens in [`admit`], in the
+/// connection's own task, so it never holds up the connections behind it.
+async fn accept_conn(
     listener: &TcpListener,
     listen_addr: &str,
     semaphore: &Arc<Semaphore>,
+    global: &GlobalConfig,
     kind: &'static str,
-) -> anyhow::Result<AcceptedConn> {
-    let (stream, addr) = match listener.accept().await {
-        Ok(pair) => pair,
-        Err(e) => {
-            error!(
+) -> anyhow::Result<(TcpStream, SocketAddr, Option<OwnedSemaphorePermit>)> {
+    let (stream, addr) = listener.accept().await.map_err(|e| {
+        error!(
+            target: "migux::master",
+            listen = %listen_addr,
+            listener = kind,
+            error = ?e,
+            "Failed to accept connection"
+        );
+        e
+    })?;
+
+    let waits_unbounded =
+        global.overload_action() == OverloadAction::Queue && global.max_queue_wait_secs().is_none();
+    if !waits_unbounded {
+        return Ok((stream, addr, None));
+    }
+    let permit = semaphore.clone().acquire_owned().await.map_err(|e| {
+        error!(
+            target: "migux::master",
+            listen = %listen_addr,
+            listener = kind,
+            error = ?e,
+            "Failed to acquire connection permit"
+        );
+        e
+    })?;
+    Ok((stream, addr, Some(permit)))
+}
+
+/// Take a `worker_connections` slot for an accepted connection, unless
+/// [`accept_conn`] already holds one, waiting up to `max_queue_wait_secs`
+/// from its own accept. `None` when the connection was shed under overload
+/// instead.
+async fn admit(
+    stream: TcpStream,
+    addr: SocketAddr,
+    permit: Option<OwnedSemaphorePermit>,
+    listen_addr: &str,
+    semaphore: &Arc<Semaphore>,
+    global: &GlobalConfig,
+    kind: &'static str,
+) -> Option<AcceptedConn> {
+    let acquired = match permit {
+        Some(permit) => Ok(Some(permit)),
+        None => acquire_permit(semaphore, global).await,
+    };
+    let permit = match acquired {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            warn!(
                 target: "migux::master",
                 listen = %listen_addr,
                 listener = kind,
-                error = ?e,
-                "Failed to accept connection"
+                client_addr = %addr,
+                overload_action = ?global.overload_action(),
+                "All worker_connections busy; shedding connection"
             );
-            return Err(e.into());
+            // A TLS client could only read the 503 after a handshake, which
+            // is the work being shed; it is just closed.
+            if kind == "http" {
+                reject_overloaded(stream).await;
+            }
+            return None;
         }
-    };
-
-    let permit = match semaphore.clone().acquire_owned().await {
-        Ok(p) => p,
         Err(e) => {
             error!(
                 target: "migux::master",
@@ -79,7 +164,7 @@ async fn accept_with_permit(
                 error = ?e,
                 "Failed to acquire connection permit"
             );
-            return Err(e.into());
+            return None;
         }
     };
 
@@ -93,13 +178,95 @@ async fn accept_with_permit(
         "Connection accepted"
     );
 
-    Ok(AcceptedConn {
+    Some(AcceptedConn {
         stream,
         addr,
         permit,
+        active: ActiveConnection::new(),
+    })
+}
+
+/// A connection slot per `overload_action`: `None` when every slot is busy
+/// and the connection must be shed (`reject`), or has waited longer than
+/// `max_queue_wait_secs` (`queue`).
+async fn acquire_permit(
+    semaphore: &Arc<Semaphore>,
+    global: &GlobalConfig,
+) -> anyhow::Result<Option<OwnedSemaphorePermit>> {
+    match (global.overload_action(), global.max_queue_wait_secs()) {
+        (OverloadAction::Reject, _) => match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Ok(Some(permit)),
+            Err(TryAcquireError::NoPermits) => Ok(None),
+            Err(e) => Err(e.into()),
+        },
+        (OverloadAction::Queue, None) => Ok(Some(semaphore.clone().acquire_owned().await?)),
+        (OverloadAction::Queue, Some(secs)) => {
+            match timeout(Duration::from_secs(secs), semaphore.clone().acquire_owned()).await {
+                Ok(permit) => Ok(Some(permit?)),
+                Err(_) => Ok(None),
+            }
+        }
+    }
+}
+
+/// Answer a shed connection with `503` + `Retry-After`, then read whatever
+/// the client already sent until it hangs up, so closing doesn't reset the
+/// connection before the 503 is read.
+async fn reject_overloaded(mut stream: TcpStream) {
+    let _ = timeout(OVERLOAD_LINGER, async {
+        send_503_retry_after(&mut stream, OVERLOAD_RETRY_AFTER_SECS).await?;
+        stream.shutdown().await?;
+        let mut buf = [0u8; 1024];
+        while stream.read(&mut buf).await? > 0 {}
+        anyhow::Ok(())
     })
+    .await;
+}
+
+/// Client connection preface that opens every HTTP/2 connection (RFC 9113 §3.4).
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Whether the client opened with the HTTP/2 preface (prior-knowledge h2c).
+///
+/// Only peeks, so the bytes are still there for whichever protocol handles
+/// the connection. HTTP/1 request lines diverge from the preface within the
+/// first few bytes; silence until `wait` falls back to HTTP/1 and its timeouts.
+async fn starts_with_h2_preface(stream: &TcpStream, wait: Duration) -> bool {
+    let deadline = Instant::now() + wait;
+    let mut buf = [0u8; H2_PREFACE.len()];
+    let mut seen = 0;
+    loop {
+        if !matches!(timeout_at(deadline, stream.readable()).await, Ok(Ok(()))) {
+            return false;
+        }
+        // Peek without waiting. A partial preface with nothing new behind it
+        // counts as `WouldBlock`, which clears the readiness so `readable`
+        // sleeps until more bytes arrive instead of returning at once.
+        let peeked = stream.try_io(Interest::READABLE, || {
+            let mut cx = Context::from_waker(Waker::noop());
+            match stream.poll_peek(&mut cx, &mut ReadBuf::new(&mut buf)) {
+                Poll::Ready(Ok(n)) if n > 0 && n == seen => Err(io::ErrorKind::WouldBlock.into()),
+                Poll::Ready(result) => result,
+                Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        });
+        let n = match peeked {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(_) => return false,
+        };
+        if n == 0 || buf[..n] != H2_PREFACE[..n] {
+            return false;
+        }
+        if n == H2_PREFACE.len() {
+            return true;
+        }
+        seen = n;
+    }
 }
 
+/// Accept loop for plain listeners; with `h2c`, connections opening with the
+/// HTTP/2 preface are served as HTTP/2, the rest as HTTP/1.
 #[instrument(
     skip(listener, semaphore, servers, proxy, cfg),
     fields(
@@ -114,6 +281,7 @@ pub(crate) async fn accept_loop(
     servers: Arc<Vec<ServerRuntime>>,
     proxy: Arc<Proxy>,
     cfg: Arc<MiguxConfig>,
+    h2c: bool,
 ) -> anyhow::Result<()> {
     info!(
         target: "migux::master",
@@ -122,19 +290,36 @@ pub(crate) async fn accept_loop(
     );
 
     loop {
-        let AcceptedConn {
-            stream,
-            addr,
-            permit,
-        } = accept_with_permit(&listener, &listen_addr, &semaphore, "http").await?;
+        let (stream, addr, permit) =
+            accept_conn(&listener, &listen_addr, &semaphore, &cfg.global, "http").await?;
 
         let servers_clone = servers.clone();
         let proxy_clone = proxy.clone();
         let cfg_clone = cfg.clone();
         let listen_for_span = listen_addr.clone();
+        let semaphore = semaphore.clone();
 
         tokio::spawn(async move {
+            let Some(AcceptedConn {
+                stream,
+                addr,
+                permit,
+                active,
+            }) = admit(
+                stream,
+                addr,
+                permit,
+                &listen_for_span,
+                &semaphore,
+                &cfg_clone.global,
+                "http",
+            )
+            .await
+            else {
+                return;
+            };
             let _permit = permit;
+            let _active = active;
             let span = tracing::info_span!(
                 "worker_connection",
                 client_addr = %addr,
@@ -147,6 +332,28 @@ pub(crate) async fn accept_loop(
                 "Worker spawned for incoming connection"
             );
 
+            let read_timeout = Duration::from_secs(cfg_clone.http.client_read_timeout_secs);
+            if h2c && starts_with_h2_preface(&stream, read_timeout).await {
+                if let Err(e) =
+                    serve_h2_connection(stream, addr, servers_clone, proxy_clone, cfg_clone, false)
+                        .await
+                {
+                    error!(
+                        target: "migux::worker",
+                        client_addr = %addr,
+                        error = ?e,
+                        "Error while handling h2c connection"
+                    );
+                } else {
+                    debug!(
+                        target: "migux::worker",
+                        client_addr = %addr,
+                        "h2c connection handled successfully"
+                    );
+                }
+                return;
+            }
+
             if let Err(e) = handle_connection(
                 Box::new(stream),
                 addr,
@@ -204,20 +411,37 @@ pub(crate) async fn accept_loop_tls(
     );
 
     loop {
-        let AcceptedConn {
-            stream,
-            addr,
-            permit,
-        } = accept_with_permit(&listener, &listen_addr, &semaphore, "tls").await?;
+        let (stream, addr, permit) =
+            accept_conn(&listener, &listen_addr, &semaphore, &cfg.global, "tls").await?;
 
         let servers_clone = servers.clone();
         let proxy_clone = proxy.clone();
         let cfg_clone = cfg.clone();
         let acceptor_clone = acceptor.clone();
         let listen_for_span = listen_addr.clone();
+        let semaphore = semaphore.clone();
 
         tokio::spawn(async move {
+            let Some(AcceptedConn {
+                stream,
+                addr,
+                permit,
+                active,
+            }) = admit(
+                stream,
+                addr,
+                permit,
+                &listen_for_span,
+                &semaphore,
+                &cfg_clone.global,
+                "tls",
+            )
+            .await
+            else {
+                return;
+            };
             let _permit = permit;
+            let _active = active;
             let span = tracing::info_span!(
                 "worker_tls_connection",
                 client_addr = %addr,
@@ -246,9 +470,15 @@ pub(crate) async fn accept_loop_tls(
             let alpn = tls_stream.get_ref().1.alpn_protocol().map(|v| v.to_vec());
 
             if matches!(alpn.as_deref(), Some(b"h2")) {
-                if let Err(e) =
-                    serve_h2_connection(tls_stream, addr, servers_clone, proxy_clone, cfg_clone)
-                        .await
+                if let Err(e) = serve_h2_connection(
+                    tls_stream,
+                    addr,
+                    servers_clone,
+                    proxy_clone,
+                    cfg_clone,
+                    true,
+                )
+                .await
                 {
                     error!(
                         target: "migux::worker",
@@ -289,3 +519,457 @@ pub(crate) async fn accept_loop_tls(
         });
     }
 }
+
+/// Accept loop for `[stream.*]` listeners: no HTTP, every connection is
+/// relayed to the stream's upstream.
+#[instrument(
+    skip(listener, semaphore, proxy, cfg),
+    fields(
+        listen = %listen_addr,
+        stream = %name,
+    )
+)]
+pub(crate) async fn accept_loop_stream(
+    listener: TcpListener,
+    listen_addr: String,
+    name: String,
+    semaphore: Arc<Semaphore>,
+    proxy: Arc<Proxy>,
+    cfg: Arc<MiguxConfig>,
+) -> anyhow::Result<()> {
+    info!(
+        target: "migux::master",
+        listen = %listen_addr,
+        stream = %name,
+        "accept_loop_stream started for stream listening socket"
+    );
+
+    loop {
+        let (stream, addr, permit) =
+            accept_conn(&listener, &listen_addr, &semaphore, &cfg.global, "stream").await?;
+
+        let proxy_clone = proxy.clone();
+        let cfg_clone = cfg.clone();
+        let name_clone = name.clone();
+        let listen_clone = listen_addr.clone();
+        let semaphore = semaphore.clone();
+
+        tokio::spawn(async move {
+            let Some(AcceptedConn {
+                stream,
+                addr,
+                permit,
+                active,
+            }) = admit(
+                stream,
+                addr,
+                permit,
+                &listen_clone,
+                &semaphore,
+                &cfg_clone.global,
+                "stream",
+            )
+            .await
+            else {
+                return;
+            };
+            let _permit = permit;
+            let _active = active;
+            let Some(stream_cfg) = cfg_clone.stream.get(&name_clone) else {
+                return;
+            };
+            if let Err(e) = proxy_clone
+                .serve_stream(stream, addr, &name_clone, stream_cfg, &cfg_clone)
+                .await
+            {
+                error!(
+                    target: "migux::worker",
+                    client_addr = %addr,
+                    stream = %name_clone,
+                    error = ?e,
+                    "Error while relaying stream connection"
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_conn, accept_loop, bind_error, starts_with_h2_preface};
+    use crate::build_servers_by_listen;
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Empty};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use migux_config::{
+        LocationConfig, LocationType, MiguxConfig, OverloadAction, UpstreamConfig, UpstreamServers,
+    };
+    use migux_proxy::Proxy;
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Semaphore;
+    use tokio::time::{Duration, Instant, timeout};
+    use tracing_test::traced_test;
+
+    #[test]
+    fn permission_denied_bind_suggests_capability_or_activation() {
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let message = format!("{:#}", bind_error("0.0.0.0:80", denied));
+        assert!(message.contains("0.0.0.0:80"), "{message}");
+        assert!(message.contains("CAP_NET_BIND_SERVICE"), "{message}");
+        assert!(message.contains("socket activation"), "{message}");
+
+        let in_use = std::io::Error::from(std::io::ErrorKind::AddrInUse);
+        let err = bind_error("0.0.0.0:80", in_use);
+        assert!(!format!("{err:#}").contains("CAP_NET_BIND_SERVICE"));
+    }
+
+    /// Serve `root` on an ephemeral port with h2c enabled.
+    async fn spawn_h2c_server(root: &std::path::Path) -> SocketAddr {
+        let mut cfg = MiguxConfig::default();
+        for server in cfg.servers.values_mut() {
+            server.root = root.to_string_lossy().into_owned();
+            server.http2 = true;
+            server.http2_cleartext = true;
+        }
+        spawn_server(cfg, Arc::new(Semaphore::new(8)), true).await
+    }
+
+    /// Run an accept loop for `cfg` on an ephemeral port.
+    async fn spawn_server(cfg: MiguxConfig, semaphore: Arc<Semaphore>, h2c: bool) -> SocketAddr {
+        let cfg = Arc::new(cfg);
+        let servers = build_servers_by_listen(&cfg)
+            .into_values()
+            .next()
+            .expect("one listener");
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(accept_loop(
+            listener,
+            addr.to_string(),
+            semaphore,
+            Arc::new(servers),
+            Arc::new(Proxy::new()),
+            cfg,
+            h2c,
+        ));
+        addr
+    }
+
+    /// Response to a plain GET on a server whose only connection slot is
+    /// taken, released again after `release_after`.
+    async fn get_while_saturated(cfg: MiguxConfig, release_after: Duration) -> String {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "served").expect("write");
+        let mut cfg = cfg;
+        for server in cfg.servers.values_mut() {
+            server.root = root.path().to_string_lossy().into_owned();
+        }
+        let semaphore = Arc::new(Semaphore::new(1));
+        let busy = semaphore.clone().acquire_owned().await.expect("permit");
+        let addr = spawn_server(cfg, semaphore, false).await;
+        tokio::spawn(async move {
+            tokio::time::sleep(release_after).await;
+            drop(busy);
+        });
+
+        let mut tcp = TcpStream::connect(addr).await.expect("connect");
+        tcp.write_all(b"GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n")
+            .await
+            .expect("write");
+        let mut out = String::new();
+        tcp.read_to_string(&mut out).await.expect("read");
+        out
+    }
+
+    #[tokio::test]
+    async fn overload_reject_sheds_with_503() {
+        let mut cfg = MiguxConfig::default();
+        cfg.global.overload_action = OverloadAction::Reject;
+        let out = get_while_saturated(cfg, Duration::from_secs(5)).await;
+        assert!(
+            out.starts_with("HTTP/1.1 503 Service Unavailable"),
+            "got: {out}"
+        );
+        assert!(out.contains("\r\nRetry-After: 1\r\n"), "got: {out}");
+        assert!(out.contains("\r\nConnection: close\r\n"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn overload_queue_waits_for_a_slot_up_to_the_limit() {
+        let out = get_while_saturated(MiguxConfig::default(), Duration::from_millis(200)).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("served"), "got: {out}");
+
+        let mut cfg = MiguxConfig::default();
+        cfg.global.max_queue_wait_secs = 1;
+        let out = get_while_saturated(cfg, Duration::from_secs(5)).await;
+        assert!(
+            out.starts_with("HTTP/1.1 503 Service Unavailable"),
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unbounded_queue_stops_accepting_while_saturated() {
+        let global = MiguxConfig::default().global;
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let semaphore = Arc::new(Semaphore::new(1));
+        let busy = semaphore.clone().acquire_owned().await.expect("permit");
+        let _client = TcpStream::connect(addr).await.expect("connect");
+
+        let accepting = accept_conn(&listener, "test", &semaphore, &global, "http");
+        tokio::pin!(accepting);
+        assert!(
+            timeout(Duration::from_millis(200), &mut accepting)
+                .await
+                .is_err(),
+            "accepted without a free slot"
+        );
+        drop(busy);
+        let (_, _, permit) = accepting.await.expect("accept");
+        assert!(permit.is_some());
+        drop(permit);
+
+        // With a wait limit the slot is left to the connection's own task.
+        let mut global = MiguxConfig::default().global;
+        global.max_queue_wait_secs = 1;
+        let _held = semaphore.clone().acquire_owned().await.expect("permit");
+        let _client = TcpStream::connect(addr).await.expect("connect");
+        let (_, _, permit) = timeout(
+            Duration::from_secs(2),
+            accept_conn(&listener, "test", &semaphore, &global, "http"),
+        )
+        .await
+        .expect("accept loop held up")
+        .expect("accept");
+        assert!(permit.is_none());
+    }
+
+    #[tokio::test]
+    async fn queue_wait_is_bounded_per_connection() {
+        let mut cfg = MiguxConfig::default();
+        cfg.global.max_queue_wait_secs = 1;
+        let semaphore = Arc::new(Semaphore::new(1));
+        let _busy = semaphore.clone().acquire_owned().await.expect("permit");
+        let addr = spawn_server(cfg, semaphore, false).await;
+
+        // Three connections queue at once; none waits for the others' turn.
+        let started = std::time::Instant::now();
+        let clients = (0..3).map(|_| {
+            tokio::spawn(async move {
+                let mut tcp = TcpStream::connect(addr).await.expect("connect");
+                tcp.write_all(b"GET / HTTP/1.1\r\nHost: example\r\n\r\n")
+                    .await
+                    .expect("write");
+                let mut out = String::new();
+                tcp.read_to_string(&mut out).await.expect("read");
+                out
+            })
+        });
+        for client in clients.collect::<Vec<_>>() {
+            let out = client.await.expect("join");
+            assert!(out.starts_with("HTTP/1.1 503"), "got: {out}");
+        }
+        assert!(
+            started.elapsed() < Duration::from_millis(1_900),
+            "took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn h2c_prior_knowledge_gets_a_response() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hello h2c").expect("write");
+        let addr = spawn_h2c_server(root.path()).await;
+
+        let tcp = TcpStream::connect(addr).await.expect("connect");
+        let (mut sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tcp))
+                .await
+                .expect("h2 handshake");
+        tokio::spawn(conn);
+
+        let req = hyper::Request::get(format!("http://{addr}/"))
+            .body(Empty::<Bytes>::new())
+            .expect("request");
+        let resp = sender.send_request(req).await.expect("response");
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert_eq!(resp.version(), hyper::Version::HTTP_2);
+        let body = resp.into_body().collect().await.expect("body").to_bytes();
+        assert_eq!(&body[..], b"hello h2c");
+    }
+
+    /// Body of a GET for each of `paths`, sent in turn on one h2c connection.
+    async fn h2c_get_all(addr: SocketAddr, paths: &[&str]) -> Vec<Bytes> {
+        let tcp = TcpStream::connect(addr).await.expect("connect");
+        let (mut sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tcp))
+                .await
+                .expect("h2 handshake");
+        tokio::spawn(conn);
+        let mut bodies = Vec::new();
+        for path in paths {
+            let req = hyper::Request::get(format!("http://{addr}{path}"))
+                .body(Empty::<Bytes>::new())
+                .expect("request");
+            let resp = sender.send_request(req).await.expect("response");
+            assert_eq!(resp.status(), hyper::StatusCode::OK, "{path}");
+            bodies.push(resp.into_body().collect().await.expect("body").to_bytes());
+        }
+        bodies
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn h2_streams_share_the_static_cache() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("h2-cache.html"), "cached").expect("write");
+        let mut cfg = MiguxConfig::default();
+        cfg.http.cache_dir = Some(cache.path().to_string_lossy().into_owned());
+        cfg.http.cache_default_ttl_secs = Some(60);
+        cfg.http.cache_max_object_bytes = Some(1024);
+        for server in cfg.servers.values_mut() {
+            server.root = root.path().to_string_lossy().into_owned();
+            server.http2 = true;
+            server.http2_cleartext = true;
+        }
+        let addr = spawn_server(cfg, Arc::new(Semaphore::new(8)), true).await;
+
+        let bodies = h2c_get_all(addr, &["/h2-cache.html", "/h2-cache.html"]).await;
+        assert!(bodies.iter().all(|body| &body[..] == b"cached"));
+
+        logs_assert(|lines| {
+            let decisions: Vec<_> = lines
+                .iter()
+                .filter(|line| {
+                    line.contains("path=/h2-cache.html ") && line.contains("Request complete")
+                })
+                .map(|line| line.contains("decision=\"cache-hit\""))
+                .collect();
+            match decisions.as_slice() {
+                [false, true] => Ok(()),
+                other => Err(format!("expected a miss then a hit, got {other:?}")),
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn h2_streams_reuse_pooled_upstream_connections() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let upstream_addr = upstream.local_addr().expect("addr").to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = upstream.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = sock.read(&mut buf).await {
+                        if n == 0
+                            || sock
+                                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                                .await
+                                .is_err()
+                        {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert(
+            "app".into(),
+            UpstreamConfig {
+                server: UpstreamServers::One(upstream_addr),
+                ..Default::default()
+            },
+        );
+        cfg.location.insert(
+            "api".into(),
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+        for server in cfg.servers.values_mut() {
+            server.http2 = true;
+            server.http2_cleartext = true;
+        }
+        let addr = spawn_server(cfg, Arc::new(Semaphore::new(8)), true).await;
+
+        let bodies = h2c_get_all(addr, &["/api/a", "/api/b", "/api/c"]).await;
+        assert!(bodies.iter().all(|body| &body[..] == b"ok"));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn h2c_listener_still_serves_http1() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hello h1").expect("write");
+        let addr = spawn_h2c_server(root.path()).await;
+
+        let mut tcp = TcpStream::connect(addr).await.expect("connect");
+        tcp.write_all(b"GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n")
+            .await
+            .expect("write");
+        let mut out = String::new();
+        tcp.read_to_string(&mut out).await.expect("read");
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("hello h1"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn preface_detection_waits_for_split_prefaces() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let client = tokio::spawn(async move {
+            let mut tcp = TcpStream::connect(addr).await.expect("connect");
+            tcp.write_all(b"PRI * HTTP/2.0\r\n").await.expect("write");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tcp.write_all(b"\r\nSM\r\n\r\n").await.expect("write");
+            tcp
+        });
+        let (server, _) = listener.accept().await.expect("accept");
+        assert!(starts_with_h2_preface(&server, Duration::from_secs(2)).await);
+        drop(client.await.expect("client"));
+
+        let client = tokio::spawn(async move {
+            let mut tcp = TcpStream::connect(addr).await.expect("connect");
+            tcp.write_all(b"PRIVATE / HTTP/1.1\r\n")
+                .await
+                .expect("write");
+            tcp
+        });
+        let (server, _) = listener.accept().await.expect("accept");
+        assert!(!starts_with_h2_preface(&server, Duration::from_secs(2)).await);
+        drop(client.await.expect("client"));
+
+        // A preface that stops halfway falls back to HTTP/1 once `wait` runs out.
+        let client = tokio::spawn(async move {
+            let mut tcp = TcpStream::connect(addr).await.expect("connect");
+            tcp.write_all(b"PRI * HTTP/2.0\r\n").await.expect("write");
+            tcp
+        });
+        let (server, _) = listener.accept().await.expect("accept");
+        let started = Instant::now();
+        assert!(!starts_with_h2_preface(&server, Duration::from_millis(200)).await);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        drop(client.await.expect("client"));
+    }
+}
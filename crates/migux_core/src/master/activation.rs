@@ -0,0 +1,124 @@
+//! Listeners handed over by systemd socket activation (`LISTEN_PID`,
+//! `LISTEN_FDS`, descriptors from 3 on), so a `.socket` unit can own
+//! ports like 80/443 while migux runs unprivileged. A listen address whose
+//! inherited socket is bound to exactly that address uses it instead of
+//! binding.
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tracing::{info, warn};
+
+/// First descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: i32 = 3;
+
+/// The listening sockets passed to this process, if any. Only the first
+/// call adopts them; later calls return nothing, so no descriptor ends up
+/// owned twice.
+pub fn systemd_listeners() -> Vec<TcpListener> {
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    listeners_from(pid.as_deref(), fds.as_deref(), LISTEN_FDS_START)
+}
+
+/// Adopt `LISTEN_FDS` descriptors starting at `first_fd` when `LISTEN_PID`
+/// names this process. Descriptors that are not TCP listeners are closed.
+fn listeners_from(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    first_fd: i32,
+) -> Vec<TcpListener> {
+    let Some(count) = listen_fds.and_then(|n| n.trim().parse::<i32>().ok()) else {
+        return Vec::new();
+    };
+    if listen_pid.and_then(|pid| pid.trim().parse::<u32>().ok()) != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let mut listeners = Vec::new();
+    for fd in first_fd..first_fd.saturating_add(count.max(0)) {
+        let Some(listener) = adopt_fd(fd) else {
+            continue;
+        };
+        match listener.local_addr() {
+            Ok(addr) => {
+                info!(
+                    target: "migux::master",
+                    fd,
+                    %addr,
+                    "Inherited listener from socket activation"
+                );
+                listeners.push(listener);
+            }
+            Err(e) => {
+                warn!(
+                    target: "migux::master",
+                    fd,
+                    error = %e,
+                    "Passed descriptor is not a TCP listener; closing it"
+                );
+            }
+        }
+    }
+    listeners
+}
+
+#[cfg(unix)]
+fn adopt_fd(fd: i32) -> Option<TcpListener> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: LISTEN_PID names this process, so systemd passed `fd` to it
+    // and nothing else in the process owns it (`systemd_listeners` adopts
+    // the descriptors at most once).
+    Some(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn adopt_fd(_fd: i32) -> Option<TcpListener> {
+    None
+}
+
+/// Remove and return the inherited listener bound to `listen_addr`.
+pub(super) fn take_listener(
+    inherited: &mut Vec<TcpListener>,
+    listen_addr: &str,
+) -> Option<TcpListener> {
+    let wanted: SocketAddr = listen_addr.parse().ok()?;
+    let index = inherited
+        .iter()
+        .position(|listener| listener.local_addr().ok() == Some(wanted))?;
+    Some(inherited.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{listeners_from, take_listener};
+    use std::{net::TcpListener, os::fd::IntoRawFd};
+
+    #[test]
+    fn passed_listener_fd_is_adopted_and_matched_by_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let fd = listener.into_raw_fd();
+        let pid = std::process::id().to_string();
+
+        let mut inherited = listeners_from(Some(&pid), Some("1"), fd);
+        assert_eq!(inherited.len(), 1);
+        assert!(take_listener(&mut inherited, "127.0.0.1:1").is_none());
+        let taken = take_listener(&mut inherited, &addr.to_string()).expect("matching listener");
+        assert_eq!(taken.local_addr().expect("addr"), addr);
+        assert!(inherited.is_empty());
+    }
+
+    #[test]
+    fn fds_for_another_process_are_left_alone() {
+        let other = (std::process::id() + 1).to_string();
+        assert!(listeners_from(Some(&other), Some("1"), 3).is_empty());
+        assert!(listeners_from(None, Some("1"), 3).is_empty());
+        assert!(listeners_from(Some(&std::process::id().to_string()), None, 3).is_empty());
+    }
+}
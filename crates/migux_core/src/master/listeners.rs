@@ -1,19 +1,79 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
+use migux_http::fastopen;
 use migux_proxy::Proxy;
+use tokio::net::TcpListener;
 use tokio::sync::Semaphore;
 use tracing::{error, info, warn};
 
 use super::Master;
-use super::accept::{accept_loop, accept_loop_tls, bind_listener};
+use super::accept::{accept_loop, accept_loop_stream, accept_loop_tls, bind_listener};
+use super::activation::take_listener;
 use super::tls::{load_tls_acceptor, tls_listener_ready};
 
 impl Master {
+    /// The inherited listener for `listen_addr`, else a fresh bind; with
+    /// Fast Open enabled when `global.tcp_fastopen` is set.
+    async fn listener_for(
+        &self,
+        listen_addr: &str,
+        kind: &'static str,
+    ) -> anyhow::Result<TcpListener> {
+        let listener = self.inherited_or_bound(listen_addr, kind).await?;
+        if self.cfg.global.tcp_fastopen()
+            && let Err(e) = fastopen::enable_on_listener(&listener)
+        {
+            warn!(
+                target: "migux::master",
+                listen = %listen_addr,
+                error = %e,
+                "TCP Fast Open not enabled on listener"
+            );
+        }
+        Ok(listener)
+    }
+
+    async fn inherited_or_bound(
+        &self,
+        listen_addr: &str,
+        kind: &'static str,
+    ) -> anyhow::Result<TcpListener> {
+        let inherited = {
+            let mut inherited = self.inherited.lock().unwrap_or_else(|e| e.into_inner());
+            take_listener(&mut inherited, listen_addr)
+        };
+        let Some(listener) = inherited else {
+            return bind_listener(listen_addr, kind).await;
+        };
+        info!(
+            target: "migux::master",
+            listen = %listen_addr,
+            listener = kind,
+            "Using inherited listener"
+        );
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener::from_std(listener)?)
+    }
+
+    /// Close inherited listeners no listen address asked for.
+    pub(super) fn drop_unused_inherited(&self) {
+        let mut inherited = self.inherited.lock().unwrap_or_else(|e| e.into_inner());
+        for listener in inherited.drain(..) {
+            warn!(
+                target: "migux::master",
+                addr = ?listener.local_addr().ok(),
+                "Inherited listener matches no listen address; closing it"
+            );
+        }
+    }
+
+    /// Bind and spawn every HTTP listener; returns the bound addresses.
     pub(super) async fn spawn_http_listeners(
         &self,
         semaphore: Arc<Semaphore>,
         proxy: Arc<Proxy>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Vec<SocketAddr>> {
+        let mut started = Vec::new();
         for (listen_addr, servers) in self.servers_by_listen.iter() {
             info!(
                 target: "migux::master",
@@ -22,16 +82,20 @@ impl Master {
                 "Preparing HTTP listener"
             );
 
-            let listener = bind_listener(listen_addr, "http").await?;
+            let listener = self.listener_for(listen_addr, "http").await?;
+            let bound = listener.local_addr()?;
+            let h2c = servers.iter().any(|s| s.config.h2c());
             let addr = listen_addr.clone();
             let servers = Arc::new(servers.clone());
             let cfg = self.cfg.clone();
             let proxy = proxy.clone();
             let semaphore = semaphore.clone();
 
-            tokio::spawn(async move {
+            let task = tokio::spawn(async move {
                 let listen_for_log = addr.clone();
-                if let Err(e) = accept_loop(listener, addr, semaphore, servers, proxy, cfg).await {
+                if let Err(e) =
+                    accept_loop(listener, addr, semaphore, servers, proxy, cfg, h2c).await
+                {
                     error!(
                         target: "migux::master",
                         listen = %listen_for_log,
@@ -46,22 +110,27 @@ impl Master {
                     );
                 }
             });
+            self.track_accept_task(task);
+            started.push(bound);
         }
 
-        Ok(())
+        Ok(started)
     }
 
+    /// Bind and spawn every ready TLS listener; returns the bound addresses.
     pub(super) async fn spawn_tls_listeners(
         &self,
         semaphore: Arc<Semaphore>,
         proxy: Arc<Proxy>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Vec<SocketAddr>> {
+        let mut started = Vec::new();
         for (listen_addr, tls_cfg) in self.tls_servers_by_listen.iter() {
             if !tls_listener_ready(listen_addr, tls_cfg) {
                 continue;
             }
 
-            let tls_acceptor = match load_tls_acceptor(&tls_cfg.tls) {
+            let http2 = tls_cfg.tls.http2 || tls_cfg.servers.iter().any(|s| s.config.http2());
+            let tls_acceptor = match load_tls_acceptor(&tls_cfg.tls, http2) {
                 Ok(a) => a,
                 Err(e) => {
                     error!(
@@ -81,14 +150,15 @@ impl Master {
                 "Preparing TLS listener"
             );
 
-            let listener = bind_listener(listen_addr, "tls").await?;
+            let listener = self.listener_for(listen_addr, "tls").await?;
+            let bound = listener.local_addr()?;
             let addr = listen_addr.clone();
             let servers = Arc::new(tls_cfg.servers.clone());
             let cfg = self.cfg.clone();
             let proxy = proxy.clone();
             let semaphore = semaphore.clone();
 
-            tokio::spawn(async move {
+            let task = tokio::spawn(async move {
                 let listen_for_log = addr.clone();
                 if let Err(e) =
                     accept_loop_tls(listener, addr, tls_acceptor, semaphore, servers, proxy, cfg)
@@ -108,8 +178,60 @@ impl Master {
                     );
                 }
             });
+            self.track_accept_task(task);
+            started.push(bound);
+        }
+
+        Ok(started)
+    }
+
+    /// Bind and spawn every `[stream.*]` listener; returns the bound addresses.
+    pub(super) async fn spawn_stream_listeners(
+        &self,
+        semaphore: Arc<Semaphore>,
+        proxy: Arc<Proxy>,
+    ) -> anyhow::Result<Vec<SocketAddr>> {
+        let mut started = Vec::new();
+        for (name, stream) in self.cfg.stream.iter() {
+            info!(
+                target: "migux::master",
+                listen = %stream.listen,
+                stream = %name,
+                upstream = %stream.upstream,
+                "Preparing stream listener"
+            );
+
+            let listener = self.listener_for(&stream.listen, "stream").await?;
+            let bound = listener.local_addr()?;
+            let addr = stream.listen.clone();
+            let name = name.clone();
+            let cfg = self.cfg.clone();
+            let proxy = proxy.clone();
+            let semaphore = semaphore.clone();
+
+            let task = tokio::spawn(async move {
+                let listen_for_log = addr.clone();
+                if let Err(e) =
+                    accept_loop_stream(listener, addr, name, semaphore, proxy, cfg).await
+                {
+                    error!(
+                        target: "migux::master",
+                        listen = %listen_for_log,
+                        error = ?e,
+                        "accept_loop_stream exited with an error"
+                    );
+                } else {
+                    warn!(
+                        target: "migux::master",
+                        listen = %listen_for_log,
+                        "accept_loop_stream exited cleanly (possible shutdown)"
+                    );
+                }
+            });
+            self.track_accept_task(task);
+            started.push(bound);
         }
 
-        Ok(())
+        Ok(started)
     }
 }
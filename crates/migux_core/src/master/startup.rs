@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
+use migux_config::LocationType;
 use migux_proxy::Proxy;
 use tokio::sync::Semaphore;
-use tracing::info;
+use tracing::{info, warn};
 
 use super::Master;
 
@@ -29,7 +30,45 @@ impl Master {
         semaphore
     }
 
+    /// Index every archive location's archive now rather than on its first
+    /// request; a broken archive is logged and answers 500 until fixed.
+    pub(super) fn preload_archives(&self) {
+        for (name, location) in &self.cfg.location {
+            if !matches!(location.r#type, LocationType::Archive) {
+                continue;
+            }
+            let Some(path) = location.archive() else {
+                continue;
+            };
+            match migux_static::preload_archive(path) {
+                Ok(entries) => info!(
+                    target: "migux::master",
+                    location = %name,
+                    archive = %path,
+                    entries,
+                    "Archive indexed"
+                ),
+                Err(e) => warn!(
+                    target: "migux::master",
+                    location = %name,
+                    archive = %path,
+                    error = %e,
+                    "Cannot index archive; its location will answer 500"
+                ),
+            }
+        }
+    }
+
     pub(super) fn start_proxy(&self) -> Arc<Proxy> {
+        for (name, upstream) in &self.cfg.upstream {
+            if upstream.tls() && !upstream.tls_verify() {
+                warn!(
+                    target: "migux::master",
+                    upstream = %name,
+                    "TLS certificate verification is DISABLED for this upstream (tls_verify = false); do not use in production"
+                );
+            }
+        }
         let proxy = Arc::new(Proxy::new());
         proxy.start_health_checks(self.cfg.clone());
         proxy
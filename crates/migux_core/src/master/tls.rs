@@ -1,6 +1,6 @@
 use std::{fs::File, io::BufReader, sync::Arc};
 
-use migux_config::TlsConfig;
+use migux_config::{ALPN_PROTOCOLS, TlsConfig};
 use tokio_rustls::{TlsAcceptor, rustls};
 use tracing::warn;
 
@@ -28,8 +28,9 @@ pub(crate) fn tls_listener_ready(listen_addr: &str, tls_cfg: &TlsListenConfig) -
     true
 }
 
-/// Build a TLS acceptor from configured certificate/key paths.
-pub(crate) fn load_tls_acceptor(cfg: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+/// Build a TLS acceptor from configured certificate/key paths. `http2`
+/// allows offering `h2` via ALPN (`tls.http2` or a server-level `http2`).
+pub(crate) fn load_tls_acceptor(cfg: &TlsConfig, http2: bool) -> anyhow::Result<TlsAcceptor> {
     let certs = load_certs(&cfg.cert_path)?;
     let key = load_private_key(&cfg.key_path)?;
 
@@ -41,15 +42,23 @@ pub(crate) fn load_tls_acceptor(cfg: &TlsConfig) -> anyhow::Result<TlsAcceptor>
         .with_no_client_auth()
         .with_single_cert(certs, key)
         .map_err(|e| anyhow::anyhow!("Invalid TLS config: {e}"))?;
-    if cfg.http2 {
-        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-    } else {
-        config.alpn_protocols = vec![b"http/1.1".to_vec()];
-    }
+    config.alpn_protocols = alpn_protocols(cfg, http2);
 
     Ok(TlsAcceptor::from(Arc::new(config)))
 }
 
+/// ALPN protocols to offer, in preference order (rustls picks the first
+/// one the client also offers): `tls.alpn` when set, else `h2` then
+/// `http/1.1`; `h2` only with `http2`.
+fn alpn_protocols(cfg: &TlsConfig, http2: bool) -> Vec<Vec<u8>> {
+    cfg.alpn()
+        .unwrap_or_else(|| ALPN_PROTOCOLS.map(String::from).to_vec())
+        .into_iter()
+        .filter(|protocol| http2 || protocol != "h2")
+        .map(String::into_bytes)
+        .collect()
+}
+
 /// Load PEM-encoded certificates from disk.
 fn load_certs(path: &str) -> anyhow::Result<Vec<rustls::Certificate>> {
     let file = File::open(path)?;
@@ -79,3 +88,87 @@ fn load_private_key(path: &str) -> anyhow::Result<rustls::PrivateKey> {
 
     anyhow::bail!("No private keys found in {}", path);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use migux_config::{StringList, TlsConfig};
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{self, Certificate, RootCertStore, ServerName},
+    };
+
+    use super::load_tls_acceptor;
+
+    const TEST_CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/self-signed-cert.pem");
+    const TEST_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/self-signed-key.pem");
+
+    fn tls_config(alpn: Option<&[&str]>, http2: bool) -> TlsConfig {
+        TlsConfig {
+            listen: "127.0.0.1:0".into(),
+            cert_path: TEST_CERT.into(),
+            key_path: TEST_KEY.into(),
+            redirect_http: false,
+            http2,
+            alpn: alpn.map(|list| StringList::Many(list.iter().map(|p| p.to_string()).collect())),
+            hsts_max_age_secs: None,
+            hsts_include_subdomains: None,
+        }
+    }
+
+    /// Handshake with a client offering `offered`; the protocol the server
+    /// picked, or `None` when the handshake failed.
+    async fn negotiate(cfg: &TlsConfig, http2: bool, offered: &[&str]) -> Option<Option<Vec<u8>>> {
+        let acceptor = load_tls_acceptor(cfg, http2).expect("acceptor");
+        let mut roots = RootCertStore::empty();
+        let pem = std::fs::read(TEST_CERT).expect("fixture");
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()).expect("cert") {
+            roots.add(&Certificate(cert)).expect("root");
+        }
+        let mut client = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client.alpn_protocols = offered.iter().map(|p| p.as_bytes().to_vec()).collect();
+        let connector = TlsConnector::from(Arc::new(client));
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let name = ServerName::try_from("localhost").expect("name");
+        let (server, _client) = tokio::join!(
+            acceptor.accept(server_io),
+            connector.connect(name, client_io)
+        );
+        let server = server.ok()?;
+        Some(server.get_ref().1.alpn_protocol().map(<[u8]>::to_vec))
+    }
+
+    #[tokio::test]
+    async fn alpn_follows_config_and_http2() {
+        // Default list: h2 only when HTTP/2 is on.
+        let cfg = tls_config(None, true);
+        assert_eq!(
+            negotiate(&cfg, true, &["h2", "http/1.1"]).await,
+            Some(Some(b"h2".to_vec()))
+        );
+        let cfg = tls_config(None, false);
+        assert_eq!(
+            negotiate(&cfg, false, &["h2", "http/1.1"]).await,
+            Some(Some(b"http/1.1".to_vec()))
+        );
+
+        // Server preference wins.
+        let cfg = tls_config(Some(&["http/1.1", "h2"]), true);
+        assert_eq!(
+            negotiate(&cfg, true, &["h2", "http/1.1"]).await,
+            Some(Some(b"http/1.1".to_vec()))
+        );
+
+        // h2-only client against an h2-disabled server: rejected when
+        // http/1.1 is offered, plain HTTP/1.1 when nothing is.
+        let cfg = tls_config(Some(&["h2", "http/1.1"]), false);
+        assert_eq!(negotiate(&cfg, false, &["h2"]).await, None);
+        let cfg = tls_config(Some(&[]), false);
+        assert_eq!(negotiate(&cfg, false, &["h2"]).await, Some(None));
+    }
+}
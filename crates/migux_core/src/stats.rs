@@ -0,0 +1,63 @@
+//! Process-wide counters reported by the admin status page.
+
+use std::{
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use migux_http::counter::ShardedCounter;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static REQUESTS: ShardedCounter = ShardedCounter::new();
+static BYTES_SENT: ShardedCounter = ShardedCounter::new();
+
+/// Record the process start time; later calls keep the first value.
+pub fn mark_started() {
+    let _ = STARTED.set(Instant::now());
+}
+
+/// Time since `mark_started` (or since the first call, if it never ran).
+pub fn uptime() -> Duration {
+    STARTED.get_or_init(Instant::now).elapsed()
+}
+
+/// Client connections currently being served.
+pub fn active_connections() -> usize {
+    ACTIVE_CONNECTIONS.load(Ordering::Relaxed)
+}
+
+/// Requests answered since start.
+pub fn requests_total() -> u64 {
+    REQUESTS.sum()
+}
+
+/// Response bytes written to clients since start.
+pub fn bytes_sent_total() -> u64 {
+    BYTES_SENT.sum()
+}
+
+/// Count one finished request and the bytes sent for it.
+pub(crate) fn record_request(bytes: u64) {
+    REQUESTS.incr();
+    BYTES_SENT.add(bytes);
+}
+
+/// Counts one client connection as active until dropped.
+pub(crate) struct ActiveConnection(());
+
+impl ActiveConnection {
+    pub(crate) fn new() -> Self {
+        ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
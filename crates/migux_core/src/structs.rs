@@ -1,5 +1,10 @@
+use std::sync::Arc;
+
 use dashmap::DashMap;
 use migux_config::{LocationConfig, ServerConfig};
+use tracing::warn;
+
+use crate::worker::{access_log::AccessLog, routing::LocationRouter};
 
 #[derive(Debug, Clone)]
 pub struct CacheStore {
@@ -19,14 +24,40 @@ pub struct ServerRuntime {
     pub name: String,
     pub config: ServerConfig,
     pub locations: Vec<LocationConfig>,
+    /// Prefix trie over `locations`.
+    pub router: LocationRouter,
+    /// Access log shared by every server of one config; off unless set
+    /// with [`ServerRuntime::with_access_log`].
+    pub(crate) access_log: Arc<AccessLog>,
 }
 
 impl ServerRuntime {
+    /// Build the runtime for server `name`. Locations that belong to another
+    /// server are dropped so routing can never apply them to this one.
     pub fn new(name: String, config: ServerConfig, locations: Vec<LocationConfig>) -> Self {
+        let (locations, foreign): (Vec<_>, Vec<_>) =
+            locations.into_iter().partition(|loc| loc.server == name);
+        for loc in &foreign {
+            warn!(
+                target: "migux::router",
+                server = %name,
+                location_server = %loc.server,
+                location_path = %loc.path,
+                "Ignoring location that belongs to another server"
+            );
+        }
+        let router = LocationRouter::new(&locations);
         Self {
             name,
             config,
             locations,
+            router,
+            access_log: Arc::default(),
         }
     }
+
+    pub(crate) fn with_access_log(mut self, access_log: Arc<AccessLog>) -> Self {
+        self.access_log = access_log;
+        self
+    }
 }
@@ -0,0 +1,499 @@
+//! Access logging with sampling and skip filters.
+//!
+//! One [`AccessLog`] is built per config (see [`crate::build_servers_by_listen`])
+//! and shared by its servers. File lines are handed to a writer thread over a
+//! bounded channel, so request tasks never block on disk; lines that find
+//! the channel full are dropped and counted.
+//!
+//! Responses are observed through [`ResponseRecorder`], which wraps the client
+//! stream and captures the status code and bytes written for each request,
+//! plus what the keep-alive checks in [`super::connection`] need: bytes read
+//! and the response's framing. It can also add one header line to the
+//! response (`debug_connection_header`).
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+    },
+    task::{Context, Poll, ready},
+    time::{Duration, SystemTime},
+};
+
+use httpdate::fmt_http_date;
+use migux_config::HttpConfig;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{error, info, warn};
+
+use super::connection::ResponseFraming;
+use super::request::ParsedRequest;
+use super::{ClientStream, admin_path};
+
+/// Lines queued for the writer thread before new ones are dropped.
+const WRITE_QUEUE_LINES: usize = 8192;
+
+enum Sink {
+    Off,
+    /// Lines for the writer thread; it exits once the log is dropped.
+    File(SyncSender<String>),
+    Tracing,
+}
+
+/// Append queued lines to `file` until every sender is gone, flushing
+/// whenever the queue runs empty.
+fn spawn_writer(path: &str, file: File) -> std::io::Result<SyncSender<String>> {
+    let (tx, rx) = mpsc::sync_channel::<String>(WRITE_QUEUE_LINES);
+    let path = path.to_string();
+    std::thread::Builder::new()
+        .name("migux-access-log".into())
+        .spawn(move || {
+            let mut out = BufWriter::new(file);
+            while let Ok(line) = rx.recv() {
+                let mut result = writeln!(out, "{line}");
+                while let Ok(line) = rx.try_recv() {
+                    result = result.and_then(|()| writeln!(out, "{line}"));
+                }
+                if let Err(e) = result.and_then(|()| out.flush()) {
+                    warn!(
+                        target: "migux::access",
+                        path = %path,
+                        error = %e,
+                        "Failed to write access log"
+                    );
+                }
+            }
+        })?;
+    Ok(tx)
+}
+
+/// Status filter: exact code or a class (`2xx`).
+#[derive(Debug, PartialEq, Eq)]
+enum StatusFilter {
+    Exact(u16),
+    Class(u16),
+}
+
+impl StatusFilter {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.as_bytes() {
+            [d @ b'1'..=b'5', b'x' | b'X', b'x' | b'X'] => Some(Self::Class(u16::from(d - b'0'))),
+            _ => raw.parse().ok().map(Self::Exact),
+        }
+    }
+
+    fn matches(&self, status: u16) -> bool {
+        match self {
+            Self::Exact(code) => *code == status,
+            Self::Class(class) => status / 100 == *class,
+        }
+    }
+}
+
+pub(crate) struct AccessLog {
+    sink: Sink,
+    sample_rate: u64,
+    counter: AtomicU64,
+    /// Lines dropped because the writer thread fell behind.
+    dropped: AtomicU64,
+    skip_paths: Vec<String>,
+    skip_statuses: Vec<StatusFilter>,
+    /// `health_check_path`, unless `health_check_log` is on.
+    health_check_path: Option<String>,
+}
+
+impl AccessLog {
+    pub(crate) fn from_config(http: &HttpConfig) -> Self {
+        let sink = match http.access_log() {
+            "off" => Sink::Off,
+            "" | "-" => Sink::Tracing,
+            path => match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|file| spawn_writer(path, file))
+            {
+                Ok(tx) => Sink::File(tx),
+                Err(e) => {
+                    // Config validation warns about a missing directory too;
+                    // this covers permissions and anything else at startup.
+                    error!(
+                        target: "migux::access",
+                        path,
+                        error = %e,
+                        "Cannot open access log; writing access lines to the tracing log instead \
+                         (set http.access_log = \"-\" for that, or \"off\")"
+                    );
+                    Sink::Tracing
+                }
+            },
+        };
+
+        Self {
+            sink,
+            sample_rate: http.access_log_sample_rate(),
+            counter: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            skip_paths: http.access_log_skip_paths(),
+            skip_statuses: http
+                .access_log_skip_statuses()
+                .iter()
+                .filter_map(|s| StatusFilter::parse(s))
+                .collect(),
+            health_check_path: http
+                .health_check_path()
+                .filter(|_| !http.health_check_log())
+                .map(|path| admin_path(path).to_string()),
+        }
+    }
+
+    /// Filters first (cheap, no counter bump), then 1-in-N sampling.
+    fn should_log(&self, path: &str, status: u16) -> bool {
+        if matches!(self.sink, Sink::Off) || self.is_skipped(path, status) {
+            return false;
+        }
+        self.sample_rate <= 1
+            || self
+                .counter
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.sample_rate)
+    }
+
+    /// Skip when the request matches both filters; an empty filter matches all.
+    fn is_skipped(&self, path: &str, status: u16) -> bool {
+        if self
+            .health_check_path
+            .as_deref()
+            .is_some_and(|health| admin_path(path) == health)
+        {
+            return true;
+        }
+        if self.skip_paths.is_empty() && self.skip_statuses.is_empty() {
+            return false;
+        }
+        let path = path.split('?').next().unwrap_or(path);
+        let path_match = self.skip_paths.is_empty()
+            || self.skip_paths.iter().any(|p| path.starts_with(p.as_str()));
+        let status_match =
+            self.skip_statuses.is_empty() || self.skip_statuses.iter().any(|f| f.matches(status));
+        path_match && status_match
+    }
+
+    /// Write one access log line for a finished request.
+    pub(crate) fn record(
+        &self,
+        client_addr: &SocketAddr,
+        req: &ParsedRequest,
+        recorder: &ResponseRecorder<'_>,
+        elapsed: Duration,
+    ) {
+        let status = recorder.status().unwrap_or(0);
+        if !self.should_log(&req.path, status) {
+            return;
+        }
+
+        let line = format!(
+            "{} [{}] \"{} {} {}\" {} {} {}ms",
+            client_addr.ip(),
+            fmt_http_date(SystemTime::now()),
+            req.method,
+            req.path,
+            req.http_version,
+            status,
+            recorder.bytes_written(),
+            elapsed.as_millis()
+        );
+
+        match &self.sink {
+            Sink::Off => {}
+            Sink::Tracing => info!(target: "migux::access", "{line}"),
+            Sink::File(tx) => {
+                if let Err(TrySendError::Full(_)) = tx.try_send(line) {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    if dropped.is_power_of_two() {
+                        warn!(
+                            target: "migux::access",
+                            dropped,
+                            "Access log writer is behind; dropping lines"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sink = match self.sink {
+            Sink::Off => "off",
+            Sink::File(_) => "file",
+            Sink::Tracing => "tracing",
+        };
+        f.debug_struct("AccessLog")
+            .field("sink", &sink)
+            .field("sample_rate", &self.sample_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for AccessLog {
+    /// No access log (`access_log = "off"`).
+    fn default() -> Self {
+        Self {
+            sink: Sink::Off,
+            sample_rate: 1,
+            counter: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            skip_paths: Vec::new(),
+            skip_statuses: Vec::new(),
+            health_check_path: None,
+        }
+    }
+}
+
+/// Client stream wrapper that records the response status and size.
+pub(crate) struct ResponseRecorder<'a> {
+    inner: &'a mut dyn ClientStream,
+    head: Vec<u8>,
+    status: Option<u16>,
+    bytes: u64,
+    bytes_read: u64,
+    framing: ResponseFraming,
+    extra_header: ExtraHeader,
+}
+
+/// A header line added right after the first status line written.
+enum ExtraHeader {
+    None,
+    /// Not written yet: waiting for the end of the status line.
+    Pending(Vec<u8>),
+    /// Status line passed through; these bytes go out before anything else.
+    Writing(Vec<u8>, usize),
+}
+
+impl<'a> ResponseRecorder<'a> {
+    pub(crate) fn new(inner: &'a mut dyn ClientStream, head_request: bool) -> Self {
+        Self {
+            inner,
+            head: Vec::new(),
+            status: None,
+            bytes: 0,
+            bytes_read: 0,
+            framing: ResponseFraming::new(head_request),
+            extra_header: ExtraHeader::None,
+        }
+    }
+
+    /// Add `name: value` to the response, after its status line. The status
+    /// line is assumed to arrive in a single write, as every writer does.
+    pub(crate) fn with_header(mut self, name: &str, value: impl std::fmt::Display) -> Self {
+        self.extra_header = ExtraHeader::Pending(format!("{name}: {value}\r\n").into_bytes());
+        self
+    }
+
+    /// Write out a header line started by an earlier call.
+    fn poll_extra_header(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while let ExtraHeader::Writing(line, written) = &mut self.extra_header {
+            if *written == line.len() {
+                self.extra_header = ExtraHeader::None;
+                break;
+            }
+            match Pin::new(&mut *self.inner).poll_write(cx, &line[*written..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+                Poll::Ready(Ok(n)) => {
+                    *written += n;
+                    self.bytes += n as u64;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    pub(crate) fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Bytes read from the client while the request was handled.
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub(crate) fn framing(&self) -> &ResponseFraming {
+        &self.framing
+    }
+
+    fn observe(&mut self, written: &[u8]) {
+        self.bytes += written.len() as u64;
+        self.framing.observe(written);
+        if self.status.is_some() || self.head.len() >= 16 {
+            return;
+        }
+        let take = written.len().min(16 - self.head.len());
+        self.head.extend_from_slice(&written[..take]);
+        // "HTTP/1.1 200 ..." -> 200
+        if self.head.len() >= 12 {
+            self.status = std::str::from_utf8(&self.head[9..12])
+                .ok()
+                .and_then(|code| code.parse().ok());
+        }
+    }
+}
+
+impl AsyncRead for ResponseRecorder<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let res = Pin::new(&mut *this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &res {
+            this.bytes_read += (buf.filled().len() - before) as u64;
+        }
+        res
+    }
+}
+
+impl AsyncWrite for ResponseRecorder<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_extra_header(cx))?;
+        // Stop the write at the end of the status line so the extra header
+        // can follow it. Interim (1xx) responses are left alone.
+        let mut status_line_end = None;
+        if let ExtraHeader::Pending(_) = this.extra_header
+            && buf.get(9) != Some(&b'1')
+        {
+            status_line_end = buf.windows(2).position(|w| w == b"\r\n").map(|i| i + 2);
+        }
+        let chunk = &buf[..status_line_end.unwrap_or(buf.len())];
+        let res = Pin::new(&mut *this.inner).poll_write(cx, chunk);
+        if let Poll::Ready(Ok(n)) = &res {
+            this.observe(&buf[..*n]);
+            if Some(*n) == status_line_end
+                && let ExtraHeader::Pending(line) =
+                    std::mem::replace(&mut this.extra_header, ExtraHeader::None)
+            {
+                this.extra_header = ExtraHeader::Writing(line, 0);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_extra_header(cx))?;
+        Pin::new(&mut *this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_extra_header(cx))?;
+        Pin::new(&mut *this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessLog, Sink, StatusFilter};
+    use std::sync::atomic::AtomicU64;
+
+    fn log(sample_rate: u64, skip_paths: &[&str], skip_statuses: &[&str]) -> AccessLog {
+        AccessLog {
+            sink: Sink::Tracing,
+            sample_rate,
+            counter: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            skip_paths: skip_paths.iter().map(|p| p.to_string()).collect(),
+            skip_statuses: skip_statuses
+                .iter()
+                .filter_map(|s| StatusFilter::parse(s))
+                .collect(),
+            health_check_path: None,
+        }
+    }
+
+    #[test]
+    fn sampling_logs_one_in_n() {
+        let log = log(10, &[], &[]);
+        let logged = (0..1000).filter(|_| log.should_log("/", 200)).count();
+        assert_eq!(logged, 100);
+    }
+
+    #[test]
+    fn default_rate_logs_everything() {
+        let log = log(1, &[], &[]);
+        assert!((0..50).all(|_| log.should_log("/", 200)));
+    }
+
+    #[test]
+    fn skips_successful_health_checks_only() {
+        let log = log(1, &["/health"], &["2xx"]);
+        assert!(!log.should_log("/health", 200));
+        assert!(!log.should_log("/health?probe=1", 204));
+        assert!(log.should_log("/health", 503));
+        assert!(log.should_log("/index.html", 200));
+    }
+
+    #[test]
+    fn health_check_path_is_not_logged() {
+        let mut log = log(1, &[], &[]);
+        log.health_check_path = Some("/healthz".into());
+        assert!(!log.should_log("/healthz", 200));
+        assert!(!log.should_log("/healthz/?probe=1", 200));
+        assert!(log.should_log("/healthz2", 200));
+    }
+
+    #[test]
+    fn file_lines_reach_the_file_once_the_log_is_dropped() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("access.log");
+        let http = migux_config::HttpConfig {
+            access_log: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let log = AccessLog::from_config(&http);
+        let Sink::File(tx) = &log.sink else {
+            panic!("expected a file sink");
+        };
+        for i in 0..100 {
+            tx.send(format!("line {i}")).expect("send");
+        }
+        drop(log);
+        // The writer drains the queue before it exits.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let lines = std::fs::read_to_string(&path).unwrap_or_default();
+            if lines.lines().count() == 100 {
+                assert!(lines.ends_with("line 99\n"));
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "got: {lines:?}");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn status_filter_parses_classes_and_codes() {
+        assert_eq!(StatusFilter::parse("2xx"), Some(StatusFilter::Class(2)));
+        assert_eq!(StatusFilter::parse("304"), Some(StatusFilter::Exact(304)));
+        assert_eq!(StatusFilter::parse("9xx"), None);
+    }
+}
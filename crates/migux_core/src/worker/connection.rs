@@ -0,0 +1,461 @@
+//! Keep-alive invariants for one request/response exchange.
+//!
+//! A connection is reused only when the request just handled was read to
+//! its end, so the next bytes are a request line, and the response written
+//! for it is complete by its own framing, so the client knows where the next
+//! one starts. Handlers decide to close for reasons they know about; the
+//! [`Exchange`] catches the cases they don't (a body left unread, a response
+//! cut short) and the worker closes instead of mis-parsing what follows.
+
+use std::fmt;
+
+use super::request::ParsedRequest;
+
+/// Response heads larger than this are not followed; the exchange then
+/// counts as incomplete.
+const MAX_RESPONSE_HEAD_BYTES: usize = 64 * 1024;
+const MAX_RESPONSE_HEADERS: usize = 128;
+
+/// What the keep-alive loop knows about the request in flight.
+pub(crate) struct Exchange {
+    body: RequestBody,
+    /// Bytes buffered for the connection when handling started, request
+    /// head included.
+    buffered_at_start: usize,
+    /// The handler read the chunked body through its last chunk.
+    chunked_body_read: bool,
+}
+
+enum RequestBody {
+    /// `Content-Length` bytes (0 when the request has no body).
+    Length(usize),
+    Chunked,
+}
+
+/// Why a connection can't be reused after an exchange.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Desync {
+    /// Request bytes that belong to the body were left unread.
+    BodyUnread,
+    /// More than the request (head plus body) was consumed.
+    BodyOverread,
+    /// A chunked body was not read through its last chunk.
+    ChunkedBodyUnread,
+    /// The response stops short of what its framing announced.
+    ResponseIncomplete,
+    /// The response is delimited by the connection closing, or says
+    /// `Connection: close`.
+    ResponseCloses,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Desync::BodyUnread => "request body not fully read",
+            Desync::BodyOverread => "read past the end of the request",
+            Desync::ChunkedBodyUnread => "chunked request body not read to its end",
+            Desync::ResponseIncomplete => "response incomplete",
+            Desync::ResponseCloses => "response is delimited by connection close",
+        })
+    }
+}
+
+impl Exchange {
+    /// `buffered` is the client buffer's length before the request is handled.
+    pub(crate) fn new(req: &ParsedRequest, buffered: usize) -> Self {
+        Self {
+            body: if req.is_chunked {
+                RequestBody::Chunked
+            } else {
+                RequestBody::Length(req.content_length)
+            },
+            buffered_at_start: buffered,
+            chunked_body_read: false,
+        }
+    }
+
+    /// The handler consumed a chunked body through its last chunk.
+    pub(crate) fn chunked_body_read(&mut self) {
+        self.chunked_body_read = true;
+    }
+
+    /// Whether the connection can take another request: `read` bytes came
+    /// off the socket while handling, `buffered` are left in the client
+    /// buffer, `head_len` is the request head and `response` followed what
+    /// was written.
+    pub(crate) fn check(
+        &self,
+        read: u64,
+        buffered: usize,
+        head_len: usize,
+        response: &ResponseFraming,
+    ) -> Result<(), Desync> {
+        let input = self.buffered_at_start as u64 + read;
+        let consumed = input.saturating_sub(buffered as u64);
+        match self.body {
+            RequestBody::Length(len) => {
+                let expected = (head_len + len) as u64;
+                if consumed < expected {
+                    return Err(Desync::BodyUnread);
+                }
+                if consumed > expected {
+                    return Err(Desync::BodyOverread);
+                }
+            }
+            RequestBody::Chunked => {
+                if !self.chunked_body_read {
+                    return Err(Desync::ChunkedBodyUnread);
+                }
+                if consumed < head_len as u64 {
+                    return Err(Desync::BodyUnread);
+                }
+            }
+        }
+        response.check()
+    }
+}
+
+/// Follows the bytes of a response as they are written and tells whether
+/// it ended where its head said it would.
+pub(crate) struct ResponseFraming {
+    head_request: bool,
+    close: bool,
+    state: FramingState,
+}
+
+enum FramingState {
+    Head(Vec<u8>),
+    Length(u64),
+    Chunked(ChunkState),
+    UntilClose,
+    Done,
+    Invalid,
+}
+
+enum ChunkState {
+    /// Reading a chunk-size line (extensions included).
+    Size(Vec<u8>),
+    /// Chunk data still to come, then its CRLF.
+    Data(u64),
+    DataEnd(usize),
+    /// Trailer lines after the last chunk; an empty one ends the body.
+    Trailer(Vec<u8>),
+}
+
+impl ResponseFraming {
+    /// Responses to `HEAD` carry no body whatever their headers say.
+    pub(crate) fn new(head_request: bool) -> Self {
+        Self {
+            head_request,
+            close: false,
+            state: FramingState::Head(Vec::new()),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, mut written: &[u8]) {
+        while !written.is_empty() {
+            let used = self.step(written);
+            written = &written[used..];
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Desync> {
+        match self.state {
+            FramingState::UntilClose => Err(Desync::ResponseCloses),
+            FramingState::Done if self.close => Err(Desync::ResponseCloses),
+            FramingState::Done => Ok(()),
+            _ => Err(Desync::ResponseIncomplete),
+        }
+    }
+
+    /// Advance over the start of `data`; returns how much was used.
+    fn step(&mut self, data: &[u8]) -> usize {
+        match &mut self.state {
+            FramingState::Head(head) => {
+                let start = head.len();
+                head.extend_from_slice(data);
+                let Some(end) = find_head_end(head) else {
+                    if head.len() > MAX_RESPONSE_HEAD_BYTES {
+                        self.state = FramingState::Invalid;
+                    }
+                    return data.len();
+                };
+                let head = std::mem::take(head);
+                self.state = self.after_head(&head[..end]);
+                end - start
+            }
+            FramingState::Length(remaining) => {
+                let used = (*remaining).min(data.len() as u64);
+                *remaining -= used;
+                if *remaining == 0 {
+                    self.state = FramingState::Done;
+                }
+                used as usize
+            }
+            FramingState::Chunked(chunk) => {
+                let (used, done) = chunk.step(data);
+                match done {
+                    Some(true) => self.state = FramingState::Done,
+                    Some(false) => self.state = FramingState::Invalid,
+                    None => {}
+                }
+                used
+            }
+            FramingState::UntilClose => data.len(),
+            // Bytes past the end of the response, or after it went wrong.
+            FramingState::Done | FramingState::Invalid => {
+                self.state = FramingState::Invalid;
+                data.len()
+            }
+        }
+    }
+
+    fn after_head(&mut self, head: &[u8]) -> FramingState {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_RESPONSE_HEADERS];
+        let mut resp = httparse::Response::new(&mut headers);
+        if !matches!(resp.parse(head), Ok(httparse::Status::Complete(_))) {
+            return FramingState::Invalid;
+        }
+        let status = resp.code.unwrap_or(0);
+        if (100..200).contains(&status) && status != 101 {
+            // Interim response: the final one follows.
+            return FramingState::Head(Vec::new());
+        }
+
+        let mut content_length = None;
+        let mut chunked = false;
+        for header in resp.headers.iter() {
+            let value = String::from_utf8_lossy(header.value);
+            if header.name.eq_ignore_ascii_case("connection") {
+                self.close |= value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("close"));
+            } else if header.name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<u64>().ok();
+            } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value
+                    .rsplit(',')
+                    .next()
+                    .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+            }
+        }
+
+        if self.head_request || matches!(status, 101 | 204 | 304) {
+            FramingState::Done
+        } else if chunked {
+            FramingState::Chunked(ChunkState::Size(Vec::new()))
+        } else {
+            match content_length {
+                Some(0) => FramingState::Done,
+                Some(len) => FramingState::Length(len),
+                None => FramingState::UntilClose,
+            }
+        }
+    }
+}
+
+impl ChunkState {
+    /// Advance over the start of `data`: bytes used, and `Some(true)` once
+    /// the body ended (`Some(false)` if it is malformed).
+    fn step(&mut self, data: &[u8]) -> (usize, Option<bool>) {
+        match self {
+            ChunkState::Size(line) | ChunkState::Trailer(line) => {
+                let Some(newline) = data.iter().position(|b| *b == b'\n') else {
+                    line.extend_from_slice(data);
+                    return (data.len(), None);
+                };
+                line.extend_from_slice(&data[..newline]);
+                let line = std::mem::take(line);
+                let line = line.strip_suffix(b"\r").unwrap_or(&line);
+                let used = newline + 1;
+                if matches!(self, ChunkState::Trailer(_)) {
+                    return if line.is_empty() {
+                        (used, Some(true))
+                    } else {
+                        (used, None)
+                    };
+                }
+                let size = std::str::from_utf8(line)
+                    .ok()
+                    .and_then(|line| line.split(';').next())
+                    .and_then(|size| u64::from_str_radix(size.trim(), 16).ok());
+                match size {
+                    None => (used, Some(false)),
+                    Some(0) => {
+                        *self = ChunkState::Trailer(Vec::new());
+                        (used, None)
+                    }
+                    Some(size) => {
+                        *self = ChunkState::Data(size);
+                        (used, None)
+                    }
+                }
+            }
+            ChunkState::Data(remaining) => {
+                let used = (*remaining).min(data.len() as u64);
+                *remaining -= used;
+                if *remaining == 0 {
+                    *self = ChunkState::DataEnd(0);
+                }
+                (used as usize, None)
+            }
+            ChunkState::DataEnd(seen) => {
+                let expected = &b"\r\n"[*seen..];
+                let used = expected.len().min(data.len());
+                if data[..used] != expected[..used] {
+                    return (used, Some(false));
+                }
+                *seen += used;
+                if *seen == 2 {
+                    *self = ChunkState::Size(Vec::new());
+                }
+                (used, None)
+            }
+        }
+    }
+}
+
+fn find_head_end(head: &[u8]) -> Option<usize> {
+    head.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Desync, Exchange, RequestBody, ResponseFraming};
+
+    fn framing(head_request: bool, chunks: &[&[u8]]) -> Result<(), Desync> {
+        let mut framing = ResponseFraming::new(head_request);
+        for chunk in chunks {
+            framing.observe(chunk);
+        }
+        framing.check()
+    }
+
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    #[test]
+    fn complete_responses_allow_reuse() {
+        assert_eq!(framing(false, &[OK]), Ok(()));
+        assert_eq!(
+            framing(
+                false,
+                &[
+                    b"HTTP/1.1 200 OK\r\nContent-",
+                    b"Length: 2\r\n\r",
+                    b"\no",
+                    b"k"
+                ]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            framing(true, &[b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n"]),
+            Ok(())
+        );
+        assert_eq!(
+            framing(false, &[b"HTTP/1.1 304 Not Modified\r\n\r\n"]),
+            Ok(())
+        );
+        assert_eq!(
+            framing(
+                false,
+                &[
+                    b"HTTP/1.1 100 Continue\r\n\r\n",
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+                    b"2;ext=1\r\nok\r",
+                    b"\n0\r\nX-Trailer: 1\r\n\r\n",
+                ]
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn mid_response_error_is_incomplete() {
+        assert_eq!(framing(false, &[]), Err(Desync::ResponseIncomplete));
+        assert_eq!(
+            framing(
+                false,
+                &[b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n12345"]
+            ),
+            Err(Desync::ResponseIncomplete)
+        );
+        assert_eq!(
+            framing(false, &[OK, b"HTTP/1.1 200 OK\r\n"]),
+            Err(Desync::ResponseIncomplete)
+        );
+    }
+
+    #[test]
+    fn streaming_abort_is_incomplete() {
+        assert_eq!(
+            framing(
+                false,
+                &[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel"]
+            ),
+            Err(Desync::ResponseIncomplete)
+        );
+        assert_eq!(
+            framing(
+                false,
+                &[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nokXX0\r\n\r\n"]
+            ),
+            Err(Desync::ResponseIncomplete)
+        );
+    }
+
+    #[test]
+    fn close_delimited_responses_end_the_connection() {
+        assert_eq!(
+            framing(false, &[b"HTTP/1.1 200 OK\r\n\r\nuntil eof"]),
+            Err(Desync::ResponseCloses)
+        );
+        assert_eq!(
+            framing(
+                false,
+                &[b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"]
+            ),
+            Err(Desync::ResponseCloses)
+        );
+    }
+
+    fn exchange(body: RequestBody, buffered: usize) -> Exchange {
+        Exchange {
+            body,
+            buffered_at_start: buffered,
+            chunked_body_read: false,
+        }
+    }
+
+    fn complete() -> ResponseFraming {
+        let mut framing = ResponseFraming::new(false);
+        framing.observe(OK);
+        framing
+    }
+
+    #[test]
+    fn unconsumed_body_is_detected() {
+        // 40-byte head, 10-byte body, 30 bytes of a pipelined request.
+        let ex = exchange(RequestBody::Length(10), 80);
+        assert_eq!(ex.check(0, 30, 40, &complete()), Ok(()));
+        assert_eq!(ex.check(0, 35, 40, &complete()), Err(Desync::BodyUnread));
+        assert_eq!(ex.check(0, 25, 40, &complete()), Err(Desync::BodyOverread));
+        // Head only in the buffer, body read from the socket.
+        let ex = exchange(RequestBody::Length(10), 40);
+        assert_eq!(ex.check(10, 0, 40, &complete()), Ok(()));
+        assert_eq!(ex.check(4, 0, 40, &complete()), Err(Desync::BodyUnread));
+        // The head itself left in the buffer.
+        let ex = exchange(RequestBody::Length(0), 40);
+        assert_eq!(ex.check(0, 40, 40, &complete()), Err(Desync::BodyUnread));
+
+        let mut ex = exchange(RequestBody::Chunked, 60);
+        assert_eq!(
+            ex.check(0, 0, 40, &complete()),
+            Err(Desync::ChunkedBodyUnread)
+        );
+        ex.chunked_body_read();
+        assert_eq!(ex.check(0, 0, 40, &complete()), Ok(()));
+    }
+}
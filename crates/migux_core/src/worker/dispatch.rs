@@ -1,18 +1,52 @@
 use std::{net::SocketAddr, sync::Arc};
 
 use bytes::BytesMut;
-use migux_config::{LocationConfig, LocationType, MiguxConfig};
-use migux_http::responses::send_405_with_allow;
-use migux_proxy::Proxy;
-use migux_static::serve_static_cached;
+use migux_config::{ErrorFormat, HttpConfig, LocationConfig, LocationType, MiguxConfig};
+use migux_http::keep_alive::KeepAlive;
+use migux_http::responses::{
+    send_400, send_405_keep_alive, send_405_with_allow, send_408, send_413, send_502, send_504,
+    send_json_error, send_options,
+};
+use migux_proxy::{Proxy, UpstreamsUnavailable};
+use migux_static::{serve_origin_pull, serve_static_cached, serve_static_fallback};
 use tokio::time::Duration;
-use tracing::{debug, warn};
+use tracing::{Span, debug, warn};
 
 use super::ClientStream;
+use super::connection::Exchange;
 use super::request::ParsedRequest;
-use super::timeouts::{discard_chunked_body, discard_content_length};
+use super::timeouts::{ChunkedBodyError, discard_chunked_body, discard_content_length};
 use crate::ServerRuntime;
 
+/// Methods a static location answers.
+const STATIC_ALLOW: &str = "GET, HEAD, OPTIONS";
+/// Methods forwarded by proxy locations (the upstream has the final say).
+const PROXY_ALLOW: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Methods routed to locations; anything else is answered 501 up front.
+/// TRACK (a TRACE alias) is known so `allow_trace` covers both.
+const KNOWN_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE", "TRACK",
+];
+
+/// Whether `method` is one migux routes (method names are case-sensitive).
+pub(crate) fn is_known_method(method: &str) -> bool {
+    KNOWN_METHODS.contains(&method)
+}
+
+/// Methods supported by at least one of the server's locations, for `OPTIONS *`.
+pub(crate) fn server_allow(locations: &[LocationConfig]) -> &'static str {
+    if locations
+        .iter()
+        .any(|loc| matches!(loc.r#type, LocationType::Proxy))
+    {
+        PROXY_ALLOW
+    } else {
+        STATIC_ALLOW
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn dispatch_location(
     stream: &mut dyn ClientStream,
     buf: &mut BytesMut,
@@ -20,7 +54,8 @@ pub(crate) async fn dispatch_location(
     server: &ServerRuntime,
     location: &LocationConfig,
     req: &ParsedRequest,
-    proxy: &Proxy,
+    exchange: &mut Exchange,
+    proxy: &Arc<Proxy>,
     client_addr: &SocketAddr,
     is_tls: bool,
 ) -> anyhow::Result<bool> {
@@ -28,16 +63,65 @@ pub(crate) async fn dispatch_location(
     let path = req.path.as_str();
     let hsts_header = build_hsts_header(server, is_tls);
 
+    // TRACE echoes the request (cookies included) back; don't let a proxy
+    // location hand it to an upstream that would.
+    if matches!(method, "TRACE" | "TRACK") && !cfg.http.allow_trace() {
+        warn!(
+            target: "migux::worker",
+            %method,
+            "TRACE/TRACK disabled (allow_trace = false); returning 405"
+        );
+        let allow = match location.r#type {
+            LocationType::Static | LocationType::OriginPull | LocationType::Archive => STATIC_ALLOW,
+            LocationType::Proxy => PROXY_ALLOW,
+        };
+        send_405_with_allow(stream, allow).await?;
+        return Ok(true);
+    }
+
     match location.r#type {
-        LocationType::Static => {
+        LocationType::Static | LocationType::OriginPull | LocationType::Archive => {
+            // Nothing here reads the request body: discard it (if any) before
+            // answering, so a keep-alive connection stays in sync and an
+            // oversized chunked body still gets its 413. If it can't be
+            // consumed exactly, the framing is lost and we must close.
+            if let Err(e) = drain_request_body(stream, buf, req, location, cfg).await {
+                debug!(
+                    target: "migux::static",
+                    %path,
+                    "Request body could not be drained; closing connection"
+                );
+                match e {
+                    ChunkedBodyError::TooLarge => {
+                        warn!(
+                            target: "migux::worker",
+                            location_path = %location.path,
+                            "Chunked request body too large"
+                        );
+                        send_413(stream).await?;
+                    }
+                    ChunkedBodyError::Timeout => send_408(stream).await?,
+                    ChunkedBodyError::Invalid => send_400(stream).await?,
+                    ChunkedBodyError::Io => {}
+                }
+                return Ok(true);
+            }
+            if req.is_chunked {
+                exchange.chunked_body_read();
+            }
+
+            if method == "OPTIONS" {
+                send_options(stream, STATIC_ALLOW, keep_alive_for(req, &cfg.http)).await?;
+                return Ok(false);
+            }
             if method != "GET" && method != "HEAD" {
                 warn!(
                     target: "migux::worker",
                     %method,
                     "Unsupported method for static file; returning 405"
                 );
-                send_405_with_allow(stream, "GET, HEAD").await?;
-                return Ok(true);
+                send_405_keep_alive(stream, STATIC_ALLOW, keep_alive_for(req, &cfg.http)).await?;
+                return Ok(false);
             }
 
             debug!(
@@ -46,69 +130,155 @@ pub(crate) async fn dispatch_location(
                 "Serving static file"
             );
 
-            let keep_alive = !req.close_after;
-            serve_static_cached(
-                stream,
-                &cfg.http,
-                &server.config,
-                location,
-                method,
-                &req.headers,
-                path,
-                keep_alive,
-                hsts_header.as_deref(),
-            )
-            .await?;
-
-            // Discard request body (if any) so keep-alive doesn't break.
-            if req.is_chunked {
-                let _ = discard_chunked_body(
+            if matches!(location.r#type, LocationType::OriginPull) {
+                serve_origin_pull(
                     stream,
-                    buf,
-                    Duration::from_secs(cfg.http.client_read_timeout_secs),
-                    cfg.http.max_request_body_bytes as usize,
+                    proxy,
+                    cfg,
+                    &server.config,
+                    location,
+                    method,
+                    &req.headers,
+                    &req.route_path,
+                    keep_alive_for(req, &cfg.http),
+                    hsts_header.as_deref(),
+                    client_addr,
                 )
-                .await;
-            } else if req.content_length > 0 {
-                let _ = discard_content_length(
+                .await?;
+            } else {
+                serve_static_cached(
                     stream,
-                    buf,
-                    req.content_length,
-                    Duration::from_secs(cfg.http.client_read_timeout_secs),
+                    &cfg.http,
+                    &server.config,
+                    location,
+                    method,
+                    &req.headers,
+                    &req.route_path,
+                    keep_alive_for(req, &cfg.http),
+                    hsts_header.as_deref(),
                 )
-                .await;
+                .await?;
             }
         }
         LocationType::Proxy => {
+            Span::current().record("decision", "proxy");
             debug!(
                 target: "migux::proxy",
                 %path,
                 "Forwarding request to upstream proxy"
             );
 
-            proxy
+            let upstream_method = match &req.original_method {
+                Some(original) if cfg.http.method_override_forward_original() => original,
+                _ => method,
+            };
+            let served = proxy
                 .serve(
                     stream,
                     buf,
                     location,
                     &req.headers,
-                    method,
+                    upstream_method,
                     path,
                     &req.http_version,
                     req.content_length,
                     req.is_chunked,
+                    keep_alive_for(req, &cfg.http),
                     is_tls,
+                    listen_port(server, is_tls),
                     hsts_header.as_deref(),
                     cfg,
                     client_addr,
                 )
-                .await?;
+                .await;
+            return match served {
+                Err(ref e) if let Some(unavailable) = e.downcast_ref::<UpstreamsUnavailable>() => {
+                    serve_fallback(
+                        stream,
+                        location,
+                        unavailable.status(),
+                        hsts_header.as_deref(),
+                        cfg.http.charset(),
+                    )
+                    .await?;
+                    Ok(true)
+                }
+                // The proxy only keeps the client after streaming the
+                // whole request body upstream.
+                Ok(false) => {
+                    if req.is_chunked {
+                        exchange.chunked_body_read();
+                    }
+                    Ok(false)
+                }
+                other => other,
+            };
         }
     }
 
     Ok(false)
 }
 
+/// Answer with the location's `fallback_static` page after every upstream
+/// failed, or with `status` (the proxy's 502 or 504) if the page can't be
+/// read.
+async fn serve_fallback(
+    stream: &mut dyn ClientStream,
+    location: &LocationConfig,
+    status: &str,
+    hsts_header: Option<&str>,
+    charset: &str,
+) -> anyhow::Result<()> {
+    let Some(path) = location.fallback_static() else {
+        return Ok(());
+    };
+    if serve_static_fallback(
+        stream,
+        path,
+        location.fallback_status(),
+        KeepAlive::Close,
+        hsts_header,
+        location.charset_or(charset),
+    )
+    .await?
+    {
+        return Ok(());
+    }
+    warn!(
+        target: "migux::proxy",
+        location_path = %location.path,
+        status,
+        "fallback_static unavailable; sending the gateway error"
+    );
+    match (location.error_format(), status.starts_with("504")) {
+        (ErrorFormat::Text, false) => send_502(stream).await,
+        (ErrorFormat::Text, true) => send_504(stream).await,
+        (ErrorFormat::Json, _) => send_json_error(stream, status).await,
+    }
+}
+
+/// Keep-alive advertised on the response: the configured idle timeout and
+/// the requests left on this connection, unless this request closes it.
+pub(crate) fn keep_alive_for(req: &ParsedRequest, http: &HttpConfig) -> KeepAlive {
+    if req.close_after {
+        return KeepAlive::Close;
+    }
+    KeepAlive::Open {
+        timeout_secs: http.keepalive_timeout_secs(),
+        max_requests: req.requests_left,
+    }
+}
+
+/// Port of the listener this request arrived on, from the server's config.
+fn listen_port(server: &ServerRuntime, is_tls: bool) -> Option<u16> {
+    let listen = if is_tls {
+        &server.config.tls.as_ref()?.listen
+    } else {
+        &server.config.listen
+    };
+    listen.parse::<SocketAddr>().ok().map(|addr| addr.port())
+}
+
 fn build_hsts_header(server: &ServerRuntime, is_tls: bool) -> Option<String> {
     if !is_tls {
         return None;
@@ -126,3 +296,38 @@ fn build_hsts_header(server: &ServerRuntime, is_tls: bool) -> Option<String> {
     }
     Some(value)
 }
+
+/// Consume the request body of a request answered without it; fails when
+/// the body is malformed, too large or times out.
+async fn drain_request_body(
+    stream: &mut dyn ClientStream,
+    buf: &mut BytesMut,
+    req: &ParsedRequest,
+    location: &LocationConfig,
+    cfg: &MiguxConfig,
+) -> Result<(), ChunkedBodyError> {
+    let read_timeout = Duration::from_secs(cfg.http.client_read_timeout_secs);
+    if req.is_chunked {
+        discard_chunked_body(
+            stream,
+            buf,
+            read_timeout,
+            cfg.http.client_buffer_size(),
+            location
+                .max_body_bytes()
+                .unwrap_or(cfg.http.max_request_body_bytes) as usize,
+        )
+        .await
+    } else if req.content_length > 0 {
+        discard_content_length(
+            stream,
+            buf,
+            req.content_length,
+            read_timeout,
+            cfg.http.client_buffer_size(),
+        )
+        .await
+    } else {
+        Ok(())
+    }
+}
@@ -3,28 +3,39 @@
 //! Reads client requests, selects the matching server/location, and dispatches
 //! to static or proxy handlers while respecting keep-alive and timeouts.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{fmt::Write as _, net::SocketAddr, sync::Arc, time::Instant};
 
 use bytes::{Buf, BytesMut};
-use migux_http::responses::{send_404, send_405_with_allow, send_redirect, send_response};
+use migux_http::responses::{
+    send_404, send_405_with_allow, send_413, send_501_with_allow, send_options, send_redirect,
+    send_response,
+};
+use migux_http::target::has_encoded_slash;
 use migux_proxy::Proxy;
-use migux_static::cache_metrics_snapshot;
+use migux_static::{CacheMetrics, cache_metrics_snapshot};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::time::Duration;
-use tracing::{debug, info, instrument, warn};
+use tracing::{Instrument, Span, debug, field::Empty, info, info_span, instrument, warn};
 
-use migux_config::MiguxConfig;
+use migux_config::{EncodedSlashes, HttpConfig, MiguxConfig};
 
 use crate::ServerRuntime;
 
+pub(crate) mod access_log;
+mod connection;
 mod dispatch;
 mod request;
-mod routing;
+pub mod routing;
+mod status;
 mod timeouts;
 
-use dispatch::dispatch_location;
+use access_log::ResponseRecorder;
+use connection::Exchange;
+use dispatch::{dispatch_location, is_known_method, keep_alive_for, server_allow};
 use request::{ParsedRequest, extract_host_header, read_http_request};
 use routing::{match_location, select_default_server};
+use status::maybe_handle_status;
+use timeouts::reclaim_client_buf;
 
 pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
 
@@ -49,6 +60,8 @@ pub async fn handle_connection(
 
     let mut buf = BytesMut::new();
     let mut first_request = true;
+    let mut requests_served: u64 = 0;
+    let connected_at = Instant::now();
 
     loop {
         let idle_timeout = if first_request {
@@ -58,10 +71,25 @@ pub async fn handle_connection(
         };
 
         // 1) Read one HTTP request (headers + optional body)
-        let req = match read_http_request(&mut stream, &mut buf, &cfg.http, idle_timeout).await? {
-            Some(req) => req,
-            None => break,
-        };
+        let mut req =
+            match read_http_request(&mut stream, &mut buf, &cfg.http, idle_timeout).await? {
+                Some(req) => req,
+                None => break,
+            };
+
+        // The last request allowed on this connection is answered with
+        // `Connection: close`.
+        requests_served += 1;
+        req.requests_left = cfg
+            .http
+            .keepalive_max_requests()
+            .saturating_sub(requests_served);
+        if req.requests_left == 0 {
+            req.close_after = true;
+        }
+
+        // Bytes buffered at once for this request (head plus anything pipelined).
+        let peak_buffered = buf.len();
 
         if req.headers.is_empty() {
             debug!(target: "migux::worker", "Empty request received; closing connection");
@@ -78,87 +106,234 @@ pub async fn handle_connection(
             "Parsed HTTP request line"
         );
 
-        if maybe_handle_cache_metrics(&mut stream, &req, client_addr).await? {
-            break;
+        // 3) Handle the request, recording status/bytes for the access log.
+        //    Routing, dispatch and the proxy fill in the span's empty fields
+        //    as they learn them.
+        let span = info_span!(
+            target: "migux::worker",
+            "request",
+            %method,
+            %path,
+            server = Empty,
+            location = Empty,
+            decision = Empty,
+            upstream = Empty,
+            status = Empty,
+            bytes = Empty,
+        );
+        let started = Instant::now();
+        let mut exchange = Exchange::new(&req, buf.len());
+        let mut recorder = ResponseRecorder::new(stream.as_mut(), req.method == "HEAD");
+        if cfg.http.debug_connection_header() {
+            recorder = recorder.with_header("X-Migux-Conn-Requests", requests_served);
         }
+        let outcome = handle_request(
+            &mut recorder,
+            &mut buf,
+            &req,
+            &mut exchange,
+            &servers,
+            &proxy,
+            &cfg,
+            &client_addr,
+            is_tls,
+        )
+        .instrument(span.clone())
+        .await;
+        if let Some(server) = servers.first() {
+            server
+                .access_log
+                .record(&client_addr, &req, &recorder, started.elapsed());
+        }
+        crate::stats::record_request(recorder.bytes_written());
 
-        // 3) Select server for this connection
-        let server = select_default_server(&servers);
-        debug!(
-            target: "migux::worker",
-            server = %server.name,
-            root = %server.config.root,
-            index = %server.config.index,
-            "Selected server for request"
-        );
-
-        if !is_tls {
-            if let Some(tls_cfg) = &server.config.tls {
-                if tls_cfg.redirect_http {
-                    let host = extract_host_header(&req.headers)
-                        .unwrap_or_else(|| server.config.server_name.clone());
-                    let location = build_https_redirect(&host, &req.path, &tls_cfg.listen);
-                    send_redirect(&mut stream, &location).await?;
-                    break;
-                }
-            }
+        if let Some(status) = recorder.status() {
+            span.record("status", status);
         }
+        span.record("bytes", recorder.bytes_written());
+        span.in_scope(|| {
+            debug!(
+                target: "migux::worker",
+                conn_requests = requests_served,
+                conn_age_ms = connected_at.elapsed().as_millis() as u64,
+                "Request complete"
+            )
+        });
 
-        if server.locations.is_empty() {
+        if outcome? {
+            break;
+        }
+        // The handler would keep the connection; make sure the next bytes
+        // really start a request and the client can tell this response ended.
+        if let Err(desync) = exchange.check(
+            recorder.bytes_read(),
+            buf.len(),
+            req.body_start,
+            recorder.framing(),
+        ) {
             warn!(
                 target: "migux::worker",
-                server = %server.name,
-                "Server has no locations; returning 404"
+                reason = %desync,
+                "Connection out of sync after request; closing"
             );
-            send_404(&mut stream).await?;
             break;
         }
 
-        // 4) Match location
-        let location = match_location(&server.locations, path);
-        debug!(
+        reclaim_client_buf(&mut buf, peak_buffered);
+        first_request = false;
+    }
+
+    info!(
+        target: "migux::worker",
+        %client_addr,
+        "Finished handling connection"
+    );
+
+    Ok(())
+}
+
+/// Handle one parsed request; returns `true` when the connection must close.
+#[allow(clippy::too_many_arguments)]
+async fn handle_request(
+    stream: &mut dyn ClientStream,
+    buf: &mut BytesMut,
+    req: &ParsedRequest,
+    exchange: &mut Exchange,
+    servers: &[ServerRuntime],
+    proxy: &Arc<Proxy>,
+    cfg: &Arc<MiguxConfig>,
+    client_addr: &SocketAddr,
+    is_tls: bool,
+) -> anyhow::Result<bool> {
+    let path = req.path.as_str();
+
+    if maybe_handle_health_check(stream, req, &cfg.http).await? {
+        return Ok(true);
+    }
+
+    // Unknown methods (typos, custom verbs) are not routed anywhere.
+    if !is_known_method(&req.method) {
+        let server = select_default_server(servers);
+        warn!(
             target: "migux::worker",
-            location_server = %location.server,
-            location_path = %location.path,
-            location_type = ?location.r#type,
-            "Matched location"
+            method = %req.method,
+            "Unknown request method; returning 501"
         );
+        send_501_with_allow(stream, server_allow(&server.locations)).await?;
+        return Ok(true);
+    }
 
-        let close_after = req.close_after;
+    if maybe_handle_cache_metrics(stream, req, *client_addr).await? {
+        return Ok(true);
+    }
+    if maybe_handle_status(stream, req, admin_path(path), *client_addr, proxy, cfg).await? {
+        return Ok(true);
+    }
 
-        // Drop headers from buffer; keep body/leftovers for streaming or next request.
-        if req.body_start > 0 {
-            buf.advance(req.body_start);
-        }
+    // 3.1) Select server for this connection
+    let server = select_default_server(servers);
+    Span::current().record("server", server.name.as_str());
+    debug!(
+        target: "migux::worker",
+        server = %server.name,
+        root = %server.config.root,
+        index = %server.config.index,
+        "Selected server for request"
+    );
 
-        // 5) Dispatch according to location type
-        let force_close = dispatch_location(
-            &mut stream,
-            &mut buf,
-            &cfg,
-            server,
-            location,
-            &req,
-            &proxy,
-            &client_addr,
-            is_tls,
+    // `OPTIONS *` asks about the server itself; no location is involved.
+    if req.method == "OPTIONS" && path == "*" {
+        buf.advance(req.body_start);
+        send_options(
+            stream,
+            server_allow(&server.locations),
+            keep_alive_for(req, &cfg.http),
         )
         .await?;
+        return Ok(req.close_after);
+    }
 
-        if force_close || close_after {
-            break;
-        }
+    if !is_tls
+        && let Some(tls_cfg) = &server.config.tls
+        && tls_cfg.redirect_http
+    {
+        let host =
+            extract_host_header(&req.headers).unwrap_or_else(|| server.config.server_name.clone());
+        let location = build_https_redirect(&host, &req.path, &tls_cfg.listen);
+        send_redirect(stream, &location).await?;
+        return Ok(true);
+    }
 
-        first_request = false;
+    if server.locations.is_empty() {
+        warn!(
+            target: "migux::worker",
+            server = %server.name,
+            "Server has no locations; returning 404"
+        );
+        send_404(stream).await?;
+        return Ok(true);
     }
 
-    info!(
+    if cfg.http.encoded_slashes() == EncodedSlashes::Reject && has_encoded_slash(path) {
+        debug!(
+            target: "migux::worker",
+            %path,
+            "Encoded slash in path (encoded_slashes = reject); returning 404"
+        );
+        send_404(stream).await?;
+        return Ok(true);
+    }
+
+    // 3.2) Match location (on the normalized path; the raw one is forwarded)
+    let location = match_location(server, &req.route_path);
+    Span::current().record("location", location.path.as_str());
+    debug!(
         target: "migux::worker",
-        %client_addr,
-        "Finished handling connection"
+        location_server = %location.server,
+        location_path = %location.path,
+        location_type = ?location.r#type,
+        "Matched location"
     );
 
-    Ok(())
+    // 3.3) Body cap: the location's max_body_bytes, else the global one
+    let max_body = location
+        .max_body_bytes()
+        .unwrap_or(cfg.http.max_request_body_bytes) as usize;
+    if !req.is_chunked && max_body > 0 && req.content_length > max_body {
+        warn!(
+            target: "migux::worker",
+            location_path = %location.path,
+            content_length = req.content_length,
+            max_body,
+            "Request body too large"
+        );
+        send_413(stream).await?;
+        return Ok(true);
+    }
+
+    let close_after = req.close_after;
+
+    // Drop headers from buffer; keep body/leftovers for streaming or next request.
+    if req.body_start > 0 {
+        buf.advance(req.body_start);
+    }
+
+    // 3.4) Dispatch according to location type
+    let force_close = dispatch_location(
+        stream,
+        buf,
+        cfg,
+        server,
+        location,
+        req,
+        exchange,
+        proxy,
+        client_addr,
+        is_tls,
+    )
+    .await?;
+
+    Ok(force_close || close_after)
 }
 
 const CACHE_METRICS_PATH: &str = "/_migux/cache";
@@ -167,17 +342,47 @@ fn strip_query(path: &str) -> &str {
     path.split('?').next().unwrap_or(path)
 }
 
-async fn maybe_handle_cache_metrics(
+/// Request path as matched against the `/_migux/*` admin endpoints.
+fn admin_path(path: &str) -> &str {
+    let path = strip_query(path);
+    if path.len() > 1 {
+        path.trim_end_matches('/')
+    } else {
+        path
+    }
+}
+
+/// `http.health_check_path`: answered by migux itself, for any client and
+/// whatever the locations say, so a load balancer sees the process alive
+/// even when every root or upstream behind it is broken.
+async fn maybe_handle_health_check(
     stream: &mut dyn ClientStream,
     req: &ParsedRequest,
-    client_addr: SocketAddr,
+    http: &HttpConfig,
 ) -> anyhow::Result<bool> {
-    let mut path = strip_query(req.path.as_str());
-    if path.len() > 1 {
-        path = path.trim_end_matches('/');
+    let Some(health_path) = http.health_check_path() else {
+        return Ok(false);
+    };
+    if admin_path(&req.path) != admin_path(health_path) {
+        return Ok(false);
+    }
+
+    if req.method != "GET" && req.method != "HEAD" {
+        send_405_with_allow(stream, "GET, HEAD").await?;
+        return Ok(true);
     }
 
-    if path != CACHE_METRICS_PATH {
+    let body: &[u8] = if req.method == "HEAD" { b"" } else { b"ok\n" };
+    send_response(stream, "200 OK", "text/plain; charset=utf-8", body).await?;
+    Ok(true)
+}
+
+async fn maybe_handle_cache_metrics(
+    stream: &mut dyn ClientStream,
+    req: &ParsedRequest,
+    client_addr: SocketAddr,
+) -> anyhow::Result<bool> {
+    if admin_path(&req.path) != CACHE_METRICS_PATH {
         return Ok(false);
     }
 
@@ -191,32 +396,157 @@ async fn maybe_handle_cache_metrics(
         return Ok(true);
     }
 
+    let metrics = cache_metrics_snapshot().await;
+    let (content_type, body) = if wants_prometheus(&req.headers) {
+        (
+            "text/plain; version=0.0.4; charset=utf-8",
+            cache_metrics_prometheus(&metrics),
+        )
+    } else {
+        (
+            "application/json; charset=utf-8",
+            cache_metrics_json(&metrics),
+        )
+    };
     let body = if req.method == "HEAD" {
         String::new()
     } else {
-        let metrics = cache_metrics_snapshot().await;
-        format!(
-            "{{\"memory_hits\":{},\"memory_misses\":{},\"disk_hits\":{},\"disk_misses\":{},\"disk_evictions\":{},\"disk_evicted_bytes\":{},\"disk_bytes\":{},\"disk_entries\":{}}}",
-            metrics.memory_hits,
+        body
+    };
+
+    send_response(stream, "200 OK", content_type, body.as_bytes()).await?;
+
+    Ok(true)
+}
+
+/// Prometheus text is served when the client asks for `text/plain` or
+/// OpenMetrics without also accepting JSON; JSON stays the default.
+fn wants_prometheus(headers: &str) -> bool {
+    let accept: Vec<String> = headers
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("accept"))
+        .map(|(_, value)| value.to_ascii_lowercase())
+        .collect();
+    accept
+        .iter()
+        .any(|value| value.contains("text/plain") || value.contains("openmetrics"))
+        && !accept
+            .iter()
+            .any(|value| value.contains("application/json"))
+}
+
+fn cache_metrics_json(metrics: &CacheMetrics) -> String {
+    format!(
+        "{{\"memory_hits\":{},\"memory_misses\":{},\"disk_hits\":{},\"disk_misses\":{},\"disk_evictions\":{},\"disk_evicted_bytes\":{},\"disk_bytes\":{},\"disk_response_bytes\":{},\"disk_entries\":{},\"disk_disabled\":{},\"disk_write_failures\":{},\"stale_served\":{},\"revalidations\":{},\"revalidation_304\":{},\"warm_fetches\":{},\"coalesced_misses\":{}}}",
+        metrics.memory_hits,
+        metrics.memory_misses,
+        metrics.disk_hits,
+        metrics.disk_misses,
+        metrics.disk_evictions,
+        metrics.disk_evicted_bytes,
+        metrics.disk_bytes,
+        metrics.disk_response_bytes,
+        metrics.disk_entries,
+        metrics.disk_disabled,
+        metrics.disk_write_failures,
+        metrics.stale_served,
+        metrics.revalidations,
+        metrics.revalidation_304,
+        metrics.warm_fetches,
+        metrics.coalesced_misses
+    )
+}
+
+fn cache_metrics_prometheus(metrics: &CacheMetrics) -> String {
+    let counters = [
+        ("memory_hits", "Memory cache hits.", metrics.memory_hits),
+        (
+            "memory_misses",
+            "Memory cache misses.",
             metrics.memory_misses,
-            metrics.disk_hits,
-            metrics.disk_misses,
+        ),
+        ("disk_hits", "Disk cache hits.", metrics.disk_hits),
+        ("disk_misses", "Disk cache misses.", metrics.disk_misses),
+        (
+            "disk_evictions",
+            "Disk cache entries evicted.",
             metrics.disk_evictions,
+        ),
+        (
+            "disk_evicted_bytes",
+            "Bytes evicted from the disk cache.",
             metrics.disk_evicted_bytes,
+        ),
+        (
+            "disk_write_failures",
+            "Failed disk cache writes.",
+            metrics.disk_write_failures,
+        ),
+        (
+            "stale_served",
+            "Stale responses served while revalidating or after an error.",
+            metrics.stale_served,
+        ),
+        (
+            "revalidations",
+            "Background refreshes of stale entries.",
+            metrics.revalidations,
+        ),
+        (
+            "revalidation_304",
+            "Refreshes that found the file unchanged.",
+            metrics.revalidation_304,
+        ),
+        (
+            "warm_fetches",
+            "Files loaded into the cache by HEAD requests.",
+            metrics.warm_fetches,
+        ),
+        (
+            "coalesced_misses",
+            "Cache misses that waited for a concurrent read of the same file.",
+            metrics.coalesced_misses,
+        ),
+    ];
+    let gauges = [
+        (
+            "disk_bytes",
+            "Bytes stored in the disk cache.",
             metrics.disk_bytes,
-            metrics.disk_entries
-        )
-    };
-
-    send_response(
-        stream,
-        "200 OK",
-        "application/json; charset=utf-8",
-        body.as_bytes(),
-    )
-    .await?;
+        ),
+        (
+            "disk_response_bytes",
+            "Size of the responses in the disk cache before compression.",
+            metrics.disk_response_bytes,
+        ),
+        (
+            "disk_entries",
+            "Entries in the disk cache.",
+            metrics.disk_entries,
+        ),
+        (
+            "disk_disabled",
+            "1 while disk cache writes are paused after failures.",
+            u64::from(metrics.disk_disabled),
+        ),
+    ];
 
-    Ok(true)
+    let mut out = String::new();
+    for (name, help, value) in counters {
+        let _ = write!(
+            out,
+            "# HELP migux_cache_{name}_total {help}\n# TYPE migux_cache_{name}_total counter\nmigux_cache_{name}_total {value}\n"
+        );
+    }
+    for (name, help, value) in gauges {
+        let _ = write!(
+            out,
+            "# HELP migux_cache_{name} {help}\n# TYPE migux_cache_{name} gauge\nmigux_cache_{name} {value}\n"
+        );
+    }
+    out
 }
 
 fn build_https_redirect(host: &str, path: &str, tls_listen: &str) -> String {
@@ -233,15 +563,15 @@ fn build_https_redirect(host: &str, path: &str, tls_listen: &str) -> String {
 
 fn split_host_port(host: &str) -> (String, Option<String>) {
     let host = host.trim();
-    if host.starts_with('[') {
-        if let Some(end) = host.find(']') {
-            let host_part = host[..=end].to_string();
-            let rest = &host[end + 1..];
-            if let Some(port) = rest.strip_prefix(':') {
-                return (host_part, Some(port.to_string()));
-            }
-            return (host_part, None);
+    if host.starts_with('[')
+        && let Some(end) = host.find(']')
+    {
+        let host_part = host[..=end].to_string();
+        let rest = &host[end + 1..];
+        if let Some(port) = rest.strip_prefix(':') {
+            return (host_part, Some(port.to_string()));
         }
+        return (host_part, None);
     }
 
     if let Some(idx) = host.rfind(':') {
@@ -254,3 +584,973 @@ fn split_host_port(host: &str) -> (String, Option<String>) {
 
     (host.to_string(), None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::handle_connection;
+    use crate::build_servers_by_listen;
+    use migux_config::{
+        EncodedSlashes, LocationConfig, LocationType, MiguxConfig, UpstreamConfig, UpstreamServers,
+    };
+    use migux_proxy::Proxy;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{Duration, timeout};
+    use tracing_test::traced_test;
+
+    /// Run one connection against `cfg`, send `input`, and collect everything
+    /// the server writes until it closes the connection.
+    async fn run_connection(cfg: MiguxConfig, input: &[u8]) -> String {
+        let cfg = Arc::new(cfg);
+        let servers = build_servers_by_listen(&cfg)
+            .into_values()
+            .next()
+            .expect("one listener");
+        let (mut client, server) = tokio::io::duplex(256 * 1024);
+        let task = tokio::spawn(handle_connection(
+            Box::new(server),
+            "127.0.0.1:40000".parse().expect("addr"),
+            Arc::new(servers),
+            Arc::new(Proxy::new()),
+            cfg,
+            false,
+        ));
+
+        client.write_all(input).await.expect("write");
+        let mut out = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut out))
+            .await
+            .expect("server did not close the connection")
+            .expect("read");
+        task.await.expect("join").expect("handle_connection");
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    #[tokio::test]
+    async fn cache_metrics_negotiate_prometheus_text() {
+        let json = run_connection(
+            MiguxConfig::default(),
+            b"GET /_migux/cache HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(
+            json.contains("Content-Type: application/json"),
+            "got: {json}"
+        );
+        assert!(json.contains("\"stale_served\":"), "got: {json}");
+        assert!(json.contains("\"warm_fetches\":"), "got: {json}");
+        assert!(json.contains("\"coalesced_misses\":"), "got: {json}");
+
+        let text = run_connection(
+            MiguxConfig::default(),
+            b"GET /_migux/cache HTTP/1.1\r\nHost: x\r\nAccept: text/plain\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(
+            text.contains("Content-Type: text/plain; version=0.0.4"),
+            "got: {text}"
+        );
+        assert!(text.contains("# TYPE migux_cache_stale_served_total counter\n"));
+        assert!(text.contains("\nmigux_cache_revalidation_304_total "));
+        assert!(text.contains("# TYPE migux_cache_disk_bytes gauge\n"));
+    }
+
+    /// A follow-up request that must never be answered.
+    const FOLLOW_UP: &str = "GET / HTTP/1.1\r\nHost: example\r\n\r\n";
+
+    fn assert_closed_with(out: &str, status: &str) {
+        assert!(out.starts_with(&format!("HTTP/1.1 {status}")), "got: {out}");
+        assert!(out.contains("\r\nConnection: close\r\n"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1 ").count(), 1, "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn bad_request_closes_connection() {
+        let input = format!("GET / HTTP/1.1\r\nUser-Agent: no-host\r\n\r\n{FOLLOW_UP}");
+        let out = run_connection(MiguxConfig::default(), input.as_bytes()).await;
+        assert_closed_with(&out, "400");
+    }
+
+    #[tokio::test]
+    async fn payload_too_large_closes_connection() {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.max_request_body_bytes = 4;
+        let input = format!(
+            "POST / HTTP/1.1\r\nHost: example\r\nContent-Length: 10\r\n\r\n0123456789{FOLLOW_UP}"
+        );
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "413");
+    }
+
+    #[tokio::test]
+    async fn oversized_header_line_gets_431() {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.max_header_value_bytes = 256;
+        let cookie = "c".repeat(300);
+        let input =
+            format!("GET / HTTP/1.1\r\nHost: example\r\nCookie: {cookie}\r\n\r\n{FOLLOW_UP}");
+        assert!(input.len() < 1024, "well under max_request_headers_bytes");
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "431");
+    }
+
+    #[tokio::test]
+    async fn uri_too_long_closes_connection() {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.max_request_uri_bytes = 16;
+        let input = format!(
+            "GET /{} HTTP/1.1\r\nHost: example\r\n\r\n{FOLLOW_UP}",
+            "a".repeat(32)
+        );
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "414");
+    }
+
+    #[tokio::test]
+    async fn headers_too_large_closes_connection() {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.max_request_headers_bytes = 64;
+        let input = format!(
+            "GET / HTTP/1.1\r\nHost: example\r\nX-Fill: {}\r\n\r\n{FOLLOW_UP}",
+            "a".repeat(128)
+        );
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "431");
+    }
+
+    #[tokio::test]
+    async fn request_timeout_closes_connection() {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.client_read_timeout_secs = 1;
+        let out = run_connection(cfg, b"GET / HTTP/1.1\r\nHost: exa").await;
+        assert_closed_with(&out, "408");
+    }
+
+    fn static_config(root: &std::path::Path) -> MiguxConfig {
+        let mut cfg = MiguxConfig::default();
+        for server in cfg.servers.values_mut() {
+            server.root = root.to_string_lossy().into_owned();
+        }
+        cfg
+    }
+
+    #[tokio::test]
+    async fn location_index_is_served_with_and_without_trailing_slash() {
+        let server_root = tempfile::tempdir().expect("tempdir");
+        let app_root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(server_root.path().join("index.html"), "server index").expect("write");
+        std::fs::write(app_root.path().join("index.html"), "app index").expect("write");
+        std::fs::create_dir(app_root.path().join("docs")).expect("mkdir");
+        std::fs::write(app_root.path().join("docs/index.html"), "docs index").expect("write");
+
+        let cfg = || {
+            let mut cfg = static_config(server_root.path());
+            cfg.location.insert(
+                "root".into(),
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/".into(),
+                    ..Default::default()
+                },
+            );
+            cfg.location.insert(
+                "app".into(),
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/app".into(),
+                    root: Some(app_root.path().to_string_lossy().into_owned()),
+                    ..Default::default()
+                },
+            );
+            cfg
+        };
+        for (path, body) in [
+            ("/", "server index"),
+            ("/app", "app index"),
+            ("/app/", "app index"),
+            ("/app/?v=1", "app index"),
+            ("/app/docs/", "docs index"),
+        ] {
+            let input =
+                format!("GET {path} HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n");
+            let out = run_connection(cfg(), input.as_bytes()).await;
+            assert!(out.starts_with("HTTP/1.1 200 OK"), "{path}: {out}");
+            assert!(out.ends_with(body), "{path}: {out}");
+        }
+    }
+
+    #[tokio::test]
+    async fn status_page_reports_upstreams_and_listeners() {
+        let status_cfg = || {
+            let mut cfg = MiguxConfig::default();
+            cfg.upstream.insert(
+                "app".into(),
+                UpstreamConfig {
+                    server: UpstreamServers::One("127.0.0.1:3001".into()),
+                    ..Default::default()
+                },
+            );
+            cfg
+        };
+
+        let input =
+            "GET /_migux/status HTTP/1.1\r\nHost: example\r\nAccept: application/json\r\n\r\n";
+        let out = run_connection(status_cfg(), input.as_bytes()).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.contains("Content-Type: application/json"), "got: {out}");
+        for field in [
+            "\"uptime_secs\":",
+            "\"active_connections\":",
+            "\"listeners\":[\"0.0.0.0:8080\"]",
+            "{\"upstream\":\"app\",\"addr\":\"127.0.0.1:3001\",\"healthy\":true,\"failures\":0}",
+            "\"cache\":{\"memory_hits\":",
+            "\"config_path\":null",
+        ] {
+            assert!(out.contains(field), "missing {field} in: {out}");
+        }
+
+        let input = "GET /_migux/status/ HTTP/1.1\r\nHost: example\r\n\r\n";
+        let out = run_connection(status_cfg(), input.as_bytes()).await;
+        assert!(out.contains("Content-Type: text/html"), "got: {out}");
+        assert!(out.contains("<td>app</td><td>127.0.0.1:3001</td><td>up</td>"));
+        assert!(out.contains("<li>0.0.0.0:8080</li>"));
+    }
+
+    #[tokio::test]
+    async fn unknown_host_is_served_by_default_server() {
+        let mut cfg = MiguxConfig::default();
+        let mut roots = Vec::new();
+        for (name, default) in [("alpha", false), ("beta", true)] {
+            let root = tempfile::tempdir().expect("tempdir");
+            std::fs::write(root.path().join("index.html"), name).expect("write");
+            let server = cfg.servers.entry(name.to_string()).or_default();
+            server.server_name = format!("{name}.example");
+            server.root = root.path().to_string_lossy().into_owned();
+            server.default_server = default;
+            roots.push(root);
+        }
+        cfg.servers.remove("main");
+
+        let input = "GET / HTTP/1.1\r\nHost: unknown.example\r\nConnection: close\r\n\r\n";
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert!(out.starts_with("HTTP/1.1 200"), "got: {out}");
+        assert!(out.ends_with("beta"), "got: {out}");
+    }
+
+    /// Send `first` followed by a plain GET and return the raw output.
+    async fn keep_alive_exchange(first: &str) -> String {
+        keep_alive_exchange_with(first, |_| {}).await
+    }
+
+    async fn keep_alive_exchange_with(first: &str, tweak: impl FnOnce(&mut MiguxConfig)) -> String {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hi").expect("write");
+        let mut cfg = static_config(root.path());
+        tweak(&mut cfg);
+        let input = format!("{first}GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n");
+        run_connection(cfg, input.as_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn http11_default_keeps_connection_open() {
+        let out = keep_alive_exchange("GET / HTTP/1.1\r\nHost: example\r\n\r\n").await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert_eq!(out.matches("\r\nConnection: keep-alive\r\n").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn keep_alive_response_advertises_timeout_and_max() {
+        let out = keep_alive_exchange_with("GET / HTTP/1.1\r\nHost: example\r\n\r\n", |cfg| {
+            cfg.http.keepalive_timeout_secs = 30;
+            cfg.http.keepalive_max_requests = 50;
+        })
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert_eq!(
+            out.matches("\r\nConnection: keep-alive\r\nKeep-Alive: timeout=30, max=49\r\n")
+                .count(),
+            1,
+            "got: {out}"
+        );
+        // The closing response doesn't advertise keep-alive parameters.
+        assert_eq!(out.matches("Keep-Alive:").count(), 1, "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn keep_alive_max_counts_down_per_request() {
+        let get = "GET / HTTP/1.1\r\nHost: example\r\n\r\n";
+        let out = keep_alive_exchange_with(&get.repeat(2), |cfg| {
+            cfg.http.keepalive_timeout_secs = 30;
+            cfg.http.keepalive_max_requests = 5;
+        })
+        .await;
+        let advertised: Vec<_> = out
+            .lines()
+            .filter(|line| line.starts_with("Keep-Alive:"))
+            .collect();
+        assert_eq!(
+            advertised,
+            [
+                "Keep-Alive: timeout=30, max=4",
+                "Keep-Alive: timeout=30, max=3"
+            ],
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn keepalive_max_requests_closes_the_last_allowed_request() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hi").expect("write");
+        let mut cfg = static_config(root.path());
+        cfg.http.keepalive_max_requests = 2;
+        let request = "GET / HTTP/1.1\r\nHost: example\r\n\r\n";
+        let out = run_connection(cfg, format!("{request}{request}{request}").as_bytes()).await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        let (first, last) = out.rsplit_once("HTTP/1.1 200").expect("two responses");
+        assert!(
+            first.contains("\r\nKeep-Alive: timeout=65, max=1\r\n"),
+            "got: {out}"
+        );
+        assert!(last.contains("\r\nConnection: close\r\n"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn debug_connection_header_counts_requests_on_the_connection() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hi").expect("write");
+        let mut cfg = static_config(root.path());
+        cfg.http.debug_connection_header = true;
+        cfg.http.keepalive_max_requests = 3;
+        let request = "GET / HTTP/1.1\r\nHost: example\r\n\r\n";
+        let out = run_connection(cfg, format!("{request}{request}{request}").as_bytes()).await;
+        let responses: Vec<&str> = out.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(responses.len(), 3, "got: {out}");
+        for (n, response) in responses.iter().enumerate() {
+            let expected = format!("X-Migux-Conn-Requests: {}\r\n", n + 1);
+            assert!(response.starts_with(&expected), "got: {out}");
+            assert!(response.ends_with("hi"), "body intact: {out}");
+        }
+
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hi").expect("write");
+        let request = "GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n";
+        let out = run_connection(static_config(root.path()), request.as_bytes()).await;
+        assert!(out.starts_with("HTTP/1.1 200"), "got: {out}");
+        assert!(!out.contains("X-Migux-Conn-Requests"), "off by default");
+    }
+
+    #[tokio::test]
+    async fn http11_connection_close_closes() {
+        let out =
+            keep_alive_exchange("GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n")
+                .await;
+        assert_closed_with(&out, "200");
+    }
+
+    #[tokio::test]
+    async fn http10_without_keep_alive_closes() {
+        let out = keep_alive_exchange("GET / HTTP/1.0\r\n\r\n").await;
+        assert_closed_with(&out, "200");
+    }
+
+    #[tokio::test]
+    async fn http10_keep_alive_keeps_connection_open() {
+        let out = keep_alive_exchange("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert_eq!(out.matches("\r\nConnection: keep-alive\r\n").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn chunked_post_to_static_is_drained_before_the_next_request() {
+        let out = keep_alive_exchange(
+            "POST / HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nGET \r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 405"), "got: {out}");
+        let first = out.split("\r\n\r\n").next().unwrap_or_default();
+        assert!(
+            first.contains("\r\nConnection: keep-alive\r\n"),
+            "got: {out}"
+        );
+        assert!(
+            first.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+            "got: {out}"
+        );
+        assert_eq!(out.matches("HTTP/1.1 ").count(), 2, "got: {out}");
+        assert!(out.contains("HTTP/1.1 200"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn oversized_chunked_body_to_static_is_413_and_closes() {
+        let out = keep_alive_exchange_with(
+            "POST / HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\n8\r\n01234567\r\n8\r\n89abcdef\r\n0\r\n\r\n",
+            |cfg| cfg.http.max_request_body_bytes = 10,
+        )
+        .await;
+        assert_closed_with(&out, "413");
+        assert_eq!(out.matches("HTTP/1.1 ").count(), 1, "got: {out}");
+    }
+
+    fn allow_get_body(cfg: &mut MiguxConfig) {
+        cfg.http.allow_get_body = true;
+    }
+
+    #[tokio::test]
+    async fn get_with_body_is_rejected_by_default() {
+        let out = keep_alive_exchange(
+            "GET / HTTP/1.1\r\nHost: example\r\nContent-Length: 5\r\n\r\nGET /",
+        )
+        .await;
+        assert_closed_with(&out, "400");
+
+        let out = keep_alive_exchange(
+            "HEAD / HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        )
+        .await;
+        assert_closed_with(&out, "400");
+    }
+
+    #[tokio::test]
+    async fn get_with_body_is_drained_when_allowed() {
+        let out = keep_alive_exchange_with(
+            "GET / HTTP/1.1\r\nHost: example\r\nContent-Length: 5\r\n\r\nGET /",
+            allow_get_body,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn chunked_body_on_static_get_is_drained() {
+        let out = keep_alive_exchange_with(
+            "GET / HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nGET /\r\n0\r\nX-Trailer: 1\r\n\r\n",
+            allow_get_body,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert!(!out.contains("HTTP/1.1 400"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn malformed_chunked_body_on_static_get_closes() {
+        let out = keep_alive_exchange_with(
+            "GET / HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            allow_get_body,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 ").count(), 1, "got: {out}");
+        assert_closed_with(&out, "400");
+    }
+
+    #[tokio::test]
+    async fn options_asterisk_lists_server_methods() {
+        let out = run_connection(
+            MiguxConfig::default(),
+            b"OPTIONS * HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_closed_with(&out, "200 OK");
+        assert!(
+            out.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+            "got: {out}"
+        );
+
+        let mut cfg = MiguxConfig::default();
+        cfg.location.insert(
+            "api".into(),
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+        let out = run_connection(
+            cfg,
+            b"OPTIONS * HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(
+            out.contains("\r\nAllow: GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS\r\n"),
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn options_path_lists_location_methods() {
+        let out =
+            keep_alive_exchange("OPTIONS /index.html HTTP/1.1\r\nHost: example\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(
+            out.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"),
+            "got: {out}"
+        );
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert_eq!(out.matches("\r\nConnection: keep-alive\r\n").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn options_asterisk_keeps_connection_open() {
+        let out = keep_alive_exchange("OPTIONS * HTTP/1.1\r\nHost: example\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert_eq!(out.matches("\r\nConnection: keep-alive\r\n").count(), 1);
+    }
+
+    /// The `Request complete` line for `path`, which carries every span field.
+    fn completed_request_line<'a>(lines: &[&'a str], path: &str) -> Result<&'a str, String> {
+        lines
+            .iter()
+            .copied()
+            .find(|line| {
+                line.contains(&format!("path={path} ")) && line.contains("Request complete")
+            })
+            .ok_or_else(|| format!("no completed request span for {path}"))
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn request_span_records_cache_hit() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "hi").expect("write");
+        std::fs::write(root.path().join("span-cache.html"), "cached").expect("write");
+        let mut cfg = static_config(root.path());
+        cfg.http.cache_dir = Some(cache.path().to_string_lossy().into_owned());
+        cfg.http.cache_default_ttl_secs = Some(60);
+        cfg.http.cache_max_object_bytes = Some(1024);
+
+        let request = "GET /span-cache.html HTTP/1.1\r\nHost: example\r\n\r\n";
+        let input = format!(
+            "{request}{request}GET / HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n"
+        );
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 3, "got: {out}");
+
+        logs_assert(|lines| {
+            let completed: Vec<_> = lines
+                .iter()
+                .filter(|line| {
+                    line.contains("path=/span-cache.html ") && line.contains("Request complete")
+                })
+                .collect();
+            let [miss, hit] = completed.as_slice() else {
+                return Err(format!("expected two requests, got {completed:?}"));
+            };
+            for field in ["server=\"main\"", "location=\"/\"", "status=200", "bytes="] {
+                if !miss.contains(field) || !hit.contains(field) {
+                    return Err(format!("missing {field}: {miss} / {hit}"));
+                }
+            }
+            if !miss.contains("decision=\"cache-miss\"") || !hit.contains("decision=\"cache-hit\"")
+            {
+                return Err(format!("wrong decisions: {miss} / {hit}"));
+            }
+            Ok(())
+        });
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn request_span_records_upstream() {
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let upstream_addr = upstream.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            let (mut conn, _) = upstream.accept().await.expect("accept");
+            let mut buf = [0u8; 1024];
+            let _ = conn.read(&mut buf).await;
+            let _ = conn
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await;
+        });
+
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert(
+            "app".into(),
+            UpstreamConfig {
+                server: UpstreamServers::One(upstream_addr.clone()),
+                ..Default::default()
+            },
+        );
+        cfg.location.insert(
+            "api".into(),
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+        let out = run_connection(
+            cfg,
+            b"GET /api/span-proxy HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+
+        logs_assert(|lines| {
+            let line = completed_request_line(lines, "/api/span-proxy")?;
+            for field in [
+                "server=\"main\"".to_string(),
+                "location=\"/api\"".to_string(),
+                "decision=\"proxy\"".to_string(),
+                format!("upstream=\"{upstream_addr}\""),
+                "status=200".to_string(),
+            ] {
+                if !line.contains(&field) {
+                    return Err(format!("missing {field}: {line}"));
+                }
+            }
+            Ok(())
+        });
+    }
+
+    /// A proxy location whose upstream refuses connections: anything that
+    /// reaches it comes back as 502.
+    fn dead_proxy_config() -> MiguxConfig {
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert(
+            "app".into(),
+            UpstreamConfig {
+                server: UpstreamServers::One("127.0.0.1:1".into()),
+                ..Default::default()
+            },
+        );
+        cfg.location.insert(
+            "api".into(),
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+        cfg
+    }
+
+    #[tokio::test]
+    async fn health_check_path_answers_200_whatever_the_locations_say() {
+        let config = || {
+            let mut cfg = dead_proxy_config();
+            cfg.location.get_mut("api").expect("api").path = "/".into();
+            cfg.http.health_check_path = Some("/healthz".into());
+            cfg
+        };
+
+        let out = run_connection(config(), b"GET /api HTTP/1.1\r\nHost: example\r\n\r\n").await;
+        assert!(out.starts_with("HTTP/1.1 502"), "got: {out}");
+
+        let out = run_connection(
+            config(),
+            b"GET /healthz?probe=1 HTTP/1.1\r\nHost: example\r\n\r\n",
+        )
+        .await;
+        assert_closed_with(&out, "200 OK");
+        assert!(out.ends_with("\r\n\r\nok\n"), "got: {out}");
+
+        let out =
+            run_connection(config(), b"POST /healthz HTTP/1.1\r\nHost: example\r\n\r\n").await;
+        assert_closed_with(&out, "405");
+    }
+
+    #[tokio::test]
+    async fn trace_to_proxy_location_is_rejected_by_default() {
+        for method in ["TRACE", "TRACK"] {
+            let input = format!("{method} /api/echo HTTP/1.1\r\nHost: example\r\n\r\n{FOLLOW_UP}");
+            let out = run_connection(dead_proxy_config(), input.as_bytes()).await;
+            assert_closed_with(&out, "405");
+            assert!(
+                out.contains("\r\nAllow: GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS\r\n"),
+                "got: {out}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_get_501_before_routing() {
+        for method in ["BREW", "get", "PROPFIND"] {
+            let input = format!("{method} /api/echo HTTP/1.1\r\nHost: example\r\n\r\n{FOLLOW_UP}");
+            let out = run_connection(dead_proxy_config(), input.as_bytes()).await;
+            assert_closed_with(&out, "501 Not Implemented");
+            assert!(
+                out.contains("\r\nAllow: GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS\r\n"),
+                "got: {out}"
+            );
+        }
+        // Known methods are still routed: a static location answers PUT with 405.
+        let out = run_connection(
+            MiguxConfig::default(),
+            b"PUT / HTTP/1.1\r\nHost: example\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 405"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn allow_trace_forwards_trace() {
+        let mut cfg = dead_proxy_config();
+        cfg.http.allow_trace = true;
+        let out = run_connection(
+            cfg,
+            b"TRACE /api/echo HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 502"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_serves_fallback_static() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let page = dir.path().join("maintenance.html");
+        std::fs::write(&page, "back soon").expect("write");
+        let fallback = |status: Option<u16>, path: &std::path::Path| {
+            let mut cfg = dead_proxy_config();
+            let location = cfg.location.get_mut("api").expect("api location");
+            location.fallback_static = Some(path.to_string_lossy().into_owned());
+            location.fallback_status = status;
+            cfg
+        };
+        let input = "GET /api/status HTTP/1.1\r\nHost: example\r\n\r\n";
+
+        let out = run_connection(fallback(None, &page), input.as_bytes()).await;
+        assert_closed_with(&out, "503 Service Unavailable");
+        assert!(
+            out.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"),
+            "got: {out}"
+        );
+        assert!(out.ends_with("\r\n\r\nback soon"), "got: {out}");
+
+        let out = run_connection(fallback(Some(500), &page), input.as_bytes()).await;
+        assert_closed_with(&out, "500 Internal Server Error");
+
+        let missing = dir.path().join("missing.html");
+        let out = run_connection(fallback(None, &missing), input.as_bytes()).await;
+        assert_closed_with(&out, "502");
+
+        // A timed-out upstream keeps its 504 when the page is missing too.
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let mut cfg = fallback(None, &missing);
+        cfg.upstream.get_mut("app").expect("app").server =
+            UpstreamServers::One(silent.local_addr().expect("addr").to_string());
+        cfg.location
+            .get_mut("api")
+            .expect("api")
+            .proxy_read_timeout_secs = Some(1);
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "504");
+    }
+
+    fn method_override(cfg: &mut MiguxConfig) {
+        cfg.http.method_override = true;
+    }
+
+    #[tokio::test]
+    async fn method_override_routes_post_as_the_overridden_method() {
+        let out = keep_alive_exchange_with(
+            "POST /?_method=GET HTTP/1.1\r\nHost: example\r\nContent-Length: 0\r\n\r\n",
+            method_override,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+
+        let out = keep_alive_exchange_with(
+            "POST / HTTP/1.1\r\nHost: example\r\nX-HTTP-Method-Override: head\r\n\r\n",
+            method_override,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert!(
+            !out.contains("\r\n\r\nhiHTTP/1.1"),
+            "HEAD sent a body: {out}"
+        );
+
+        // Without method_override it is a plain POST: 405, connection kept.
+        let out = keep_alive_exchange(
+            "POST /?_method=GET HTTP/1.1\r\nHost: example\r\nContent-Length: 0\r\n\r\n",
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 405"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 1, "got: {out}");
+
+        let out = keep_alive_exchange_with(
+            "POST / HTTP/1.1\r\nHost: example\r\nX-HTTP-Method-Override: TRACE\r\n\r\n",
+            method_override,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 405"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 1, "got: {out}");
+
+        let out = keep_alive_exchange_with(
+            "PUT /?_method=GET HTTP/1.1\r\nHost: example\r\nContent-Length: 0\r\n\r\n",
+            method_override,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 405"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 1, "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn overridden_post_keeps_its_body() {
+        let out = keep_alive_exchange_with(
+            "POST / HTTP/1.1\r\nHost: example\r\nX-HTTP-Method-Override: GET\r\nContent-Length: 5\r\n\r\nGET /",
+            method_override,
+        )
+        .await;
+        assert_eq!(out.matches("HTTP/1.1 200").count(), 2, "got: {out}");
+        assert!(!out.contains("HTTP/1.1 400"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn truncated_upstream_response_is_not_followed_by_pipelined_request() {
+        let upstream_addr =
+            spawn_upstream(|_| "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n12345".into()).await;
+        let mut cfg = dead_proxy_config();
+        cfg.upstream.get_mut("app").expect("app upstream").server =
+            UpstreamServers::One(upstream_addr);
+
+        let request = "GET /api/x HTTP/1.1\r\nHost: example\r\n\r\n";
+        let out = run_connection(cfg, format!("{request}{request}").as_bytes()).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert_eq!(out.matches("HTTP/1.1").count(), 1, "got: {out}");
+    }
+
+    /// Upstream that answers each connection with `reply(first read)`;
+    /// returns its address.
+    async fn spawn_upstream(reply: fn(&str) -> String) -> String {
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let upstream_addr = upstream.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = upstream.accept().await {
+                let mut buf = [0u8; 1024];
+                let n = conn.read(&mut buf).await.unwrap_or(0);
+                let response = reply(&String::from_utf8_lossy(&buf[..n]));
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        upstream_addr
+    }
+
+    /// Request line the upstream receives when `request` is proxied through
+    /// `cfg` (its `app` upstream is pointed at a one-shot echo server).
+    async fn upstream_request_line(mut cfg: MiguxConfig, request: &[u8]) -> String {
+        let upstream_addr = spawn_upstream(|head| {
+            let line = head.lines().next().unwrap_or("");
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{line}",
+                line.len()
+            )
+        })
+        .await;
+
+        cfg.upstream.get_mut("app").expect("app upstream").server =
+            UpstreamServers::One(upstream_addr);
+        let out = run_connection(cfg, request).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        out.rsplit("\r\n\r\n").next().unwrap_or("").to_string()
+    }
+
+    /// Method line the upstream of a proxied `POST /api/items?_method=DELETE`
+    /// receives.
+    async fn upstream_method_for_override(forward_original: bool) -> String {
+        let mut cfg = dead_proxy_config();
+        cfg.http.method_override = true;
+        cfg.http.method_override_forward_original = forward_original;
+        let line = upstream_request_line(
+            cfg,
+            b"POST /api/items?_method=DELETE HTTP/1.1\r\nHost: example\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        line.split(' ').next().unwrap_or("").to_string()
+    }
+
+    #[tokio::test]
+    async fn method_override_choice_of_upstream_method() {
+        assert_eq!(upstream_method_for_override(false).await, "DELETE");
+        assert_eq!(upstream_method_for_override(true).await, "POST");
+    }
+
+    #[tokio::test]
+    async fn encoded_slashes_reach_the_upstream_as_sent() {
+        let line = upstream_request_line(
+            dead_proxy_config(),
+            b"GET /api/files/a%2Fb%2fc?x=%2F HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(line, "GET /files/a%2Fb%2fc?x=%2F HTTP/1.1");
+
+        // Routing uses the normalized path; the raw tail is still forwarded.
+        let line = upstream_request_line(
+            dead_proxy_config(),
+            b"GET /%61pi/a%2Fb HTTP/1.1\r\nHost: example\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(line, "GET /a%2Fb HTTP/1.1");
+
+        let mut cfg = dead_proxy_config();
+        cfg.http.encoded_slashes = EncodedSlashes::Reject;
+        let input = format!("GET /api/a%2Fb HTTP/1.1\r\nHost: example\r\n\r\n{FOLLOW_UP}");
+        let out = run_connection(cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "404");
+    }
+
+    #[tokio::test]
+    async fn body_cap_follows_the_matched_location() {
+        let cfg = || {
+            let mut cfg = dead_proxy_config();
+            cfg.http.max_request_body_bytes = 4;
+            cfg.location.get_mut("api").expect("api").max_body_bytes = Some(64);
+            cfg.location.insert(
+                "root".into(),
+                LocationConfig {
+                    server: "main".into(),
+                    path: "/".into(),
+                    ..Default::default()
+                },
+            );
+            cfg
+        };
+
+        let line = upstream_request_line(
+            cfg(),
+            b"POST /api/upload HTTP/1.1\r\nHost: example\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789",
+        )
+        .await;
+        assert_eq!(line, "POST /upload HTTP/1.1");
+
+        let input = format!(
+            "POST /api/upload HTTP/1.1\r\nHost: example\r\nContent-Length: 65\r\n\r\n{}{FOLLOW_UP}",
+            "a".repeat(65)
+        );
+        let out = run_connection(cfg(), input.as_bytes()).await;
+        assert_closed_with(&out, "413");
+
+        let input = format!(
+            "POST /other HTTP/1.1\r\nHost: example\r\nContent-Length: 10\r\n\r\n0123456789{FOLLOW_UP}"
+        );
+        let out = run_connection(cfg(), input.as_bytes()).await;
+        assert_closed_with(&out, "413");
+
+        // A chunked body is only caught while it is streamed upstream.
+        let upstream_addr =
+            spawn_upstream(|_| "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".into()).await;
+        let mut chunked_cfg = cfg();
+        chunked_cfg.upstream.get_mut("app").expect("app").server =
+            UpstreamServers::One(upstream_addr);
+        let input = format!(
+            "POST /api/upload HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\n\r\n41\r\n{}\r\n0\r\n\r\n{FOLLOW_UP}",
+            "a".repeat(65)
+        );
+        let out = run_connection(chunked_cfg, input.as_bytes()).await;
+        assert_closed_with(&out, "413");
+    }
+}
@@ -1,6 +1,8 @@
 use bytes::BytesMut;
-use migux_config::HttpConfig;
-use migux_http::responses::{send_400, send_408, send_413, send_431};
+use migux_config::{EncodedSlashes, HttpConfig};
+use migux_http::content_length::parse_content_length;
+use migux_http::responses::{send_400, send_408, send_414, send_431};
+use migux_http::target::normalize_path;
 use tokio::time::Duration;
 use tracing::{debug, instrument, warn};
 
@@ -15,15 +17,32 @@ use super::timeouts::{ReadOutcome, read_more};
 #[derive(Debug)]
 pub(crate) struct ParsedRequest {
     pub(crate) headers: String,
+    /// Effective method: the request line's, or the `method_override` one.
     pub(crate) method: String,
+    /// Request-line method when `method_override` replaced it.
+    pub(crate) original_method: Option<String>,
+    /// Request target exactly as sent; forwarded to upstreams.
     pub(crate) path: String,
+    /// `path` normalized for location matching and static files
+    /// (`migux_http::target`).
+    pub(crate) route_path: String,
     pub(crate) http_version: String,
     pub(crate) content_length: usize,
     pub(crate) is_chunked: bool,
     pub(crate) close_after: bool,
+    /// Requests this connection may still carry after this one; set by the
+    /// connection loop from `keepalive_max_requests`.
+    pub(crate) requests_left: u64,
     pub(crate) body_start: usize,
 }
 
+/// Read the next request from `stream`.
+///
+/// `Ok(None)` means the connection must be closed: either the client went
+/// away / idled out, or an error response (400/408/414/431, all with
+/// `Connection: close`) was sent and the remaining bytes can no longer be
+/// framed. The body cap depends on the location, so 413 is left to the
+/// caller.
 #[instrument(skip(stream, buf, http), fields())]
 pub(crate) async fn read_http_request(
     stream: &mut dyn ClientStream,
@@ -33,10 +52,15 @@ pub(crate) async fn read_http_request(
 ) -> anyhow::Result<Option<ParsedRequest>> {
     let read_timeout = Duration::from_secs(http.client_read_timeout_secs);
     let max_headers = http.max_request_headers_bytes as usize;
-    let max_body = http.max_request_body_bytes as usize;
+    let max_uri = http.max_request_uri_bytes as usize;
 
     let headers_end = loop {
         if let Some(pos) = find_headers_end(buf) {
+            // The whole head may arrive in one read; enforce the cap here too.
+            if max_headers > 0 && pos > max_headers {
+                send_431(stream).await?;
+                return Ok(None);
+            }
             break pos;
         }
 
@@ -50,7 +74,7 @@ pub(crate) async fn read_http_request(
         } else {
             read_timeout
         };
-        match read_more(stream, buf, timeout_dur).await? {
+        match read_more(stream, buf, timeout_dur, http.client_buffer_size()).await? {
             ReadOutcome::Timeout => {
                 if buf.is_empty() {
                     return Ok(None);
@@ -72,6 +96,19 @@ pub(crate) async fn read_http_request(
         "Parsed HTTP headers"
     );
 
+    let max_line = http.max_header_value_bytes() as usize;
+    if let Some((name, len)) = oversized_header_line(&headers_str, max_line) {
+        warn!(
+            target: "migux::http",
+            header = %name,
+            line_len = len,
+            max_line,
+            "Request header line too large"
+        );
+        send_431(stream).await?;
+        return Ok(None);
+    }
+
     let meta = match parse_request_metadata(&headers_str) {
         Ok(meta) => meta,
         Err(err) => {
@@ -85,8 +122,19 @@ pub(crate) async fn read_http_request(
         }
     };
 
+    if max_uri > 0 && meta.path.len() > max_uri {
+        warn!(
+            target: "migux::http",
+            uri_len = meta.path.len(),
+            max_uri,
+            "Request URI too long"
+        );
+        send_414(stream).await?;
+        return Ok(None);
+    }
+
     let RequestMetadata {
-        method,
+        mut method,
         path,
         http_version,
         mut content_length,
@@ -94,6 +142,35 @@ pub(crate) async fn read_http_request(
         is_chunked,
     } = meta;
 
+    // On the request-line method: a POST overridden to GET keeps its body.
+    if !http.allow_get_body
+        && (method == "GET" || method == "HEAD")
+        && (is_chunked || content_length > 0)
+    {
+        warn!(
+            target: "migux::http",
+            %method,
+            content_length,
+            is_chunked,
+            "Rejecting request body on GET/HEAD (allow_get_body = false)"
+        );
+        send_400(stream).await?;
+        return Ok(None);
+    }
+
+    let mut original_method = None;
+    if http.method_override()
+        && method == "POST"
+        && let Some(overridden) = method_override(&headers_str, &path)
+    {
+        debug!(
+            target: "migux::http",
+            method = overridden,
+            "Applying method override to POST"
+        );
+        original_method = Some(std::mem::replace(&mut method, overridden.to_string()));
+    }
+
     if is_chunked && content_length > 0 {
         warn!(
             target: "migux::http",
@@ -104,10 +181,6 @@ pub(crate) async fn read_http_request(
     }
 
     if !is_chunked && content_length > 0 {
-        if max_body > 0 && content_length > max_body {
-            send_413(stream).await?;
-            return Ok(None);
-        }
         debug!(
             target: "migux::http",
             content_length,
@@ -118,15 +191,42 @@ pub(crate) async fn read_http_request(
     Ok(Some(ParsedRequest {
         headers: headers_str,
         method,
+        original_method,
+        route_path: normalize_path(&path, http.encoded_slashes() == EncodedSlashes::Decode),
         path,
         http_version,
         content_length,
         is_chunked,
         close_after,
+        requests_left: 0,
         body_start: headers_end + 4,
     }))
 }
 
+/// Methods a POST may be overridden to; never TRACE/CONNECT or POST itself.
+const OVERRIDABLE_METHODS: [&str; 5] = ["GET", "HEAD", "PUT", "PATCH", "DELETE"];
+
+/// Method requested by `X-HTTP-Method-Override`, or failing that by a
+/// `_method` query parameter. Values outside [`OVERRIDABLE_METHODS`] are
+/// ignored.
+fn method_override(headers: &str, path: &str) -> Option<&'static str> {
+    let header = headers.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("x-http-method-override")
+            .then(|| value.trim())
+    });
+    let query = path.split_once('?').and_then(|(_, query)| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("_method="))
+    });
+    let requested = header.or(query)?;
+    OVERRIDABLE_METHODS
+        .into_iter()
+        .find(|method| method.eq_ignore_ascii_case(requested))
+}
+
 pub(crate) fn extract_host_header(headers: &str) -> Option<String> {
     for line in headers.lines().skip(1) {
         let line = line.trim();
@@ -169,6 +269,8 @@ enum HeaderParseError {
     MissingHost,
     InvalidTransferEncoding,
     TransferEncodingContentLength,
+    /// `#` in the request target; fragments are never sent (RFC 9112 3.2).
+    FragmentInTarget,
 }
 
 #[derive(Default)]
@@ -187,8 +289,8 @@ impl ContentLengthState {
                 continue;
             }
             any = true;
-            match trimmed.parse::<usize>() {
-                Ok(len) => {
+            match parse_content_length(trimmed) {
+                Some(len) => {
                     if let Some(prev) = self.value {
                         if prev != len {
                             self.conflict = true;
@@ -198,7 +300,7 @@ impl ContentLengthState {
                         self.value = Some(len);
                     }
                 }
-                Err(_) => {
+                None => {
                     self.invalid = true;
                 }
             }
@@ -224,6 +326,22 @@ fn split_header_tokens(value: &str) -> impl Iterator<Item = String> + '_ {
     })
 }
 
+/// The first header line (name, colon and value) longer than `max_line`
+/// bytes, as its name and length; `max_line = 0` checks nothing. The
+/// request line is bounded by `max_request_uri_bytes` instead.
+fn oversized_header_line(headers: &str, max_line: usize) -> Option<(&str, usize)> {
+    if max_line == 0 {
+        return None;
+    }
+    headers.lines().skip(1).find_map(|line| {
+        let line = line.trim_end();
+        (line.len() > max_line).then(|| {
+            let name = line.split_once(':').map_or("", |(name, _)| name.trim());
+            (name, line.len())
+        })
+    })
+}
+
 fn parse_request_metadata(headers: &str) -> Result<RequestMetadata, HeaderParseError> {
     let mut lines = headers.lines();
     let request_line = lines.next().unwrap_or("");
@@ -231,6 +349,9 @@ fn parse_request_metadata(headers: &str) -> Result<RequestMetadata, HeaderParseE
     let method = parts.next().unwrap_or("-").to_string();
     let path = parts.next().unwrap_or("/").to_string();
     let http_version = parts.next().unwrap_or("HTTP/1.1").to_string();
+    if path.contains('#') {
+        return Err(HeaderParseError::FragmentInTarget);
+    }
 
     let mut content_length = ContentLengthState::default();
     let mut connection_close = false;
@@ -306,10 +427,10 @@ fn parse_request_metadata(headers: &str) -> Result<RequestMetadata, HeaderParseE
         if !is_valid_host(host) {
             return Err(HeaderParseError::InvalidHost);
         }
-    } else if let Some(host) = host_value.as_deref() {
-        if !is_valid_host(host) {
-            return Err(HeaderParseError::InvalidHost);
-        }
+    } else if let Some(host) = host_value.as_deref()
+        && !is_valid_host(host)
+    {
+        return Err(HeaderParseError::InvalidHost);
     }
 
     if content_length.invalid {
@@ -377,7 +498,7 @@ fn is_valid_token(token: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::{HeaderParseError, parse_request_metadata};
+    use super::{HeaderParseError, method_override, oversized_header_line, parse_request_metadata};
 
     #[test]
     fn parse_request_metadata_accepts_duplicate_content_length() {
@@ -386,6 +507,35 @@ mod tests {
         assert_eq!(meta.content_length, 5);
     }
 
+    #[test]
+    fn parse_request_metadata_rejects_fragment_in_target() {
+        for target in ["/page#section", "/search?q=1#top", "/#"] {
+            let headers = format!("GET {target} HTTP/1.1\r\nHost: example\r\n\r\n");
+            let err = parse_request_metadata(&headers).unwrap_err();
+            assert!(
+                matches!(err, HeaderParseError::FragmentInTarget),
+                "{target}"
+            );
+        }
+        let headers = "GET /search?q=%23tag HTTP/1.1\r\nHost: example\r\n\r\n";
+        assert!(parse_request_metadata(headers).is_ok());
+    }
+
+    #[test]
+    fn oversized_header_line_is_found_under_a_small_total() {
+        let cookie = format!("Cookie: {}", "a".repeat(200));
+        let headers = format!("GET / HTTP/1.1\r\nHost: example\r\n{cookie}\r\nAccept: */*");
+        assert_eq!(
+            oversized_header_line(&headers, 128),
+            Some(("Cookie", cookie.len()))
+        );
+        assert_eq!(oversized_header_line(&headers, cookie.len()), None);
+        assert_eq!(oversized_header_line(&headers, 0), None);
+
+        let long_target = format!("GET /{} HTTP/1.1\r\nHost: example", "x".repeat(300));
+        assert_eq!(oversized_header_line(&long_target, 128), None);
+    }
+
     #[test]
     fn parse_request_metadata_rejects_conflicting_content_length() {
         let headers = "POST /upload HTTP/1.1\r\nHost: example\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n";
@@ -400,6 +550,23 @@ mod tests {
         assert!(matches!(err, HeaderParseError::InvalidContentLength));
     }
 
+    #[test]
+    fn parse_request_metadata_rejects_oversized_content_length() {
+        for len in [
+            "99999999999999999999",
+            "18446744073709551615",
+            "9223372036854775808",
+        ] {
+            let headers =
+                format!("POST /upload HTTP/1.1\r\nHost: example\r\nContent-Length: {len}\r\n\r\n");
+            let err = parse_request_metadata(&headers).unwrap_err();
+            assert!(
+                matches!(err, HeaderParseError::InvalidContentLength),
+                "{len}"
+            );
+        }
+    }
+
     #[test]
     fn parse_request_metadata_connection_tokens() {
         let headers =
@@ -424,4 +591,26 @@ mod tests {
         let err = parse_request_metadata(headers).unwrap_err();
         assert!(matches!(err, HeaderParseError::MissingHost));
     }
+
+    #[test]
+    fn method_override_reads_header_then_query() {
+        let headers =
+            "POST /items/1 HTTP/1.1\r\nHost: example\r\nX-HTTP-Method-Override: delete\r\n";
+        assert_eq!(method_override(headers, "/items/1"), Some("DELETE"));
+        assert_eq!(
+            method_override(headers, "/items/1?_method=PUT"),
+            Some("DELETE")
+        );
+
+        let headers = "POST /items/1 HTTP/1.1\r\nHost: example\r\n";
+        assert_eq!(
+            method_override(headers, "/items/1?a=1&_method=PATCH"),
+            Some("PATCH")
+        );
+        assert_eq!(method_override(headers, "/items/1"), None);
+        for unsafe_method in ["TRACE", "CONNECT", "POST", "BREW"] {
+            let path = format!("/items/1?_method={unsafe_method}");
+            assert_eq!(method_override(headers, &path), None, "{unsafe_method}");
+        }
+    }
 }
@@ -3,21 +3,87 @@ use tracing::debug;
 
 use crate::ServerRuntime;
 
-/// For now, select the first server bound to this listen address.
+/// For now, select the first server bound to this listen address: the one
+/// marked `default_server`, otherwise the first by name (see `sort_servers`).
 /// In the future this could implement name-based or SNI-based selection.
-pub fn select_default_server<'a>(servers: &'a [ServerRuntime]) -> &'a ServerRuntime {
+pub fn select_default_server(servers: &[ServerRuntime]) -> &ServerRuntime {
     // Assumes there is at least one server per listen group.
     &servers[0]
 }
 
+/// Byte trie over a server's location paths, built once at startup.
+///
+/// A lookup walks the request path once and remembers the deepest node that
+/// ends a location path, so matching costs O(path length) however many
+/// locations the server has. Paths are matched byte-wise like `starts_with`.
+#[derive(Debug, Clone)]
+pub struct LocationRouter {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    /// Child node per next byte, sorted by byte.
+    children: Vec<(u8, usize)>,
+    /// Location whose path ends here.
+    location: Option<usize>,
+}
+
+impl TrieNode {
+    fn child(&self, byte: u8) -> Option<usize> {
+        self.children
+            .binary_search_by_key(&byte, |(b, _)| *b)
+            .ok()
+            .map(|pos| self.children[pos].1)
+    }
+}
+
+impl LocationRouter {
+    pub fn new(locations: &[LocationConfig]) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for (idx, loc) in locations.iter().enumerate() {
+            let mut node = 0;
+            for &byte in loc.path.as_bytes() {
+                node = match nodes[node]
+                    .children
+                    .binary_search_by_key(&byte, |(b, _)| *b)
+                {
+                    Ok(pos) => nodes[node].children[pos].1,
+                    Err(pos) => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.insert(pos, (byte, child));
+                        child
+                    }
+                };
+            }
+            // Duplicate paths: the earliest location wins.
+            nodes[node].location.get_or_insert(idx);
+        }
+        Self { nodes }
+    }
+
+    /// Index of the location with the longest `path` prefixing `path`.
+    pub fn find(&self, path: &str) -> Option<usize> {
+        let mut node = &self.nodes[0];
+        let mut found = node.location;
+        for &byte in path.as_bytes() {
+            let Some(next) = node.child(byte) else {
+                break;
+            };
+            node = &self.nodes[next];
+            found = node.location.or(found);
+        }
+        found
+    }
+}
+
 /// Selects the `location` whose `path` is the longest prefix of the request path.
+/// Ties go to the earliest location (section name order, see `server_locations`).
 /// If no match is found, falls back to the first location.
-pub fn match_location<'a>(locations: &'a [LocationConfig], path: &str) -> &'a LocationConfig {
-    let loc = locations
-        .iter()
-        .filter(|loc| path.starts_with(&loc.path))
-        .max_by_key(|loc| loc.path.len())
-        .unwrap_or(&locations[0]);
+pub fn match_location<'a>(server: &'a ServerRuntime, path: &str) -> &'a LocationConfig {
+    let idx = server.router.find(path).unwrap_or(0);
+    let loc = &server.locations[idx];
 
     debug!(
         target: "migux::router",
@@ -28,3 +94,41 @@ pub fn match_location<'a>(locations: &'a [LocationConfig], path: &str) -> &'a Lo
 
     loc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LocationRouter;
+    use migux_config::LocationConfig;
+
+    fn locations(paths: &[&str]) -> Vec<LocationConfig> {
+        paths
+            .iter()
+            .map(|path| LocationConfig {
+                path: path.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let locs = locations(&["/", "/api", "/api/v2", "/static"]);
+        let router = LocationRouter::new(&locs);
+        assert_eq!(router.find("/api/v2/users"), Some(2));
+        assert_eq!(router.find("/api/v1/users"), Some(1));
+        assert_eq!(router.find("/apix"), Some(1));
+        assert_eq!(router.find("/static/app.js"), Some(3));
+        assert_eq!(router.find("/other"), Some(0));
+    }
+
+    #[test]
+    fn misses_and_duplicates() {
+        let locs = locations(&["/docs", "/docs", "/ñ"]);
+        let router = LocationRouter::new(&locs);
+        assert_eq!(router.find("/"), None);
+        assert_eq!(router.find("/docs/a"), Some(0));
+        assert_eq!(router.find("/ña"), Some(2));
+        assert_eq!(router.find("/ñ"), Some(2));
+        assert_eq!(router.find("/n"), None);
+    }
+}
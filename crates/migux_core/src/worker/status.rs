@@ -0,0 +1,241 @@
+//! Operator status page (`/_migux/status`), loopback-only like the cache
+//! metrics endpoint.
+//!
+//! Answers HTML by default and JSON when the client `Accept`s
+//! `application/json`. The status is 503 when some upstream has no healthy
+//! address left, so the page doubles as a load-balancer check.
+
+use std::{fmt::Write, net::SocketAddr};
+
+use migux_config::MiguxConfig;
+use migux_http::responses::{send_404, send_405_with_allow, send_response};
+use migux_proxy::{Proxy, UpstreamNodeStatus};
+use migux_static::{CacheMetrics, cache_metrics_snapshot};
+
+use super::{ClientStream, request::ParsedRequest};
+use crate::stats;
+
+pub(super) const STATUS_PATH: &str = "/_migux/status";
+
+/// Everything shown on the status page.
+struct StatusSnapshot {
+    uptime_secs: u64,
+    active_connections: usize,
+    requests: u64,
+    bytes_sent: u64,
+    listeners: Vec<String>,
+    upstreams: Vec<UpstreamNodeStatus>,
+    cache: CacheMetrics,
+    config_path: Option<String>,
+}
+
+impl StatusSnapshot {
+    async fn collect(proxy: &Proxy, cfg: &MiguxConfig) -> Self {
+        let mut listeners: Vec<String> = cfg
+            .servers
+            .values()
+            .flat_map(|server| {
+                let tls = server
+                    .tls
+                    .as_ref()
+                    .map(|tls| format!("{} (tls)", tls.listen));
+                std::iter::once(server.listen.clone()).chain(tls)
+            })
+            .collect();
+        listeners.sort();
+        listeners.dedup();
+
+        Self {
+            uptime_secs: stats::uptime().as_secs(),
+            active_connections: stats::active_connections(),
+            requests: stats::requests_total(),
+            bytes_sent: stats::bytes_sent_total(),
+            listeners,
+            upstreams: proxy.upstream_status(cfg),
+            cache: cache_metrics_snapshot().await,
+            config_path: cfg.source_path().map(str::to_string),
+        }
+    }
+
+    /// An upstream with configured addresses but none healthy.
+    fn degraded(&self) -> bool {
+        self.upstreams.iter().any(|node| {
+            !self
+                .upstreams
+                .iter()
+                .any(|other| other.upstream == node.upstream && other.healthy)
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let listeners: Vec<String> = self.listeners.iter().map(|l| json_str(l)).collect();
+        let upstreams: Vec<String> = self
+            .upstreams
+            .iter()
+            .map(|node| {
+                format!(
+                    "{{\"upstream\":{},\"addr\":{},\"healthy\":{},\"failures\":{}}}",
+                    json_str(&node.upstream),
+                    json_str(&node.addr),
+                    node.healthy,
+                    node.failures
+                )
+            })
+            .collect();
+        let config_path = self
+            .config_path
+            .as_deref()
+            .map(json_str)
+            .unwrap_or_else(|| "null".into());
+        format!(
+            "{{\"uptime_secs\":{},\"active_connections\":{},\"requests\":{},\"bytes_sent\":{},\"listeners\":[{}],\"upstreams\":[{}],\"cache\":{{\"memory_hits\":{},\"memory_misses\":{},\"disk_hits\":{},\"disk_misses\":{},\"disk_bytes\":{},\"disk_entries\":{},\"disk_disabled\":{},\"disk_write_failures\":{}}},\"config_path\":{}}}",
+            self.uptime_secs,
+            self.active_connections,
+            self.requests,
+            self.bytes_sent,
+            listeners.join(","),
+            upstreams.join(","),
+            self.cache.memory_hits,
+            self.cache.memory_misses,
+            self.cache.disk_hits,
+            self.cache.disk_misses,
+            self.cache.disk_bytes,
+            self.cache.disk_entries,
+            self.cache.disk_disabled,
+            self.cache.disk_write_failures,
+            config_path
+        )
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html><head><title>migux status</title></head><body>\n<h1>migux status</h1>\n<ul>\n",
+        );
+        let _ = writeln!(out, "<li>Uptime: {}s</li>", self.uptime_secs);
+        let _ = writeln!(
+            out,
+            "<li>Active connections: {}</li>",
+            self.active_connections
+        );
+        let _ = writeln!(
+            out,
+            "<li>Requests: {} ({} bytes sent)</li>",
+            self.requests, self.bytes_sent
+        );
+        let _ = writeln!(
+            out,
+            "<li>Config file: {}</li>",
+            html_escape(self.config_path.as_deref().unwrap_or("(built-in defaults)"))
+        );
+        out.push_str("</ul>\n<h2>Listeners</h2>\n<ul>\n");
+        for listen in &self.listeners {
+            let _ = writeln!(out, "<li>{}</li>", html_escape(listen));
+        }
+        out.push_str("</ul>\n<h2>Upstreams</h2>\n<table>\n<tr><th>Upstream</th><th>Address</th><th>State</th><th>Failures</th></tr>\n");
+        for node in &self.upstreams {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(&node.upstream),
+                html_escape(&node.addr),
+                if node.healthy { "up" } else { "down" },
+                node.failures
+            );
+        }
+        let _ = write!(
+            out,
+            "</table>\n<h2>Cache</h2>\n<ul>\n<li>Memory hits/misses: {}/{}</li>\n<li>Disk hits/misses: {}/{}</li>\n<li>Disk usage: {} bytes in {} entries</li>\n<li>Disk writes: {} ({} failures)</li>\n</ul>\n</body></html>\n",
+            self.cache.memory_hits,
+            self.cache.memory_misses,
+            self.cache.disk_hits,
+            self.cache.disk_misses,
+            self.cache.disk_bytes,
+            self.cache.disk_entries,
+            if self.cache.disk_disabled {
+                "paused"
+            } else {
+                "enabled"
+            },
+            self.cache.disk_write_failures
+        );
+        out
+    }
+}
+
+/// Serve the status page if `req` targets it; returns `true` when handled.
+pub(super) async fn maybe_handle_status(
+    stream: &mut dyn ClientStream,
+    req: &ParsedRequest,
+    path: &str,
+    client_addr: SocketAddr,
+    proxy: &Proxy,
+    cfg: &MiguxConfig,
+) -> anyhow::Result<bool> {
+    if path != STATUS_PATH {
+        return Ok(false);
+    }
+
+    if !client_addr.ip().is_loopback() {
+        send_404(stream).await?;
+        return Ok(true);
+    }
+
+    if req.method != "GET" && req.method != "HEAD" {
+        send_405_with_allow(stream, "GET, HEAD").await?;
+        return Ok(true);
+    }
+
+    let snapshot = StatusSnapshot::collect(proxy, cfg).await;
+    let status = if snapshot.degraded() {
+        "503 Service Unavailable"
+    } else {
+        "200 OK"
+    };
+    let (content_type, body) = if wants_json(&req.headers) {
+        ("application/json; charset=utf-8", snapshot.to_json())
+    } else {
+        ("text/html; charset=utf-8", snapshot.to_html())
+    };
+    let body = if req.method == "HEAD" {
+        String::new()
+    } else {
+        body
+    };
+
+    send_response(stream, status, content_type, body.as_bytes()).await?;
+    Ok(true)
+}
+
+fn wants_json(headers: &str) -> bool {
+    headers.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("accept")
+                && value.to_ascii_lowercase().contains("application/json")
+        })
+    })
+}
+
+fn json_str(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,4 +1,4 @@
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use tokio::io::AsyncReadExt;
 use tokio::time::{Duration, timeout};
 
@@ -9,24 +9,39 @@ pub(crate) enum ReadOutcome {
     Timeout,
 }
 
+/// Read up to `buffer_size` bytes (`http.client_buffer_size`) into `buf`.
 pub(crate) async fn read_more(
     stream: &mut dyn ClientStream,
     buf: &mut BytesMut,
     timeout_dur: Duration,
+    buffer_size: usize,
 ) -> anyhow::Result<ReadOutcome> {
-    let mut tmp = [0u8; 4096];
-    match timeout(timeout_dur, stream.read(&mut tmp)).await {
-        Ok(res) => {
-            let n = res?;
-            if n > 0 {
-                buf.extend_from_slice(&tmp[..n]);
-            }
-            Ok(ReadOutcome::Read(n))
-        }
+    buf.reserve(buffer_size);
+    let mut spare = (&mut *buf).limit(buffer_size);
+    match timeout(timeout_dur, stream.read_buf(&mut spare)).await {
+        Ok(res) => Ok(ReadOutcome::Read(res?)),
         Err(_) => Ok(ReadOutcome::Timeout),
     }
 }
 
+/// Buffer size a connection may keep between requests; a buffer that grew
+/// past this (big head, pipelined burst) is reallocated once drained.
+pub(crate) const CLIENT_BUF_RETAIN_BYTES: usize = 16 * 1024;
+
+/// Release `buf`'s allocation after a request that buffered `peak` bytes at
+/// once, keeping any pipelined bytes that are still pending.
+///
+/// `BytesMut::capacity` only counts space after the read position, so the
+/// allocation size is judged from `peak` rather than from `buf` itself.
+pub(crate) fn reclaim_client_buf(buf: &mut BytesMut, peak: usize) {
+    if peak <= CLIENT_BUF_RETAIN_BYTES || buf.len() > CLIENT_BUF_RETAIN_BYTES {
+        return;
+    }
+    let mut fresh = BytesMut::with_capacity(buf.len().max(4096));
+    fresh.extend_from_slice(buf);
+    *buf = fresh;
+}
+
 pub(crate) enum ChunkedBodyError {
     Timeout,
     Invalid,
@@ -38,12 +53,13 @@ pub(crate) async fn discard_chunked_body(
     stream: &mut dyn ClientStream,
     buf: &mut BytesMut,
     read_timeout: Duration,
+    buffer_size: usize,
     max_body: usize,
 ) -> Result<(), ChunkedBodyError> {
     let mut body_bytes = 0usize;
 
     loop {
-        let line = read_line_bytes(stream, buf, read_timeout).await?;
+        let line = read_line_bytes(stream, buf, read_timeout, buffer_size).await?;
         let size_str = match std::str::from_utf8(&line[..line.len() - 2]) {
             Ok(s) => s.split(';').next().unwrap_or("").trim(),
             Err(_) => return Err(ChunkedBodyError::Invalid),
@@ -53,7 +69,7 @@ pub(crate) async fn discard_chunked_body(
 
         if chunk_size == 0 {
             loop {
-                let trailer = read_line_bytes(stream, buf, read_timeout).await?;
+                let trailer = read_line_bytes(stream, buf, read_timeout, buffer_size).await?;
                 if trailer == b"\r\n" {
                     return Ok(());
                 }
@@ -65,7 +81,7 @@ pub(crate) async fn discard_chunked_body(
             return Err(ChunkedBodyError::TooLarge);
         }
 
-        discard_exact(stream, buf, chunk_size + 2, read_timeout).await?;
+        discard_exact(stream, buf, chunk_size + 2, read_timeout, buffer_size).await?;
     }
 }
 
@@ -74,6 +90,7 @@ pub(crate) async fn discard_content_length(
     buf: &mut BytesMut,
     mut remaining: usize,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> Result<(), ChunkedBodyError> {
     while remaining > 0 {
         if !buf.is_empty() {
@@ -82,7 +99,7 @@ pub(crate) async fn discard_content_length(
             remaining -= take;
             continue;
         }
-        match read_more(stream, buf, read_timeout)
+        match read_more(stream, buf, read_timeout, buffer_size)
             .await
             .map_err(|_| ChunkedBodyError::Io)?
         {
@@ -94,17 +111,24 @@ pub(crate) async fn discard_content_length(
     Ok(())
 }
 
+/// Longest chunk-size (or trailer) line buffered before giving up.
+const MAX_CHUNK_LINE_BYTES: usize = 8 * 1024;
+
 async fn read_line_bytes(
     stream: &mut dyn ClientStream,
     buf: &mut BytesMut,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> Result<Vec<u8>, ChunkedBodyError> {
     loop {
         if let Some(end) = find_crlf(buf, 0) {
             let line = buf.split_to(end + 2);
             return Ok(line.to_vec());
         }
-        match read_more(stream, buf, read_timeout)
+        if buf.len() > MAX_CHUNK_LINE_BYTES {
+            return Err(ChunkedBodyError::Invalid);
+        }
+        match read_more(stream, buf, read_timeout, buffer_size)
             .await
             .map_err(|_| ChunkedBodyError::Io)?
         {
@@ -120,6 +144,7 @@ async fn discard_exact(
     buf: &mut BytesMut,
     mut remaining: usize,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> Result<(), ChunkedBodyError> {
     while remaining > 0 {
         if !buf.is_empty() {
@@ -128,7 +153,7 @@ async fn discard_exact(
             remaining -= take;
             continue;
         }
-        match read_more(stream, buf, read_timeout)
+        match read_more(stream, buf, read_timeout, buffer_size)
             .await
             .map_err(|_| ChunkedBodyError::Io)?
         {
@@ -146,3 +171,34 @@ fn find_crlf(buf: &BytesMut, start: usize) -> Option<usize> {
         .position(|w| w == b"\r\n")
         .map(|i| start + i)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CLIENT_BUF_RETAIN_BYTES, reclaim_client_buf};
+    use bytes::{Buf, BytesMut};
+
+    #[test]
+    fn reclaim_releases_buffer_after_large_request() {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&vec![b'h'; 256 * 1024]);
+        buf.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let peak = buf.len();
+        // The large request has been consumed; a pipelined one is pending.
+        buf.advance(256 * 1024);
+        let old_ptr = buf.as_ptr();
+
+        reclaim_client_buf(&mut buf, peak);
+        assert_ne!(buf.as_ptr(), old_ptr, "buffer was not reallocated");
+        assert!(buf.capacity() <= CLIENT_BUF_RETAIN_BYTES);
+        assert_eq!(&buf[..], b"GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn reclaim_keeps_small_buffers() {
+        let mut buf = BytesMut::with_capacity(4096);
+        buf.extend_from_slice(b"abc");
+        let ptr = buf.as_ptr();
+        reclaim_client_buf(&mut buf, 3);
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+}
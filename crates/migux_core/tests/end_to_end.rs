@@ -0,0 +1,650 @@
+//! End-to-end tests: a real `Master` on an ephemeral port, an in-process
+//! upstream, and a raw TCP client. No fixed ports and no sleeps; every wait
+//! is on a socket read.
+
+use std::{
+    io::Read,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use migux_config::{
+    LocationConfig, LocationType, MiguxConfig, ServerConfig, StreamConfig, UpstreamConfig,
+    UpstreamServers,
+};
+use migux_core::master::Master;
+use migux_static::cache_metrics_snapshot;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+struct Response {
+    head: String,
+    body: String,
+    /// The body as received (before any lossy UTF-8 conversion).
+    raw: Vec<u8>,
+}
+
+impl Response {
+    fn status(&self) -> &str {
+        self.head.split(' ').nth(1).unwrap_or("")
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Read one `Content-Length`-framed or chunked response off `stream`,
+/// leaving anything after it unread so the connection can be reused.
+async fn read_response(stream: &mut TcpStream) -> Response {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let mut byte = [0u8; 1];
+        let n = stream.read(&mut byte).await.expect("read head");
+        assert!(n > 0, "connection closed mid-head: {buf:?}");
+        buf.push(byte[0]);
+    };
+    let head = String::from_utf8(buf[..head_end - 4].to_vec()).expect("utf-8 head");
+    let mut response = Response {
+        head,
+        body: String::new(),
+        raw: Vec::new(),
+    };
+    if response.header("Transfer-Encoding") == Some("chunked") {
+        response.raw = read_chunked(stream).await;
+    } else {
+        let len: usize = response
+            .header("Content-Length")
+            .map(|v| v.parse().expect("numeric Content-Length"))
+            .unwrap_or(0);
+        response.raw = vec![0u8; len];
+        stream
+            .read_exact(&mut response.raw)
+            .await
+            .expect("read body");
+    }
+    response.body = String::from_utf8_lossy(&response.raw).into_owned();
+    response
+}
+
+async fn read_line(stream: &mut TcpStream) -> String {
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        let mut byte = [0u8; 1];
+        let n = stream.read(&mut byte).await.expect("read line");
+        assert!(n > 0, "connection closed mid-line: {line:?}");
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).expect("utf-8 line")
+}
+
+/// Chunked body without trailers.
+async fn read_chunked(stream: &mut TcpStream) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let size =
+            usize::from_str_radix(read_line(stream).await.trim_end(), 16).expect("chunk size");
+        let mut chunk = vec![0u8; size + 2];
+        stream.read_exact(&mut chunk).await.expect("read chunk");
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+async fn send(stream: &mut TcpStream, method: &str, path: &str) -> Response {
+    send_with(stream, method, path, "").await
+}
+
+/// `extra` is a block of `Name: value\r\n` header lines.
+async fn send_with(stream: &mut TcpStream, method: &str, path: &str, extra: &str) -> Response {
+    let request = format!("{method} {path} HTTP/1.1\r\nHost: example\r\n{extra}\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .expect("write request");
+    read_response(stream).await
+}
+
+/// Upstream that answers every request with `backend saw <path>`, keeping
+/// connections open so the proxy can pool them.
+async fn spawn_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind backend");
+    let addr = listener.local_addr().expect("backend addr");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                loop {
+                    while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+                        buf.drain(..end + 4);
+                        let path = head.split(' ').nth(1).unwrap_or("").to_string();
+                        let body = format!("backend saw {path}");
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// One server on `127.0.0.1:0`: static files from `root`, `/api` proxied
+/// to `backend` (with the prefix stripped, as by default).
+fn config(root: &Path, backend: SocketAddr) -> MiguxConfig {
+    let mut cfg = MiguxConfig::default();
+    for server in cfg.servers.values_mut() {
+        server.listen = "127.0.0.1:0".into();
+        server.root = root.to_string_lossy().into_owned();
+    }
+    cfg.upstream.insert(
+        "app".into(),
+        UpstreamConfig {
+            server: UpstreamServers::One(backend.to_string()),
+            ..Default::default()
+        },
+    );
+    cfg.location.insert(
+        "files".into(),
+        LocationConfig {
+            server: "main".into(),
+            path: "/".into(),
+            ..Default::default()
+        },
+    );
+    cfg.location.insert(
+        "api".into(),
+        LocationConfig {
+            server: "main".into(),
+            path: "/api".into(),
+            r#type: LocationType::Proxy,
+            upstream: Some("app".into()),
+            ..Default::default()
+        },
+    );
+    cfg
+}
+
+/// Start the master and return a connected client plus the temp root it
+/// serves (kept alive for the duration of the test).
+async fn start() -> (TcpStream, tempfile::TempDir) {
+    let root = tempfile::tempdir().expect("tempdir");
+    std::fs::write(root.path().join("index.html"), "<h1>home</h1>").expect("write index");
+    std::fs::write(root.path().join("hello.txt"), "hello from disk").expect("write file");
+
+    let backend = spawn_backend().await;
+    let master = Master::new(config(root.path(), backend));
+    let bound = master.start().await.expect("start master");
+    assert_eq!(bound.http.len(), 1, "bound: {bound:?}");
+    assert_ne!(bound.http[0].port(), 0);
+
+    let client = TcpStream::connect(bound.http[0]).await.expect("connect");
+    (client, root)
+}
+
+#[tokio::test]
+async fn serves_a_static_file() {
+    let (mut client, _root) = start().await;
+    let response = send(&mut client, "GET", "/hello.txt").await;
+    assert_eq!(response.status(), "200", "head: {}", response.head);
+    assert_eq!(response.body, "hello from disk");
+}
+
+#[tokio::test]
+async fn proxies_to_the_backend() {
+    let (mut client, _root) = start().await;
+    let response = send(&mut client, "GET", "/api/users?id=7").await;
+    assert_eq!(response.status(), "200", "head: {}", response.head);
+    assert_eq!(response.body, "backend saw /users?id=7");
+}
+
+#[tokio::test]
+async fn missing_file_is_404() {
+    let (mut client, _root) = start().await;
+    let response = send(&mut client, "GET", "/nope.txt").await;
+    assert_eq!(response.status(), "404", "head: {}", response.head);
+}
+
+#[tokio::test]
+async fn keep_alive_serves_a_sequence_on_one_connection() {
+    let (mut client, _root) = start().await;
+    let static_hit = send(&mut client, "GET", "/hello.txt").await;
+    assert_eq!(static_hit.body, "hello from disk");
+    let proxied = send(&mut client, "GET", "/api/one").await;
+    assert_eq!(proxied.body, "backend saw /one");
+    let missing = send(&mut client, "GET", "/missing").await;
+    assert_eq!(missing.status(), "404", "head: {}", missing.head);
+    let proxied = send(&mut client, "GET", "/api/two").await;
+    assert_eq!(proxied.body, "backend saw /two");
+    let index = send(&mut client, "GET", "/").await;
+    assert_eq!(index.status(), "200", "head: {}", index.head);
+    assert_eq!(index.body, "<h1>home</h1>");
+}
+
+/// Origin for `origin_pull`: `/logo.css` (cacheable for a minute), `/slow.css`
+/// (the same, once a permit is added to the returned semaphore), `/plain.css`
+/// (no `Cache-Control`), 404 for anything else. Counts the requests it
+/// answers.
+async fn spawn_origin() -> (SocketAddr, Arc<AtomicUsize>, Arc<Semaphore>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind origin");
+    let addr = listener.local_addr().expect("origin addr");
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&hits);
+    let release = Arc::new(Semaphore::new(0));
+    let gate = Arc::clone(&release);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let counter = Arc::clone(&counter);
+            let gate = Arc::clone(&gate);
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                loop {
+                    while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+                        buf.drain(..end + 4);
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let response = match head.split(' ').nth(1) {
+                            Some("/logo.css") => {
+                                "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nCache-Control: public, max-age=60\r\nContent-Length: 22\r\n\r\nbody { color: tomato }"
+                            }
+                            Some("/slow.css") => {
+                                let _ = gate.acquire().await.map(|permit| permit.forget());
+                                "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nCache-Control: public, max-age=60\r\nContent-Length: 20\r\n\r\nbody { color: navy }"
+                            }
+                            Some("/plain.css") => {
+                                "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Length: 21\r\n\r\nbody { color: black }"
+                            }
+                            _ => {
+                                "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 9\r\n\r\nno origin"
+                            }
+                        };
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+            });
+        }
+    });
+    (addr, hits, release)
+}
+
+#[tokio::test]
+async fn origin_pull_fetches_once_then_serves_locally() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let store = tempfile::tempdir().expect("store");
+    let cache = tempfile::tempdir().expect("cache");
+    let (origin, hits, _) = spawn_origin().await;
+
+    let mut cfg = config(root.path(), origin);
+    cfg.http.cache_dir = Some(cache.path().to_string_lossy().into_owned());
+    cfg.http.cache_max_object_bytes = Some(1024 * 1024);
+    cfg.location.insert(
+        "assets".into(),
+        LocationConfig {
+            server: "main".into(),
+            path: "/assets".into(),
+            r#type: LocationType::OriginPull,
+            upstream: Some("app".into()),
+            root: Some(store.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+    );
+    let bound = Master::new(cfg).start().await.expect("start master");
+    let mut client = TcpStream::connect(bound.http[0]).await.expect("connect");
+
+    let first = send(&mut client, "GET", "/assets/logo.css").await;
+    assert_eq!(first.status(), "200", "head: {}", first.head);
+    assert_eq!(first.body, "body { color: tomato }");
+    assert_eq!(first.header("Cache-Control"), Some("public, max-age=60"));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+    assert!(store.path().join("logo.css").is_file());
+
+    let second = send(&mut client, "GET", "/assets/logo.css").await;
+    assert_eq!(second.status(), "200", "head: {}", second.head);
+    assert_eq!(second.body, first.body);
+    assert_eq!(
+        hits.load(Ordering::SeqCst),
+        1,
+        "second request hit the origin"
+    );
+
+    let etag = second.header("ETag").expect("etag").to_string();
+    let not_modified = send_with(
+        &mut client,
+        "GET",
+        "/assets/logo.css",
+        &format!("If-None-Match: {etag}\r\n"),
+    )
+    .await;
+    assert_eq!(not_modified.status(), "304", "head: {}", not_modified.head);
+    let partial = send_with(
+        &mut client,
+        "GET",
+        "/assets/logo.css",
+        "Range: bytes=0-3\r\n",
+    )
+    .await;
+    assert_eq!(partial.status(), "206", "head: {}", partial.head);
+    assert_eq!(partial.body, "body");
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // Origin errors are relayed, not stored.
+    let missing = send(&mut client, "GET", "/assets/missing.css").await;
+    assert_eq!(missing.status(), "404", "head: {}", missing.head);
+    assert_eq!(missing.body, "no origin");
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+    assert!(!store.path().join("missing.css").exists());
+
+    // So are responses the origin did not mark cacheable.
+    let plain = send(&mut client, "GET", "/assets/plain.css").await;
+    assert_eq!(plain.status(), "200", "head: {}", plain.head);
+    assert_eq!(plain.body, "body { color: black }");
+    assert!(!store.path().join("plain.css").exists());
+    send(&mut client, "GET", "/assets/plain.css").await;
+    assert_eq!(hits.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn origin_pull_coalesces_concurrent_misses() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let store = tempfile::tempdir().expect("store");
+    let (origin, hits, release) = spawn_origin().await;
+
+    let mut cfg = config(root.path(), origin);
+    cfg.location.insert(
+        "assets".into(),
+        LocationConfig {
+            server: "main".into(),
+            path: "/assets".into(),
+            r#type: LocationType::OriginPull,
+            upstream: Some("app".into()),
+            root: Some(store.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+    );
+    let bound = Master::new(cfg).start().await.expect("start master");
+
+    let before = cache_metrics_snapshot().await.coalesced_misses;
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        let addr = bound.http[0];
+        clients.push(tokio::spawn(async move {
+            let mut client = TcpStream::connect(addr).await.expect("connect");
+            send(&mut client, "GET", "/assets/slow.css").await
+        }));
+    }
+    // Hold the origin until the other three are waiting on the first fetch.
+    while cache_metrics_snapshot().await.coalesced_misses < before + 3 {
+        tokio::task::yield_now().await;
+    }
+    release.add_permits(4);
+    for client in clients {
+        let resp = client.await.expect("client");
+        assert_eq!(resp.status(), "200", "head: {}", resp.head);
+        assert_eq!(resp.body, "body { color: navy }");
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn origin_pull_serves_an_expired_copy_while_refetching_it() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let store = tempfile::tempdir().expect("store");
+    let (origin, hits, _) = spawn_origin().await;
+    let stored = store.path().join("logo.css");
+    std::fs::write(&stored, "body { color: gray }").expect("write");
+    std::fs::write(
+        store.path().join("logo.css.httpheaders"),
+        "Cache-Control: max-age=1\n",
+    )
+    .expect("write sidecar");
+    // Stored half a minute ago: expired, inside the revalidate window.
+    std::fs::File::options()
+        .write(true)
+        .open(&stored)
+        .expect("open")
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(30))
+        .expect("set mtime");
+
+    let mut cfg = config(root.path(), origin);
+    cfg.http.cache_stale_while_revalidate_secs = Some(60);
+    cfg.location.insert(
+        "assets".into(),
+        LocationConfig {
+            server: "main".into(),
+            path: "/assets".into(),
+            r#type: LocationType::OriginPull,
+            upstream: Some("app".into()),
+            root: Some(store.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+    );
+    let bound = Master::new(cfg).start().await.expect("start master");
+    let mut client = TcpStream::connect(bound.http[0]).await.expect("connect");
+
+    let stale = send(&mut client, "GET", "/assets/logo.css").await;
+    assert_eq!(stale.status(), "200", "head: {}", stale.head);
+    assert_eq!(stale.body, "body { color: gray }");
+
+    // The background fetch replaces the stored copy.
+    while std::fs::read(&stored).expect("read") != b"body { color: tomato }" {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+    let fresh = send(&mut client, "GET", "/assets/logo.css").await;
+    assert_eq!(fresh.body, "body { color: tomato }");
+    assert_eq!(fresh.header("Cache-Control"), Some("public, max-age=60"));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn proxy_compress_gzips_plain_text_for_clients_that_accept_it() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let backend = spawn_backend().await;
+    let mut cfg = config(root.path(), backend);
+    let api = cfg.location.get_mut("api").expect("api location");
+    api.proxy_compress = Some(true);
+    api.proxy_compress_min_length = Some(8);
+    let bound = Master::new(cfg).start().await.expect("start master");
+    let mut client = TcpStream::connect(bound.http[0]).await.expect("connect");
+
+    let compressed = send_with(
+        &mut client,
+        "GET",
+        "/api/report.txt",
+        "Accept-Encoding: gzip\r\n",
+    )
+    .await;
+    assert_eq!(compressed.status(), "200", "head: {}", compressed.head);
+    assert_eq!(compressed.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(compressed.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(compressed.header("Content-Length"), None);
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&compressed.raw[..])
+        .read_to_string(&mut decoded)
+        .expect("gunzip");
+    assert_eq!(decoded, "backend saw /report.txt");
+
+    // Same connection: a client without Accept-Encoding gets the body as is.
+    let plain = send(&mut client, "GET", "/api/report.txt").await;
+    assert_eq!(plain.header("Content-Encoding"), None);
+    assert_eq!(plain.body, "backend saw /report.txt");
+}
+
+#[tokio::test]
+async fn serves_on_an_inherited_listener() {
+    let root = tempfile::tempdir().expect("tempdir");
+    std::fs::write(root.path().join("hello.txt"), "hello from disk").expect("write file");
+    // Bound elsewhere, as a systemd .socket unit would for a privileged port.
+    let inherited = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = inherited.local_addr().expect("addr");
+
+    let backend = spawn_backend().await;
+    let mut cfg = config(root.path(), backend);
+    for server in cfg.servers.values_mut() {
+        server.listen = addr.to_string();
+    }
+    let bound = Master::new(cfg)
+        .with_inherited_listeners(vec![inherited])
+        .start()
+        .await
+        .expect("start master");
+    assert_eq!(bound.http, vec![addr]);
+
+    let mut client = TcpStream::connect(addr).await.expect("connect");
+    let response = send(&mut client, "GET", "/hello.txt").await;
+    assert_eq!(response.status(), "200", "head: {}", response.head);
+    assert_eq!(response.body, "hello from disk");
+}
+
+#[tokio::test]
+async fn server_built_in_code_serves_until_shut_down() {
+    let root = tempfile::tempdir().expect("tempdir");
+    std::fs::write(root.path().join("hello.txt"), "hello from disk").expect("write file");
+    let backend = spawn_backend().await;
+
+    let builder = MiguxConfig::builder()
+        .server(
+            "main",
+            ServerConfig {
+                listen: "127.0.0.1:0".into(),
+                root: root.path().to_string_lossy().into_owned(),
+                ..Default::default()
+            },
+        )
+        .upstream(
+            "app",
+            UpstreamConfig {
+                server: UpstreamServers::One(backend.to_string()),
+                ..Default::default()
+            },
+        )
+        .location(
+            "files",
+            LocationConfig {
+                server: "main".into(),
+                path: "/".into(),
+                ..Default::default()
+            },
+        )
+        .location(
+            "api",
+            LocationConfig {
+                server: "main".into(),
+                path: "/api".into(),
+                r#type: LocationType::Proxy,
+                upstream: Some("app".into()),
+                ..Default::default()
+            },
+        );
+    let handle = Master::from_builder(builder)
+        .expect("valid config")
+        .serve()
+        .await
+        .expect("serve");
+    let addr = handle.listeners().http[0];
+
+    let mut client = TcpStream::connect(addr).await.expect("connect");
+    let response = send(&mut client, "GET", "/hello.txt").await;
+    assert_eq!(response.status(), "200", "head: {}", response.head);
+    assert_eq!(response.body, "hello from disk");
+    let proxied = send(&mut client, "GET", "/api/users").await;
+    assert_eq!(proxied.body, "backend saw /users");
+
+    handle.shutdown().await;
+    assert!(TcpStream::connect(addr).await.is_err(), "listener closed");
+}
+
+/// Raw TCP backend that echoes every connection back until EOF.
+async fn spawn_echo_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind echo");
+    let addr = listener.local_addr().expect("addr");
+    tokio::spawn(async move {
+        while let Ok((mut sock, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut rd, mut wr) = sock.split();
+                let _ = tokio::io::copy(&mut rd, &mut wr).await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn stream_listener_relays_raw_tcp_to_the_upstream() {
+    let echo = spawn_echo_backend().await;
+    // The dead first server is skipped: failover works as for HTTP.
+    let builder = MiguxConfig::builder()
+        .upstream(
+            "echo",
+            UpstreamConfig {
+                server: UpstreamServers::Many(vec!["127.0.0.1:1".into(), echo.to_string()]),
+                strategy: Some("failover".into()),
+                ..Default::default()
+            },
+        )
+        .stream(
+            "echo",
+            StreamConfig {
+                listen: "127.0.0.1:0".into(),
+                upstream: "echo".into(),
+                ..Default::default()
+            },
+        );
+    let handle = Master::from_builder(builder)
+        .expect("valid config")
+        .serve()
+        .await
+        .expect("serve");
+    assert!(handle.listeners().http.is_empty(), "no default HTTP server");
+    let addr = handle.listeners().stream[0];
+
+    for _ in 0..2 {
+        let mut client = TcpStream::connect(addr).await.expect("connect");
+        // Not HTTP: bytes go through untouched.
+        client
+            .write_all(b"\x00PING not-http\r\n")
+            .await
+            .expect("write");
+        client.shutdown().await.expect("half-close");
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.expect("read");
+        assert_eq!(echoed, b"\x00PING not-http\r\n");
+    }
+
+    handle.shutdown().await;
+}
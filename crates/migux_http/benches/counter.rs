@@ -0,0 +1,59 @@
+//! Increment throughput of a shared counter under N threads: one
+//! `AtomicU64` versus `ShardedCounter`. Run with `cargo bench -p migux_http`.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use migux_http::counter::ShardedCounter;
+
+const INCREMENTS_PER_THREAD: u64 = 10_000;
+
+/// Time `threads` threads each running `INCREMENTS_PER_THREAD` increments.
+fn contended(threads: usize, iters: u64, incr: &(dyn Fn() + Sync)) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    for _ in 0..INCREMENTS_PER_THREAD {
+                        incr();
+                    }
+                });
+            }
+        });
+        total += start.elapsed();
+    }
+    total
+}
+
+fn bench_counters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("counter");
+    for threads in [1, 4, 8] {
+        let single = AtomicU64::new(0);
+        group.bench_with_input(
+            BenchmarkId::new("atomic", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    contended(threads, iters, &|| {
+                        single.fetch_add(1, Ordering::Relaxed);
+                    })
+                })
+            },
+        );
+        let sharded = ShardedCounter::new();
+        group.bench_with_input(
+            BenchmarkId::new("sharded", threads),
+            &threads,
+            |b, &threads| b.iter_custom(|iters| contended(threads, iters, &|| sharded.incr())),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_counters);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Content-Length values, shared by client request and upstream response
+//! parsing.
+
+/// Largest Content-Length accepted regardless of the configured body limits
+/// (which may be 0, i.e. unlimited). Offsets and the `u64`/`i64` arithmetic
+/// in the streaming and spooling paths stay in range below it.
+pub const MAX_CONTENT_LENGTH: u64 = i64::MAX as u64;
+
+/// Parse one Content-Length value (`1*DIGIT`), rejecting anything above
+/// [`MAX_CONTENT_LENGTH`] or what `usize` can hold.
+pub fn parse_content_length(value: &str) -> Option<usize> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let len = value.parse::<u64>().ok()?;
+    if len > MAX_CONTENT_LENGTH {
+        return None;
+    }
+    usize::try_from(len).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_CONTENT_LENGTH, parse_content_length};
+
+    #[test]
+    fn accepts_digits_up_to_the_cap() {
+        assert_eq!(parse_content_length("0"), Some(0));
+        assert_eq!(parse_content_length("00042"), Some(42));
+        assert_eq!(
+            parse_content_length(&MAX_CONTENT_LENGTH.to_string()),
+            usize::try_from(MAX_CONTENT_LENGTH).ok()
+        );
+    }
+
+    #[test]
+    fn rejects_overflow_and_near_max_values() {
+        assert_eq!(parse_content_length("99999999999999999999"), None);
+        assert_eq!(parse_content_length(&u64::MAX.to_string()), None);
+        assert_eq!(
+            parse_content_length(&(MAX_CONTENT_LENGTH + 1).to_string()),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_non_digits() {
+        for value in ["", "+5", "-1", "5 ", "0x10", "1e3"] {
+            assert_eq!(parse_content_length(value), None, "{value:?}");
+        }
+    }
+}
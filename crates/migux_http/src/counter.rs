@@ -0,0 +1,80 @@
+//! Striped counters for hot, process-wide metrics.
+//!
+//! A single `AtomicU64` bumped from every worker thread makes its cache line
+//! bounce between cores. [`ShardedCounter`] spreads increments over
+//! cache-line-aligned shards picked per thread and only sums them on read,
+//! which is rare (status pages, metrics snapshots).
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+const SHARDS: usize = 16;
+
+#[derive(Debug)]
+#[repr(align(64))]
+struct Shard(AtomicU64);
+
+/// Monotonic counter with cheap concurrent increments and O(shards) reads.
+#[derive(Debug)]
+pub struct ShardedCounter {
+    shards: [Shard; SHARDS],
+}
+
+impl ShardedCounter {
+    pub const fn new() -> Self {
+        Self {
+            shards: [const { Shard(AtomicU64::new(0)) }; SHARDS],
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.shards[shard_index()].0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn incr(&self) {
+        self.add(1);
+    }
+
+    /// Current total. Concurrent increments may or may not be included.
+    pub fn sum(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.0.load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shard for the current thread, assigned round robin on first use.
+fn shard_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+    INDEX.with(|index| *index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedCounter;
+
+    #[test]
+    fn sums_increments_from_all_threads() {
+        static COUNTER: ShardedCounter = ShardedCounter::new();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        COUNTER.incr();
+                    }
+                    COUNTER.add(5);
+                });
+            }
+        });
+        assert_eq!(COUNTER.sum(), 8 * 1005);
+    }
+}
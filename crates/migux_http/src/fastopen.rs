@@ -0,0 +1,152 @@
+//! TCP Fast Open (`global.tcp_fastopen`): data in the SYN on accepted and
+//! upstream connections, saving a round-trip once a client holds a cookie.
+//!
+//! Linux only. Elsewhere the setters return `Unsupported` and [`connect`]
+//! is a plain connect. With Fast Open the kernel defers the SYN of an
+//! upstream connect to the first write, so a refused connection surfaces
+//! there instead of in `connect`.
+
+use std::io;
+
+use tokio::net::{TcpListener, TcpSocket, TcpStream, ToSocketAddrs, lookup_host};
+
+/// Pending Fast Open requests a listener queues (`TCP_FASTOPEN` value).
+pub const LISTEN_QUEUE: i32 = 256;
+
+/// Accept data in the SYN on `listener`.
+pub fn enable_on_listener(listener: &TcpListener) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        set_tcp_option(listener.as_raw_fd(), libc::TCP_FASTOPEN, LISTEN_QUEUE)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = listener;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Send the first write of `socket` in its SYN (`TCP_FASTOPEN_CONNECT`);
+/// set before connecting.
+pub fn enable_on_connect(socket: &TcpSocket) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        set_tcp_option(socket.as_raw_fd(), libc::TCP_FASTOPEN_CONNECT, 1)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Connect to `addr` (`host:port`, or addresses resolved already) with Fast
+/// Open where the platform has it, trying each address like
+/// `TcpStream::connect`.
+pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for sock_addr in lookup_host(addr).await? {
+        let socket = if sock_addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        // Without it this is an ordinary connect.
+        let _ = enable_on_connect(&socket);
+        match socket.connect(sock_addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    }))
+}
+
+#[cfg(target_os = "linux")]
+fn set_tcp_option(
+    fd: std::os::fd::RawFd,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: `fd` is a live socket borrowed from its owner for this call,
+    // and `value` outlives it.
+    let rc = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::{LISTEN_QUEUE, enable_on_connect, enable_on_listener};
+    use std::os::fd::{AsRawFd, RawFd};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpSocket},
+    };
+
+    fn tcp_option(fd: RawFd, option: libc::c_int) -> libc::c_int {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `fd` is a live socket and `value`/`len` are valid for writes.
+        let rc = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                option,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        assert_eq!(rc, 0, "getsockopt: {}", std::io::Error::last_os_error());
+        value
+    }
+
+    #[tokio::test]
+    async fn fast_open_is_set_on_listener_and_connect_and_data_flows() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        assert_eq!(tcp_option(listener.as_raw_fd(), libc::TCP_FASTOPEN), 0);
+        enable_on_listener(&listener).expect("listener option");
+        assert_eq!(
+            tcp_option(listener.as_raw_fd(), libc::TCP_FASTOPEN),
+            LISTEN_QUEUE
+        );
+
+        let socket = TcpSocket::new_v4().expect("socket");
+        enable_on_connect(&socket).expect("connect option");
+        assert_eq!(
+            tcp_option(socket.as_raw_fd(), libc::TCP_FASTOPEN_CONNECT),
+            1
+        );
+
+        let addr = listener.local_addr().expect("addr").to_string();
+        let server = tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.expect("accept");
+            let mut buf = [0u8; 4];
+            sock.read_exact(&mut buf).await.expect("read");
+            sock.write_all(&buf).await.expect("write");
+        });
+        let mut stream = super::connect(&addr).await.expect("connect");
+        stream.write_all(b"ping").await.expect("write");
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.expect("read");
+        assert_eq!(&buf, b"ping");
+        server.await.expect("server");
+    }
+}
@@ -0,0 +1,57 @@
+//! Connection persistence advertised on responses, shared by the static and
+//! proxy handlers.
+
+/// Whether a response keeps the client connection open. An open connection
+/// is advertised as `Connection: keep-alive` plus
+/// `Keep-Alive: timeout=<secs>, max=<requests>` so clients know when the idle
+/// connection will be dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAlive {
+    Close,
+    Open {
+        timeout_secs: u64,
+        max_requests: u64,
+    },
+}
+
+impl KeepAlive {
+    pub fn is_open(self) -> bool {
+        matches!(self, Self::Open { .. })
+    }
+
+    /// `Connection` header value.
+    pub fn connection(self) -> &'static str {
+        match self {
+            Self::Close => "close",
+            Self::Open { .. } => "keep-alive",
+        }
+    }
+
+    /// `Keep-Alive` header value; `None` when the connection closes.
+    pub fn header_value(self) -> Option<String> {
+        match self {
+            Self::Close => None,
+            Self::Open {
+                timeout_secs,
+                max_requests,
+            } => Some(format!("timeout={timeout_secs}, max={max_requests}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeepAlive;
+
+    #[test]
+    fn only_open_connections_advertise_parameters() {
+        let open = KeepAlive::Open {
+            timeout_secs: 65,
+            max_requests: 1000,
+        };
+        assert_eq!(open.connection(), "keep-alive");
+        assert_eq!(open.header_value().as_deref(), Some("timeout=65, max=1000"));
+        assert_eq!(KeepAlive::Close.connection(), "close");
+        assert_eq!(KeepAlive::Close.header_value(), None);
+    }
+}
@@ -1,4 +1,10 @@
+pub mod content_length;
+pub mod counter;
+pub mod fastopen;
+pub mod keep_alive;
 pub mod responses;
+pub mod spool;
+pub mod target;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -1,5 +1,7 @@
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+use crate::keep_alive::KeepAlive;
+
 /// Helper genérico para enviar una respuesta HTTP con cuerpo binario.
 pub async fn send_response<W: AsyncWrite + Unpin + ?Sized>(
     stream: &mut W,
@@ -32,6 +34,24 @@ async fn send_text_response<W: AsyncWrite + Unpin + ?Sized>(
     send_response(stream, status, "text/plain; charset=utf-8", body.as_bytes()).await
 }
 
+/// Render a JSON error body for a status line such as `404 Not Found`:
+/// `{"error":"Not Found","status":404}`.
+pub fn json_error_body(status: &str) -> String {
+    let (code, reason) = status.split_once(' ').unwrap_or((status, ""));
+    let code: u16 = code.parse().unwrap_or(500);
+    let reason = reason.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("{{\"error\":\"{reason}\",\"status\":{code}}}")
+}
+
+/// Send an error response with a JSON body (see [`json_error_body`]).
+pub async fn send_json_error<W: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut W,
+    status: &str,
+) -> anyhow::Result<()> {
+    let body = json_error_body(status);
+    send_response(stream, status, "application/json", body.as_bytes()).await
+}
+
 /// Send a 404 Not Found response.
 pub async fn send_404<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
     send_text_response(stream, "404 Not Found", "404 Not Found\n").await
@@ -39,12 +59,30 @@ pub async fn send_404<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow:
 
 /// Send a 501 Not Implemented response.
 pub async fn send_501<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
-    send_text_response(
-        stream,
-        "501 Not Implemented",
-        "501 Not Implemented (proxy TODO)\n",
-    )
-    .await
+    send_text_response(stream, "501 Not Implemented", "501 Not Implemented\n").await
+}
+
+/// Send a 501 Not Implemented response for an unknown method, with the
+/// methods that are supported in `Allow`.
+pub async fn send_501_with_allow<W: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut W,
+    allow: &str,
+) -> anyhow::Result<()> {
+    let body = "501 Not Implemented\n";
+    let response = format!(
+        "HTTP/1.1 501 Not Implemented\r\n\
+         Server: migux/0.1.0\r\n\
+         Allow: {allow}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
 }
 
 /// Send a 500 Internal Server Error response.
@@ -57,11 +95,21 @@ pub async fn send_500<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow:
     .await
 }
 
+/// Send a 406 Not Acceptable response.
+pub async fn send_406<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
+    send_text_response(stream, "406 Not Acceptable", "406 Not Acceptable\n").await
+}
+
 /// Send a 502 Bad Gateway response.
 pub async fn send_502<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
     send_text_response(stream, "502 Bad Gateway", "502 Bad Gateway\n").await
 }
 
+/// Send a 504 Gateway Timeout response.
+pub async fn send_504<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
+    send_text_response(stream, "504 Gateway Timeout", "504 Gateway Timeout\n").await
+}
+
 /// Send a 405 Method Not Allowed response.
 pub async fn send_405<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
     send_text_response(stream, "405 Method Not Allowed", "405 Method Not Allowed\n").await
@@ -72,13 +120,54 @@ pub async fn send_405_with_allow<W: AsyncWrite + Unpin + ?Sized>(
     stream: &mut W,
     allow: &str,
 ) -> anyhow::Result<()> {
+    send_405_keep_alive(stream, allow, KeepAlive::Close).await
+}
+
+/// Send a 405 that can leave the connection open, for callers that have
+/// already consumed the request body.
+pub async fn send_405_keep_alive<W: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut W,
+    allow: &str,
+    keep_alive: KeepAlive,
+) -> anyhow::Result<()> {
+    let keep_alive_header = keep_alive
+        .header_value()
+        .map(|value| format!("Keep-Alive: {value}\r\n"))
+        .unwrap_or_default();
     let response = format!(
         "HTTP/1.1 405 Method Not Allowed\r\n\
          Server: migux/0.1.0\r\n\
          Allow: {allow}\r\n\
          Content-Length: 0\r\n\
-         Connection: close\r\n\
-         \r\n"
+         Connection: {}\r\n\
+         {keep_alive_header}\
+         \r\n",
+        keep_alive.connection()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Send a 200 OK answer to an OPTIONS request with an Allow header.
+pub async fn send_options<W: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut W,
+    allow: &str,
+    keep_alive: KeepAlive,
+) -> anyhow::Result<()> {
+    let keep_alive_header = keep_alive
+        .header_value()
+        .map(|value| format!("Keep-Alive: {value}\r\n"))
+        .unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Server: migux/0.1.0\r\n\
+         Allow: {allow}\r\n\
+         Content-Length: 0\r\n\
+         Connection: {}\r\n\
+         {keep_alive_header}\
+         \r\n",
+        keep_alive.connection()
     );
     stream.write_all(response.as_bytes()).await?;
     stream.flush().await?;
@@ -100,6 +189,11 @@ pub async fn send_413<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow:
     send_text_response(stream, "413 Payload Too Large", "413 Payload Too Large\n").await
 }
 
+/// Send a 414 URI Too Long response.
+pub async fn send_414<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
+    send_text_response(stream, "414 URI Too Long", "414 URI Too Long\n").await
+}
+
 /// Send a 431 Request Header Fields Too Large response.
 pub async fn send_431<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow::Result<()> {
     send_text_response(
@@ -110,6 +204,29 @@ pub async fn send_431<W: AsyncWrite + Unpin + ?Sized>(stream: &mut W) -> anyhow:
     .await
 }
 
+/// Send a 503 Service Unavailable response asking the client to retry
+/// after `retry_after_secs`.
+pub async fn send_503_retry_after<W: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut W,
+    retry_after_secs: u64,
+) -> anyhow::Result<()> {
+    let body = "503 Service Unavailable\n";
+    let response = format!(
+        "HTTP/1.1 503 Service Unavailable\r\n\
+         Server: migux/0.1.0\r\n\
+         Retry-After: {retry_after_secs}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
 /// Send an HTTP 301 redirect.
 pub async fn send_redirect<W: AsyncWrite + Unpin + ?Sized>(
     stream: &mut W,
@@ -0,0 +1,82 @@
+//! Temp files for spooled bodies (`http.temp_dir`), such as files fetched
+//! by `origin_pull` locations.
+//!
+//! Spool files are written under the temp dir and later moved into place with
+//! [`persist`]. A rename is atomic when both paths share a filesystem; across
+//! filesystems the data is copied next to the destination first and renamed
+//! from there, so readers never see a partial file.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::fs;
+
+/// Unique path for a new spool file inside `dir`.
+pub fn temp_file_path(dir: &Path, prefix: &str) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("{prefix}.tmp-{}-{nanos}-{seq}", std::process::id()))
+}
+
+/// Move the spool file `tmp` to `dest`, replacing any existing file.
+pub async fn persist(tmp: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(tmp, dest).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_then_rename(tmp, dest).await,
+        other => other,
+    }
+}
+
+/// Cross-filesystem fallback: copy into `dest`'s directory, rename, then drop
+/// the source.
+async fn copy_then_rename(tmp: &Path, dest: &Path) -> io::Result<()> {
+    let dest_dir = dest.parent().unwrap_or(Path::new("."));
+    let staged = temp_file_path(dest_dir, "spool");
+    if let Err(e) = fs::copy(tmp, &staged).await {
+        let _ = fs::remove_file(&staged).await;
+        return Err(e);
+    }
+    if let Err(e) = fs::rename(&staged, dest).await {
+        let _ = fs::remove_file(&staged).await;
+        return Err(e);
+    }
+    fs::remove_file(tmp).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_then_rename, persist, temp_file_path};
+
+    #[test]
+    fn temp_paths_are_unique() {
+        let dir = std::path::Path::new("/tmp");
+        assert_ne!(temp_file_path(dir, "body"), temp_file_path(dir, "body"));
+    }
+
+    #[tokio::test]
+    async fn persist_renames_into_place() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let dir = dir.path();
+        let tmp = temp_file_path(dir, "body");
+        let dest = dir.join("dest");
+        tokio::fs::write(&tmp, b"old").await.expect("write");
+        tokio::fs::write(&dest, b"stale").await.expect("write");
+
+        persist(&tmp, &dest).await.expect("persist");
+        assert_eq!(tokio::fs::read(&dest).await.expect("read"), b"old");
+        assert!(!tmp.exists());
+
+        let tmp = temp_file_path(dir, "body");
+        tokio::fs::write(&tmp, b"copied").await.expect("write");
+        copy_then_rename(&tmp, &dest).await.expect("copy");
+        assert_eq!(tokio::fs::read(&dest).await.expect("read"), b"copied");
+        assert!(!tmp.exists());
+    }
+}
@@ -0,0 +1,143 @@
+//! Request-target paths: the normalized form used for routing, and prefix
+//! stripping that keeps the client's raw encoding for upstreams.
+//!
+//! Normalization decodes percent-escapes of unreserved characters
+//! (`%61` -> `a`) and upper-cases the hex of every other escape, so
+//! `/%61pi%2fx` routes as `/api%2Fx`. Encoded slashes stay encoded unless
+//! `decode_slashes` is set. The query is left as sent.
+
+/// Whether `path` (query excluded) contains an encoded slash (`%2F`).
+pub fn has_encoded_slash(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or(path).as_bytes();
+    path.windows(3)
+        .any(|w| w[0] == b'%' && w[1] == b'2' && w[2].eq_ignore_ascii_case(&b'f'))
+}
+
+/// Routing form of a request target (see the module docs).
+pub fn normalize_path(target: &str, decode_slashes: bool) -> String {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    let mut out = String::with_capacity(target.len());
+    let mut rest = path;
+    while let Some(c) = rest.chars().next() {
+        let (piece, len) = normalized_piece(rest, decode_slashes);
+        match piece {
+            Piece::Char(byte) => out.push(byte as char),
+            Piece::Escape(byte) => out.push_str(&format!("%{byte:02X}")),
+            Piece::Raw => out.push(c),
+        }
+        rest = &rest[len..];
+    }
+    if let Some(query) = query {
+        out.push('?');
+        out.push_str(query);
+    }
+    out
+}
+
+/// Strip `prefix` (a location path, compared against the routing form)
+/// from the raw `target`, returning the rest exactly as the client sent it.
+/// `None` when the routing form of `target` does not start with `prefix`.
+pub fn strip_raw_prefix<'a>(
+    target: &'a str,
+    prefix: &str,
+    decode_slashes: bool,
+) -> Option<&'a str> {
+    let mut rest = target;
+    let mut prefix = prefix;
+    while !prefix.is_empty() {
+        let c = rest.chars().next()?;
+        let (piece, len) = normalized_piece(rest, decode_slashes);
+        let consumed = match piece {
+            Piece::Char(byte) => (prefix.as_bytes()[0] == byte).then_some(1),
+            Piece::Escape(byte) => {
+                let escape = format!("%{byte:02X}");
+                prefix
+                    .get(..3)
+                    .is_some_and(|p| p.eq_ignore_ascii_case(&escape))
+                    .then_some(3)
+            }
+            Piece::Raw => prefix.starts_with(c).then(|| c.len_utf8()),
+        }?;
+        prefix = &prefix[consumed..];
+        rest = &rest[len..];
+    }
+    Some(rest)
+}
+
+enum Piece {
+    /// A character whose escape is decoded.
+    Char(u8),
+    /// An escape kept encoded.
+    Escape(u8),
+    /// Anything else, as is.
+    Raw,
+}
+
+/// The first piece of `s` (non-empty) and its length in `s`.
+fn normalized_piece(s: &str, decode_slashes: bool) -> (Piece, usize) {
+    let bytes = s.as_bytes();
+    if bytes[0] == b'%'
+        && let Some(byte) = bytes.get(1..3).and_then(|hex| {
+            let hex = std::str::from_utf8(hex).ok()?;
+            u8::from_str_radix(hex, 16).ok()
+        })
+    {
+        let decode = byte.is_ascii_alphanumeric()
+            || matches!(byte, b'-' | b'.' | b'_' | b'~')
+            || (decode_slashes && byte == b'/');
+        return (
+            if decode {
+                Piece::Char(byte)
+            } else {
+                Piece::Escape(byte)
+            },
+            3,
+        );
+    }
+    let c = s.chars().next().unwrap_or_default();
+    if c.is_ascii() {
+        (Piece::Char(bytes[0]), 1)
+    } else {
+        (Piece::Raw, c.len_utf8())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_encoded_slash, normalize_path, strip_raw_prefix};
+
+    #[test]
+    fn unreserved_escapes_decode_and_reserved_ones_stay() {
+        assert_eq!(normalize_path("/%61pi/a%2fb", false), "/api/a%2Fb");
+        assert_eq!(normalize_path("/api/a%2fb", true), "/api/a/b");
+        assert_eq!(normalize_path("/a%20b%7e?q=%61", false), "/a%20b~?q=%61");
+        assert_eq!(normalize_path("/bad%zz%4", false), "/bad%zz%4");
+        assert_eq!(normalize_path("/caf\u{e9}", false), "/caf\u{e9}");
+    }
+
+    #[test]
+    fn stripping_keeps_the_raw_tail() {
+        assert_eq!(
+            strip_raw_prefix("/api/a%2Fb", "/api", false),
+            Some("/a%2Fb")
+        );
+        assert_eq!(
+            strip_raw_prefix("/%61pi/a%2fb", "/api", false),
+            Some("/a%2fb")
+        );
+        assert_eq!(strip_raw_prefix("/api%2Fv1/x", "/api/v1", false), None);
+        assert_eq!(strip_raw_prefix("/api%2Fv1/x", "/api/v1", true), Some("/x"));
+        assert_eq!(strip_raw_prefix("/a%2fb/c", "/a%2Fb", false), Some("/c"));
+        assert_eq!(strip_raw_prefix("/ap", "/api", false), None);
+    }
+
+    #[test]
+    fn encoded_slashes_are_found_in_the_path_only() {
+        assert!(has_encoded_slash("/a%2Fb"));
+        assert!(has_encoded_slash("/a%2fb"));
+        assert!(!has_encoded_slash("/a/b?next=%2F"));
+    }
+}
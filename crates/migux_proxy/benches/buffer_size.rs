@@ -0,0 +1,167 @@
+//! Proxying one large `Content-Length` response with `proxy_buffer_size` at
+//! its default (8 KiB) versus 64 KiB. Each upstream read is forwarded as one
+//! client write, so the write count printed per size is the read count.
+//! Run with `cargo bench -p migux_proxy`.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use migux_config::{LocationConfig, LocationType, MiguxConfig, UpstreamConfig, UpstreamServers};
+use migux_http::keep_alive::KeepAlive;
+use migux_proxy::Proxy;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::TcpListener,
+    runtime::Runtime,
+};
+
+const BODY_LEN: usize = 4 * 1024 * 1024;
+
+/// Client side of the proxy: reads nothing, discards and counts writes.
+#[derive(Default)]
+struct CountingSink {
+    writes: u64,
+}
+
+impl AsyncRead for CountingSink {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for CountingSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes += 1;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Upstream answering every request on a connection with the same large body.
+async fn spawn_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {BODY_LEN}\r\n\r\n"
+    )
+    .into_bytes();
+    response.resize(response.len() + BODY_LEN, b'x');
+    let response: Arc<[u8]> = response.into();
+    tokio::spawn(async move {
+        while let Ok((mut sock, _)) = listener.accept().await {
+            let response = Arc::clone(&response);
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = sock.read(&mut buf).await {
+                    if n == 0 || sock.write_all(&response).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+fn config(upstream: SocketAddr, proxy_buffer_size: usize) -> (Arc<MiguxConfig>, LocationConfig) {
+    let mut cfg = MiguxConfig::default();
+    cfg.http.proxy_buffer_size = proxy_buffer_size;
+    cfg.upstream.insert(
+        "app".into(),
+        UpstreamConfig {
+            server: UpstreamServers::One(upstream.to_string()),
+            ..Default::default()
+        },
+    );
+    let location = LocationConfig {
+        path: "/".into(),
+        r#type: LocationType::Proxy,
+        upstream: Some("app".into()),
+        ..Default::default()
+    };
+    (Arc::new(cfg), location)
+}
+
+/// Proxy one response and return the number of client writes it took.
+async fn proxy_once(proxy: &Proxy, cfg: &Arc<MiguxConfig>, location: &LocationConfig) -> u64 {
+    let mut sink = CountingSink::default();
+    let mut client_buf = BytesMut::new();
+    let client_addr: SocketAddr = "127.0.0.1:5555".parse().expect("addr");
+    proxy
+        .serve(
+            &mut sink,
+            &mut client_buf,
+            location,
+            "GET / HTTP/1.1\r\nHost: example",
+            "GET",
+            "/",
+            "HTTP/1.1",
+            0,
+            false,
+            KeepAlive::Open {
+                timeout_secs: 65,
+                max_requests: 1000,
+            },
+            false,
+            None,
+            None,
+            cfg,
+            &client_addr,
+        )
+        .await
+        .expect("serve");
+    sink.writes
+}
+
+fn bench_buffer_size(c: &mut Criterion) {
+    let rt = Runtime::new().expect("runtime");
+    let upstream = rt.block_on(spawn_upstream());
+    let mut group = c.benchmark_group("proxy_buffer_size");
+    group.throughput(criterion::Throughput::Bytes(BODY_LEN as u64));
+    for size in [8 * 1024, 64 * 1024] {
+        let (cfg, location) = config(upstream, size);
+        let proxy = Proxy::new();
+        let writes = rt.block_on(proxy_once(&proxy, &cfg, &location));
+        eprintln!("proxy_buffer_size={size}: {writes} client writes for {BODY_LEN} bytes");
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let start = Instant::now();
+                        proxy_once(&proxy, &cfg, &location).await;
+                        total += start.elapsed();
+                    }
+                    total
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_buffer_size);
+criterion_main!(benches);
@@ -1,3 +1,3 @@
 pub mod proxy;
 
-pub use proxy::Proxy;
+pub use proxy::{FetchedResponse, Proxy, ProxyError, UpstreamNodeStatus, UpstreamsUnavailable};
@@ -0,0 +1,87 @@
+//! Pause between failover attempts (`proxy_retry_backoff_ms`, doubled per
+//! failover, optionally jittered) and the overall budget for trying
+//! candidates (`proxy_request_timeout_secs`). The first attempt never waits.
+
+use migux_config::HttpConfig;
+use tokio::time::{Duration, Instant, sleep};
+
+use super::random::random_u64;
+
+/// Doublings stop here (base × 1024), so the delay cannot overflow.
+const MAX_DOUBLINGS: u32 = 10;
+
+pub(super) struct RetryBudget {
+    base: Duration,
+    jitter: bool,
+    deadline: Option<Instant>,
+}
+
+impl RetryBudget {
+    /// Budget starting now.
+    pub(super) fn new(http: &HttpConfig) -> Self {
+        let timeout_secs = http.proxy_request_timeout_secs();
+        Self {
+            base: Duration::from_millis(http.proxy_retry_backoff_ms()),
+            jitter: http.proxy_retry_backoff_jitter(),
+            deadline: (timeout_secs > 0)
+                .then(|| Instant::now() + Duration::from_secs(timeout_secs)),
+        }
+    }
+
+    /// Wait before the attempt that follows `failures` failed ones.
+    /// `false` (without waiting) when the backoff would run past the
+    /// deadline: no further candidate is tried.
+    pub(super) async fn before_retry(&self, failures: u32) -> bool {
+        let delay = backoff_delay(self.base, failures, self.jitter.then(random_u64));
+        if let Some(deadline) = self.deadline
+            && Instant::now() + delay >= deadline
+        {
+            return false;
+        }
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        true
+    }
+}
+
+/// `base` × 2^(failures - 1); with a jitter `sample`, somewhere between half
+/// of that and all of it.
+fn backoff_delay(base: Duration, failures: u32, sample: Option<u64>) -> Duration {
+    let doublings = failures.saturating_sub(1).min(MAX_DOUBLINGS);
+    let delay = base.saturating_mul(1 << doublings);
+    let Some(sample) = sample else {
+        return delay;
+    };
+    let half = delay / 2;
+    let spread = u64::try_from((delay - half).as_micros()).unwrap_or(u64::MAX);
+    half + Duration::from_micros(sample % spread.saturating_add(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backoff_delay;
+    use std::time::Duration;
+
+    #[test]
+    fn delay_doubles_per_failover_and_jitter_stays_within_half() {
+        let base = Duration::from_millis(100);
+        let delays: Vec<_> = (1..=4).map(|n| backoff_delay(base, n, None)).collect();
+        assert_eq!(delays, [100, 200, 400, 800].map(Duration::from_millis));
+        assert_eq!(
+            backoff_delay(base, 40, None),
+            Duration::from_millis(100 * 1024)
+        );
+
+        assert_eq!(backoff_delay(base, 2, Some(0)), Duration::from_millis(100));
+        assert_eq!(
+            backoff_delay(base, 2, Some(100_000)),
+            Duration::from_millis(200)
+        );
+        for sample in [1, 12_345, u64::MAX] {
+            let delay = backoff_delay(base, 2, Some(sample));
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+        assert_eq!(backoff_delay(Duration::ZERO, 3, Some(7)), Duration::ZERO);
+    }
+}
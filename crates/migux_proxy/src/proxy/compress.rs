@@ -0,0 +1,265 @@
+//! On-the-fly compression of proxied responses (`proxy_compress`).
+//!
+//! The coding is negotiated from the client's `Accept-Encoding` before the
+//! request goes upstream; whether a given response is compressed is decided
+//! once its headers are in (type, existing encoding, size).
+
+use std::io::{self, Write};
+
+use brotli::CompressorWriter;
+use flate2::{Compression, write::GzEncoder};
+
+use super::headers::header_fields;
+
+/// Brotli quality: well below the maximum (11), which is far too slow to
+/// run on every response.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_LGWIN: u32 = 22;
+const BROTLI_BUFFER: usize = 4096;
+
+/// A content coding migux can produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Coding {
+    Gzip,
+    Brotli,
+}
+
+impl Coding {
+    /// `Content-Encoding` token.
+    pub(super) fn token(self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Brotli => "br",
+        }
+    }
+}
+
+/// How to compress responses to one request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct CompressPlan {
+    pub(super) coding: Coding,
+    /// Responses with a smaller `Content-Length` are sent as-is.
+    pub(super) min_length: usize,
+}
+
+/// What a request's `Accept-Encoding` allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct AcceptEncoding {
+    /// Preferred coding migux can produce; `None` when neither is accepted.
+    pub(super) coding: Option<Coding>,
+    /// `identity;q=0` (or `*;q=0` without an `identity` entry): the body
+    /// must not be sent unencoded.
+    pub(super) identity_refused: bool,
+}
+
+/// Negotiate from the request's `Accept-Encoding` headers: brotli over gzip
+/// at equal weight, `q=0` refuses, `*` stands for any coding not listed.
+/// Without the header anything goes, identity included.
+pub(super) fn negotiate_encoding(req_headers: &str) -> AcceptEncoding {
+    let mut gzip = None;
+    let mut brotli = None;
+    let mut identity = None;
+    let mut any = None;
+    for (_, value) in
+        header_fields(req_headers).filter(|(name, _)| name.eq_ignore_ascii_case("accept-encoding"))
+    {
+        for item in value.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or("").trim();
+            let q = params
+                .filter_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then(|| value.trim().parse::<f32>().ok())?
+                })
+                .next()
+                .unwrap_or(1.0);
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                gzip = Some(q);
+            } else if coding.eq_ignore_ascii_case("br") {
+                brotli = Some(q);
+            } else if coding.eq_ignore_ascii_case("identity") {
+                identity = Some(q);
+            } else if coding == "*" {
+                any = Some(q);
+            }
+        }
+    }
+
+    let identity_refused = identity.or(any).is_some_and(|q| q <= 0.0);
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let coding = if brotli <= 0.0 && gzip <= 0.0 {
+        None
+    } else if brotli >= gzip {
+        Some(Coding::Brotli)
+    } else {
+        Some(Coding::Gzip)
+    };
+    AcceptEncoding {
+        coding,
+        identity_refused,
+    }
+}
+
+/// Media types worth compressing: text (except event streams, which must
+/// reach the client as they are produced), JSON, JavaScript, XML and SVG.
+pub(super) fn compressible_type(media_type: &[u8]) -> bool {
+    let media_type = String::from_utf8_lossy(media_type.trim_ascii()).to_ascii_lowercase();
+    if media_type == "text/event-stream" {
+        return false;
+    }
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/xml"
+                | "image/svg+xml"
+        )
+}
+
+/// Streaming encoder; output is collected in memory and drained after each
+/// write so the caller can frame it as a chunk.
+pub(super) enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    pub(super) fn new(coding: Coding) -> Self {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Coding::Brotli => Encoder::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_LGWIN,
+            ))),
+        }
+    }
+
+    /// Compress `data`, returning whatever output the encoder has produced
+    /// so far (often nothing for small writes).
+    pub(super) fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// End the stream and return the remaining output.
+    pub(super) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{Coding, Encoder, compressible_type, negotiate_encoding};
+
+    fn negotiate(req_headers: &str) -> Option<Coding> {
+        negotiate_encoding(req_headers).coding
+    }
+
+    fn identity_refused(accept_encoding: &str) -> bool {
+        negotiate_encoding(&request(accept_encoding)).identity_refused
+    }
+
+    fn request(accept_encoding: &str) -> String {
+        format!("GET / HTTP/1.1\r\nHost: x\r\nAccept-Encoding: {accept_encoding}\r\n")
+    }
+
+    #[test]
+    fn negotiate_prefers_brotli_and_honors_weights() {
+        assert_eq!(
+            negotiate(&request("gzip, deflate, br")),
+            Some(Coding::Brotli)
+        );
+        assert_eq!(negotiate(&request("gzip")), Some(Coding::Gzip));
+        assert_eq!(negotiate(&request("br;q=0.5, gzip")), Some(Coding::Gzip));
+        assert_eq!(negotiate(&request("br;q=0, gzip;q=0")), None);
+        assert_eq!(negotiate(&request("*")), Some(Coding::Brotli));
+        assert_eq!(negotiate(&request("*, br;q=0")), Some(Coding::Gzip));
+        assert_eq!(negotiate(&request("identity")), None);
+        assert_eq!(negotiate("GET / HTTP/1.1\r\nHost: x\r\n"), None);
+    }
+
+    #[test]
+    fn identity_is_refused_only_by_a_zero_weight() {
+        assert!(identity_refused("identity;q=0, gzip"));
+        assert!(identity_refused("gzip, *;q=0"));
+        assert!(identity_refused("Identity; q=0.0"));
+        assert!(!identity_refused("gzip, *;q=0, identity"));
+        assert!(!identity_refused("gzip;q=0"));
+        assert!(!identity_refused("identity;q=0.5"));
+        assert!(!negotiate_encoding("GET / HTTP/1.1\r\nHost: x\r\n").identity_refused);
+        assert_eq!(
+            negotiate_encoding(&request("identity;q=0, gzip")).coding,
+            Some(Coding::Gzip)
+        );
+    }
+
+    #[test]
+    fn only_textual_types_are_compressible() {
+        for media_type in [
+            &b"text/html"[..],
+            b"Text/Plain",
+            b"application/json",
+            b"application/problem+json",
+            b"image/svg+xml",
+        ] {
+            assert!(compressible_type(media_type), "{media_type:?}");
+        }
+        for media_type in [
+            &b"text/event-stream"[..],
+            b"image/png",
+            b"application/octet-stream",
+        ] {
+            assert!(!compressible_type(media_type), "{media_type:?}");
+        }
+    }
+
+    #[test]
+    fn gzip_output_round_trips() {
+        let mut encoder = Encoder::new(Coding::Gzip);
+        let mut out = encoder.write(b"hello ").expect("write");
+        out.extend(encoder.write(b"world").expect("write"));
+        out.extend(encoder.finish().expect("finish"));
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&out[..])
+            .read_to_string(&mut decoded)
+            .expect("gunzip");
+        assert_eq!(decoded, "hello world");
+    }
+
+    #[test]
+    fn brotli_output_round_trips() {
+        let mut encoder = Encoder::new(Coding::Brotli);
+        let mut out = encoder.write(&b"abc".repeat(1000)).expect("write");
+        out.extend(encoder.finish().expect("finish"));
+        assert!(out.len() < 3000);
+
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&out[..], 4096)
+            .read_to_end(&mut decoded)
+            .expect("unbrotli");
+        assert_eq!(decoded, b"abc".repeat(1000));
+    }
+}
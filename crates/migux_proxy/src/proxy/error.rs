@@ -0,0 +1,166 @@
+//! Categories of proxy failures. They travel inside `anyhow::Error` (as the
+//! error, or as context on an I/O error) and are read back with
+//! [`ProxyError::of`] to pick the failover path, the 502/504 status and the
+//! `proxy_error_detail` category.
+
+use std::{fmt, io};
+
+use super::response::ResponseAborted;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyError {
+    /// TCP connect or TLS handshake past `proxy_connect_timeout_secs`.
+    ConnectTimeout,
+    /// Nothing read from the upstream in time (read timeout, interim
+    /// deadline, response header budget, or `proxy_request_timeout_secs`
+    /// spent).
+    ReadTimeout,
+    /// Request not written upstream within `proxy_write_timeout_secs`.
+    WriteTimeout,
+    /// The upstream refused the connection.
+    Refused,
+    /// The upstream address did not resolve.
+    DnsFailure,
+    /// A malformed, ambiguous or truncated message (request or response).
+    BadFraming,
+    /// A request or response body over its limit.
+    BodyTooLarge,
+    /// The client stopped reading or sending.
+    ClientGone,
+    /// An upstream status that was not forwarded (5xx retried elsewhere).
+    UpstreamStatus(u16),
+}
+
+impl ProxyError {
+    /// The category carried by `err`, looking through context and
+    /// [`ResponseAborted`]. `None` for uncategorized errors.
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        if let Some(kind) = err.downcast_ref::<Self>() {
+            return Some(*kind);
+        }
+        err.downcast_ref::<ResponseAborted>()
+            .and_then(|aborted| Self::of(&aborted.0))
+    }
+
+    /// Status for the client when this was the last upstream failure:
+    /// 504 for timeouts, 502 otherwise. `None` for [`ProxyError::ClientGone`],
+    /// where nobody is left to answer.
+    pub fn status(self) -> Option<u16> {
+        match self {
+            Self::ConnectTimeout | Self::ReadTimeout | Self::WriteTimeout => Some(504),
+            Self::ClientGone => None,
+            Self::Refused
+            | Self::DnsFailure
+            | Self::BadFraming
+            | Self::BodyTooLarge
+            | Self::UpstreamStatus(_) => Some(502),
+        }
+    }
+
+    /// Category reported under `proxy_error_detail`.
+    pub(super) fn category(self) -> &'static str {
+        match self {
+            Self::ConnectTimeout | Self::ReadTimeout | Self::WriteTimeout => "timeout",
+            Self::Refused => "refused",
+            Self::DnsFailure => "dns",
+            Self::ClientGone => "client",
+            Self::BadFraming | Self::BodyTooLarge | Self::UpstreamStatus(_) => "error",
+        }
+    }
+
+    /// This category with `message` (e.g. the address) on top.
+    pub(super) fn context<C>(self, message: C) -> anyhow::Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        anyhow::Error::from(self).context(message)
+    }
+
+    /// A failed connect to resolved addresses, categorized when refused;
+    /// the I/O error stays in the chain. Lookups are categorized where
+    /// they happen (see `pool::resolve`).
+    pub(super) fn connect_failed(err: io::Error) -> anyhow::Error {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => anyhow::Error::from(err).context(Self::Refused),
+            _ => err.into(),
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConnectTimeout => f.write_str("Upstream connect timeout"),
+            Self::ReadTimeout => f.write_str("Upstream read timeout"),
+            Self::WriteTimeout => f.write_str("Upstream write timeout"),
+            Self::Refused => f.write_str("Upstream refused the connection"),
+            Self::DnsFailure => f.write_str("Upstream address did not resolve"),
+            Self::BadFraming => f.write_str("Malformed HTTP message"),
+            Self::BodyTooLarge => f.write_str("Body too large"),
+            Self::ClientGone => f.write_str("Client went away"),
+            Self::UpstreamStatus(status) => write!(f, "Upstream returned {status}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyError;
+    use crate::proxy::response::ResponseAborted;
+
+    #[test]
+    fn timeouts_map_to_504_and_other_upstream_failures_to_502() {
+        for kind in [
+            ProxyError::ConnectTimeout,
+            ProxyError::ReadTimeout,
+            ProxyError::WriteTimeout,
+        ] {
+            assert_eq!(kind.status(), Some(504), "{kind:?}");
+        }
+        for kind in [
+            ProxyError::Refused,
+            ProxyError::DnsFailure,
+            ProxyError::BadFraming,
+            ProxyError::BodyTooLarge,
+            ProxyError::UpstreamStatus(503),
+        ] {
+            assert_eq!(kind.status(), Some(502), "{kind:?}");
+        }
+        assert_eq!(ProxyError::ClientGone.status(), None);
+    }
+
+    #[test]
+    fn category_is_found_through_context_and_aborts() {
+        let err = ProxyError::ReadTimeout.context("Upstream read timeout from 10.0.0.1:80");
+        assert_eq!(ProxyError::of(&err), Some(ProxyError::ReadTimeout));
+        assert_eq!(
+            ProxyError::of(&err.context("while streaming")),
+            Some(ProxyError::ReadTimeout)
+        );
+
+        let io = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        let gone = anyhow::Error::from(io).context(ProxyError::ClientGone);
+        let aborted = anyhow::Error::from(ResponseAborted(gone));
+        assert_eq!(ProxyError::of(&aborted), Some(ProxyError::ClientGone));
+
+        assert_eq!(ProxyError::of(&anyhow::anyhow!("something else")), None);
+    }
+
+    #[test]
+    fn connect_errors_are_categorized() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let err = ProxyError::connect_failed(refused);
+        assert_eq!(ProxyError::of(&err), Some(ProxyError::Refused));
+        assert!(err.chain().any(|cause| cause.is::<std::io::Error>()));
+
+        // Lookup errors are not guessed from the connect error.
+        let dns = std::io::Error::other("failed to lookup address information");
+        assert_eq!(ProxyError::of(&ProxyError::connect_failed(dns)), None);
+        let invalid = std::io::Error::from(std::io::ErrorKind::InvalidInput);
+        assert_eq!(ProxyError::of(&ProxyError::connect_failed(invalid)), None);
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(ProxyError::of(&ProxyError::connect_failed(reset)), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! Upstream failure details for 502/504 bodies (`proxy_error_detail`).
+
+use std::io;
+
+use migux_http::responses::json_error_body;
+
+use super::error::ProxyError;
+
+/// Coarse cause of an upstream failure: `timeout`, `refused`, `dns`, `tls`,
+/// `reset`, or `error` for anything else (bad responses, limits).
+pub(super) fn category(err: &anyhow::Error) -> &'static str {
+    if let Some(kind) = ProxyError::of(err) {
+        return kind.category();
+    }
+    for cause in err.chain() {
+        if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+            match io_err.kind() {
+                io::ErrorKind::ConnectionRefused => return "refused",
+                io::ErrorKind::TimedOut => return "timeout",
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => return "reset",
+                _ => {}
+            }
+        }
+    }
+    // Uncategorized errors (TLS setup, raw I/O) only show up in the message.
+    let message = format!("{err:#}");
+    if message.contains("timeout") {
+        "timeout"
+    } else if message.contains("invalid socket address") {
+        "dns"
+    } else if message.contains("TLS") || message.contains("certificate") {
+        "tls"
+    } else {
+        "error"
+    }
+}
+
+/// Plain-text body: the status line, then `error:` and `upstreams:` lines.
+pub(super) fn text_body(status: &str, category: &str, addrs: &[String]) -> String {
+    format!(
+        "{status}\nerror: {category}\nupstreams: {}\n",
+        addrs.join(", ")
+    )
+}
+
+/// [`json_error_body`] plus `detail` and `upstreams` fields.
+pub(super) fn json_body(status: &str, category: &str, addrs: &[String]) -> String {
+    let base = json_error_body(status);
+    let upstreams = addrs
+        .iter()
+        .map(|addr| format!("\"{}\"", addr.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{},\"detail\":\"{category}\",\"upstreams\":[{upstreams}]}}",
+        base.trim_end_matches('}')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{category, json_body, text_body};
+    use crate::proxy::error::ProxyError;
+
+    #[test]
+    fn errors_are_categorized() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(category(&refused.into()), "refused");
+        assert_eq!(category(&ProxyError::ReadTimeout.into()), "timeout");
+        assert_eq!(
+            category(
+                &ProxyError::ConnectTimeout.context("Upstream connect timeout to 10.0.0.1:80")
+            ),
+            "timeout"
+        );
+        assert_eq!(
+            category(&ProxyError::UpstreamStatus(503).context("Upstream returned 503")),
+            "error"
+        );
+        let dns = ProxyError::DnsFailure.context("Cannot resolve upstream no-such-host.invalid:80");
+        assert_eq!(category(&dns), "dns");
+        let tls = anyhow::anyhow!("invalid peer certificate").context("TLS handshake failed");
+        assert_eq!(category(&tls), "tls");
+        assert_eq!(category(&anyhow::anyhow!("Invalid chunk size")), "error");
+    }
+
+    #[test]
+    fn bodies_list_category_and_addresses() {
+        let addrs = vec!["10.0.0.1:80".to_string(), "10.0.0.2:80".to_string()];
+        assert_eq!(
+            text_body("502 Bad Gateway", "refused", &addrs),
+            "502 Bad Gateway\nerror: refused\nupstreams: 10.0.0.1:80, 10.0.0.2:80\n"
+        );
+        assert_eq!(
+            json_body("504 Gateway Timeout", "timeout", &addrs),
+            r#"{"error":"Gateway Timeout","status":504,"detail":"timeout","upstreams":["10.0.0.1:80","10.0.0.2:80"]}"#
+        );
+    }
+}
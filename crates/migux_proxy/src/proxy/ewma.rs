@@ -0,0 +1,157 @@
+//! Latency-aware balancing (`strategy = "ewma"`).
+//!
+//! Each upstream address keeps an exponentially-weighted moving average of
+//! its response time plus the number of requests in flight. A request picks
+//! two random candidates and goes to the one with the lower
+//! `ewma × (in_flight + 1)` ("power of two choices").
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use super::{Proxy, random::random_u64};
+
+/// Weight of the newest latency sample in the moving average.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// Latency/in-flight state tracked per upstream address.
+#[derive(Debug, Default)]
+pub(super) struct UpstreamLoad {
+    /// EWMA of response time in microseconds, stored as `f64` bits.
+    ewma_micros: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl UpstreamLoad {
+    fn ewma_micros(&self) -> f64 {
+        f64::from_bits(self.ewma_micros.load(Ordering::Relaxed))
+    }
+
+    /// Lower is better. Unmeasured nodes score 0 so they get probed first.
+    fn score(&self) -> f64 {
+        self.ewma_micros() * (self.in_flight.load(Ordering::Relaxed) + 1) as f64
+    }
+
+    fn observe(&self, sample: Duration) {
+        let sample = sample.as_micros() as f64;
+        let _ = self
+            .ewma_micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let current = f64::from_bits(bits);
+                let next = if current == 0.0 {
+                    sample
+                } else {
+                    current + EWMA_ALPHA * (sample - current)
+                };
+                Some(next.to_bits())
+            });
+    }
+}
+
+/// Counts one request against an upstream until dropped.
+pub(super) struct InFlight {
+    load: Arc<UpstreamLoad>,
+}
+
+impl InFlight {
+    /// Feed the response time of a completed request into the average.
+    pub(super) fn finish(self, elapsed: Duration) {
+        self.load.observe(elapsed);
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.load.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Proxy {
+    fn upstream_load(&self, addr: &str) -> Arc<UpstreamLoad> {
+        self.loads.entry(addr.to_string()).or_default().clone()
+    }
+
+    /// Mark a request as in flight to `addr`.
+    pub(super) fn start_request(&self, addr: &str) -> InFlight {
+        let load = self.upstream_load(addr);
+        load.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { load }
+    }
+
+    /// Move the power-of-two-choices winner to the front; the rest keep their
+    /// order as fallbacks.
+    pub(super) fn ewma_order(&self, mut addrs: Vec<String>) -> Vec<String> {
+        if addrs.len() < 2 {
+            return addrs;
+        }
+        let a = random_index(addrs.len());
+        let b = (a + 1 + random_index(addrs.len() - 1)) % addrs.len();
+        let chosen =
+            if self.upstream_load(&addrs[b]).score() < self.upstream_load(&addrs[a]).score() {
+                b
+            } else {
+                a
+            };
+        let first = addrs.remove(chosen);
+        addrs.insert(0, first);
+        addrs
+    }
+}
+
+fn random_index(len: usize) -> usize {
+    (random_u64() % len as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Proxy;
+    use std::time::Duration;
+
+    fn addrs() -> Vec<String> {
+        vec!["fast".into(), "slow".into(), "slower".into()]
+    }
+
+    #[test]
+    fn faster_node_gets_more_traffic() {
+        let proxy = Proxy::new();
+        let latencies = [("fast", 2), ("slow", 20), ("slower", 50)];
+        for (addr, ms) in latencies {
+            proxy.start_request(addr).finish(Duration::from_millis(ms));
+        }
+
+        let mut hits = [0usize; 3];
+        for _ in 0..3000 {
+            let picked = proxy.ewma_order(addrs()).remove(0);
+            let idx = latencies.iter().position(|(a, _)| *a == picked).unwrap();
+            // Replay the node's fixed latency so the averages stay put.
+            proxy
+                .start_request(&picked)
+                .finish(Duration::from_millis(latencies[idx].1));
+            hits[idx] += 1;
+        }
+
+        // P2C: "fast" wins every pair it is drawn into (2 of 3 pairs).
+        assert!(hits[0] > 1800, "hits: {hits:?}");
+        assert!(hits[0] > hits[1] && hits[1] > hits[2], "hits: {hits:?}");
+        assert_eq!(hits[2], 0, "slowest node never wins a pair: {hits:?}");
+    }
+
+    #[test]
+    fn in_flight_requests_raise_the_score() {
+        let proxy = Proxy::new();
+        proxy.start_request("a").finish(Duration::from_millis(10));
+        proxy.start_request("b").finish(Duration::from_millis(15));
+
+        let busy: Vec<_> = (0..3).map(|_| proxy.start_request("a")).collect();
+        // a: 10ms × 4 in flight > b: 15ms × 1.
+        for _ in 0..20 {
+            assert_eq!(proxy.ewma_order(vec!["a".into(), "b".into()])[0], "b");
+        }
+        drop(busy);
+        assert_eq!(proxy.ewma_order(vec!["a".into(), "b".into()])[0], "a");
+    }
+}
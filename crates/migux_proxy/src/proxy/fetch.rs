@@ -0,0 +1,206 @@
+//! Buffered upstream fetches, for callers that need a whole response in
+//! memory (origin pulls) rather than streaming it to a client.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::BytesMut;
+use migux_config::{LocationConfig, MiguxConfig};
+use migux_http::keep_alive::KeepAlive;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::Proxy;
+
+/// An upstream response read to completion, with a chunked body decoded.
+#[derive(Debug)]
+pub struct FetchedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl FetchedResponse {
+    /// First value of header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Proxy {
+    /// `GET req_path` from the location's upstream through the regular proxy
+    /// path (failover, pooling, header rewrite, limits) and buffer the reply.
+    ///
+    /// When every upstream fails the result is the 502/504 that would have
+    /// been sent to a client; with `fallback_static` set it is
+    /// [`super::UpstreamsUnavailable`] instead.
+    pub async fn fetch(
+        &self,
+        location: &LocationConfig,
+        req_path: &str,
+        host: Option<&str>,
+        cfg: &Arc<MiguxConfig>,
+        client_addr: &SocketAddr,
+    ) -> anyhow::Result<FetchedResponse> {
+        // Identity encoding: the body is stored and served as-is.
+        let mut req_headers = format!("GET {req_path} HTTP/1.1\r\nAccept-Encoding: identity");
+        if let Some(host) = host {
+            req_headers.push_str("\r\nHost: ");
+            req_headers.push_str(host);
+        }
+        let mut sink = MemoryStream::default();
+        self.serve(
+            &mut sink,
+            &mut BytesMut::new(),
+            location,
+            &req_headers,
+            "GET",
+            req_path,
+            "HTTP/1.1",
+            0,
+            false,
+            KeepAlive::Close,
+            false,
+            None,
+            None,
+            cfg,
+            client_addr,
+        )
+        .await?;
+        parse_fetched(&sink.0)
+    }
+}
+
+/// Client side of a fetch: nothing to read, writes are collected.
+#[derive(Default)]
+struct MemoryStream(Vec<u8>);
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Split a complete response into status, headers and decoded body.
+fn parse_fetched(raw: &[u8]) -> anyhow::Result<FetchedResponse> {
+    let head_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Fetched response has no header terminator"))?;
+    let head = std::str::from_utf8(&raw[..head_end])?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Fetched response has an invalid status line"))?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    let body = &raw[head_end + 4..];
+    let chunked = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("transfer-encoding")
+            && value
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    });
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok(FetchedResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// Decode a complete chunked body (extensions and trailers are ignored).
+fn decode_chunked(mut raw: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(raw.len());
+    loop {
+        let line_end = raw
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow::anyhow!("Truncated chunk size line"))?;
+        let line = std::str::from_utf8(&raw[..line_end])?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| anyhow::anyhow!("Invalid chunk size '{size}'"))?;
+        raw = &raw[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if raw.len() < size + 2 || &raw[size..size + 2] != b"\r\n" {
+            anyhow::bail!("Truncated chunk");
+        }
+        body.extend_from_slice(&raw[..size]);
+        raw = &raw[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_chunked, parse_fetched};
+
+    #[test]
+    fn parses_content_length_and_chunked_responses() {
+        let plain = parse_fetched(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Length: 4\r\n\r\nbody",
+        )
+        .expect("plain");
+        assert_eq!(plain.status, 200);
+        assert_eq!(plain.header("content-type"), Some("text/css"));
+        assert_eq!(plain.body, b"body");
+
+        let chunked = parse_fetched(
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3;x=1\r\nabc\r\n2\r\nde\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        )
+        .expect("chunked");
+        assert_eq!(chunked.status, 404);
+        assert_eq!(chunked.body, b"abcde");
+    }
+
+    #[test]
+    fn malformed_chunks_are_rejected() {
+        assert!(decode_chunked(b"zz\r\nabc\r\n0\r\n\r\n").is_err());
+        assert!(decode_chunked(b"5\r\nabc\r\n0\r\n\r\n").is_err());
+        assert!(decode_chunked(b"3\r\nabc\r\n").is_err());
+        assert!(parse_fetched(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
@@ -1,3 +1,13 @@
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    io::Write,
+};
+
+use migux_config::{ForwardedHeader, ForwardedHost, SetHeader};
+
+use super::random::random_u64;
+
 /// =======================================================
 /// HEADER REWRITE (proxy semantics)
 /// =======================================================
@@ -11,147 +21,391 @@
 ///   - X-Real-IP
 ///   - X-Forwarded-Proto
 ///   - X-Forwarded-Host (si habia Host)
+///   - X-Forwarded-Port (con `forwarded_host = split`)
+///   - Forwarded (RFC 7239, con `proxy_forwarded_header = rfc7239|both`)
 ///
 /// Y ademas:
 /// - Controla `Connection` hacia upstream (keep-alive o close)
 ///   segun la politica que decidas en el caller.
+/// - Aplica `proxy_hide_header` / `proxy_set_header` de la location.
+///
+/// The header block is appended to `out` straight from slices of
+/// `req_headers`; only `proxy_set_header` values with variables and the
+/// request's `Connection` tokens allocate.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn rewrite_proxy_headers(
+    out: &mut Vec<u8>,
     req_headers: &str,
     client_ip: &str,
     scheme: &str,
     keep_alive: bool,
     body_len: usize,
     is_chunked: bool,
-) -> String {
-    let connection_tokens = collect_connection_tokens(req_headers);
-    let mut lines = req_headers.lines();
-    let _ = lines.next(); // request line (GET /... HTTP/1.1)
+    rules: &HeaderRules<'_>,
+) {
+    // Headers replaced (or removed) by proxy_set_header.
+    let overridden = |name: &str| {
+        rules
+            .set
+            .iter()
+            .any(|set| set.name.eq_ignore_ascii_case(name))
+    };
+    let mut host_value: Option<&str> = None;
+    // Trusted client `Forwarded` values, to be extended with our element.
+    let mut prior_forwarded: Vec<&str> = Vec::new();
+    let connection_tokens = ConnectionTokens::parse(req_headers);
 
-    let mut headers: Vec<(String, String)> = Vec::new();
-    let mut host_value: Option<String> = None;
+    for (name, value) in header_fields(req_headers) {
+        // Captura Host original
+        if name.eq_ignore_ascii_case("host") {
+            host_value = Some(value);
+        }
 
-    for line in lines {
-        let line = line.trim();
-        if line.is_empty() {
+        // Drop previous forwarded headers and hop-by-hop headers
+        // (no deben ser reenviados por un proxy)
+        if is_one_of(name, FORWARDED_HEADERS)
+            || is_one_of(name, HOP_BY_HOP_HEADERS)
+            || connection_tokens.contains(name)
+            || rules
+                .hide
+                .iter()
+                .any(|hide| hide.eq_ignore_ascii_case(name))
+            || overridden(name)
+        {
+            continue;
+        }
+        if name.eq_ignore_ascii_case("forwarded") {
+            if rules.trust_forwarded {
+                prior_forwarded.push(value);
+            }
             continue;
         }
 
-        if let Some((name, value)) = line.split_once(':') {
-            let name_trim = name.trim().to_string();
-            let value_trim = value.trim().to_string();
-            let name_lower = name_trim.to_ascii_lowercase();
+        push_name(out, name, rules.canonicalize_names);
+        push_value(out, value);
+    }
 
-            // Captura Host original
-            if name_trim.eq_ignore_ascii_case("host") {
-                host_value = Some(value_trim.clone());
+    // Add forward headers
+    if rules.forwarded_header.emits_xff() {
+        let (forwarded_host, forwarded_port) = match rules.forwarded_host {
+            ForwardedHost::Verbatim => (host_value, None),
+            ForwardedHost::Split => {
+                let (host, port) = host_value.map_or((None, None), |host| {
+                    let (host, port) = split_host_port(host);
+                    (Some(host), port)
+                });
+                let port =
+                    port.or(rules.server_port)
+                        .unwrap_or(if scheme == "https" { 443 } else { 80 });
+                (host, Some(port))
             }
-
-            // Drop previous forwarded headers
-            if name_trim.eq_ignore_ascii_case("x-forwarded-for")
-                || name_trim.eq_ignore_ascii_case("x-real-ip")
-                || name_trim.eq_ignore_ascii_case("x-forwarded-proto")
-                || name_trim.eq_ignore_ascii_case("x-forwarded-host")
+        };
+        let forwarded = [
+            ("X-Forwarded-For", Some(client_ip)),
+            ("X-Real-IP", Some(client_ip)),
+            ("X-Forwarded-Proto", Some(scheme)),
+            ("X-Forwarded-Host", forwarded_host),
+        ];
+        for (name, value) in forwarded {
+            if let Some(value) = value
+                && !overridden(name)
             {
-                continue;
+                push_header(out, name, value);
             }
+        }
+        if let Some(port) = forwarded_port
+            && !overridden("X-Forwarded-Port")
+        {
+            let _ = write!(out, "X-Forwarded-Port: {port}\r\n");
+        }
+    }
+    if rules.forwarded_header.emits_rfc7239() && !overridden("Forwarded") {
+        out.extend_from_slice(b"Forwarded: ");
+        for prior in &prior_forwarded {
+            out.extend_from_slice(prior.as_bytes());
+            out.extend_from_slice(b", ");
+        }
+        write_forwarded_element(out, client_ip, scheme, host_value);
+        out.extend_from_slice(b"\r\n");
+    } else {
+        for prior in &prior_forwarded {
+            push_header(out, "Forwarded", prior);
+        }
+    }
 
-            // Drop hop-by-hop headers
-            // (no deben ser reenviados por un proxy)
-            if name_trim.eq_ignore_ascii_case("connection")
-                || name_trim.eq_ignore_ascii_case("keep-alive")
-                || name_trim.eq_ignore_ascii_case("proxy-connection")
-                || name_trim.eq_ignore_ascii_case("te")
-                || name_trim.eq_ignore_ascii_case("trailer")
-                || name_trim.eq_ignore_ascii_case("transfer-encoding")
-                || name_trim.eq_ignore_ascii_case("upgrade")
-                || name_trim.eq_ignore_ascii_case("content-length")
-            {
-                continue;
-            }
+    // proxy_set_header: reemplaza (o borra, si el valor queda vacio); a later
+    // entry for the same header wins.
+    let mut request_id: Option<String> = None;
+    for (idx, set) in rules.set.iter().enumerate() {
+        if rules.set[idx + 1..]
+            .iter()
+            .any(|later| later.name.eq_ignore_ascii_case(&set.name))
+        {
+            continue;
+        }
+        let value = set.render(|var| match var {
+            "host" => host_value.unwrap_or_default().to_string(),
+            "remote_addr" => client_ip.to_string(),
+            "request_uri" => rules.request_uri.to_string(),
+            "scheme" => scheme.to_string(),
+            "request_id" => request_id.get_or_insert_with(new_request_id).clone(),
+            _ => String::new(),
+        });
+        if !value.is_empty() {
+            push_name(out, &set.name, rules.canonicalize_names);
+            push_value(out, &value);
+        }
+    }
 
-            if connection_tokens.contains(&name_lower) {
-                continue;
-            }
+    let connection_value = if keep_alive { "keep-alive" } else { "close" };
+    push_header(out, "Connection", connection_value);
 
-            headers.push((name_trim, value_trim));
-        }
+    if is_chunked {
+        push_header(out, "Transfer-Encoding", "chunked");
+    } else {
+        let _ = write!(out, "Content-Length: {body_len}\r\n");
     }
+}
 
-    // Add forward headers
-    headers.push(("X-Forwarded-For".to_string(), client_ip.to_string()));
-    headers.push(("X-Real-IP".to_string(), client_ip.to_string()));
-    headers.push(("X-Forwarded-Proto".to_string(), scheme.to_string()));
+/// Client-supplied forwarding headers; replaced by our own.
+const FORWARDED_HEADERS: &[&str] = &[
+    "x-forwarded-for",
+    "x-real-ip",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-forwarded-port",
+];
+
+/// Hop-by-hop headers, plus the framing headers we set ourselves.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+fn is_one_of(name: &str, list: &[&str]) -> bool {
+    list.iter().any(|item| item.eq_ignore_ascii_case(name))
+}
+
+/// Trimmed `(name, value)` pairs after the request line.
+pub(super) fn header_fields(req_headers: &str) -> impl Iterator<Item = (&str, &str)> {
+    req_headers.lines().skip(1).filter_map(|line| {
+        let (name, value) = line.trim().split_once(':')?;
+        Some((name.trim(), value.trim()))
+    })
+}
+
+/// Header names listed in the request's `Connection` headers (and so
+/// hop-by-hop), parsed once per request so each header is a set lookup.
+struct ConnectionTokens<'a>(HashSet<AsciiCaseless<'a>>);
 
-    if let Some(h) = host_value {
-        headers.push(("X-Forwarded-Host".to_string(), h));
+impl<'a> ConnectionTokens<'a> {
+    fn parse(req_headers: &'a str) -> Self {
+        let tokens = header_fields(req_headers)
+            .filter(|(header, _)| header.eq_ignore_ascii_case("connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(|token| token.trim().trim_matches(|c| c == '"' || c == '\''))
+            .filter(|token| !token.is_empty())
+            .map(AsciiCaseless)
+            .collect();
+        Self(tokens)
     }
 
-    let connection_value = if keep_alive { "keep-alive" } else { "close" };
-    headers.push(("Connection".to_string(), connection_value.to_string()));
+    fn contains(&self, name: &str) -> bool {
+        !self.0.is_empty() && self.0.contains(&AsciiCaseless(name))
+    }
+}
 
-    if is_chunked {
-        headers.push(("Transfer-Encoding".to_string(), "chunked".to_string()));
+/// `&str` hashed and compared ignoring ASCII case, so lookups don't need a
+/// lowercased copy of the header name.
+struct AsciiCaseless<'a>(&'a str);
+
+impl PartialEq for AsciiCaseless<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(other.0)
+    }
+}
+
+impl Eq for AsciiCaseless<'_> {}
+
+impl Hash for AsciiCaseless<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.0.bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+        state.write_u8(0xff);
+    }
+}
+
+/// Split a Host value into hostname and port: `example.com:8080`,
+/// `[::1]:8080`. No port (or an unparsable one) leaves the value whole.
+fn split_host_port(host: &str) -> (&str, Option<u16>) {
+    let colon = if host.starts_with('[') {
+        host.rfind("]:").map(|idx| idx + 1)
+    } else if host.matches(':').count() == 1 {
+        host.find(':')
     } else {
-        headers.push(("Content-Length".to_string(), body_len.to_string()));
+        None
+    };
+    match colon.and_then(|idx| Some((&host[..idx], host[idx + 1..].parse().ok()?))) {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
     }
+}
 
-    // Serializa headers con CRLF
-    let mut out = String::new();
-    for (name, value) in headers {
-        out.push_str(&name);
-        out.push_str(": ");
-        out.push_str(&value);
-        out.push_str("\r\n");
+/// One RFC 7239 element: `for=<ip>;proto=<scheme>[;host=<host>]`. IPv6
+/// nodes are quoted and bracketed; hosts are quoted unless a plain token.
+fn write_forwarded_element(out: &mut Vec<u8>, client_ip: &str, scheme: &str, host: Option<&str>) {
+    if client_ip.parse::<std::net::Ipv6Addr>().is_ok() {
+        let _ = write!(out, "for=\"[{client_ip}]\"");
+    } else {
+        out.extend_from_slice(b"for=");
+        push_token_or_quoted(out, client_ip);
     }
+    let _ = write!(out, ";proto={scheme}");
+    if let Some(host) = host {
+        out.extend_from_slice(b";host=");
+        push_token_or_quoted(out, host);
+    }
+}
 
-    out
+/// `value` as an RFC 7230 token when it is one, else as a quoted-string.
+fn push_token_or_quoted(out: &mut Vec<u8>, value: &str) {
+    let is_tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    if !value.is_empty() && value.bytes().all(is_tchar) {
+        out.extend_from_slice(value.as_bytes());
+        return;
+    }
+    out.push(b'"');
+    for b in value.bytes() {
+        if b == b'"' || b == b'\\' {
+            out.push(b'\\');
+        }
+        out.push(b);
+    }
+    out.push(b'"');
 }
 
-fn collect_connection_tokens(req_headers: &str) -> std::collections::HashSet<String> {
-    let mut tokens = std::collections::HashSet::new();
-    let mut lines = req_headers.lines();
-    let _ = lines.next(); // skip request line
-    for line in lines {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+fn push_header(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    push_value(out, value);
+}
+
+/// A header name as given, or in canonical form.
+fn push_name(out: &mut Vec<u8>, name: &str, canonical: bool) {
+    if canonical {
+        push_canonical_name(out, name);
+    } else {
+        out.extend_from_slice(name.as_bytes());
+    }
+}
+
+fn push_value(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+/// `-`-separated segments spelled differently from plain title case.
+const CANONICAL_SEGMENTS: &[&str] = &[
+    "CSRF",
+    "DNT",
+    "ETag",
+    "IP",
+    "MD5",
+    "TE",
+    "UA",
+    "WebSocket",
+    "WWW",
+    "XSS",
+];
+
+/// `name` in Train-Case (`x-forwarded-for` -> `X-Forwarded-For`), with the
+/// usual spellings of acronyms (`ETag`, `WWW-Authenticate`, `X-Real-IP`).
+fn push_canonical_name(out: &mut Vec<u8>, name: &str) {
+    for (i, segment) in name.split('-').enumerate() {
+        if i > 0 {
+            out.push(b'-');
         }
-        let Some((name, value)) = line.split_once(':') else {
-            continue;
-        };
-        if !name.trim().eq_ignore_ascii_case("connection") {
+        if let Some(known) = CANONICAL_SEGMENTS
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(segment))
+        {
+            out.extend_from_slice(known.as_bytes());
             continue;
         }
-        for token in split_header_tokens(value) {
-            tokens.insert(token);
+        let mut bytes = segment.bytes();
+        if let Some(first) = bytes.next() {
+            out.push(first.to_ascii_uppercase());
         }
+        out.extend(bytes.map(|b| b.to_ascii_lowercase()));
     }
-    tokens
 }
 
-fn split_header_tokens(value: &str) -> impl Iterator<Item = String> + '_ {
-    value.split(',').filter_map(|token| {
-        let trimmed = token.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(
-                trimmed
-                    .trim_matches(|c| c == '"' || c == '\'')
-                    .to_ascii_lowercase(),
-            )
-        }
-    })
+/// Per-location header manipulation applied by `rewrite_proxy_headers`.
+#[derive(Debug, Default)]
+pub(super) struct HeaderRules<'a> {
+    pub set: &'a [SetHeader],
+    /// Lowercased client header names to drop.
+    pub hide: &'a [String],
+    /// Original request target, for `$request_uri`.
+    pub request_uri: &'a str,
+    /// How the client's Host becomes `X-Forwarded-Host`/`X-Forwarded-Port`.
+    pub forwarded_host: ForwardedHost,
+    /// Port of the listener the request came in on; `X-Forwarded-Port`
+    /// when the Host header carries none.
+    pub server_port: Option<u16>,
+    /// `X-Forwarded-*`, RFC 7239 `Forwarded`, or both.
+    pub forwarded_header: ForwardedHeader,
+    /// Append to the client's `Forwarded` header instead of dropping it.
+    pub trust_forwarded: bool,
+    /// Forward client and `proxy_set_header` names in canonical form.
+    pub canonicalize_names: bool,
+}
+
+/// 128-bit random id as 32 hex digits (`$request_id`).
+fn new_request_id() -> String {
+    format!("{:016x}{:016x}", random_u64(), random_u64())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::rewrite_proxy_headers;
+    use super::{HeaderRules, push_canonical_name, rewrite_proxy_headers, split_host_port};
+    use migux_config::{ForwardedHeader, ForwardedHost, SetHeader};
+
+    fn rewrite(
+        req: &str,
+        client_ip: &str,
+        scheme: &str,
+        keep_alive: bool,
+        body_len: usize,
+        is_chunked: bool,
+        rules: &HeaderRules<'_>,
+    ) -> String {
+        let mut out = Vec::new();
+        rewrite_proxy_headers(
+            &mut out, req, client_ip, scheme, keep_alive, body_len, is_chunked, rules,
+        );
+        String::from_utf8(out).expect("utf8")
+    }
 
     #[test]
     fn rewrite_proxy_headers_drops_connection_token_headers() {
         let req = "GET / HTTP/1.1\r\nHost: example\r\nConnection: \"Foo\", keep-alive\r\nFoo: bar\r\nX-Test: ok\r\n\r\n";
-        let out = rewrite_proxy_headers(req, "127.0.0.1", "http", true, 0, false);
+        let out = rewrite(
+            req,
+            "127.0.0.1",
+            "http",
+            true,
+            0,
+            false,
+            &HeaderRules::default(),
+        );
         assert!(!out.contains("\r\nFoo:"));
         assert!(out.contains("\r\nX-Test: ok\r\n"));
         assert!(out.contains("\r\nConnection: keep-alive\r\n"));
@@ -160,8 +414,550 @@ mod tests {
     #[test]
     fn rewrite_proxy_headers_sets_chunked_without_content_length() {
         let req = "POST /upload HTTP/1.1\r\nHost: example\r\nTransfer-Encoding: chunked\r\nContent-Length: 10\r\n\r\n";
-        let out = rewrite_proxy_headers(req, "127.0.0.1", "https", true, 10, true);
+        let out = rewrite(
+            req,
+            "127.0.0.1",
+            "https",
+            true,
+            10,
+            true,
+            &HeaderRules::default(),
+        );
         assert!(out.contains("\r\nTransfer-Encoding: chunked\r\n"));
         assert!(!out.contains("\r\nContent-Length: 10\r\n"));
     }
+
+    #[test]
+    fn host_is_split_into_forwarded_host_and_port() {
+        let forwarded = |host: &str, scheme, server_port| {
+            let req = format!("GET / HTTP/1.1\r\nHost: {host}\r\nX-Forwarded-Port: 1\r\n\r\n");
+            let rules = HeaderRules {
+                server_port,
+                ..Default::default()
+            };
+            let out = rewrite(&req, "127.0.0.1", scheme, true, 0, false, &rules);
+            let value = |name: &str| {
+                out.lines()
+                    .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+                    .map(str::to_string)
+            };
+            (value("X-Forwarded-Host"), value("X-Forwarded-Port"))
+        };
+        let some = |host: &str, port: &str| (Some(host.to_string()), Some(port.to_string()));
+
+        assert_eq!(
+            forwarded("example.com:8080", "http", Some(80)),
+            some("example.com", "8080")
+        );
+        assert_eq!(
+            forwarded("[::1]:8443", "https", None),
+            some("[::1]", "8443")
+        );
+        // Without a port in Host: the listener's port, else the scheme's.
+        assert_eq!(
+            forwarded("example.com", "http", Some(8080)),
+            some("example.com", "8080")
+        );
+        assert_eq!(
+            forwarded("example.com", "https", None),
+            some("example.com", "443")
+        );
+        assert_eq!(
+            forwarded("example.com", "http", None),
+            some("example.com", "80")
+        );
+    }
+
+    #[test]
+    fn verbatim_forwarded_host_keeps_the_port_in_host() {
+        let req = "GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n";
+        let rules = HeaderRules {
+            forwarded_host: ForwardedHost::Verbatim,
+            server_port: Some(8080),
+            ..Default::default()
+        };
+        let out = rewrite(req, "127.0.0.1", "http", true, 0, false, &rules);
+        assert!(
+            out.contains("\r\nX-Forwarded-Host: example.com:8080\r\n"),
+            "got: {out}"
+        );
+        assert!(!out.contains("X-Forwarded-Port"), "got: {out}");
+    }
+
+    fn forwarded_values(out: &str) -> Vec<&str> {
+        out.lines()
+            .filter_map(|line| line.strip_prefix("Forwarded: "))
+            .collect()
+    }
+
+    #[test]
+    fn rfc7239_forwarded_for_ipv4() {
+        let req = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let rules = HeaderRules {
+            forwarded_header: ForwardedHeader::Rfc7239,
+            ..Default::default()
+        };
+        let out = rewrite(req, "192.0.2.60", "http", true, 0, false, &rules);
+        assert_eq!(
+            forwarded_values(&out),
+            ["for=192.0.2.60;proto=http;host=example.com"]
+        );
+        assert!(!out.contains("X-Forwarded-"), "got: {out}");
+        assert!(!out.contains("X-Real-IP"), "got: {out}");
+    }
+
+    #[test]
+    fn rfc7239_forwarded_for_ipv6_is_quoted_and_bracketed() {
+        let req = "GET / HTTP/1.1\r\nHost: example.com:8443\r\n\r\n";
+        let rules = HeaderRules {
+            forwarded_header: ForwardedHeader::Both,
+            ..Default::default()
+        };
+        let out = rewrite(req, "2001:db8::17", "https", true, 0, false, &rules);
+        assert_eq!(
+            forwarded_values(&out),
+            [r#"for="[2001:db8::17]";proto=https;host="example.com:8443""#]
+        );
+        assert!(
+            out.contains("\r\nX-Forwarded-For: 2001:db8::17\r\n"),
+            "got: {out}"
+        );
+    }
+
+    #[test]
+    fn client_forwarded_is_appended_to_only_when_trusted() {
+        let req = "GET / HTTP/1.1\r\nHost: example.com\r\nForwarded: for=198.51.100.1\r\nForwarded: for=\"[2001:db8::1]\";proto=https\r\n\r\n";
+        let rewrite_with = |trust_forwarded| {
+            let rules = HeaderRules {
+                forwarded_header: ForwardedHeader::Rfc7239,
+                trust_forwarded,
+                ..Default::default()
+            };
+            rewrite(req, "10.0.0.2", "http", true, 0, false, &rules)
+        };
+
+        let trusted = rewrite_with(true);
+        assert_eq!(
+            forwarded_values(&trusted),
+            [
+                r#"for=198.51.100.1, for="[2001:db8::1]";proto=https, for=10.0.0.2;proto=http;host=example.com"#
+            ]
+        );
+
+        let untrusted = rewrite_with(false);
+        assert_eq!(
+            forwarded_values(&untrusted),
+            ["for=10.0.0.2;proto=http;host=example.com"]
+        );
+
+        // xff-only: a trusted header passes through untouched, an untrusted
+        // one is dropped.
+        let xff = |trust_forwarded| {
+            let rules = HeaderRules {
+                trust_forwarded,
+                ..Default::default()
+            };
+            rewrite(req, "10.0.0.2", "http", true, 0, false, &rules)
+        };
+        assert_eq!(forwarded_values(&xff(true)).len(), 2);
+        assert!(forwarded_values(&xff(false)).is_empty());
+    }
+
+    #[test]
+    fn split_host_port_handles_ipv6_and_bad_ports() {
+        assert_eq!(split_host_port("example.com"), ("example.com", None));
+        assert_eq!(split_host_port("[::1]"), ("[::1]", None));
+        assert_eq!(split_host_port("[::1]:80"), ("[::1]", Some(80)));
+        assert_eq!(
+            split_host_port("example.com:http"),
+            ("example.com:http", None)
+        );
+        assert_eq!(split_host_port("::1"), ("::1", None));
+    }
+
+    fn set(raw: &str) -> SetHeader {
+        SetHeader::parse(raw).expect("valid proxy_set_header")
+    }
+
+    #[test]
+    fn set_header_substitutes_variables() {
+        let req = "GET /a?b=1 HTTP/1.1\r\nHost: example.com\r\nX-Client: old\r\n\r\n";
+        let set = [
+            set("X-Client: $remote_addr via $scheme://$host$request_uri"),
+            set("X-Request-Id: $request_id"),
+            set("Authorization: Bearer s3cr3t"),
+        ];
+        let rules = HeaderRules {
+            set: &set,
+            hide: &[],
+            request_uri: "/a?b=1",
+            ..Default::default()
+        };
+        let out = rewrite(req, "10.0.0.7", "https", true, 0, false, &rules);
+        assert!(out.contains("\r\nX-Client: 10.0.0.7 via https://example.com/a?b=1\r\n"));
+        assert!(!out.contains("X-Client: old"));
+        assert!(out.contains("\r\nAuthorization: Bearer s3cr3t\r\n"));
+        let id = out
+            .lines()
+            .find_map(|l| l.strip_prefix("X-Request-Id: "))
+            .expect("request id");
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn hide_and_empty_set_remove_headers() {
+        let req = "GET / HTTP/1.1\r\nHost: example\r\nCookie: session=1\r\nX-Debug: on\r\nAccept: */*\r\n\r\n";
+        let set = [set("X-Debug:")];
+        let hide = ["cookie".to_string()];
+        let rules = HeaderRules {
+            set: &set,
+            hide: &hide,
+            request_uri: "/",
+            ..Default::default()
+        };
+        let out = rewrite(req, "127.0.0.1", "http", true, 0, false, &rules);
+        assert!(!out.contains("Cookie"));
+        assert!(!out.contains("X-Debug"));
+        assert!(out.contains("\r\nAccept: */*\r\n"));
+    }
+
+    fn canonical(name: &str) -> String {
+        let mut out = Vec::new();
+        push_canonical_name(&mut out, name);
+        String::from_utf8(out).expect("utf8")
+    }
+
+    #[test]
+    fn header_names_are_canonicalized_to_train_case() {
+        for (name, expected) in [
+            ("content-type", "Content-Type"),
+            ("ACCEPT-LANGUAGE", "Accept-Language"),
+            ("x-request-id", "X-Request-Id"),
+            ("user-agent", "User-Agent"),
+            ("host", "Host"),
+        ] {
+            assert_eq!(canonical(name), expected);
+        }
+    }
+
+    #[test]
+    fn acronym_segments_keep_their_usual_spelling() {
+        for (name, expected) in [
+            ("etag", "ETag"),
+            ("if-none-match", "If-None-Match"),
+            ("www-authenticate", "WWW-Authenticate"),
+            ("x-real-ip", "X-Real-IP"),
+            ("content-md5", "Content-MD5"),
+            ("sec-websocket-key", "Sec-WebSocket-Key"),
+            ("x-xss-protection", "X-XSS-Protection"),
+            ("dnt", "DNT"),
+            ("te", "TE"),
+        ] {
+            assert_eq!(canonical(name), expected);
+        }
+    }
+
+    #[test]
+    fn canonicalize_names_rewrites_forwarded_names_only_when_enabled() {
+        let req = "GET / HTTP/1.1\r\nhost: example\r\ncontent-type: text/plain\r\nx-custom-thing: 1\r\n\r\n";
+        let set = [set("x-api-key: k")];
+        let rules = HeaderRules {
+            set: &set,
+            request_uri: "/",
+            canonicalize_names: true,
+            ..Default::default()
+        };
+        let out = rewrite(req, "127.0.0.1", "http", true, 0, false, &rules);
+        assert!(out.starts_with("Host: example\r\n"), "got: {out}");
+        assert!(out.contains("\r\nContent-Type: text/plain\r\n"));
+        assert!(out.contains("\r\nX-Custom-Thing: 1\r\n"));
+        assert!(out.contains("\r\nX-Api-Key: k\r\n"));
+
+        let rules = HeaderRules {
+            request_uri: "/",
+            ..Default::default()
+        };
+        let out = rewrite(req, "127.0.0.1", "http", true, 0, false, &rules);
+        assert!(out.contains("\r\ncontent-type: text/plain\r\n"));
+    }
+
+    #[test]
+    fn many_connection_tokens_drop_only_the_listed_headers() {
+        let mut req = String::from("GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive");
+        for i in 0..500 {
+            req.push_str(&format!(", X-Hop-{i}"));
+        }
+        req.push_str("\r\n");
+        for i in 0..1_000 {
+            req.push_str(&format!("x-hop-{i}: v\r\n"));
+        }
+        req.push_str("\r\n");
+        let rules = HeaderRules {
+            request_uri: "/",
+            ..Default::default()
+        };
+        let out = rewrite(&req, "127.0.0.1", "http", true, 0, false, &rules);
+        assert!(!out.contains("x-hop-499:"), "got: {out}");
+        assert!(out.contains("\r\nx-hop-500: v\r\n"));
+        assert!(out.contains("\r\nx-hop-999: v\r\n"));
+    }
+
+    /// The allocation-heavy implementation this module replaced, kept as a
+    /// reference for output parity.
+    mod legacy {
+        use super::super::{HeaderRules, new_request_id};
+
+        pub(super) fn legacy_rewrite(
+            req_headers: &str,
+            client_ip: &str,
+            scheme: &str,
+            keep_alive: bool,
+            body_len: usize,
+            is_chunked: bool,
+            rules: &HeaderRules<'_>,
+        ) -> String {
+            let connection_tokens = collect_connection_tokens(req_headers);
+            let mut lines = req_headers.lines();
+            let _ = lines.next(); // request line (GET /... HTTP/1.1)
+
+            let mut headers: Vec<(String, String)> = Vec::new();
+            let mut host_value: Option<String> = None;
+
+            for line in lines {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+
+                if let Some((name, value)) = line.split_once(':') {
+                    let name_trim = name.trim().to_string();
+                    let value_trim = value.trim().to_string();
+                    let name_lower = name_trim.to_ascii_lowercase();
+
+                    // Captura Host original
+                    if name_trim.eq_ignore_ascii_case("host") {
+                        host_value = Some(value_trim.clone());
+                    }
+
+                    // Drop previous forwarded headers
+                    if name_trim.eq_ignore_ascii_case("x-forwarded-for")
+                        || name_trim.eq_ignore_ascii_case("x-real-ip")
+                        || name_trim.eq_ignore_ascii_case("x-forwarded-proto")
+                        || name_trim.eq_ignore_ascii_case("x-forwarded-host")
+                    {
+                        continue;
+                    }
+
+                    // Drop hop-by-hop headers
+                    // (no deben ser reenviados por un proxy)
+                    if name_trim.eq_ignore_ascii_case("connection")
+                        || name_trim.eq_ignore_ascii_case("keep-alive")
+                        || name_trim.eq_ignore_ascii_case("proxy-connection")
+                        || name_trim.eq_ignore_ascii_case("te")
+                        || name_trim.eq_ignore_ascii_case("trailer")
+                        || name_trim.eq_ignore_ascii_case("transfer-encoding")
+                        || name_trim.eq_ignore_ascii_case("upgrade")
+                        || name_trim.eq_ignore_ascii_case("content-length")
+                    {
+                        continue;
+                    }
+
+                    if connection_tokens.contains(&name_lower) {
+                        continue;
+                    }
+
+                    if rules.hide.contains(&name_lower) {
+                        continue;
+                    }
+
+                    headers.push((name_trim, value_trim));
+                }
+            }
+
+            // Add forward headers
+            headers.push(("X-Forwarded-For".to_string(), client_ip.to_string()));
+            headers.push(("X-Real-IP".to_string(), client_ip.to_string()));
+            headers.push(("X-Forwarded-Proto".to_string(), scheme.to_string()));
+
+            if let Some(h) = &host_value {
+                headers.push(("X-Forwarded-Host".to_string(), h.clone()));
+            }
+
+            // proxy_set_header: reemplaza (o borra, si el valor queda vacio)
+            let mut request_id: Option<String> = None;
+            for set in rules.set {
+                let value = set.render(|var| match var {
+                    "host" => host_value.clone().unwrap_or_default(),
+                    "remote_addr" => client_ip.to_string(),
+                    "request_uri" => rules.request_uri.to_string(),
+                    "scheme" => scheme.to_string(),
+                    "request_id" => request_id.get_or_insert_with(new_request_id).clone(),
+                    _ => String::new(),
+                });
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&set.name));
+                if !value.is_empty() {
+                    headers.push((set.name.clone(), value));
+                }
+            }
+
+            let connection_value = if keep_alive { "keep-alive" } else { "close" };
+            headers.push(("Connection".to_string(), connection_value.to_string()));
+
+            if is_chunked {
+                headers.push(("Transfer-Encoding".to_string(), "chunked".to_string()));
+            } else {
+                headers.push(("Content-Length".to_string(), body_len.to_string()));
+            }
+
+            // Serializa headers con CRLF
+            let mut out = String::new();
+            for (name, value) in headers {
+                out.push_str(&name);
+                out.push_str(": ");
+                out.push_str(&value);
+                out.push_str("\r\n");
+            }
+
+            out
+        }
+
+        fn collect_connection_tokens(req_headers: &str) -> std::collections::HashSet<String> {
+            let mut tokens = std::collections::HashSet::new();
+            let mut lines = req_headers.lines();
+            let _ = lines.next(); // skip request line
+            for line in lines {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let Some((name, value)) = line.split_once(':') else {
+                    continue;
+                };
+                if !name.trim().eq_ignore_ascii_case("connection") {
+                    continue;
+                }
+                for token in split_header_tokens(value) {
+                    tokens.insert(token);
+                }
+            }
+            tokens
+        }
+
+        fn split_header_tokens(value: &str) -> impl Iterator<Item = String> + '_ {
+            value.split(',').filter_map(|token| {
+                let trimmed = token.trim();
+                if trimmed.is_empty() {
+                    None
+                } else {
+                    Some(
+                        trimmed
+                            .trim_matches(|c| c == '"' || c == '\'')
+                            .to_ascii_lowercase(),
+                    )
+                }
+            })
+        }
+    }
+
+    /// Counts heap allocations made by the current thread.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    fn allocations_in(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(|n| n.get());
+        f();
+        ALLOCATIONS.with(|n| n.get()) - before
+    }
+
+    const BROWSER_REQUEST: &str = "GET /app?q=1 HTTP/1.1\r\nHost: example.com\r\nUser-Agent: bench/1.0\r\nAccept: text/html,*/*\r\nAccept-Language: en\r\nAccept-Encoding: gzip, br\r\nCookie: a=1; b=2\r\nConnection: keep-alive, X-Hop\r\nX-Hop: 1\r\nX-Forwarded-For: 10.9.9.9\r\nUpgrade-Insecure-Requests: 1\r\n\r\n";
+
+    #[test]
+    fn output_matches_previous_implementation() {
+        let requests = [
+            BROWSER_REQUEST,
+            "POST /u HTTP/1.1\r\nhost: a\r\nTransfer-Encoding: chunked\r\nTE: trailers\r\nContent-Length: 3\r\n\r\n",
+            "GET / HTTP/1.0\r\nHost: a\r\nHost: b\r\nno-colon line\r\nConnection: \"Foo\"\r\nfoo: x\r\n  X-Pad :  spaced  \r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+        ];
+        let set = [
+            set("X-Forwarded-Proto: $scheme"),
+            set("X-Tag: one"),
+            set("Accept:"),
+            set("x-tag: $host$request_uri"),
+            set("X-Client: $remote_addr"),
+        ];
+        let hide = ["cookie".to_string(), "x-pad".to_string()];
+        // The previous implementation forwarded Host verbatim.
+        let rule_sets = [
+            HeaderRules {
+                forwarded_host: ForwardedHost::Verbatim,
+                ..Default::default()
+            },
+            HeaderRules {
+                set: &set,
+                hide: &hide,
+                request_uri: "/app?q=1",
+                forwarded_host: ForwardedHost::Verbatim,
+                ..Default::default()
+            },
+        ];
+        for req in requests {
+            for rules in &rule_sets {
+                for (keep_alive, len, chunked) in
+                    [(true, 0, false), (false, 12, false), (true, 0, true)]
+                {
+                    assert_eq!(
+                        rewrite(req, "10.0.0.1", "https", keep_alive, len, chunked, rules),
+                        legacy::legacy_rewrite(
+                            req, "10.0.0.1", "https", keep_alive, len, chunked, rules
+                        ),
+                        "request: {req:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rewrite_does_not_allocate_per_header() {
+        let rules = HeaderRules::default();
+        let mut out = Vec::with_capacity(1024);
+        let new = allocations_in(|| {
+            rewrite_proxy_headers(
+                &mut out,
+                BROWSER_REQUEST,
+                "10.0.0.1",
+                "http",
+                true,
+                0,
+                false,
+                &rules,
+            )
+        });
+        let old = allocations_in(|| {
+            let _ =
+                legacy::legacy_rewrite(BROWSER_REQUEST, "10.0.0.1", "http", true, 0, false, &rules);
+        });
+        // Into a pre-sized buffer, only the set of `Connection` tokens.
+        assert!(new <= 1, "{new} allocations");
+        assert!(old > 20, "previous implementation: {old} allocations");
+    }
 }
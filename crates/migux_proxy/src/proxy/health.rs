@@ -14,16 +14,65 @@ use super::{Proxy, upstream};
 pub(super) struct HealthPolicy {
     pub(super) fail_threshold: u32,
     pub(super) cooldown: Duration,
+    /// Cap on a `Retry-After` cooldown; zero ignores `Retry-After`.
+    pub(super) max_retry_after: Duration,
+    /// Failed active checks in a row that hold a node down.
+    pub(super) unhealthy_threshold: u32,
+    /// Successful active checks in a row that bring a down node back.
+    pub(super) healthy_threshold: u32,
 }
 
 /// Health state tracked per upstream address.
 #[derive(Debug, Clone, Default)]
 pub(super) struct UpstreamHealth {
+    /// Failed requests in a row (`fail_threshold`).
     pub(super) failures: u32,
+    /// Failed active checks in a row (`unhealthy_threshold`).
+    pub(super) check_failures: u32,
+    /// Successful active checks in a row while down.
+    pub(super) successes: u32,
     pub(super) down_until: Option<Instant>,
+    /// Marked down by active checks: no cooldown, only `healthy_threshold`
+    /// successful checks bring it back.
+    pub(super) held_down: bool,
+}
+
+/// Health of one configured upstream address, for status reporting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamNodeStatus {
+    pub upstream: String,
+    pub addr: String,
+    pub healthy: bool,
+    /// Longer of the request and active-check failure streaks.
+    pub failures: u32,
 }
 
 impl Proxy {
+    /// Health of every configured upstream address, sorted by upstream name.
+    pub fn upstream_status(&self, cfg: &MiguxConfig) -> Vec<UpstreamNodeStatus> {
+        let now = Instant::now();
+        let mut names: Vec<&String> = cfg.upstream.keys().collect();
+        names.sort();
+        let mut out = Vec::new();
+        for name in names {
+            let servers = upstream::normalize_servers(&cfg.upstream[name]).unwrap_or_default();
+            for addr in servers {
+                let failures = self
+                    .health
+                    .get(&health_key(name, &addr))
+                    .map(|entry| entry.failures.max(entry.check_failures))
+                    .unwrap_or(0);
+                out.push(UpstreamNodeStatus {
+                    upstream: name.clone(),
+                    healthy: self.is_healthy(name, &addr, now),
+                    addr,
+                    failures,
+                });
+            }
+        }
+        out
+    }
+
     /// Start background active health checks for upstream pools.
     pub fn start_health_checks(self: &std::sync::Arc<Self>, cfg: std::sync::Arc<MiguxConfig>) {
         for (upstream_name, upstream_cfg) in cfg.upstream.iter() {
@@ -49,7 +98,9 @@ impl Proxy {
             let timeout_secs = upstream_cfg.health.timeout_secs.max(1);
             let check_interval = Duration::from_secs(interval_secs);
             let check_timeout = Duration::from_secs(timeout_secs);
-            let proxy = std::sync::Arc::clone(self);
+            // Weak, so the checks stop once the proxy is dropped (an
+            // embedded server that was shut down).
+            let proxy = std::sync::Arc::downgrade(self);
             let upstream_name = upstream_name.clone();
 
             tokio::spawn(async move {
@@ -58,11 +109,10 @@ impl Proxy {
                     ticker.tick().await;
                     for addr in &servers {
                         let ok = connect_with_timeout(addr, check_timeout).await.is_ok();
-                        if ok {
-                            proxy.record_success(&upstream_name, addr);
-                        } else {
-                            proxy.record_failure(&upstream_name, addr, &policy);
-                        }
+                        let Some(proxy) = proxy.upgrade() else {
+                            return;
+                        };
+                        proxy.record_check(&upstream_name, addr, &policy, ok);
                     }
                 }
             });
@@ -85,16 +135,31 @@ impl Proxy {
         if healthy.is_empty() { addrs } else { healthy }
     }
 
+    /// `strategy = "failover"`: healthy addresses in config order, then the
+    /// ones marked down, kept as a last resort instead of being dropped.
+    pub(super) fn failover_order(&self, upstream_name: &str, addrs: Vec<String>) -> Vec<String> {
+        let now = Instant::now();
+        let (mut ordered, down): (Vec<_>, Vec<_>) = addrs
+            .into_iter()
+            .partition(|addr| self.is_healthy(upstream_name, addr, now));
+        ordered.extend(down);
+        ordered
+    }
+
     fn is_healthy(&self, upstream_name: &str, addr: &str, now: Instant) -> bool {
         let key = health_key(upstream_name, addr);
-        if let Some(mut entry) = self.health.get_mut(&key) {
-            if let Some(until) = entry.down_until {
-                if until > now {
-                    return false;
-                }
-                entry.down_until = None;
-                entry.failures = 0;
+        let Some(mut entry) = self.health.get_mut(&key) else {
+            return true;
+        };
+        if entry.held_down {
+            return false;
+        }
+        if let Some(until) = entry.down_until {
+            if until > now {
+                return false;
             }
+            entry.down_until = None;
+            entry.failures = 0;
         }
         true
     }
@@ -102,10 +167,7 @@ impl Proxy {
     /// Record a connection failure and update circuit-breaker state.
     pub(super) fn record_failure(&self, upstream_name: &str, addr: &str, policy: &HealthPolicy) {
         let key = health_key(upstream_name, addr);
-        let mut entry = self
-            .health
-            .entry(key)
-            .or_insert_with(UpstreamHealth::default);
+        let mut entry = self.health.entry(key).or_default();
         entry.failures = entry.failures.saturating_add(1);
         let threshold = policy.fail_threshold.max(1);
         if entry.failures >= threshold {
@@ -119,7 +181,36 @@ impl Proxy {
         }
     }
 
-    /// Record a successful connection and clear failure state.
+    /// Honor an upstream `Retry-After`: the address is down for that long
+    /// (capped by the policy), whatever its failure count.
+    pub(super) fn record_retry_after(
+        &self,
+        upstream_name: &str,
+        addr: &str,
+        policy: &HealthPolicy,
+        retry_after: Duration,
+    ) {
+        if policy.max_retry_after.is_zero() {
+            self.record_failure(upstream_name, addr, policy);
+            return;
+        }
+        let cooldown = retry_after.min(policy.max_retry_after);
+        let key = health_key(upstream_name, addr);
+        let mut entry = self.health.entry(key).or_default();
+        entry.failures = entry.failures.saturating_add(1);
+        entry.down_until = Some(Instant::now() + cooldown);
+        tracing::debug!(
+            target: "migux::proxy",
+            upstream = %upstream_name,
+            addr = %addr,
+            cooldown_secs = cooldown.as_secs(),
+            "Marking upstream as down per Retry-After"
+        );
+    }
+
+    /// Record a successful connection: ends the request failure streak and
+    /// any passive cooldown. A node held down by active checks stays down;
+    /// only `record_check` brings it back.
     pub(super) fn record_success(&self, upstream_name: &str, addr: &str) {
         let key = health_key(upstream_name, addr);
         if let Some(mut entry) = self.health.get_mut(&key) {
@@ -127,6 +218,49 @@ impl Proxy {
             entry.down_until = None;
         }
     }
+
+    /// Record an active check. `unhealthy_threshold` failures in a row hold
+    /// the node down; while down, `healthy_threshold` successes in a row
+    /// bring it back, and any failure restarts that count.
+    pub(super) fn record_check(
+        &self,
+        upstream_name: &str,
+        addr: &str,
+        policy: &HealthPolicy,
+        ok: bool,
+    ) {
+        let key = health_key(upstream_name, addr);
+        let mut entry = self.health.entry(key).or_default();
+        let down = entry.held_down || entry.down_until.is_some_and(|until| until > Instant::now());
+        if !ok {
+            entry.successes = 0;
+            entry.check_failures = entry.check_failures.saturating_add(1);
+            if !entry.held_down && entry.check_failures >= policy.unhealthy_threshold.max(1) {
+                entry.held_down = true;
+                tracing::debug!(
+                    target: "migux::proxy",
+                    upstream = %upstream_name,
+                    addr = %addr,
+                    "Active checks failing; marking upstream as down"
+                );
+            }
+            return;
+        }
+        if !down {
+            entry.check_failures = 0;
+            return;
+        }
+        entry.successes = entry.successes.saturating_add(1);
+        if entry.successes >= policy.healthy_threshold.max(1) {
+            *entry = UpstreamHealth::default();
+            tracing::debug!(
+                target: "migux::proxy",
+                upstream = %upstream_name,
+                addr = %addr,
+                "Active checks passing; marking upstream as up"
+            );
+        }
+    }
 }
 
 /// Build a health policy from upstream config.
@@ -136,6 +270,9 @@ pub(super) fn health_policy(cfg: &UpstreamConfig) -> HealthPolicy {
     HealthPolicy {
         fail_threshold: threshold,
         cooldown: Duration::from_secs(cooldown_secs),
+        max_retry_after: Duration::from_secs(cfg.health.max_retry_after_secs),
+        unhealthy_threshold: cfg.health.unhealthy_threshold(),
+        healthy_threshold: cfg.health.healthy_threshold(),
     }
 }
 
@@ -148,13 +285,27 @@ mod tests {
     use super::{HealthPolicy, Proxy, UpstreamHealth, health_key};
     use std::time::{Duration, Instant};
 
+    /// Policy with the given thresholds, a `cooldown_secs` cooldown and
+    /// `Retry-After` capped at 300s.
+    fn policy(
+        fail_threshold: u32,
+        cooldown_secs: u64,
+        unhealthy: u32,
+        healthy: u32,
+    ) -> HealthPolicy {
+        HealthPolicy {
+            fail_threshold,
+            cooldown: Duration::from_secs(cooldown_secs),
+            max_retry_after: Duration::from_secs(300),
+            unhealthy_threshold: unhealthy,
+            healthy_threshold: healthy,
+        }
+    }
+
     #[test]
     fn filter_healthy_addrs_skips_down_nodes() {
         let proxy = Proxy::new();
-        let policy = HealthPolicy {
-            fail_threshold: 1,
-            cooldown: Duration::from_secs(60),
-        };
+        let policy = policy(1, 60, 1, 1);
         proxy.record_failure("api", "127.0.0.1:3000", &policy);
         let addrs = vec!["127.0.0.1:3000".to_string(), "127.0.0.1:3001".to_string()];
         let filtered = proxy.filter_healthy_addrs("api", addrs);
@@ -164,10 +315,7 @@ mod tests {
     #[test]
     fn filter_healthy_addrs_falls_back_when_all_down() {
         let proxy = Proxy::new();
-        let policy = HealthPolicy {
-            fail_threshold: 1,
-            cooldown: Duration::from_secs(60),
-        };
+        let policy = policy(1, 60, 1, 1);
         proxy.record_failure("api", "127.0.0.1:3000", &policy);
         proxy.record_failure("api", "127.0.0.1:3001", &policy);
         let addrs = vec!["127.0.0.1:3000".to_string(), "127.0.0.1:3001".to_string()];
@@ -184,10 +332,101 @@ mod tests {
             UpstreamHealth {
                 failures: 1,
                 down_until: Some(Instant::now() - Duration::from_secs(1)),
+                ..Default::default()
             },
         );
         let addrs = vec!["127.0.0.1:3000".to_string()];
         let filtered = proxy.filter_healthy_addrs("api", addrs);
         assert_eq!(filtered, vec!["127.0.0.1:3000".to_string()]);
     }
+
+    #[test]
+    fn retry_after_overrides_threshold_and_is_capped() {
+        let proxy = Proxy::new();
+        let policy = HealthPolicy {
+            max_retry_after: Duration::from_secs(60),
+            ..policy(3, 10, 3, 1)
+        };
+        proxy.record_retry_after("api", "127.0.0.1:3000", &policy, Duration::from_secs(600));
+        let until = proxy
+            .health
+            .get(&health_key("api", "127.0.0.1:3000"))
+            .and_then(|entry| entry.down_until)
+            .expect("down");
+        let cooldown = until - Instant::now();
+        assert!(cooldown > Duration::from_secs(55) && cooldown <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn active_check_streaks_mark_down_and_up() {
+        let proxy = Proxy::new();
+        let policy = policy(1, 0, 3, 2);
+        let addr = "127.0.0.1:3000";
+        let healthy = || proxy.is_healthy("api", addr, Instant::now());
+
+        // A success in between restarts the failure streak.
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, true);
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, false);
+        assert!(healthy());
+        proxy.record_check("api", addr, &policy, false);
+        assert!(!healthy(), "three failed checks in a row");
+
+        // Held down with no cooldown at all; a failure restarts the
+        // success streak.
+        proxy.record_check("api", addr, &policy, true);
+        assert!(!healthy());
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, true);
+        assert!(!healthy());
+        proxy.record_check("api", addr, &policy, true);
+        assert!(healthy(), "two passing checks in a row");
+
+        // Back up with a clean slate.
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, false);
+        assert!(healthy());
+    }
+
+    #[test]
+    fn passive_down_node_needs_the_check_streak_within_its_cooldown() {
+        let proxy = Proxy::new();
+        let policy = policy(1, 60, 1, 3);
+        let addr = "127.0.0.1:3000";
+        proxy.record_failure("api", addr, &policy);
+        for _ in 0..2 {
+            proxy.record_check("api", addr, &policy, true);
+            assert!(!proxy.is_healthy("api", addr, Instant::now()));
+        }
+        proxy.record_check("api", addr, &policy, true);
+        assert!(proxy.is_healthy("api", addr, Instant::now()));
+    }
+
+    #[test]
+    fn request_success_does_not_lift_an_active_check_hold_down() {
+        let proxy = Proxy::new();
+        let policy = policy(2, 60, 1, 2);
+        let addr = "127.0.0.1:3000";
+        proxy.record_check("api", addr, &policy, false);
+        proxy.record_check("api", addr, &policy, true);
+        proxy.record_success("api", addr);
+        assert!(!proxy.is_healthy("api", addr, Instant::now()));
+        // The success streak of checks survived the request success.
+        proxy.record_check("api", addr, &policy, true);
+        assert!(proxy.is_healthy("api", addr, Instant::now()));
+    }
+
+    #[test]
+    fn request_and_check_failures_keep_separate_streaks() {
+        let proxy = Proxy::new();
+        let policy = policy(2, 60, 2, 1);
+        let addr = "127.0.0.1:3000";
+        proxy.record_failure("api", addr, &policy);
+        proxy.record_check("api", addr, &policy, false);
+        assert!(proxy.is_healthy("api", addr, Instant::now()));
+        proxy.record_check("api", addr, &policy, false);
+        assert!(!proxy.is_healthy("api", addr, Instant::now()));
+    }
 }
@@ -1,26 +1,70 @@
-use std::{net::SocketAddr, sync::Arc, sync::atomic::AtomicUsize};
+use std::{io::Write as _, net::SocketAddr, sync::Arc, sync::atomic::AtomicUsize, time::Instant};
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use dashmap::DashMap;
-use migux_config::{LocationConfig, MiguxConfig};
-use migux_http::responses::send_502;
+use migux_config::{EncodedSlashes, ErrorFormat, LocationConfig, MiguxConfig, UpstreamConfig};
+use migux_http::keep_alive::KeepAlive;
+use migux_http::responses::{
+    send_406, send_413, send_502, send_504, send_json_error, send_response,
+};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
     time::{Duration, timeout},
 };
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, warn};
 
+mod backoff;
+mod compress;
+mod error;
+mod error_detail;
+mod ewma;
+mod fetch;
 mod headers;
 mod health;
 mod path;
 mod pool;
+mod random;
+mod redirect;
 mod response;
+mod stream;
+mod tls;
 mod upstream;
 
+pub use error::ProxyError;
+use ewma::UpstreamLoad;
+pub use fetch::FetchedResponse;
+pub use health::UpstreamNodeStatus;
 use health::{UpstreamHealth, health_policy};
 use pool::PooledStream;
 use pool::connect_fresh;
+use tls::{UpstreamStream, UpstreamTls};
+
+/// Every upstream failed before any response byte reached the client and
+/// the location has a `fallback_static` page for the caller to serve.
+#[derive(Debug)]
+pub struct UpstreamsUnavailable {
+    timed_out: bool,
+}
+
+impl UpstreamsUnavailable {
+    /// Status line the proxy would have sent without the page: 504 when the
+    /// last upstream timed out, else 502.
+    pub fn status(&self) -> &'static str {
+        if self.timed_out {
+            "504 Gateway Timeout"
+        } else {
+            "502 Bad Gateway"
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamsUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "All upstreams failed ({})", self.status())
+    }
+}
+
+impl std::error::Error for UpstreamsUnavailable {}
 
 /// =======================================================
 /// PROXY STATE
@@ -41,6 +85,12 @@ pub struct Proxy {
 
     /// Health state per upstream address (circuit breaker)
     health: DashMap<String, UpstreamHealth>,
+
+    /// Latency EWMA + in-flight count per upstream address (`strategy = "ewma"`)
+    loads: DashMap<String, Arc<UpstreamLoad>>,
+
+    /// TLS client settings per upstream name (`tls = true`)
+    tls: DashMap<String, UpstreamTls>,
 }
 
 impl Proxy {
@@ -55,6 +105,8 @@ impl Proxy {
             rr_counters: DashMap::new(),
             pools: DashMap::new(),
             health: DashMap::new(),
+            loads: DashMap::new(),
+            tls: DashMap::new(),
         }
     }
 
@@ -68,10 +120,18 @@ impl Proxy {
     /// - lee response
     /// - la escribe al cliente
     /// - si reusable, devuelve conexion a pool
-    #[instrument(
-        skip(self, client_stream, client_buf, location, req_headers, cfg),
-        fields(client = %client_addr, location_path = %location.path)
-    )]
+    ///
+    /// Returns `true` when the client connection must be closed: the client
+    /// did not ask for keep-alive, the response body ran to upstream EOF,
+    /// every upstream failed and a 502 was sent, or the client disconnected
+    /// mid-response (no other upstream is tried then).
+    ///
+    /// When every upstream fails and the location has `fallback_static`,
+    /// nothing is written and [`UpstreamsUnavailable`] is returned instead.
+    ///
+    /// Runs in the caller's request span and records the upstream address
+    /// each attempt goes to in its `upstream` field.
+    #[allow(clippy::too_many_arguments)]
     pub async fn serve<S>(
         &self,
         client_stream: &mut S,
@@ -83,11 +143,13 @@ impl Proxy {
         http_version: &str,
         content_length: usize,
         is_chunked: bool,
+        client_keep_alive: KeepAlive,
         client_is_tls: bool,
+        server_port: Option<u16>,
         hsts_header: Option<&str>,
         cfg: &Arc<MiguxConfig>,
         client_addr: &SocketAddr,
-    ) -> anyhow::Result<()>
+    ) -> anyhow::Result<bool>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + ?Sized,
     {
@@ -104,26 +166,42 @@ impl Proxy {
             .ok_or_else(|| anyhow::anyhow!("Upstream '{}' not found in config", upstream_name))?;
 
         // 3) obtener candidatos en orden rr (y fallback)
-        let candidate_addrs = upstream::choose_upstream_addrs_rr_order(
-            &self.rr_counters,
-            upstream_name,
-            upstream_cfg,
-        )?;
+        let candidate_addrs = self.candidate_addrs(upstream_name, upstream_cfg)?;
         let policy = health_policy(upstream_cfg);
-        let candidate_addrs = self.filter_healthy_addrs(upstream_name, candidate_addrs);
+        let retry_budget = backoff::RetryBudget::new(&cfg.http);
         let client_ip = client_addr.ip().to_string();
         let connect_timeout = Duration::from_secs(cfg.http.proxy_connect_timeout_secs);
+        let fastopen = cfg.global.tcp_fastopen();
         let idle_ttl = Duration::from_secs(cfg.http.proxy_pool_idle_timeout_secs);
         let max_pool = cfg.http.proxy_pool_max_per_addr;
         let write_timeout = Duration::from_secs(cfg.http.proxy_write_timeout_secs);
-        let read_timeout = Duration::from_secs(cfg.http.proxy_read_timeout_secs);
+        let read_timeout = Duration::from_secs(
+            location
+                .proxy_read_timeout_secs()
+                .unwrap_or(cfg.http.proxy_read_timeout_secs),
+        );
         let client_read_timeout = Duration::from_secs(cfg.http.client_read_timeout_secs);
         let max_resp_headers = cfg.http.max_upstream_response_headers_bytes as usize;
         let max_resp_body = cfg.http.max_upstream_response_body_bytes as usize;
+        let interim = response::InterimLimits {
+            max: cfg.http.proxy_max_interim_responses,
+            timeout: Duration::from_secs(cfg.http.proxy_interim_timeout_secs),
+            header_timeout: location
+                .proxy_response_header_timeout_secs()
+                .or(upstream_cfg.proxy_response_header_timeout_secs())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        };
 
-        // 4) strip_prefix para upstream path (usa location.strip_prefix si está definido, si no location.path)
-        let prefix = location.strip_prefix().unwrap_or(location.path());
-        let upstream_path = path::strip_prefix_path(req_path, prefix);
+        // 4) upstream path: reglas `rewrite` si existen; si no, strip_prefix
+        //    (usa location.strip_prefix si está definido, si no location.path)
+        let upstream_path = if location.rewrite_rules().is_empty() {
+            let prefix = location.strip_prefix().unwrap_or(location.path());
+            let decode_slashes = cfg.http.encoded_slashes() == EncodedSlashes::Decode;
+            path::strip_prefix_path(req_path, prefix, decode_slashes)
+        } else {
+            path::rewrite_path(req_path, location.rewrite_rules())
+        };
 
         debug!(
             target: "migux::proxy",
@@ -136,35 +214,118 @@ impl Proxy {
         );
 
         // 6) reescribir headers para upstream
-        let keep_alive = http_version != "HTTP/1.0";
+        //    La version nunca supera la del cliente: la respuesta se le reenvia tal cual
+        //    (un cliente HTTP/1.0 no entiende chunked).
+        let upstream_version = if http_version == "HTTP/1.0" {
+            "HTTP/1.0"
+        } else {
+            upstream_cfg.proxy_http_version()
+        };
+        let keep_alive = upstream_cfg.proxy_keep_alive();
         let upstream_is_chunked = is_chunked && content_length == 0;
         let scheme = if client_is_tls { "https" } else { "http" };
-        let rest_of_headers = headers::rewrite_proxy_headers(
+        let hide_headers = location.proxy_hide_headers();
+        let header_rules = headers::HeaderRules {
+            set: location.set_headers(),
+            hide: &hide_headers,
+            request_uri: req_path,
+            forwarded_host: upstream_cfg.forwarded_host(),
+            server_port,
+            forwarded_header: cfg.http.proxy_forwarded_header(),
+            trust_forwarded: cfg.http.proxy_trust_forwarded(),
+            canonicalize_names: upstream_cfg.proxy_canonicalize_headers(),
+        };
+
+        // 7) construir request completa (start line + headers + blank line + body)
+        let mut out = Vec::with_capacity(req_headers.len() + 256);
+        let _ = write!(out, "{method} {upstream_path} {upstream_version}\r\n");
+        headers::rewrite_proxy_headers(
+            &mut out,
             req_headers,
             &client_ip,
             scheme,
             keep_alive,
             content_length,
             upstream_is_chunked,
+            &header_rules,
         );
-
-        // 7) construir request completa (start line + headers + blank line + body)
-        let mut out = Vec::new();
-        let start_line = format!("{method} {upstream_path} {http_version}\r\n");
-        out.extend_from_slice(start_line.as_bytes());
-        out.extend_from_slice(rest_of_headers.as_bytes());
         out.extend_from_slice(b"\r\n");
 
+        // Responses are re-chunked when compressed, which HTTP/1.0 clients
+        // can't read.
+        let accept_encoding = compress::negotiate_encoding(req_headers);
+        let refuse_identity = location.proxy_compress_strict() && accept_encoding.identity_refused;
+        let compress_plan = (location.proxy_compress() && http_version != "HTTP/1.0")
+            .then_some(accept_encoding.coding)
+            .flatten()
+            .map(|coding| compress::CompressPlan {
+                coding,
+                min_length: if refuse_identity {
+                    0
+                } else {
+                    usize::try_from(location.proxy_compress_min_length()).unwrap_or(usize::MAX)
+                },
+            });
+
+        // `proxy_redirect default` puts back what was stripped from the path
+        // (nothing when `rewrite` built it).
+        let redirect_rules = location.proxy_redirects();
+        let public_prefix = if location.rewrite_rules().is_empty() {
+            location.strip_prefix().unwrap_or(location.path())
+        } else {
+            "/"
+        };
         let mut last_err: Option<anyhow::Error> = None;
 
+        // A broken TLS setup (unreadable tls_ca_path) fails every candidate.
+        let (tls, candidate_addrs) = match self.upstream_tls(upstream_name, upstream_cfg) {
+            Ok(tls) => (tls, candidate_addrs),
+            Err(e) => {
+                error!(target: "migux::proxy", upstream = %upstream_name, error = ?e, "Cannot set up upstream TLS");
+                last_err = Some(e);
+                (None, Vec::new())
+            }
+        };
+
+        // GET/HEAD sin body: se puede reintentar un 5xx en otro upstream
+        let retry_5xx = cfg.http.proxy_retry_5xx_get
+            && matches!(method, "GET" | "HEAD")
+            && content_length == 0
+            && !is_chunked;
+
         // 8) intentar cada upstream (primero elegido por rr, luego fallback)
-        for upstream_addr in &candidate_addrs {
+        for (attempt, upstream_addr) in candidate_addrs.iter().enumerate() {
+            // Every earlier attempt failed: back off before the next one.
+            if attempt > 0 && !retry_budget.before_retry(attempt as u32).await {
+                warn!(
+                    target: "migux::proxy",
+                    upstream = %upstream_name,
+                    upstream_addr = %upstream_addr,
+                    "proxy_request_timeout_secs reached; not trying further upstreams"
+                );
+                last_err = Some(ProxyError::ReadTimeout.context(format!(
+                    "Request timeout reached before trying {upstream_addr}"
+                )));
+                break;
+            }
+            let attempt_started = Instant::now();
+            let in_flight = self.start_request(upstream_addr);
+
             // 8.1) sacar del pool o conectar
             let mut upstream_stream = match self
-                .checkout_upstream_stream(upstream_addr, connect_timeout, idle_ttl)
+                .checkout_upstream_stream(
+                    upstream_addr,
+                    tls.as_ref(),
+                    connect_timeout,
+                    idle_ttl,
+                    fastopen,
+                )
                 .await
             {
-                Ok(s) => s,
+                Ok(mut s) => {
+                    s.read_size = cfg.http.proxy_buffer_size();
+                    s
+                }
                 Err(e) => {
                     error!(target: "migux::proxy", upstream=%upstream_addr, error=?e, "Failed to get upstream connection");
                     last_err = Some(e);
@@ -173,6 +334,7 @@ impl Proxy {
                 }
             };
 
+            tracing::Span::current().record("upstream", upstream_addr.as_str());
             info!(
                 target: "migux::proxy",
                 method = %method,
@@ -197,7 +359,9 @@ impl Proxy {
                         "Write failed (likely dead pooled socket). Retrying with fresh connection"
                     );
 
-                    match connect_fresh(upstream_addr, connect_timeout).await {
+                    match connect_fresh(upstream_addr, tls.as_ref(), connect_timeout, fastopen)
+                        .await
+                    {
                         Ok(mut fresh) => {
                             match timeout(write_timeout, fresh.stream.write_all(&out)).await {
                                 Ok(Ok(())) => {
@@ -220,10 +384,9 @@ impl Proxy {
                                         upstream_addr = %upstream_addr,
                                         "Write timed out even with fresh connection"
                                     );
-                                    last_err = Some(anyhow::anyhow!(
-                                        "Upstream write timeout to {}",
-                                        upstream_addr
-                                    ));
+                                    last_err = Some(ProxyError::WriteTimeout.context(format!(
+                                        "Upstream write timeout to {upstream_addr}"
+                                    )));
                                     self.record_failure(upstream_name, upstream_addr, &policy);
                                     continue;
                                 }
@@ -249,7 +412,9 @@ impl Proxy {
                         "Write timed out. Retrying with fresh connection"
                     );
 
-                    match connect_fresh(upstream_addr, connect_timeout).await {
+                    match connect_fresh(upstream_addr, tls.as_ref(), connect_timeout, fastopen)
+                        .await
+                    {
                         Ok(mut fresh) => {
                             match timeout(write_timeout, fresh.stream.write_all(&out)).await {
                                 Ok(Ok(())) => {
@@ -272,10 +437,9 @@ impl Proxy {
                                         upstream_addr = %upstream_addr,
                                         "Write timed out even with fresh connection"
                                     );
-                                    last_err = Some(anyhow::anyhow!(
-                                        "Upstream write timeout to {}",
-                                        upstream_addr
-                                    ));
+                                    last_err = Some(ProxyError::WriteTimeout.context(format!(
+                                        "Upstream write timeout to {upstream_addr}"
+                                    )));
                                     self.record_failure(upstream_name, upstream_addr, &policy);
                                     continue;
                                 }
@@ -297,19 +461,46 @@ impl Proxy {
             }
 
             // 8.3) stream request body to upstream (if any)
-            stream_request_body(
+            let streamed = stream_request_body(
                 client_stream,
                 client_buf,
                 &mut upstream_stream.stream,
                 is_chunked,
                 content_length,
                 client_read_timeout,
-                cfg.http.max_request_body_bytes as usize,
+                cfg.http.client_buffer_size(),
+                location
+                    .max_body_bytes()
+                    .unwrap_or(cfg.http.max_request_body_bytes) as usize,
             )
-            .await?;
+            .await;
+            // Only a chunked body can go over the cap here (a Content-Length
+            // one is refused before dispatch); the upstream got a truncated
+            // request, so answer 413 and close.
+            if let Err(e) = streamed {
+                if ProxyError::of(&e) != Some(ProxyError::BodyTooLarge) {
+                    return Err(e);
+                }
+                warn!(
+                    target: "migux::proxy",
+                    location_path = %location.path,
+                    "Chunked request body too large"
+                );
+                match location.error_format() {
+                    ErrorFormat::Text => send_413(client_stream).await?,
+                    ErrorFormat::Json => {
+                        send_json_error(client_stream, "413 Payload Too Large").await?
+                    }
+                }
+                return Ok(true);
+            }
+            // TLS may still hold the tail of the request in its buffer.
+            upstream_stream.stream.flush().await?;
 
             // 8.4) leer respuesta del upstream y streamear al cliente
-            let reusable = match response::stream_http_response(
+            //      (el ultimo candidato siempre se forwardea, sea cual sea el status)
+            let is_last = attempt + 1 == candidate_addrs.len();
+            let (reusable, keep_client, retry_after) = match response::stream_http_response(
                 &mut upstream_stream,
                 client_stream,
                 method,
@@ -317,10 +508,88 @@ impl Proxy {
                 max_resp_headers,
                 max_resp_body,
                 hsts_header,
+                retry_5xx && !is_last,
+                client_keep_alive,
+                cfg.http.proxy_strict_framing,
+                compress_plan,
+                refuse_identity,
+                interim,
+                &redirect::RedirectRewrite::new(
+                    &redirect_rules,
+                    if tls.is_some() { "https" } else { "http" },
+                    upstream_addr,
+                    public_prefix,
+                ),
             )
             .await
             {
-                Ok(r) => r,
+                Ok(response::ResponseOutcome::Done { capped: true, .. }) => {
+                    // Too late to fail over: the client has the head and part
+                    // of the body. The response had no length, so closing is
+                    // what ends it.
+                    warn!(
+                        target: "migux::proxy",
+                        upstream = %upstream_name,
+                        upstream_addr = %upstream_addr,
+                        max_body = max_resp_body,
+                        "Upstream response without length exceeded the body limit; truncated and closing"
+                    );
+                    self.record_failure(upstream_name, upstream_addr, &policy);
+                    return Ok(true);
+                }
+                Ok(response::ResponseOutcome::Done {
+                    reusable,
+                    keep_client,
+                    retry_after,
+                    capped: false,
+                }) => (reusable, keep_client, retry_after),
+                Ok(response::ResponseOutcome::NotAcceptable) => {
+                    debug!(
+                        target: "migux::proxy",
+                        upstream_addr = %upstream_addr,
+                        "Client refuses identity and the response cannot be encoded; answering 406"
+                    );
+                    // el body no se ha leido: la conexion no es reutilizable
+                    drop(upstream_stream);
+                    in_flight.finish(attempt_started.elapsed());
+                    self.record_success(upstream_name, upstream_addr);
+                    match location.error_format() {
+                        ErrorFormat::Text => send_406(client_stream).await?,
+                        ErrorFormat::Json => {
+                            send_json_error(client_stream, "406 Not Acceptable").await?
+                        }
+                    }
+                    return Ok(true);
+                }
+                Ok(response::ResponseOutcome::Retry5xx(status, retry_after)) => {
+                    info!(
+                        target: "migux::proxy",
+                        upstream_addr = %upstream_addr,
+                        status,
+                        "Upstream returned 5xx; retrying on next upstream"
+                    );
+                    // el body no se ha leido: la conexion no es reutilizable
+                    drop(upstream_stream);
+                    if let Some(retry_after) = retry_after {
+                        self.record_retry_after(upstream_name, upstream_addr, &policy, retry_after);
+                    }
+                    last_err = Some(
+                        ProxyError::UpstreamStatus(status)
+                            .context(format!("Upstream {upstream_addr} returned {status}")),
+                    );
+                    continue;
+                }
+                Err(e) if ProxyError::of(&e) == Some(ProxyError::ClientGone) => {
+                    // Nobody left to answer: don't blame the upstream and
+                    // don't spend another upstream request on failover.
+                    debug!(
+                        target: "migux::proxy",
+                        upstream_addr = %upstream_addr,
+                        error = %e,
+                        "Client disconnected while streaming response"
+                    );
+                    return Ok(true);
+                }
                 Err(e) => {
                     error!(
                         target: "migux::proxy",
@@ -328,42 +597,96 @@ impl Proxy {
                         error = ?e,
                         "Error reading response from upstream"
                     );
-                    last_err = Some(e);
                     self.record_failure(upstream_name, upstream_addr, &policy);
+                    // Part of the response already went out: nothing to retry,
+                    // the client connection has to be aborted.
+                    if e.is::<response::ResponseAborted>() {
+                        return Err(e);
+                    }
+                    last_err = Some(e);
                     continue;
                 }
             };
 
-            // 8.5) si reusable, devolver socket al pool
-            if reusable {
-                self.checkin_upstream_stream(upstream_addr, upstream_stream, max_pool);
+            in_flight.finish(attempt_started.elapsed());
+
+            // 8.5) si reusable (y pedimos keep-alive), devolver socket al pool
+            if reusable && keep_alive {
+                self.checkin_upstream_stream(
+                    upstream_addr,
+                    tls.as_ref(),
+                    upstream_stream,
+                    max_pool,
+                );
             }
 
-            self.record_success(upstream_name, upstream_addr);
+            match retry_after {
+                Some(retry_after) => {
+                    self.record_retry_after(upstream_name, upstream_addr, &policy, retry_after)
+                }
+                None => self.record_success(upstream_name, upstream_addr),
+            }
 
             // exito: ya hemos respondido al cliente
-            return Ok(());
+            return Ok(!keep_client);
         }
 
-        // 9) si todos fallan => 502
+        // 9) si todos fallan => 502/504 (o la pagina fallback_static, que sirve el worker)
         error!(
             target: "migux::proxy",
             upstream = %upstream_name,
             error = ?last_err,
-            "All upstreams failed; returning 502"
+            "All upstreams failed"
         );
-        send_502(client_stream).await?;
-        Ok(())
+        let timed_out = last_err
+            .as_ref()
+            .and_then(ProxyError::of)
+            .and_then(ProxyError::status)
+            == Some(504);
+        if location.fallback_static().is_some() {
+            return Err(UpstreamsUnavailable { timed_out }.into());
+        }
+        if cfg.http.proxy_error_detail() {
+            let status = if timed_out {
+                "504 Gateway Timeout"
+            } else {
+                "502 Bad Gateway"
+            };
+            let category = last_err.as_ref().map_or("error", error_detail::category);
+            let (content_type, body) = match location.error_format() {
+                ErrorFormat::Text => (
+                    "text/plain; charset=utf-8",
+                    error_detail::text_body(status, category, &candidate_addrs),
+                ),
+                ErrorFormat::Json => (
+                    "application/json",
+                    error_detail::json_body(status, category, &candidate_addrs),
+                ),
+            };
+            send_response(client_stream, status, content_type, body.as_bytes()).await?;
+            return Ok(true);
+        }
+        match (location.error_format(), timed_out) {
+            (ErrorFormat::Text, false) => send_502(client_stream).await?,
+            (ErrorFormat::Text, true) => send_504(client_stream).await?,
+            (ErrorFormat::Json, false) => send_json_error(client_stream, "502 Bad Gateway").await?,
+            (ErrorFormat::Json, true) => {
+                send_json_error(client_stream, "504 Gateway Timeout").await?
+            }
+        }
+        Ok(true)
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn stream_request_body<S>(
     client_stream: &mut S,
     client_buf: &mut BytesMut,
-    upstream_stream: &mut TcpStream,
+    upstream_stream: &mut UpstreamStream,
     is_chunked: bool,
     content_length: usize,
     read_timeout: Duration,
+    buffer_size: usize,
     max_body: usize,
 ) -> anyhow::Result<()>
 where
@@ -375,6 +698,7 @@ where
             client_buf,
             upstream_stream,
             read_timeout,
+            buffer_size,
             max_body,
         )
         .await?;
@@ -386,7 +710,7 @@ where
     }
 
     if max_body > 0 && content_length > max_body {
-        anyhow::bail!("Client request body too large");
+        return Err(ProxyError::BodyTooLarge.context("Client request body too large"));
     }
 
     stream_exact(
@@ -395,6 +719,7 @@ where
         upstream_stream,
         content_length,
         read_timeout,
+        buffer_size,
     )
     .await
 }
@@ -402,8 +727,9 @@ where
 async fn stream_chunked_body<S>(
     client_stream: &mut S,
     client_buf: &mut BytesMut,
-    upstream_stream: &mut TcpStream,
+    upstream_stream: &mut UpstreamStream,
     read_timeout: Duration,
+    buffer_size: usize,
     max_body: usize,
 ) -> anyhow::Result<()>
 where
@@ -412,7 +738,7 @@ where
     let mut body_bytes = 0usize;
 
     loop {
-        let line = read_line_bytes(client_stream, client_buf, read_timeout).await?;
+        let line = read_line_bytes(client_stream, client_buf, read_timeout, buffer_size).await?;
         upstream_stream.write_all(&line).await?;
 
         let line_str = String::from_utf8_lossy(&line);
@@ -422,11 +748,12 @@ where
             .trim_end_matches('\n');
         let size_str = size_str.split(';').next().unwrap_or("").trim();
         let chunk_size = usize::from_str_radix(size_str, 16)
-            .map_err(|_| anyhow::anyhow!("Invalid chunk size"))?;
+            .map_err(|_| ProxyError::BadFraming.context("Invalid chunk size"))?;
 
         if chunk_size == 0 {
             loop {
-                let trailer = read_line_bytes(client_stream, client_buf, read_timeout).await?;
+                let trailer =
+                    read_line_bytes(client_stream, client_buf, read_timeout, buffer_size).await?;
                 upstream_stream.write_all(&trailer).await?;
                 if trailer == b"\r\n" {
                     return Ok(());
@@ -435,7 +762,7 @@ where
         }
 
         if max_body > 0 && body_bytes + chunk_size > max_body {
-            anyhow::bail!("Client request body too large");
+            return Err(ProxyError::BodyTooLarge.context("Client request body too large"));
         }
 
         stream_exact(
@@ -444,6 +771,7 @@ where
             upstream_stream,
             chunk_size + 2,
             read_timeout,
+            buffer_size,
         )
         .await?;
 
@@ -451,10 +779,14 @@ where
     }
 }
 
+/// Longest chunk-size (or trailer) line buffered before giving up.
+const MAX_CHUNK_LINE_BYTES: usize = 8 * 1024;
+
 async fn read_line_bytes<S>(
     client_stream: &mut S,
     client_buf: &mut BytesMut,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> anyhow::Result<Vec<u8>>
 where
     S: AsyncRead + Unpin + ?Sized,
@@ -464,67 +796,56 @@ where
             let line = client_buf.split_to(end + 2);
             return Ok(line.to_vec());
         }
-        read_more_client(client_stream, client_buf, read_timeout).await?;
+        if client_buf.len() > MAX_CHUNK_LINE_BYTES {
+            return Err(ProxyError::BadFraming.context("Chunk size line too long"));
+        }
+        read_more_client(client_stream, client_buf, read_timeout, buffer_size).await?;
     }
 }
 
 async fn stream_exact<S>(
     client_stream: &mut S,
     client_buf: &mut BytesMut,
-    upstream_stream: &mut TcpStream,
+    upstream_stream: &mut UpstreamStream,
     mut remaining: usize,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + ?Sized,
 {
     while remaining > 0 {
-        if !client_buf.is_empty() {
-            let take = remaining.min(client_buf.len());
-            upstream_stream.write_all(&client_buf[..take]).await?;
-            client_buf.advance(take);
-            remaining -= take;
-            continue;
-        }
-
-        let mut tmp = [0u8; 4096];
-        let n = match timeout(read_timeout, client_stream.read(&mut tmp)).await {
-            Ok(res) => res?,
-            Err(_) => anyhow::bail!("Client read timeout"),
-        };
-        if n == 0 {
-            anyhow::bail!("Client closed connection while streaming body");
-        }
-
-        if n > remaining {
-            upstream_stream.write_all(&tmp[..remaining]).await?;
-            client_buf.extend_from_slice(&tmp[remaining..n]);
-            remaining = 0;
-        } else {
-            upstream_stream.write_all(&tmp[..n]).await?;
-            remaining -= n;
+        if client_buf.is_empty() {
+            read_more_client(client_stream, client_buf, read_timeout, buffer_size).await?;
         }
+        // Bytes past `remaining` stay buffered for the caller.
+        let take = remaining.min(client_buf.len());
+        upstream_stream.write_all(&client_buf[..take]).await?;
+        client_buf.advance(take);
+        remaining -= take;
     }
     Ok(())
 }
 
+/// Read up to `buffer_size` bytes from the client into `client_buf`.
 async fn read_more_client<S>(
     client_stream: &mut S,
     client_buf: &mut BytesMut,
     read_timeout: Duration,
+    buffer_size: usize,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + Unpin + ?Sized,
 {
-    let mut tmp = [0u8; 4096];
-    let n = match timeout(read_timeout, client_stream.read(&mut tmp)).await {
-        Ok(res) => res?,
-        Err(_) => anyhow::bail!("Client read timeout"),
+    client_buf.reserve(buffer_size);
+    let mut spare = (&mut *client_buf).limit(buffer_size);
+    let n = match timeout(read_timeout, client_stream.read_buf(&mut spare)).await {
+        Ok(res) => res.map_err(|e| anyhow::Error::from(e).context(ProxyError::ClientGone))?,
+        Err(_) => return Err(ProxyError::ClientGone.context("Client read timeout")),
     };
     if n == 0 {
-        anyhow::bail!("Client closed connection");
+        return Err(ProxyError::ClientGone.context("Client closed connection"));
     }
-    client_buf.extend_from_slice(&tmp[..n]);
     Ok(())
 }
 
@@ -535,6 +856,45 @@ fn find_crlf(buf: &BytesMut, start: usize) -> Option<usize> {
         .map(|i| start + i)
 }
 
+impl Proxy {
+    /// Addresses to try for one request, in order, per the upstream's
+    /// strategy and health state.
+    fn candidate_addrs(
+        &self,
+        upstream_name: &str,
+        upstream_cfg: &UpstreamConfig,
+    ) -> anyhow::Result<Vec<String>> {
+        let addrs = upstream::choose_upstream_addrs_rr_order(
+            &self.rr_counters,
+            upstream_name,
+            upstream_cfg,
+        )?;
+        Ok(match upstream_cfg.strategy() {
+            Some("failover") => self.failover_order(upstream_name, addrs),
+            Some("ewma") => self.ewma_order(self.filter_healthy_addrs(upstream_name, addrs)),
+            _ => self.filter_healthy_addrs(upstream_name, addrs),
+        })
+    }
+
+    /// TLS settings for `upstream_name`, built on first use; `None` for
+    /// plain-HTTP upstreams.
+    fn upstream_tls(
+        &self,
+        upstream_name: &str,
+        upstream_cfg: &UpstreamConfig,
+    ) -> anyhow::Result<Option<UpstreamTls>> {
+        if !upstream_cfg.tls() {
+            return Ok(None);
+        }
+        if let Some(tls) = self.tls.get(upstream_name) {
+            return Ok(Some(tls.clone()));
+        }
+        let tls = UpstreamTls::new(upstream_name, upstream_cfg)?;
+        self.tls.insert(upstream_name.to_string(), tls.clone());
+        Ok(Some(tls))
+    }
+}
+
 impl Default for Proxy {
     fn default() -> Self {
         Self::new()
@@ -542,3 +902,1218 @@ impl Default for Proxy {
 }
 
 // Health and pooling helpers live in their respective modules.
+
+#[cfg(test)]
+mod tests {
+    use super::Proxy;
+    use bytes::BytesMut;
+    use migux_config::{
+        ErrorFormat, LocationConfig, LocationType, MiguxConfig, SetHeader, StringList,
+        UpstreamConfig, UpstreamServers,
+    };
+    use migux_http::keep_alive::KeepAlive;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    const KEEP_ALIVE: KeepAlive = KeepAlive::Open {
+        timeout_secs: 65,
+        max_requests: 1000,
+    };
+
+    /// Spawn an upstream that reads each request (up to 1 KiB) and hands the
+    /// socket and what it read to `respond`. Connections are served
+    /// concurrently and closed once `respond` returns.
+    async fn spawn_upstream<F, Fut>(respond: F) -> String
+    where
+        F: Fn(TcpStream, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let n = sock.read(&mut buf).await.unwrap_or(0);
+                    let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+                    respond(sock, head).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Future of the canned [`spawn_upstream`] behaviours below.
+    type Reply = std::pin::Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    /// [`spawn_upstream`] behaviour: write `raw`, then close.
+    fn reply(
+        raw: impl Into<String>,
+    ) -> impl Fn(TcpStream, String) -> Reply + Send + Sync + 'static {
+        let raw: Arc<str> = raw.into().into();
+        move |mut sock, _| {
+            let raw = raw.clone();
+            Box::pin(async move {
+                let _ = sock.write_all(raw.as_bytes()).await;
+            })
+        }
+    }
+
+    /// A complete response with `status` and `body`.
+    fn response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn proxy_config(servers: Vec<String>, retry_5xx: bool) -> (Arc<MiguxConfig>, LocationConfig) {
+        let mut cfg = MiguxConfig::default();
+        cfg.http.proxy_retry_5xx_get = retry_5xx;
+        cfg.upstream.insert(
+            "app".into(),
+            UpstreamConfig {
+                server: UpstreamServers::Many(servers),
+                ..Default::default()
+            },
+        );
+        let location = LocationConfig {
+            path: "/".into(),
+            r#type: LocationType::Proxy,
+            upstream: Some("app".into()),
+            ..Default::default()
+        };
+        (Arc::new(cfg), location)
+    }
+
+    async fn proxy_get(cfg: &Arc<MiguxConfig>, location: &LocationConfig) -> String {
+        proxy_get_with(&Proxy::new(), cfg, location).await
+    }
+
+    async fn proxy_get_with(
+        proxy: &Proxy,
+        cfg: &Arc<MiguxConfig>,
+        location: &LocationConfig,
+    ) -> String {
+        let (result, out) = proxy_serve(proxy, cfg, location).await;
+        result.expect("serve");
+        out
+    }
+
+    /// Run `serve` for a plain GET and return its result plus the client output.
+    async fn proxy_serve(
+        proxy: &Proxy,
+        cfg: &Arc<MiguxConfig>,
+        location: &LocationConfig,
+    ) -> (anyhow::Result<bool>, String) {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let mut client_buf = BytesMut::new();
+        let client_addr = "127.0.0.1:5555".parse().expect("addr");
+        let result = proxy
+            .serve(
+                &mut server,
+                &mut client_buf,
+                location,
+                "GET / HTTP/1.1\r\nHost: example",
+                "GET",
+                "/",
+                "HTTP/1.1",
+                0,
+                false,
+                KEEP_ALIVE,
+                false,
+                None,
+                None,
+                cfg,
+                &client_addr,
+            )
+            .await;
+        drop(server);
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.expect("read");
+        (result, out)
+    }
+
+    #[tokio::test]
+    async fn endless_unframed_response_is_cut_at_the_body_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            let mut buf = [0u8; 1024];
+            let _ = sock.read(&mut buf).await;
+            let _ = sock.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await;
+            // No length and no end: only a failed write (proxy gone) stops it.
+            while sock.write_all(&[b'x'; 1000]).await.is_ok() {}
+        });
+        let (mut cfg, location) = proxy_config(vec![addr], false);
+        Arc::get_mut(&mut cfg)
+            .expect("unshared")
+            .http
+            .max_upstream_response_body_bytes = 4096;
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(result.expect("serve"), "client connection must close");
+        let (head, body) = out.split_once("\r\n\r\n").expect("head");
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "got: {head}");
+        assert!(head.contains("Connection: close"), "got: {head}");
+        assert_eq!(body.len(), 4096);
+    }
+
+    /// Answer every connection on `listener` with `name` as the body.
+    fn serve_named(listener: TcpListener, name: &'static str) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = sock.read(&mut buf).await;
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{name}",
+                    name.len()
+                );
+                let _ = sock.write_all(resp.as_bytes()).await;
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn fast_open_connects_proxy_and_fail_over_from_a_refused_server() {
+        let dead = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let dead_addr = dead.local_addr().expect("addr").to_string();
+        drop(dead);
+        let live = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let live_addr = live.local_addr().expect("addr").to_string();
+        serve_named(live, "live");
+
+        let (mut cfg, location) = proxy_config(vec![dead_addr, live_addr], false);
+        let cfg_mut = Arc::get_mut(&mut cfg).expect("unshared");
+        cfg_mut.global.tcp_fastopen = true;
+        cfg_mut.upstream.get_mut("app").expect("app").strategy = Some("single".into());
+
+        // With Fast Open the refusal may only show on the first write.
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.ends_with("\r\n\r\nlive"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn failover_strategy_sticks_to_primary_until_it_fails_and_returns_after_recovery() {
+        let primary_listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let primary = primary_listener.local_addr().expect("addr");
+        let primary_task = serve_named(primary_listener, "primary");
+        let secondary_listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let secondary = secondary_listener.local_addr().expect("addr").to_string();
+        serve_named(secondary_listener, "secondary");
+
+        let (mut cfg, location) = proxy_config(vec![primary.to_string(), secondary], false);
+        let upstream = Arc::get_mut(&mut cfg)
+            .expect("unshared")
+            .upstream
+            .get_mut("app")
+            .expect("app");
+        upstream.strategy = Some("failover".into());
+        upstream.health.cooldown_secs = 1;
+        let proxy = Proxy::new();
+
+        for _ in 0..3 {
+            let out = proxy_get_with(&proxy, &cfg, &location).await;
+            assert!(out.ends_with("\r\n\r\nprimary"), "got: {out}");
+        }
+
+        primary_task.abort();
+        let _ = primary_task.await;
+        for _ in 0..3 {
+            let out = proxy_get_with(&proxy, &cfg, &location).await;
+            assert!(out.ends_with("\r\n\r\nsecondary"), "got: {out}");
+        }
+
+        let primary_listener = TcpListener::bind(primary).await.expect("rebind primary");
+        serve_named(primary_listener, "primary");
+        // Still inside the cooldown: the primary is not tried yet.
+        let out = proxy_get_with(&proxy, &cfg, &location).await;
+        assert!(out.ends_with("\r\n\r\nsecondary"), "got: {out}");
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let out = proxy_get_with(&proxy, &cfg, &location).await;
+        assert!(out.ends_with("\r\n\r\nprimary"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn failover_strategy_keeps_down_servers_as_last_resort() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let primary = listener.local_addr().expect("addr").to_string();
+        serve_named(listener, "primary");
+        let dead = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let secondary = dead.local_addr().expect("addr").to_string();
+        drop(dead);
+
+        let (mut cfg, location) = proxy_config(vec![primary.clone(), secondary], false);
+        Arc::get_mut(&mut cfg)
+            .expect("unshared")
+            .upstream
+            .get_mut("app")
+            .expect("app")
+            .strategy = Some("failover".into());
+        let proxy = Proxy::new();
+        let policy = super::health_policy(&cfg.upstream["app"]);
+        // Marked down (say by a failed health check) but actually serving:
+        // it is tried after the secondary fails.
+        proxy.record_failure("app", &primary, &policy);
+
+        let out = proxy_get_with(&proxy, &cfg, &location).await;
+        assert!(out.ends_with("\r\n\r\nprimary"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn retry_5xx_get_fails_over_to_next_upstream() {
+        let bad = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let good = spawn_upstream(reply(response("200 OK", "ok"))).await;
+        let (cfg, location) = proxy_config(vec![bad, good], true);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("ok"));
+        assert!(!out.contains("503"));
+    }
+
+    #[tokio::test]
+    async fn retry_5xx_disabled_forwards_first_response() {
+        let bad = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let good = spawn_upstream(reply(response("200 OK", "ok"))).await;
+        let (cfg, location) = proxy_config(vec![bad, good], false);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 503"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn retry_5xx_forwards_last_candidate_response() {
+        let bad1 = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let bad2 = spawn_upstream(reply(response("502 Bad Gateway", "still down"))).await;
+        let (cfg, location) = proxy_config(vec![bad1, bad2], true);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(out.ends_with("still down"));
+    }
+
+    #[tokio::test]
+    async fn failover_backs_off_between_candidates() {
+        let bad1 = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let bad2 = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let good = spawn_upstream(reply(response("200 OK", "ok"))).await;
+        let (mut cfg, location) = proxy_config(vec![bad1, bad2, good], true);
+        Arc::get_mut(&mut cfg)
+            .expect("unshared")
+            .http
+            .proxy_retry_backoff_ms = 100;
+
+        let started = std::time::Instant::now();
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        // 100ms before the second candidate, 200ms before the third.
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= std::time::Duration::from_millis(300),
+            "elapsed: {elapsed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn backoff_past_the_request_timeout_stops_failover_with_504() {
+        let bad1 = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let bad2 = spawn_upstream(reply(response("503 Service Unavailable", "down"))).await;
+        let hits = Arc::new(AtomicUsize::new(0));
+        let good = spawn_upstream(count_and_reply_large(hits.clone())).await;
+        let (mut cfg, location) = proxy_config(vec![bad1, bad2, good], true);
+        let http = &mut Arc::get_mut(&mut cfg).expect("unshared").http;
+        http.proxy_retry_backoff_ms = 600;
+        http.proxy_request_timeout_secs = 1;
+
+        let started = std::time::Instant::now();
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 504"), "got: {out}");
+        // 600ms fits the budget, the following 1200ms does not.
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= std::time::Duration::from_millis(600)
+                && elapsed < std::time::Duration::from_secs(1),
+            "elapsed: {elapsed:?}"
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn upstream_503_retry_after_sets_the_cooldown() {
+        let addr = spawn_upstream(reply(
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 30\r\nContent-Length: 4\r\n\r\nbusy",
+        ))
+        .await;
+        let (cfg, location) = proxy_config(vec![addr.clone()], false);
+        let proxy = Proxy::new();
+
+        let out = proxy_get_with(&proxy, &cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 503"), "got: {out}");
+        let until = proxy
+            .health
+            .get(&format!("app|{addr}"))
+            .and_then(|entry| entry.down_until)
+            .expect("marked down");
+        let cooldown = until - std::time::Instant::now();
+        assert!(
+            cooldown > std::time::Duration::from_secs(28)
+                && cooldown <= std::time::Duration::from_secs(30),
+            "cooldown: {cooldown:?}"
+        );
+        assert!(!proxy.upstream_status(&cfg)[0].healthy);
+    }
+
+    #[tokio::test]
+    async fn all_upstreams_down_uses_location_error_format() {
+        // Bind then drop to get a local port that refuses connections.
+        let closed = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = closed.local_addr().expect("addr").to_string();
+        drop(closed);
+        let (cfg, mut location) = proxy_config(vec![addr], false);
+        location.error_format = Some(ErrorFormat::Json);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(
+            out.contains("Content-Type: application/json\r\n"),
+            "got: {out}"
+        );
+        assert!(out.ends_with(r#"{"error":"Bad Gateway","status":502}"#));
+    }
+
+    #[tokio::test]
+    async fn proxy_error_detail_names_cause_and_upstreams_only_when_enabled() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = closed.local_addr().expect("addr").to_string();
+        drop(closed);
+        let (cfg, mut location) = proxy_config(vec![addr.clone()], false);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.ends_with("\r\n\r\n502 Bad Gateway\n"), "got: {out}");
+        assert!(!out.contains(&addr), "got: {out}");
+
+        let (mut detailed, _) = proxy_config(vec![addr.clone()], false);
+        Arc::get_mut(&mut detailed)
+            .expect("unshared config")
+            .http
+            .proxy_error_detail = true;
+        let out = proxy_get(&detailed, &location).await;
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(
+            out.ends_with(&format!(
+                "\r\n\r\n502 Bad Gateway\nerror: refused\nupstreams: {addr}\n"
+            )),
+            "got: {out}"
+        );
+
+        location.error_format = Some(ErrorFormat::Json);
+        let out = proxy_get(&detailed, &location).await;
+        assert!(
+            out.ends_with(&format!(
+                r#"{{"error":"Bad Gateway","status":502,"detail":"refused","upstreams":["{addr}"]}}"#
+            )),
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unresolvable_upstream_is_reported_as_dns() {
+        let addr = "no-such-host.invalid:80".to_string();
+        let (mut cfg, location) = proxy_config(vec![addr.clone()], false);
+        Arc::get_mut(&mut cfg)
+            .expect("unshared config")
+            .http
+            .proxy_error_detail = true;
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(
+            out.ends_with(&format!("\nerror: dns\nupstreams: {addr}\n")),
+            "got: {out}"
+        );
+    }
+
+    /// [`spawn_upstream`] behaviour: answer with the request head received.
+    async fn echo(mut sock: TcpStream, head: String) {
+        let _ = sock.write_all(response("200 OK", &head).as_bytes()).await;
+    }
+
+    /// Proxy one GET with the given upstream settings; returns the request
+    /// head seen upstream and whether the connection was pooled.
+    async fn pooling_with(version: Option<&str>, connection: Option<&str>) -> (String, bool) {
+        let addr = spawn_upstream(echo).await;
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert(
+            "app".into(),
+            UpstreamConfig {
+                server: UpstreamServers::One(addr.clone()),
+                proxy_http_version: version.map(str::to_string),
+                proxy_connection: connection.map(str::to_string),
+                ..Default::default()
+            },
+        );
+        let location = LocationConfig {
+            path: "/".into(),
+            r#type: LocationType::Proxy,
+            upstream: Some("app".into()),
+            ..Default::default()
+        };
+        let proxy = Proxy::new();
+        let out = proxy_get_with(&proxy, &Arc::new(cfg), &location).await;
+        let pooled = proxy.pools.get(&addr).is_some_and(|pool| !pool.is_empty());
+        (out, pooled)
+    }
+
+    #[tokio::test]
+    async fn resolved_spellings_share_one_pool() {
+        let addr = spawn_upstream(echo).await;
+        let port = addr.rsplit_once(':').expect("port").1;
+        let mut upstream = UpstreamConfig {
+            server: UpstreamServers::Many(vec![format!("localhost:{port}"), addr.clone()]),
+            resolve: true,
+            ..Default::default()
+        };
+        upstream.resolve_servers();
+        assert_eq!(upstream.server.addrs(), vec![addr.clone()]);
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert("app".into(), upstream);
+        let cfg = Arc::new(cfg);
+        let location = LocationConfig {
+            path: "/".into(),
+            r#type: LocationType::Proxy,
+            upstream: Some("app".into()),
+            ..Default::default()
+        };
+
+        let proxy = Proxy::new();
+        let candidates = proxy
+            .candidate_addrs("app", &cfg.upstream["app"])
+            .expect("candidates");
+        assert_eq!(candidates, vec![addr.clone()]);
+        let out = proxy_get_with(&proxy, &cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 200"), "got: {out}");
+        let keys: Vec<String> = proxy.pools.iter().map(|e| e.key().clone()).collect();
+        assert_eq!(keys, vec![addr.clone()]);
+        let status = proxy.upstream_status(&cfg);
+        assert_eq!(status.len(), 1, "{status:?}");
+        assert_eq!(status[0].addr, addr);
+    }
+
+    #[tokio::test]
+    async fn default_upstream_is_http11_keep_alive_and_pooled() {
+        let (out, pooled) = pooling_with(None, None).await;
+        assert!(out.contains("GET / HTTP/1.1\r\n"), "got: {out}");
+        assert!(out.contains("\r\nConnection: keep-alive\r\n"), "got: {out}");
+        assert!(pooled);
+    }
+
+    #[tokio::test]
+    async fn http10_close_upstream_is_never_pooled() {
+        let (out, pooled) = pooling_with(Some("1.0"), Some("close")).await;
+        assert!(out.contains("GET / HTTP/1.0\r\n"), "got: {out}");
+        assert!(out.contains("\r\nConnection: close\r\n"), "got: {out}");
+        assert!(!pooled);
+    }
+
+    #[tokio::test]
+    async fn http11_close_upstream_is_not_pooled() {
+        let (out, pooled) = pooling_with(Some("1.1"), Some("close")).await;
+        assert!(out.contains("GET / HTTP/1.1\r\n"), "got: {out}");
+        assert!(out.contains("\r\nConnection: close\r\n"), "got: {out}");
+        assert!(!pooled);
+    }
+
+    #[tokio::test]
+    async fn http10_keep_alive_upstream_is_pooled() {
+        let (out, pooled) = pooling_with(Some("1.0"), Some("keep-alive")).await;
+        assert!(out.contains("GET / HTTP/1.0\r\n"), "got: {out}");
+        assert!(out.contains("\r\nConnection: keep-alive\r\n"), "got: {out}");
+        assert!(pooled);
+    }
+
+    /// [`spawn_upstream`] behaviour: write `raw`, then stall with the socket open.
+    fn stall_after(raw: &'static str) -> impl Fn(TcpStream, String) -> Reply + Send + Sync {
+        move |mut sock, _| {
+            Box::pin(async move {
+                let _ = sock.write_all(raw.as_bytes()).await;
+                tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+            })
+        }
+    }
+
+    fn timeout_location(addr: String) -> (Arc<MiguxConfig>, LocationConfig) {
+        let (cfg, mut location) = proxy_config(vec![addr], false);
+        location.proxy_read_timeout_secs = Some(1);
+        (cfg, location)
+    }
+
+    #[tokio::test]
+    async fn upstream_without_headers_times_out_as_504() {
+        let addr = spawn_upstream(stall_after("")).await;
+        let (cfg, location) = timeout_location(addr);
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(
+            result.expect("serve"),
+            "504 must close the client connection"
+        );
+        assert!(
+            out.starts_with("HTTP/1.1 504 Gateway Timeout"),
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn upstream_stalling_mid_body_aborts_client() {
+        let addr = spawn_upstream(stall_after(
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc",
+        ))
+        .await;
+        let (cfg, location) = timeout_location(addr);
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        let err = result.expect_err("stalled body must abort");
+        assert!(err.is::<super::response::ResponseAborted>(), "got: {err:?}");
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(
+            out.ends_with("\r\n\r\nabc"),
+            "no error page after partial body: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn interim_responses_are_skipped() {
+        let addr = spawn_upstream(reply(
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ))
+        .await;
+        let (cfg, location) = proxy_config(vec![addr], false);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(!out.contains("103") && !out.contains("Link:"), "got: {out}");
+        assert!(out.ends_with("ok"));
+    }
+
+    #[tokio::test]
+    async fn endless_interim_responses_are_a_502() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _ = sock.read(&mut buf).await;
+                    while sock
+                        .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                        .await
+                        .is_ok()
+                    {}
+                });
+            }
+        });
+        let (cfg, location) = proxy_config(vec![addr.clone()], false);
+        let proxy = Proxy::new();
+
+        let (result, out) = proxy_serve(&proxy, &cfg, &location).await;
+        assert!(result.expect("serve"));
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(proxy.pools.get(&addr).is_none_or(|pool| pool.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn final_response_after_interim_is_bounded_in_time() {
+        let addr = spawn_upstream(stall_after("HTTP/1.1 102 Processing\r\n\r\n")).await;
+        let (cfg, location) = proxy_config(vec![addr], false);
+        let mut cfg = Arc::into_inner(cfg).expect("sole owner");
+        cfg.http.proxy_interim_timeout_secs = 1;
+        let cfg = Arc::new(cfg);
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(result.expect("serve"));
+        assert!(
+            out.starts_with("HTTP/1.1 504 Gateway Timeout"),
+            "got: {out}"
+        );
+    }
+
+    /// [`spawn_upstream`] behaviour: redirect to `<its own address><path>`.
+    fn redirect_to(path: &'static str) -> impl Fn(TcpStream, String) -> Reply + Send + Sync {
+        move |mut sock, _| {
+            Box::pin(async move {
+                let addr = sock.local_addr().expect("addr");
+                let resp = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: http://{addr}{path}\r\nContent-Length: 0\r\n\r\n"
+                );
+                let _ = sock.write_all(resp.as_bytes()).await;
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_to_the_upstream_address_are_rewritten_to_the_location() {
+        let addr = spawn_upstream(redirect_to("/login?next=%2F")).await;
+        let (cfg, mut location) = proxy_config(vec![addr], false);
+        location.path = "/app".into();
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 302 Found"), "got: {out}");
+        assert!(
+            out.contains("\r\nLocation: /app/login?next=%2F\r\n"),
+            "got: {out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_redirect_off_passes_location_through() {
+        let addr = spawn_upstream(redirect_to("/login")).await;
+        let (cfg, mut location) = proxy_config(vec![addr.clone()], false);
+        location.path = "/app".into();
+        location.proxy_redirect = Some(StringList::One("off".into()));
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(
+            out.contains(&format!("\r\nLocation: http://{addr}/login\r\n")),
+            "got: {out}"
+        );
+    }
+
+    /// [`spawn_upstream`] behaviour: answer 200 after `delay`.
+    fn reply_after(
+        delay: std::time::Duration,
+    ) -> impl Fn(TcpStream, String) -> Reply + Send + Sync {
+        move |mut sock, _| {
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                let _ = sock.write_all(response("200 OK", "slow").as_bytes()).await;
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn response_headers_past_the_header_timeout_are_a_504() {
+        let addr = spawn_upstream(reply_after(std::time::Duration::from_secs(5))).await;
+        let (cfg, mut location) = proxy_config(vec![addr], false);
+        location.proxy_response_header_timeout_secs = Some(1);
+
+        let started = std::time::Instant::now();
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(result.expect("serve"));
+        assert!(
+            out.starts_with("HTTP/1.1 504 Gateway Timeout"),
+            "got: {out}"
+        );
+        // Well inside the 30s read timeout.
+        assert!(started.elapsed() < std::time::Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn header_timeout_on_the_upstream_fails_over_to_the_next_server() {
+        let slow = spawn_upstream(reply_after(std::time::Duration::from_secs(5))).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let fast = listener.local_addr().expect("addr").to_string();
+        serve_named(listener, "fast");
+        let (mut cfg, location) = proxy_config(vec![slow, fast], false);
+        Arc::get_mut(&mut cfg)
+            .expect("unshared")
+            .upstream
+            .get_mut("app")
+            .expect("app")
+            .proxy_response_header_timeout_secs = Some(1);
+
+        let out = proxy_get(&cfg, &location).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("fast"), "got: {out}");
+    }
+
+    const UNFRAMED: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil eof";
+
+    /// GET with `Accept-Encoding: <accept_encoding>`; the client output.
+    async fn get_accepting(
+        cfg: &Arc<MiguxConfig>,
+        location: &LocationConfig,
+        accept_encoding: &str,
+    ) -> Vec<u8> {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let client_addr = "127.0.0.1:5555".parse().expect("addr");
+        let req_headers =
+            format!("GET / HTTP/1.1\r\nHost: example\r\nAccept-Encoding: {accept_encoding}");
+        Proxy::new()
+            .serve(
+                &mut server,
+                &mut BytesMut::new(),
+                location,
+                &req_headers,
+                "GET",
+                "/",
+                "HTTP/1.1",
+                0,
+                false,
+                KEEP_ALIVE,
+                false,
+                None,
+                None,
+                cfg,
+                &client_addr,
+            )
+            .await
+            .expect("serve");
+        drop(server);
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).await.expect("read");
+        out
+    }
+
+    #[tokio::test]
+    async fn strict_compress_answers_406_when_identity_is_refused_and_nothing_can_encode() {
+        let png = spawn_upstream(reply(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\n\r\n.PNG",
+        ))
+        .await;
+        let (cfg, mut location) = proxy_config(vec![png], false);
+        location.proxy_compress = Some(true);
+        location.proxy_compress_strict = Some(true);
+        let out = get_accepting(&cfg, &location, "identity;q=0, gzip").await;
+        let out = String::from_utf8_lossy(&out);
+        assert!(out.starts_with("HTTP/1.1 406 Not Acceptable"), "got: {out}");
+
+        // Identity still acceptable, or strict off: sent as-is.
+        let out = get_accepting(&cfg, &location, "gzip").await;
+        assert!(out.starts_with(b"HTTP/1.1 200 OK"));
+        location.proxy_compress_strict = None;
+        let out = get_accepting(&cfg, &location, "identity;q=0, gzip").await;
+        assert!(out.starts_with(b"HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn strict_compress_encodes_small_bodies_when_identity_is_refused() {
+        let text = spawn_upstream(reply(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello",
+        ))
+        .await;
+        let (cfg, mut location) = proxy_config(vec![text], false);
+        location.proxy_compress = Some(true);
+        location.proxy_compress_strict = Some(true);
+
+        // Below proxy_compress_min_length, but identity is not an option.
+        let out = get_accepting(&cfg, &location, "identity;q=0, gzip").await;
+        let split = out.windows(4).position(|w| w == b"\r\n\r\n").expect("head") + 4;
+        let head = String::from_utf8_lossy(&out[..split]);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "got: {head}");
+        assert!(
+            head.contains("\r\nContent-Encoding: gzip\r\n"),
+            "got: {head}"
+        );
+
+        let out = get_accepting(&cfg, &location, "gzip").await;
+        let out = String::from_utf8_lossy(&out);
+        assert!(!out.contains("Content-Encoding"), "got: {out}");
+        assert!(out.ends_with("hello"));
+    }
+
+    #[tokio::test]
+    async fn proxy_compress_rechunks_a_chunked_upstream_body() {
+        use std::io::Read;
+
+        let addr = spawn_upstream(reply(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n6\r\n{\"a\":1\r\n1\r\n}\r\n0\r\nX-Trailer: t\r\n\r\n",
+        ))
+        .await;
+        let (cfg, mut location) = proxy_config(vec![addr], false);
+        location.proxy_compress = Some(true);
+
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let client_addr = "127.0.0.1:5555".parse().expect("addr");
+        let close = Proxy::new()
+            .serve(
+                &mut server,
+                &mut BytesMut::new(),
+                &location,
+                "GET / HTTP/1.1\r\nHost: example\r\nAccept-Encoding: br, gzip",
+                "GET",
+                "/",
+                "HTTP/1.1",
+                0,
+                false,
+                KEEP_ALIVE,
+                false,
+                None,
+                None,
+                &cfg,
+                &client_addr,
+            )
+            .await
+            .expect("serve");
+        assert!(!close, "re-chunked response keeps the client connection");
+        drop(server);
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).await.expect("read");
+
+        let split = out.windows(4).position(|w| w == b"\r\n\r\n").expect("head") + 4;
+        let head = String::from_utf8_lossy(&out[..split]);
+        assert!(head.contains("\r\nContent-Encoding: br\r\n"), "got: {head}");
+        assert!(!head.contains("X-Trailer"));
+        // Single-chunk body: `<size>\r\n<data>\r\n0\r\n\r\n`.
+        let body = &out[split..];
+        let line_end = body.windows(2).position(|w| w == b"\r\n").expect("size");
+        let size = usize::from_str_radix(std::str::from_utf8(&body[..line_end]).expect("hex"), 16)
+            .expect("size");
+        let data = &body[line_end + 2..line_end + 2 + size];
+        assert_eq!(&body[line_end + 2 + size..], b"\r\n0\r\n\r\n");
+        let mut decoded = String::new();
+        brotli::Decompressor::new(data, 4096)
+            .read_to_string(&mut decoded)
+            .expect("unbrotli");
+        assert_eq!(decoded, "{\"a\":1}");
+    }
+
+    #[tokio::test]
+    async fn unframed_keep_alive_response_streams_to_eof_by_default() {
+        let addr = spawn_upstream(reply(UNFRAMED)).await;
+        let (cfg, location) = proxy_config(vec![addr], false);
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(
+            result.expect("serve"),
+            "EOF-delimited body closes the client"
+        );
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.contains("\r\nConnection: close\r\n"), "got: {out}");
+        assert!(out.ends_with("until eof"));
+    }
+
+    #[tokio::test]
+    async fn unframed_keep_alive_response_is_502_when_strict() {
+        let addr = spawn_upstream(reply(UNFRAMED)).await;
+        let (mut cfg, location) = proxy_config(vec![addr], false);
+        Arc::get_mut(&mut cfg).unwrap().http.proxy_strict_framing = true;
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(result.expect("serve"));
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(!out.contains("until eof"));
+    }
+
+    #[tokio::test]
+    async fn response_with_chunked_and_content_length_is_502() {
+        let addr = spawn_upstream(reply(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n3\r\nabc\r\n0\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nsmuggled",
+        ))
+        .await;
+        let (cfg, location) = proxy_config(vec![addr.clone()], false);
+        let proxy = Proxy::new();
+
+        let (result, out) = proxy_serve(&proxy, &cfg, &location).await;
+        assert!(result.expect("serve"));
+        assert!(out.starts_with("HTTP/1.1 502 Bad Gateway"), "got: {out}");
+        assert!(!out.contains("smuggled"), "got: {out}");
+        assert!(proxy.pools.get(&addr).is_none_or(|pool| pool.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn small_proxy_buffer_size_still_forwards_the_whole_body() {
+        const BODY: &str = concat!(
+            "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+            "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        );
+        let addr = spawn_upstream(reply(response("200 OK", BODY))).await;
+        let (cfg, location) = proxy_config(vec![addr], false);
+        let mut cfg = Arc::into_inner(cfg).expect("sole owner");
+        cfg.http.proxy_buffer_size = 16;
+        let cfg = Arc::new(cfg);
+
+        let (result, out) = proxy_serve(&Proxy::new(), &cfg, &location).await;
+        assert!(!result.expect("serve"));
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with(BODY), "got: {out}");
+    }
+
+    /// [`spawn_upstream`] behaviour: count the request in `hits` and answer
+    /// with a large body, so the client can hang up before it has been
+    /// forwarded.
+    fn count_and_reply_large(
+        hits: Arc<AtomicUsize>,
+    ) -> impl Fn(TcpStream, String) -> Reply + Send + Sync {
+        move |mut sock, _| {
+            hits.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let body = vec![b'x'; 256 * 1024];
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len());
+                let _ = sock.write_all(head.as_bytes()).await;
+                let _ = sock.write_all(&body).await;
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn client_disconnect_mid_response_skips_failover() {
+        let (first_hits, second_hits) = (Arc::default(), Arc::default());
+        let first = spawn_upstream(count_and_reply_large(Arc::clone(&first_hits))).await;
+        let second = spawn_upstream(count_and_reply_large(Arc::clone(&second_hits))).await;
+        let (cfg, location) = proxy_config(vec![first, second], false);
+        let proxy = Proxy::new();
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let _ = client.read(&mut buf).await;
+            // hang up after the first bytes of the response
+        });
+        let mut client_buf = BytesMut::new();
+        let client_addr = "127.0.0.1:5555".parse().expect("addr");
+        let result = proxy
+            .serve(
+                &mut server,
+                &mut client_buf,
+                &location,
+                "GET / HTTP/1.1\r\nHost: example",
+                "GET",
+                "/",
+                "HTTP/1.1",
+                0,
+                false,
+                KEEP_ALIVE,
+                false,
+                None,
+                None,
+                &cfg,
+                &client_addr,
+            )
+            .await;
+
+        assert!(result.expect("client hang-up is not an error"));
+        let hits = first_hits.load(Ordering::SeqCst) + second_hits.load(Ordering::SeqCst);
+        assert_eq!(hits, 1, "no second upstream attempt for a gone client");
+        assert!(
+            proxy.health.is_empty(),
+            "upstream not blamed for the client"
+        );
+    }
+
+    #[tokio::test]
+    async fn event_stream_is_forwarded_per_event() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.expect("accept");
+            let mut buf = [0u8; 1024];
+            let _ = sock.read(&mut buf).await;
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n";
+            let _ = sock.write_all(head.as_bytes()).await;
+            let _ = sock.write_all(b"a\r\ndata: one\n\r\n").await;
+            // The second event only goes out once the client saw the first.
+            let _ = release_rx.await;
+            let _ = sock.write_all(b"a\r\ndata: two\n\r\n0\r\n\r\n").await;
+        });
+        let (cfg, location) = proxy_config(vec![addr], false);
+        let proxy = Proxy::new();
+
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let reader = async move {
+            let mut out = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !String::from_utf8_lossy(&out).contains("data: one") {
+                let n =
+                    tokio::time::timeout(std::time::Duration::from_secs(2), client.read(&mut buf))
+                        .await
+                        .expect("first event arrives before the stream ends")
+                        .expect("read");
+                assert!(n > 0, "stream closed early");
+                out.extend_from_slice(&buf[..n]);
+            }
+            let _ = release_tx.send(());
+            client.read_to_end(&mut out).await.expect("read");
+            String::from_utf8(out).expect("utf8")
+        };
+        let serve = async {
+            let mut client_buf = BytesMut::new();
+            let client_addr = "127.0.0.1:5555".parse().expect("addr");
+            let result = proxy
+                .serve(
+                    &mut server,
+                    &mut client_buf,
+                    &location,
+                    "GET /events HTTP/1.1\r\nHost: example",
+                    "GET",
+                    "/events",
+                    "HTTP/1.1",
+                    0,
+                    false,
+                    KeepAlive::Close,
+                    false,
+                    None,
+                    None,
+                    &cfg,
+                    &client_addr,
+                )
+                .await;
+            drop(server);
+            result
+        };
+        let (out, result) = tokio::join!(reader, serve);
+        result.expect("serve");
+        assert!(out.contains("data: one\n"), "got: {out}");
+        assert!(out.ends_with("data: two\n\r\n0\r\n\r\n"), "got: {out}");
+    }
+
+    const TEST_CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/self-signed-cert.pem");
+    const TEST_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/self-signed-key.pem");
+
+    /// HTTPS backend with a self-signed certificate for `localhost` and
+    /// `127.0.0.1`, answering every request with `secure`.
+    async fn spawn_tls_upstream() -> String {
+        spawn_tls_upstream_with(|_, _| "secure".into()).await
+    }
+
+    /// HTTPS backend answering with `respond(sni, request head)`.
+    async fn spawn_tls_upstream_with(
+        respond: impl Fn(Option<&str>, &str) -> String + Send + Sync + 'static,
+    ) -> String {
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let pem = |path| std::io::BufReader::new(std::fs::File::open(path).expect("fixture"));
+        let certs = rustls_pemfile::certs(&mut pem(TEST_CERT)).expect("cert");
+        let key = rustls_pemfile::pkcs8_private_keys(&mut pem(TEST_KEY))
+            .expect("key")
+            .remove(0);
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                certs.into_iter().map(Certificate).collect(),
+                PrivateKey(key),
+            )
+            .expect("server config");
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let respond = Arc::new(respond);
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let Ok(mut tls) = acceptor.accept(sock).await else {
+                        return;
+                    };
+                    let mut buf = [0u8; 1024];
+                    let n = tls.read(&mut buf).await.unwrap_or(0);
+                    let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+                    let body = respond(tls.get_ref().1.server_name(), &head);
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = tls.write_all(response.as_bytes()).await;
+                    let _ = tls.shutdown().await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn https_get(addr: &str, tweak: impl FnOnce(&mut UpstreamConfig)) -> String {
+        https_get_at(addr, tweak, LocationConfig::default()).await
+    }
+
+    async fn https_get_at(
+        addr: &str,
+        tweak: impl FnOnce(&mut UpstreamConfig),
+        location: LocationConfig,
+    ) -> String {
+        let mut upstream = UpstreamConfig {
+            server: UpstreamServers::One(addr.to_string()),
+            tls: true,
+            ..Default::default()
+        };
+        tweak(&mut upstream);
+        let mut cfg = MiguxConfig::default();
+        cfg.upstream.insert("app".into(), upstream);
+        let location = LocationConfig {
+            path: "/".into(),
+            r#type: LocationType::Proxy,
+            upstream: Some("app".into()),
+            ..location
+        };
+        proxy_get(&Arc::new(cfg), &location).await
+    }
+
+    #[tokio::test]
+    async fn ip_addressed_https_upstream_gets_configured_sni_and_host() {
+        let addr = spawn_tls_upstream_with(|sni, head| {
+            let host = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Host: "))
+                .unwrap_or("-");
+            format!("sni={} host={host}", sni.unwrap_or("-"))
+        })
+        .await;
+        assert!(addr.starts_with("127.0.0.1:"));
+        let location = LocationConfig {
+            set_headers: vec![SetHeader::parse("Host: origin.example").expect("valid")],
+            ..Default::default()
+        };
+        let out = https_get_at(
+            &addr,
+            |up| {
+                up.tls_ca_path = Some(TEST_CERT.into());
+                up.tls_server_name = Some("localhost".into());
+            },
+            location,
+        )
+        .await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(
+            out.ends_with("\r\n\r\nsni=localhost host=origin.example"),
+            "got: {out}"
+        );
+
+        // Neither set: no SNI for an IP, and the client's Host goes through.
+        let out = https_get(&addr, |up| up.tls_ca_path = Some(TEST_CERT.into())).await;
+        assert!(out.ends_with("\r\n\r\nsni=- host=example"), "got: {out}");
+    }
+
+    #[tokio::test]
+    async fn https_upstream_verification_modes() {
+        let addr = spawn_tls_upstream().await;
+
+        let out = https_get(&addr, |up| up.tls_ca_path = Some(TEST_CERT.into())).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("\r\n\r\nsecure"), "got: {out}");
+
+        let out = https_get(&addr, |up| {
+            up.tls_ca_path = Some(TEST_CERT.into());
+            up.tls_server_name = Some("localhost".into());
+        })
+        .await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+
+        // Unknown issuer, then a name the certificate doesn't cover.
+        let out = https_get(&addr, |_| {}).await;
+        assert!(out.starts_with("HTTP/1.1 502"), "got: {out}");
+        let out = https_get(&addr, |up| {
+            up.tls_ca_path = Some(TEST_CERT.into());
+            up.tls_server_name = Some("other.example".into());
+        })
+        .await;
+        assert!(out.starts_with("HTTP/1.1 502"), "got: {out}");
+
+        let out = https_get(&addr, |up| up.tls_verify = Some(false)).await;
+        assert!(out.starts_with("HTTP/1.1 200 OK"), "got: {out}");
+        assert!(out.ends_with("\r\n\r\nsecure"), "got: {out}");
+    }
+}
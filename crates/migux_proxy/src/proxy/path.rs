@@ -1,3 +1,6 @@
+use migux_config::{RewriteFlag, RewriteRule};
+use migux_http::target::strip_raw_prefix;
+
 /// =======================================================
 /// URL REWRITE: strip_prefix tipo nginx
 /// =======================================================
@@ -5,16 +8,22 @@
 /// Esto implementa la idea tipica:
 /// location /api/ { proxy_pass http://app; }  ->  /api/users -> /users
 ///
+/// - El prefijo se compara con la forma normalizada (la del routing), pero
+///   el resto se devuelve tal cual lo envio el cliente (`%2F` sigue `%2F`)
 /// - Si req_path NO empieza por location_path => no toca nada
 /// - Si el "tail" queda vacio => "/"
 /// - Asegura que empiece por '/'
-pub(super) fn strip_prefix_path(req_path: &str, location_path: &str) -> String {
-    if !req_path.starts_with(location_path) {
+pub(super) fn strip_prefix_path(
+    req_path: &str,
+    location_path: &str,
+    decode_slashes: bool,
+) -> String {
+    let Some(tail) = strip_raw_prefix(req_path, location_path, decode_slashes) else {
         return req_path.to_string();
-    }
+    };
 
     // parte restante despues del prefijo
-    let mut tail = req_path[location_path.len()..].to_string();
+    let mut tail = tail.to_string();
 
     // exact match: "/api" -> "/"
     if tail.is_empty() {
@@ -28,3 +37,74 @@ pub(super) fn strip_prefix_path(req_path: &str, location_path: &str) -> String {
 
     tail
 }
+
+/// =======================================================
+/// URL REWRITE: reglas regex (`rewrite = "^/old/(.*)$ /new/$1"`)
+/// =======================================================
+///
+/// - Aplica las reglas en orden sobre el path ya reescrito
+/// - `last` / `break` cortan el procesamiento tras un match
+/// - Sin match => el path pasa tal cual
+pub(super) fn rewrite_path(req_path: &str, rules: &[RewriteRule]) -> String {
+    let mut path = req_path.to_string();
+    for rule in rules {
+        if !rule.pattern.is_match(&path) {
+            continue;
+        }
+        path = rule
+            .pattern
+            .replace(&path, rule.replacement.as_str())
+            .into_owned();
+        if rule.flag != RewriteFlag::Continue {
+            break;
+        }
+    }
+
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_path, strip_prefix_path};
+    use migux_config::RewriteRule;
+
+    fn rules(raw: &[&str]) -> Vec<RewriteRule> {
+        raw.iter()
+            .map(|r| RewriteRule::parse(r).expect("valid rule"))
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_substitutes_capture_groups() {
+        let rules = rules(&["^/legacy/(.*)$ /v2/$1"]);
+        assert_eq!(rewrite_path("/legacy/users/7", &rules), "/v2/users/7");
+    }
+
+    #[test]
+    fn rewrite_passes_through_without_match() {
+        let rules = rules(&["^/legacy/(.*)$ /v2/$1"]);
+        assert_eq!(rewrite_path("/api/users", &rules), "/api/users");
+    }
+
+    #[test]
+    fn rewrite_rules_chain_until_last() {
+        let rules = rules(&["^/a/(.*)$ /b/$1", "^/b/(.*)$ /c/$1 last", "^/c/(.*)$ /d/$1"]);
+        assert_eq!(rewrite_path("/a/x", &rules), "/c/x");
+    }
+
+    #[test]
+    fn strip_prefix_exact_match_is_root() {
+        assert_eq!(strip_prefix_path("/api", "/api", false), "/");
+        assert_eq!(strip_prefix_path("/api/users", "/api", false), "/users");
+    }
+
+    #[test]
+    fn strip_prefix_keeps_encoded_characters() {
+        assert_eq!(strip_prefix_path("/api/a%2Fb", "/api", false), "/a%2Fb");
+        assert_eq!(strip_prefix_path("/%61pi/a%2fb", "/api", false), "/a%2fb");
+        assert_eq!(strip_prefix_path("/api%2Fv1/x", "/api/v1", true), "/x");
+    }
+}
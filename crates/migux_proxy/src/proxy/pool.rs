@@ -1,28 +1,38 @@
 //! Connection pooling helpers for upstream sockets.
 
-use std::time::Instant;
+use std::{net::SocketAddr, time::Instant};
 
 use bytes::BytesMut;
+use migux_http::fastopen;
 use tokio::{
-    net::TcpStream,
+    net::{TcpStream, lookup_host},
     time::{Duration, timeout},
 };
 use tracing::{debug, info, instrument};
 
 use super::Proxy;
+use super::error::ProxyError;
+use super::tls::{UpstreamStream, UpstreamTls};
+
+/// Bytes requested per upstream read unless `http.proxy_buffer_size` says
+/// otherwise.
+pub(super) const DEFAULT_READ_SIZE: usize = 8 * 1024;
 
 /// A pooled upstream connection with its read buffer.
 pub(super) struct PooledStream {
-    pub(super) stream: TcpStream,
+    pub(super) stream: UpstreamStream,
     pub(super) read_buf: BytesMut,
+    /// Spare capacity reserved in `read_buf` before each read.
+    pub(super) read_size: usize,
     pub(super) last_used: Instant,
 }
 
 impl PooledStream {
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(super) fn new(stream: UpstreamStream) -> Self {
         Self {
             stream,
             read_buf: BytesMut::new(),
+            read_size: DEFAULT_READ_SIZE,
             last_used: Instant::now(),
         }
     }
@@ -34,15 +44,17 @@ impl Proxy {
     /// Flow:
     /// - Try entry.pop() (LIFO) from pool for addr
     /// - Reuse if still within idle TTL
-    /// - Otherwise create a new connection
-    #[instrument(skip(self))]
+    /// - Otherwise create a new connection (TLS when `tls` is set)
+    #[instrument(skip(self, tls))]
     pub(super) async fn checkout_upstream_stream(
         &self,
         addr: &str,
+        tls: Option<&UpstreamTls>,
         connect_timeout: Duration,
         idle_ttl: Duration,
+        fastopen: bool,
     ) -> anyhow::Result<PooledStream> {
-        if let Some(mut entry) = self.pools.get_mut(addr) {
+        if let Some(mut entry) = self.pools.get_mut(&pool_key(addr, tls)) {
             while let Some(pooled) = entry.pop() {
                 if idle_ttl.is_zero() || pooled.last_used.elapsed() <= idle_ttl {
                     debug!(target: "migux::proxy", upstream = %addr, "Reusing pooled upstream connection");
@@ -53,19 +65,19 @@ impl Proxy {
         }
 
         info!(target: "migux::proxy", upstream = %addr, "Creating new upstream connection");
-        let stream = connect_with_timeout(addr, connect_timeout).await?;
-        Ok(PooledStream::new(stream))
+        connect_fresh(addr, tls, connect_timeout, fastopen).await
     }
 
     /// Returns an upstream connection back to the pool so it can be reused.
     pub(super) fn checkin_upstream_stream(
         &self,
         addr: &str,
+        tls: Option<&UpstreamTls>,
         mut pooled: PooledStream,
         max_pool: usize,
     ) {
         pooled.last_used = Instant::now();
-        let mut entry = self.pools.entry(addr.to_string()).or_insert_with(Vec::new);
+        let mut entry = self.pools.entry(pool_key(addr, tls)).or_default();
         if entry.len() >= max_pool {
             debug!(target: "migux::proxy", upstream = %addr, "Pool full; dropping connection");
             return;
@@ -76,22 +88,86 @@ impl Proxy {
     }
 }
 
+/// Plain connections are pooled per address; TLS ones per upstream too.
+fn pool_key(addr: &str, tls: Option<&UpstreamTls>) -> String {
+    tls.map_or_else(|| addr.to_string(), |tls| tls.pool_key(addr))
+}
+
 /// Create a fresh upstream connection (used when a pooled socket is dead).
+/// `connect_timeout` covers the TLS handshake as well. `fastopen` sends the
+/// first write in the SYN (`global.tcp_fastopen`).
 pub(super) async fn connect_fresh(
     addr: &str,
+    tls: Option<&UpstreamTls>,
     timeout_dur: Duration,
+    fastopen: bool,
 ) -> anyhow::Result<PooledStream> {
-    let stream = connect_with_timeout(addr, timeout_dur).await?;
-    Ok(PooledStream::new(stream))
+    let stream = if fastopen {
+        connect_fast_open(addr, timeout_dur).await?
+    } else {
+        connect_with_timeout(addr, timeout_dur).await?
+    };
+    let Some(tls) = tls else {
+        return Ok(PooledStream::new(UpstreamStream::Plain(stream)));
+    };
+    match timeout(timeout_dur, tls.connect(addr, stream)).await {
+        Ok(res) => Ok(PooledStream::new(UpstreamStream::Tls(Box::new(res?)))),
+        Err(_) => {
+            Err(ProxyError::ConnectTimeout
+                .context(format!("Upstream TLS handshake timeout to {addr}")))
+        }
+    }
+}
+
+/// Resolve `addr` (`host:port`); a failed or empty lookup is a
+/// [`ProxyError::DnsFailure`].
+async fn resolve(addr: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let resolved = match lookup_host(addr).await {
+        Ok(addrs) => addrs.collect::<Vec<_>>(),
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context(ProxyError::DnsFailure)
+                .context(format!("Cannot resolve upstream {addr}")));
+        }
+    };
+    if resolved.is_empty() {
+        return Err(ProxyError::DnsFailure.context(format!("Upstream {addr} resolved to nothing")));
+    }
+    Ok(resolved)
 }
 
-/// Connect to an upstream with a timeout.
+/// Connect to an upstream with a timeout (covering the lookup too).
 pub(super) async fn connect_with_timeout(
     addr: &str,
     timeout_dur: Duration,
 ) -> anyhow::Result<TcpStream> {
-    match timeout(timeout_dur, TcpStream::connect(addr)).await {
-        Ok(res) => Ok(res?),
-        Err(_) => anyhow::bail!("Upstream connect timeout to {}", addr),
+    let connect = async {
+        let resolved = resolve(addr).await?;
+        TcpStream::connect(&resolved[..])
+            .await
+            .map_err(ProxyError::connect_failed)
+    };
+    match timeout(timeout_dur, connect).await {
+        Ok(res) => res,
+        Err(_) => {
+            Err(ProxyError::ConnectTimeout.context(format!("Upstream connect timeout to {addr}")))
+        }
+    }
+}
+
+/// [`connect_with_timeout`] with TCP Fast Open. Not for health checks: the
+/// SYN waits for the first write, so the connect alone proves nothing.
+async fn connect_fast_open(addr: &str, timeout_dur: Duration) -> anyhow::Result<TcpStream> {
+    let connect = async {
+        let resolved = resolve(addr).await?;
+        fastopen::connect(&resolved[..])
+            .await
+            .map_err(ProxyError::connect_failed)
+    };
+    match timeout(timeout_dur, connect).await {
+        Ok(res) => res,
+        Err(_) => {
+            Err(ProxyError::ConnectTimeout.context(format!("Upstream connect timeout to {addr}")))
+        }
     }
 }
@@ -0,0 +1,17 @@
+//! Cheap non-cryptographic randomness for balancing, retry jitter and
+//! request ids.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A random `u64` from a freshly seeded `RandomState`. The counter keeps
+/// two calls from hashing the same input.
+pub(super) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
@@ -0,0 +1,139 @@
+//! `proxy_redirect`: the `Location` header of upstream responses is
+//! rewritten so clients are sent to the public URL, not to the backend
+//! address the upstream believes it lives at.
+
+use migux_config::ProxyRedirect;
+
+/// `from` -> `to` prefix replacements for one upstream attempt, tried in
+/// order; the first match wins.
+#[derive(Debug, Default)]
+pub(super) struct RedirectRewrite {
+    maps: Vec<(String, String)>,
+}
+
+impl RedirectRewrite {
+    /// Replacements for a response from `upstream_addr`. `Default` maps
+    /// `scheme://upstream_addr` (also without a default port) to
+    /// `public_prefix`, the path prefix stripped before forwarding.
+    pub(super) fn new(
+        rules: &[ProxyRedirect],
+        scheme: &str,
+        upstream_addr: &str,
+        public_prefix: &str,
+    ) -> Self {
+        let mut maps = Vec::new();
+        for rule in rules {
+            match rule {
+                ProxyRedirect::Default => {
+                    let prefix = public_prefix.trim_end_matches('/');
+                    let default_port = if scheme == "https" { ":443" } else { ":80" };
+                    maps.push((format!("{scheme}://{upstream_addr}/"), format!("{prefix}/")));
+                    if let Some(host) = upstream_addr.strip_suffix(default_port) {
+                        maps.push((format!("{scheme}://{host}/"), format!("{prefix}/")));
+                    }
+                }
+                ProxyRedirect::Map { from, to } => maps.push((from.clone(), to.clone())),
+            }
+        }
+        Self { maps }
+    }
+
+    /// The rewritten `value`, or `None` when no replacement applies.
+    /// Prefixes compare case-insensitively (scheme and host are).
+    fn apply(&self, value: &str) -> Option<String> {
+        self.maps.iter().find_map(|(from, to)| {
+            let head = value.get(..from.len())?;
+            head.eq_ignore_ascii_case(from)
+                .then(|| format!("{to}{}", &value[from.len()..]))
+        })
+    }
+}
+
+/// `headers_bytes` (a full response head) with its `Location` rewritten.
+pub(super) fn rewrite_location(headers_bytes: Vec<u8>, rewrite: &RedirectRewrite) -> Vec<u8> {
+    if rewrite.maps.is_empty() {
+        return headers_bytes;
+    }
+    let header_len = headers_bytes.len().saturating_sub(4);
+    let header_str = String::from_utf8_lossy(&headers_bytes[..header_len]);
+    let location = header_str.split("\r\n").skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("location")
+            .then(|| (name, rewrite.apply(value.trim())))
+    });
+    let Some((name, Some(new_value))) = location else {
+        return headers_bytes;
+    };
+
+    let mut out = Vec::with_capacity(headers_bytes.len() + new_value.len());
+    for (idx, line) in header_str.split("\r\n").enumerate() {
+        let is_location = idx > 0
+            && line
+                .split_once(':')
+                .is_some_and(|(n, _)| n.trim().eq_ignore_ascii_case("location"));
+        if is_location {
+            out.extend_from_slice(format!("{}: {new_value}", name.trim()).as_bytes());
+        } else {
+            out.extend_from_slice(line.as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RedirectRewrite, rewrite_location};
+    use migux_config::ProxyRedirect;
+
+    fn location_of(head: &[u8]) -> String {
+        let head = String::from_utf8_lossy(head);
+        head.lines()
+            .find_map(|line| line.strip_prefix("Location: "))
+            .expect("Location")
+            .to_string()
+    }
+
+    #[test]
+    fn default_maps_the_upstream_origin_to_the_location_prefix() {
+        let rewrite =
+            RedirectRewrite::new(&[ProxyRedirect::Default], "http", "10.0.0.5:80", "/api/");
+        for (upstream, public) in [
+            ("http://10.0.0.5:80/login?next=/", "/api/login?next=/"),
+            ("HTTP://10.0.0.5/login", "/api/login"),
+            ("https://10.0.0.5/login", "https://10.0.0.5/login"),
+            ("http://example.com/login", "http://example.com/login"),
+            ("/relative", "/relative"),
+        ] {
+            let head =
+                format!("HTTP/1.1 302 Found\r\nLocation: {upstream}\r\nContent-Length: 0\r\n\r\n");
+            let out = rewrite_location(head.into_bytes(), &rewrite);
+            assert_eq!(location_of(&out), public);
+            assert!(out.ends_with(b"Content-Length: 0\r\n\r\n"));
+        }
+    }
+
+    #[test]
+    fn explicit_maps_apply_in_order() {
+        let rules = [
+            ProxyRedirect::parse("http://backend:8080/app/ https://www.example.com/").expect("map"),
+            ProxyRedirect::parse("http://backend:8080/ /").expect("map"),
+        ];
+        let rewrite = RedirectRewrite::new(&rules, "http", "127.0.0.1:8080", "/");
+        let head = b"HTTP/1.1 301 Moved Permanently\r\nlocation: http://backend:8080/app/x\r\n\r\n";
+        let out = String::from_utf8(rewrite_location(head.to_vec(), &rewrite)).expect("utf8");
+        assert!(
+            out.contains("\r\nlocation: https://www.example.com/x\r\n"),
+            "got: {out}"
+        );
+
+        let head = b"HTTP/1.1 301 Moved Permanently\r\nLocation: http://backend:8080/other\r\n\r\n";
+        assert_eq!(
+            location_of(&rewrite_location(head.to_vec(), &rewrite)),
+            "/other"
+        );
+        assert!(ProxyRedirect::parse("one two three").is_err());
+    }
+}
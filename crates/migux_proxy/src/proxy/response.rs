@@ -3,14 +3,21 @@
 //! Handles header parsing, chunked transfer decoding, and body forwarding
 //! while enforcing configured limits.
 
-use bytes::BytesMut;
+use std::io::Write as _;
+
+use bytes::{Buf, BufMut, BytesMut};
+use migux_http::content_length::parse_content_length;
+use migux_http::keep_alive::KeepAlive;
 use tokio::{
     io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
-    time::{Duration, timeout},
+    time::{Duration, Instant, timeout, timeout_at},
 };
 use tracing::{debug, instrument, warn};
 
+use super::compress::{CompressPlan, Encoder, compressible_type};
+use super::error::ProxyError;
 use super::pool::PooledStream;
+use super::redirect::{RedirectRewrite, rewrite_location};
 
 /// =======================================================
 /// HTTP RESPONSE STREAMER
@@ -22,9 +29,32 @@ use super::pool::PooledStream;
 ///   - chunked: parsea chunks y los forwardea
 ///   - content-length: forwardea exactamente CL bytes
 ///   - sin CL: read-to-EOF (no reusable)
-/// Stream an upstream HTTP response to the client and return whether the
+/// Stream an upstream HTTP response to the client and report whether the
 /// upstream connection is reusable.
+///
+/// With `retry_5xx`, a 5xx status is returned as `Retry5xx` before anything
+/// is written to the client (the upstream connection must then be dropped).
+/// Either way a 5xx `Retry-After` is reported for passive health.
+/// Interim 1xx responses are skipped within `interim` limits.
+///
+/// The upstream `Connection`/`Keep-Alive` headers are replaced by ours for
+/// `client_keep_alive`; a body delimited by upstream EOF always closes
+/// the client connection. A keep-alive response with no framing at all is
+/// logged, or rejected before forwarding when `strict_framing` is set.
+///
+/// Server-Sent Events (`Content-Type: text/event-stream`) are flushed to the
+/// client after every write and are exempt from `max_body`; `read_timeout`
+/// applies per read, so it acts as an idle timeout between events.
+///
+/// With a `compress` plan, a compressible response that is not already
+/// encoded is sent through the encoder: `Content-Encoding` and `Vary` are
+/// set and the body is re-framed as chunked, since its length changes.
+/// With `refuse_identity`, a 2xx body that would go out unencoded is not
+/// forwarded: the caller answers 406.
+///
+/// The `Location` header goes through `redirect` (`proxy_redirect`).
 #[instrument(skip(upstream, client_stream))]
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn stream_http_response<S>(
     upstream: &mut PooledStream,
     client_stream: &mut S,
@@ -33,49 +63,296 @@ pub(super) async fn stream_http_response<S>(
     max_headers: usize,
     max_body: usize,
     hsts_header: Option<&str>,
-) -> anyhow::Result<bool>
+    retry_5xx: bool,
+    client_keep_alive: KeepAlive,
+    strict_framing: bool,
+    compress: Option<CompressPlan>,
+    refuse_identity: bool,
+    interim: InterimLimits,
+    redirect: &RedirectRewrite,
+) -> anyhow::Result<ResponseOutcome>
 where
     S: AsyncWrite + Unpin + ?Sized,
 {
-    let headers_end = read_response_headers(upstream, read_timeout, max_headers).await?;
-    let headers_bytes = upstream.read_buf.split_to(headers_end + 4);
-    let header_len = headers_bytes.len().saturating_sub(4);
+    let headers = read_final_headers(upstream, read_timeout, max_headers, interim);
+    let (headers_end, info) = match interim.header_timeout {
+        None => headers.await?,
+        Some(limit) => timeout(limit, headers).await.map_err(|_| {
+            ProxyError::ReadTimeout.context(format!(
+                "No response headers within proxy_response_header_timeout_secs ({}s)",
+                limit.as_secs()
+            ))
+        })??,
+    };
 
-    let info = parse_response_headers(&headers_bytes[..header_len])?;
-    let no_body = is_no_body(method, info.status_code);
+    if retry_5xx && let Some(status @ 500..=599) = info.status_code {
+        return Ok(ResponseOutcome::Retry5xx(status, info.retry_after));
+    }
 
-    let header_out = maybe_inject_hsts(&headers_bytes, hsts_header);
-    client_stream.write_all(&header_out).await?;
+    let max_body = if info.is_event_stream { 0 } else { max_body };
+    if let Some(cl) = info.content_length
+        && max_body > 0
+        && cl > max_body
+    {
+        return Err(ProxyError::BodyTooLarge.context("Upstream response body too large"));
+    }
 
-    let mut reusable = if info.is_http10 {
+    let headers_bytes = upstream.read_buf.split_to(headers_end + 4);
+    let no_body = is_no_body(method, info.status_code);
+
+    let framed = no_body || info.is_chunked || info.content_length.is_some();
+    let claims_keep_alive = if info.is_http10 {
         info.connection_keep_alive && !info.connection_close
     } else {
         !info.connection_close
     };
+    if !framed && claims_keep_alive {
+        warn!(
+            target: "migux::proxy",
+            status = ?info.status_code,
+            strict = strict_framing,
+            "Upstream response has no Content-Length or chunked encoding on a keep-alive connection"
+        );
+        if strict_framing {
+            return Err(ProxyError::BadFraming.context(
+                "Upstream response framing is ambiguous (no Content-Length, not chunked)",
+            ));
+        }
+    }
+    let keep_client = if framed {
+        client_keep_alive
+    } else {
+        KeepAlive::Close
+    };
+    let encoder = compress
+        .filter(|plan| info.should_compress(no_body, plan.min_length))
+        .map(|plan| plan.coding);
+    if refuse_identity
+        && encoder.is_none()
+        && !no_body
+        && !info.content_encoded
+        && matches!(info.status_code, Some(200..=299))
+    {
+        return Ok(ResponseOutcome::NotAcceptable);
+    }
+    let header_out = maybe_inject_hsts(&headers_bytes, hsts_header);
+    let header_out = match encoder {
+        Some(coding) => encoded_headers(&header_out, coding.token()),
+        None => header_out,
+    };
+    let header_out = rewrite_location(header_out, redirect);
+    let header_out = rewrite_connection(&header_out, keep_client);
+    if let Some(coding) = encoder {
+        debug!(target: "migux::proxy", coding = coding.token(), "Compressing upstream response");
+    }
+
+    // From here on the client has (part of) the response: failures are aborts.
+    let forwarded = async {
+        forward(client_stream, &header_out, info.is_event_stream).await?;
+        let mut sink = BodySink {
+            client: client_stream,
+            flush: info.is_event_stream,
+            encoder: encoder.map(Encoder::new),
+        };
+        stream_body(upstream, &mut sink, &info, no_body, read_timeout, max_body).await
+    };
+    let end = forwarded.await.map_err(ResponseAborted)?;
+
+    let reusable = end == BodyEnd::Complete && framed && claims_keep_alive;
+
+    Ok(ResponseOutcome::Done {
+        reusable,
+        keep_client: keep_client.is_open(),
+        retry_after: info.retry_after,
+        capped: end == BodyEnd::Capped,
+    })
+}
+
+/// How a forwarded response body ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyEnd {
+    /// Read to its framed end.
+    Complete,
+    /// Not read to a framed end (cut short, or delimited by EOF): the
+    /// upstream connection must be dropped.
+    Unfinished,
+    /// An unframed body stopped at `max_body`: the client got a prefix.
+    Capped,
+}
 
+/// Forward the response body.
+async fn stream_body<S>(
+    upstream: &mut PooledStream,
+    sink: &mut BodySink<'_, S>,
+    info: &ResponseInfo,
+    no_body: bool,
+    read_timeout: Duration,
+    max_body: usize,
+) -> anyhow::Result<BodyEnd>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
     if no_body {
-        return Ok(reusable);
+        return Ok(BodyEnd::Complete);
     }
 
     if info.is_chunked {
-        stream_chunked_body(upstream, client_stream, read_timeout, max_body).await?;
-        return Ok(reusable);
+        stream_chunked_body(upstream, sink, read_timeout, max_body).await?;
+        sink.finish().await?;
+        return Ok(BodyEnd::Complete);
     }
 
     if let Some(cl) = info.content_length {
-        if max_body > 0 && cl > max_body {
-            anyhow::bail!("Upstream response body too large");
-        }
-        let complete = stream_content_length(upstream, client_stream, cl, read_timeout).await?;
-        if !complete {
-            reusable = false;
+        let complete = stream_content_length(upstream, sink, cl, read_timeout).await?;
+        if complete {
+            sink.finish().await?;
+        } else if sink.is_encoding() {
+            // Without a Content-Length the client can't tell the body was
+            // cut short; abort instead of ending the chunked stream.
+            return Err(ProxyError::BadFraming.context("Upstream closed before full body was read"));
         }
-        return Ok(reusable);
+        return Ok(if complete {
+            BodyEnd::Complete
+        } else {
+            BodyEnd::Unfinished
+        });
     }
 
     // Sin Content-Length y no chunked: leer hasta EOF -> no reusable
-    stream_until_eof(upstream, client_stream, read_timeout, max_body).await?;
-    Ok(false)
+    if !stream_until_eof(upstream, sink, read_timeout, max_body).await? {
+        // An encoded body stays unterminated, so the client can tell.
+        return Ok(BodyEnd::Capped);
+    }
+    sink.finish().await?;
+    Ok(BodyEnd::Unfinished)
+}
+
+/// The client side of a response body: bytes go straight through, or
+/// through an encoder and out as chunks (the upstream's own chunk framing
+/// is then dropped).
+struct BodySink<'a, S: ?Sized> {
+    client: &'a mut S,
+    /// Flush after every write (Server-Sent Events).
+    flush: bool,
+    encoder: Option<Encoder>,
+}
+
+impl<S> BodySink<'_, S>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    fn is_encoding(&self) -> bool {
+        self.encoder.is_some()
+    }
+
+    /// Body payload.
+    async fn data(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        match &mut self.encoder {
+            Some(encoder) => {
+                let out = encoder.write(bytes)?;
+                write_chunk(self.client, &out).await
+            }
+            None => forward(self.client, bytes, self.flush).await,
+        }
+    }
+
+    /// Upstream chunk framing (size lines, CRLFs, trailers).
+    async fn framing(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        if self.encoder.is_some() {
+            return Ok(());
+        }
+        forward(self.client, bytes, self.flush).await
+    }
+
+    /// End the encoded body with the encoder's last output and the final
+    /// chunk; nothing to do when passing through.
+    async fn finish(&mut self) -> anyhow::Result<()> {
+        let Some(encoder) = self.encoder.take() else {
+            return Ok(());
+        };
+        let out = encoder.finish()?;
+        write_chunk(self.client, &out).await?;
+        write_client(self.client, b"0\r\n\r\n").await
+    }
+}
+
+/// Write `data` as one chunk; empty output is skipped (an empty chunk would
+/// end the body).
+async fn write_chunk<S>(client_stream: &mut S, data: &[u8]) -> anyhow::Result<()>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    if data.is_empty() {
+        return Ok(());
+    }
+    let mut frame = Vec::with_capacity(data.len() + 12);
+    let _ = write!(frame, "{:x}\r\n", data.len());
+    frame.extend_from_slice(data);
+    frame.extend_from_slice(b"\r\n");
+    write_client(client_stream, &frame).await
+}
+
+/// An upstream failure after part of the response reached the client; the
+/// client connection can only be aborted.
+#[derive(Debug)]
+pub(super) struct ResponseAborted(pub(super) anyhow::Error);
+
+impl std::fmt::Display for ResponseAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Upstream response aborted mid-stream: {}", self.0)
+    }
+}
+
+impl std::error::Error for ResponseAborted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+async fn write_client<S>(client_stream: &mut S, bytes: &[u8]) -> anyhow::Result<()>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    client_stream.write_all(bytes).await.map_err(client_gone)
+}
+
+/// Writing to the client failed: it has disconnected, so there is nobody
+/// left to serve and no upstream is to blame.
+fn client_gone(err: std::io::Error) -> anyhow::Error {
+    anyhow::Error::from(err).context(ProxyError::ClientGone)
+}
+
+/// Write body bytes, flushing them through when the response is streamed
+/// live (SSE) so nothing sits in a TLS or h2 buffer.
+async fn forward<S>(client_stream: &mut S, bytes: &[u8], flush: bool) -> anyhow::Result<()>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    write_client(client_stream, bytes).await?;
+    if flush {
+        client_stream.flush().await.map_err(client_gone)?;
+    }
+    Ok(())
+}
+
+/// Result of handling one upstream response.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum ResponseOutcome {
+    /// Response forwarded to the client; `keep_client` is false when the
+    /// client connection must be closed. `retry_after` is a 5xx response's
+    /// `Retry-After`. `capped`: a body without length went past
+    /// `max_upstream_response_body_bytes` and was cut there.
+    Done {
+        reusable: bool,
+        keep_client: bool,
+        retry_after: Option<Duration>,
+        capped: bool,
+    },
+    /// 5xx seen and retry requested; nothing was forwarded.
+    Retry5xx(u16, Option<Duration>),
+    /// The client refused identity and the body cannot be encoded; nothing
+    /// was forwarded.
+    NotAcceptable,
 }
 
 fn maybe_inject_hsts(headers_bytes: &[u8], hsts_header: Option<&str>) -> Vec<u8> {
@@ -96,6 +373,116 @@ fn maybe_inject_hsts(headers_bytes: &[u8], hsts_header: Option<&str>) -> Vec<u8>
     out
 }
 
+/// Drop hop-by-hop `Connection`/`Keep-Alive` headers from an upstream head
+/// and append the ones we actually honor towards the client.
+fn rewrite_connection(headers_bytes: &[u8], keep_alive: KeepAlive) -> Vec<u8> {
+    let mut out = Vec::with_capacity(headers_bytes.len() + 24);
+    for (idx, line) in head_lines(headers_bytes).enumerate() {
+        if idx > 0 {
+            let (name, _) = split_field(line).unwrap_or((line.trim_ascii(), b""));
+            if name.eq_ignore_ascii_case(b"connection") || name.eq_ignore_ascii_case(b"keep-alive")
+            {
+                continue;
+            }
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"Connection: ");
+    out.extend_from_slice(keep_alive.connection().as_bytes());
+    if let Some(value) = keep_alive.header_value() {
+        out.extend_from_slice(b"\r\nKeep-Alive: ");
+        out.extend_from_slice(value.as_bytes());
+    }
+    out.extend_from_slice(b"\r\n\r\n");
+    out
+}
+
+/// Headers for a body we compress: the upstream's length and framing go,
+/// the encoding is announced, `Vary` covers `Accept-Encoding` and a strong
+/// `ETag` is weakened (the bytes no longer match the upstream's).
+fn encoded_headers(headers_bytes: &[u8], coding: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(headers_bytes.len() + 96);
+    let mut vary_seen = false;
+    for (idx, line) in head_lines(headers_bytes).enumerate() {
+        let (name, value) = match split_field(line) {
+            Some(field) if idx > 0 => field,
+            _ => {
+                out.extend_from_slice(line);
+                out.extend_from_slice(b"\r\n");
+                continue;
+            }
+        };
+        if name.eq_ignore_ascii_case(b"content-length")
+            || name.eq_ignore_ascii_case(b"transfer-encoding")
+            || name.eq_ignore_ascii_case(b"content-encoding")
+        {
+            continue;
+        }
+        if name.eq_ignore_ascii_case(b"etag") && value.starts_with(b"\"") {
+            push_field(&mut out, name, &[b"W/", value]);
+            continue;
+        }
+        if name.eq_ignore_ascii_case(b"vary") {
+            vary_seen = true;
+            let covered = value.split(|&b| b == b',').any(|token| {
+                let token = token.trim_ascii();
+                token == b"*" || token.eq_ignore_ascii_case(b"accept-encoding")
+            });
+            if !covered {
+                push_field(&mut out, name, &[value, b", Accept-Encoding"]);
+                continue;
+            }
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    if !vary_seen {
+        out.extend_from_slice(b"Vary: Accept-Encoding\r\n");
+    }
+    let _ = write!(
+        out,
+        "Content-Encoding: {coding}\r\nTransfer-Encoding: chunked\r\n\r\n"
+    );
+    out
+}
+
+/// Lines of a response head (status line first) without their CRLFs. Works
+/// on bytes so obs-text in upstream header values passes through untouched.
+fn head_lines(headers_bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let header_len = headers_bytes.len().saturating_sub(4);
+    let mut rest = Some(&headers_bytes[..header_len]);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        match current.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => {
+                rest = Some(&current[pos + 2..]);
+                Some(&current[..pos])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+/// Trimmed name and value of a `name: value` header line.
+fn split_field(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let colon = line.iter().position(|&b| b == b':')?;
+    Some((line[..colon].trim_ascii(), line[colon + 1..].trim_ascii()))
+}
+
+/// Append `name: <value parts>\r\n` to `out`.
+fn push_field(out: &mut Vec<u8>, name: &[u8], value: &[&[u8]]) {
+    out.extend_from_slice(name);
+    out.extend_from_slice(b": ");
+    for part in value {
+        out.extend_from_slice(part);
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
 fn headers_contain_hsts(headers_bytes: &[u8]) -> bool {
     let header_len = headers_bytes.len().saturating_sub(4);
     let header_str = String::from_utf8_lossy(&headers_bytes[..header_len]);
@@ -104,13 +491,12 @@ fn headers_contain_hsts(headers_bytes: &[u8]) -> bool {
         if line.is_empty() {
             continue;
         }
-        if let Some((name, _)) = line.split_once(':') {
-            if name
+        if let Some((name, _)) = line.split_once(':')
+            && name
                 .trim()
                 .eq_ignore_ascii_case("strict-transport-security")
-            {
-                return true;
-            }
+        {
+            return true;
         }
     }
     false
@@ -127,26 +513,75 @@ async fn read_response_headers(
         }
 
         if max_headers > 0 && upstream.read_buf.len() > max_headers {
-            anyhow::bail!("Upstream response headers too large");
+            return Err(ProxyError::BadFraming.context("Upstream response headers too large"));
         }
 
         let n = read_more(upstream, read_timeout).await?;
         if n == 0 {
-            anyhow::bail!("Upstream closed connection while reading headers");
+            return Err(
+                ProxyError::BadFraming.context("Upstream closed connection while reading headers")
+            );
+        }
+    }
+}
+
+/// Bounds on waiting for the final response: interim (1xx) responses and
+/// time-to-first-byte.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct InterimLimits {
+    /// Interim responses tolerated; one more is an error.
+    pub(super) max: u32,
+    /// Time allowed for the final response, counted from the first one.
+    pub(super) timeout: Duration,
+    /// Time allowed for the final response, counted from the request
+    /// (`proxy_response_header_timeout_secs`).
+    pub(super) header_timeout: Option<Duration>,
+}
+
+/// Read response heads until a final one, dropping interim (1xx, except
+/// `101`) responses from the buffer. Errors when `limits` are exceeded;
+/// the connection is then unusable.
+async fn read_final_headers(
+    upstream: &mut PooledStream,
+    read_timeout: Duration,
+    max_headers: usize,
+    limits: InterimLimits,
+) -> anyhow::Result<(usize, ResponseInfo)> {
+    let mut interim = 0u32;
+    let mut deadline = None;
+    loop {
+        let read = read_response_headers(upstream, read_timeout, max_headers);
+        let headers_end = match deadline {
+            None => read.await?,
+            Some(deadline) => timeout_at(deadline, read).await.map_err(|_| {
+                ProxyError::ReadTimeout.context("No final response within the interim timeout")
+            })??,
+        };
+        let info = parse_response_headers(&upstream.read_buf[..headers_end])?;
+        if !matches!(info.status_code, Some(100 | 102..=199)) {
+            return Ok((headers_end, info));
+        }
+        interim += 1;
+        if interim > limits.max {
+            return Err(ProxyError::BadFraming.context(format!(
+                "Upstream sent more than {} 1xx responses",
+                limits.max
+            )));
         }
+        debug!(target: "migux::proxy", status = ?info.status_code, "Skipping interim upstream response");
+        upstream.read_buf.advance(headers_end + 4);
+        deadline.get_or_insert_with(|| Instant::now() + limits.timeout);
     }
 }
 
+/// Read up to `read_size` bytes straight into `read_buf`.
 async fn read_more(upstream: &mut PooledStream, read_timeout: Duration) -> anyhow::Result<usize> {
-    let mut tmp = [0u8; 8192];
-    let n = match timeout(read_timeout, upstream.stream.read(&mut tmp)).await {
-        Ok(res) => res?,
-        Err(_) => anyhow::bail!("Upstream read timeout"),
-    };
-    if n > 0 {
-        upstream.read_buf.extend_from_slice(&tmp[..n]);
+    upstream.read_buf.reserve(upstream.read_size);
+    let mut spare = (&mut upstream.read_buf).limit(upstream.read_size);
+    match timeout(read_timeout, upstream.stream.read_buf(&mut spare)).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err(ProxyError::ReadTimeout.into()),
     }
-    Ok(n)
 }
 
 fn find_headers_end(buf: &BytesMut) -> Option<usize> {
@@ -161,7 +596,30 @@ struct ResponseInfo {
     connection_keep_alive: bool,
     is_http10: bool,
     is_chunked: bool,
+    /// `Content-Type: text/event-stream` (Server-Sent Events).
+    is_event_stream: bool,
+    /// `Content-Type` is textual enough to be worth compressing.
+    compressible: bool,
+    /// A `Content-Encoding` other than `identity` is already applied.
+    content_encoded: bool,
+    /// `Cache-Control: no-transform`.
+    no_transform: bool,
     status_code: Option<u16>,
+    /// `Retry-After` of a 5xx response.
+    retry_after: Option<Duration>,
+}
+
+impl ResponseInfo {
+    /// Whether `proxy_compress` applies to this response.
+    fn should_compress(&self, no_body: bool, min_length: usize) -> bool {
+        !no_body
+            && self.compressible
+            && !self.content_encoded
+            && !self.no_transform
+            && !self.is_http10
+            && self.status_code != Some(206)
+            && self.content_length.is_none_or(|len| len >= min_length)
+    }
 }
 
 /// Tracks Content-Length parsing state for duplicate header detection.
@@ -181,8 +639,8 @@ impl ContentLengthState {
                 continue;
             }
             any = true;
-            match trimmed.parse::<usize>() {
-                Ok(len) => {
+            match parse_content_length(trimmed) {
+                Some(len) => {
                     if let Some(prev) = self.value {
                         if prev != len {
                             self.conflict = true;
@@ -192,7 +650,7 @@ impl ContentLengthState {
                         self.value = Some(len);
                     }
                 }
-                Err(_) => {
+                None => {
                     self.invalid = true;
                 }
             }
@@ -203,81 +661,112 @@ impl ContentLengthState {
     }
 }
 
-fn split_header_tokens(value: &str) -> impl Iterator<Item = String> + '_ {
-    value.split(',').filter_map(|token| {
-        let trimmed = token.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(
-                trimmed
-                    .trim_matches(|c| c == '"' || c == '\'')
-                    .to_ascii_lowercase(),
-            )
-        }
-    })
+/// Comma-separated tokens of a header value, trimmed and unquoted.
+fn header_tokens(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value
+        .split(|&b| b == b',')
+        .map(|token| token.trim_ascii())
+        .filter(|token| !token.is_empty())
+        .map(|token| {
+            let start = token.iter().position(|&b| b != b'"' && b != b'\'');
+            let end = token.iter().rposition(|&b| b != b'"' && b != b'\'');
+            match (start, end) {
+                (Some(start), Some(end)) => &token[start..=end],
+                _ => &[][..],
+            }
+        })
 }
 
 /// Parse HTTP response headers and extract body/connection metadata.
+///
+/// Works on the raw header block: lines and names are compared as byte
+/// slices, so a response costs no allocations here.
 fn parse_response_headers(header_bytes: &[u8]) -> anyhow::Result<ResponseInfo> {
-    let header_str = String::from_utf8_lossy(header_bytes);
     let mut info = ResponseInfo::default();
     let mut content_length = ContentLengthState::default();
+    let mut transfer_encoding = false;
 
-    let mut lines = header_str.lines();
+    let mut lines = header_bytes
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
 
     if let Some(status_line) = lines.next() {
-        debug!(target: "migux::proxy", status_line = %status_line, "Received upstream status line");
-        if status_line.starts_with("HTTP/1.0") {
+        debug!(
+            target: "migux::proxy",
+            status_line = %String::from_utf8_lossy(status_line),
+            "Received upstream status line"
+        );
+        if status_line.starts_with(b"HTTP/1.0") {
             info.is_http10 = true;
         }
         info.status_code = status_line
-            .split_whitespace()
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|part| !part.is_empty())
             .nth(1)
-            .and_then(|s| s.parse::<u16>().ok());
+            .and_then(|code| std::str::from_utf8(code).ok())
+            .and_then(|code| code.parse::<u16>().ok());
     }
 
     for line in lines {
-        let line = line.trim();
+        let line = line.trim_ascii();
         if line.is_empty() {
             continue;
         }
-        let Some((name, value)) = line.split_once(':') else {
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
             continue;
         };
-        let name = name.trim();
-        let value = value.trim();
-        let name_lower = name.to_ascii_lowercase();
+        let name = line[..colon].trim_ascii();
+        let value = line[colon + 1..].trim_ascii();
 
-        match name_lower.as_str() {
-            "content-length" => {
-                content_length.add(value);
-            }
-            "connection" => {
-                for token in split_header_tokens(value) {
-                    match token.as_str() {
-                        "close" => info.connection_close = true,
-                        "keep-alive" => info.connection_keep_alive = true,
-                        _ => {}
-                    }
-                }
-            }
-            "transfer-encoding" => {
-                for token in split_header_tokens(value) {
-                    if token == "chunked" {
-                        info.is_chunked = true;
-                    }
+        if name.eq_ignore_ascii_case(b"content-length") {
+            // Non-UTF-8 is never a valid length.
+            content_length.add(std::str::from_utf8(value).unwrap_or("?"));
+        } else if name.eq_ignore_ascii_case(b"connection") {
+            for token in header_tokens(value) {
+                if token.eq_ignore_ascii_case(b"close") {
+                    info.connection_close = true;
+                } else if token.eq_ignore_ascii_case(b"keep-alive") {
+                    info.connection_keep_alive = true;
                 }
             }
-            _ => {}
+        } else if name.eq_ignore_ascii_case(b"content-type") {
+            let media_type = value.split(|&b| b == b';').next().unwrap_or(b"");
+            info.is_event_stream = media_type
+                .trim_ascii()
+                .eq_ignore_ascii_case(b"text/event-stream");
+            info.compressible = compressible_type(media_type);
+        } else if name.eq_ignore_ascii_case(b"content-encoding") {
+            info.content_encoded |=
+                header_tokens(value).any(|token| !token.eq_ignore_ascii_case(b"identity"));
+        } else if name.eq_ignore_ascii_case(b"cache-control") {
+            info.no_transform |=
+                header_tokens(value).any(|token| token.eq_ignore_ascii_case(b"no-transform"));
+        } else if name.eq_ignore_ascii_case(b"transfer-encoding") {
+            transfer_encoding = true;
+            info.is_chunked |=
+                header_tokens(value).any(|token| token.eq_ignore_ascii_case(b"chunked"));
+        } else if name.eq_ignore_ascii_case(b"retry-after")
+            && matches!(info.status_code, Some(500..=599))
+        {
+            info.retry_after = std::str::from_utf8(value).ok().and_then(parse_retry_after);
         }
     }
 
     if content_length.invalid {
         if content_length.conflict {
-            anyhow::bail!("Conflicting Content-Length in upstream response");
+            return Err(
+                ProxyError::BadFraming.context("Conflicting Content-Length in upstream response")
+            );
         }
-        anyhow::bail!("Invalid Content-Length in upstream response");
+        return Err(ProxyError::BadFraming.context("Invalid Content-Length in upstream response"));
+    }
+
+    // Like requests with both: the two framings could disagree about where
+    // the body ends, and a pooled connection would then carry the leftover
+    // bytes into the next response.
+    if transfer_encoding && content_length.value.is_some() {
+        return Err(ProxyError::BadFraming
+            .context("Upstream response has both Transfer-Encoding and Content-Length"));
     }
 
     info.content_length = content_length.value;
@@ -285,6 +774,19 @@ fn parse_response_headers(header_bytes: &[u8]) -> anyhow::Result<ResponseInfo> {
     Ok(info)
 }
 
+/// `Retry-After` as delta-seconds or an HTTP date (a past date is zero).
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
 fn is_no_body(method: &str, status_code: Option<u16>) -> bool {
     if method.eq_ignore_ascii_case("HEAD") {
         return true;
@@ -298,7 +800,7 @@ fn is_no_body(method: &str, status_code: Option<u16>) -> bool {
 
 async fn stream_content_length<S>(
     upstream: &mut PooledStream,
-    client_stream: &mut S,
+    sink: &mut BodySink<'_, S>,
     mut remaining: usize,
     read_timeout: Duration,
 ) -> anyhow::Result<bool>
@@ -320,52 +822,47 @@ where
 
         let take = remaining.min(upstream.read_buf.len());
         let chunk = upstream.read_buf.split_to(take);
-        client_stream.write_all(&chunk).await?;
+        sink.data(&chunk).await?;
         remaining -= take;
     }
 
     Ok(true)
 }
 
+/// Forward an unframed body until the upstream closes. `Ok(false)` when it
+/// went past `max_body`: the first `max_body` bytes were forwarded and the
+/// rest is left unread (without a length the cut can't be signalled other
+/// than by closing).
 async fn stream_until_eof<S>(
     upstream: &mut PooledStream,
-    client_stream: &mut S,
+    sink: &mut BodySink<'_, S>,
     read_timeout: Duration,
     max_body: usize,
-) -> anyhow::Result<()>
+) -> anyhow::Result<bool>
 where
     S: AsyncWrite + Unpin + ?Sized,
 {
-    let mut body_bytes = 0usize;
-
-    if !upstream.read_buf.is_empty() {
-        body_bytes += upstream.read_buf.len();
-        if max_body > 0 && body_bytes > max_body {
-            anyhow::bail!("Upstream response body too large");
-        }
-        let chunk = upstream.read_buf.split_to(upstream.read_buf.len());
-        client_stream.write_all(&chunk).await?;
-    }
+    let mut room = if max_body > 0 { max_body } else { usize::MAX };
 
     loop {
-        let n = read_more(upstream, read_timeout).await?;
-        if n == 0 {
-            break;
+        if upstream.read_buf.is_empty() && read_more(upstream, read_timeout).await? == 0 {
+            return Ok(true);
         }
-        body_bytes += n;
-        if max_body > 0 && body_bytes > max_body {
-            anyhow::bail!("Upstream response body too large");
+        let take = upstream.read_buf.len().min(room);
+        if take > 0 {
+            let chunk = upstream.read_buf.split_to(take);
+            sink.data(&chunk).await?;
+            room -= take;
+        }
+        if !upstream.read_buf.is_empty() {
+            return Ok(false);
         }
-        let chunk = upstream.read_buf.split_to(n);
-        client_stream.write_all(&chunk).await?;
     }
-
-    Ok(())
 }
 
 async fn stream_chunked_body<S>(
     upstream: &mut PooledStream,
-    client_stream: &mut S,
+    sink: &mut BodySink<'_, S>,
     read_timeout: Duration,
     max_body: usize,
 ) -> anyhow::Result<()>
@@ -376,7 +873,7 @@ where
 
     loop {
         let line = read_line(upstream, read_timeout).await?;
-        client_stream.write_all(&line).await?;
+        sink.framing(&line).await?;
 
         let line_str = String::from_utf8_lossy(&line);
         let size_str = line_str
@@ -385,13 +882,13 @@ where
             .trim_end_matches('\n');
         let size_str = size_str.split(';').next().unwrap_or("").trim();
         let chunk_size = usize::from_str_radix(size_str, 16)
-            .map_err(|_| anyhow::anyhow!("Invalid chunk size"))?;
+            .map_err(|_| ProxyError::BadFraming.context("Invalid chunk size"))?;
 
         if chunk_size == 0 {
             // Trailers: forward until empty line
             loop {
                 let trailer = read_line(upstream, read_timeout).await?;
-                client_stream.write_all(&trailer).await?;
+                sink.framing(&trailer).await?;
                 if trailer == b"\r\n" {
                     return Ok(());
                 }
@@ -399,11 +896,11 @@ where
         }
 
         if max_body > 0 && body_bytes + chunk_size > max_body {
-            anyhow::bail!("Upstream response body too large");
+            return Err(ProxyError::BodyTooLarge.context("Upstream response body too large"));
         }
 
-        let total = chunk_size + 2; // data + CRLF
-        read_exact_from_buf(upstream, client_stream, read_timeout, total).await?;
+        read_exact_from_buf(upstream, sink, read_timeout, chunk_size, false).await?;
+        read_exact_from_buf(upstream, sink, read_timeout, 2, true).await?; // CRLF
 
         body_bytes += chunk_size;
     }
@@ -418,16 +915,20 @@ async fn read_line(upstream: &mut PooledStream, read_timeout: Duration) -> anyho
 
         let n = read_more(upstream, read_timeout).await?;
         if n == 0 {
-            anyhow::bail!("Upstream closed connection while reading chunked line");
+            return Err(ProxyError::BadFraming
+                .context("Upstream closed connection while reading chunked line"));
         }
     }
 }
 
+/// Pass `remaining` bytes from upstream to `sink`, as chunk framing or as
+/// body data.
 async fn read_exact_from_buf<S>(
     upstream: &mut PooledStream,
-    client_stream: &mut S,
+    sink: &mut BodySink<'_, S>,
     read_timeout: Duration,
     mut remaining: usize,
+    framing: bool,
 ) -> anyhow::Result<()>
 where
     S: AsyncWrite + Unpin + ?Sized,
@@ -436,13 +937,18 @@ where
         if upstream.read_buf.is_empty() {
             let n = read_more(upstream, read_timeout).await?;
             if n == 0 {
-                anyhow::bail!("Upstream closed connection while reading chunked body");
+                return Err(ProxyError::BadFraming
+                    .context("Upstream closed connection while reading chunked body"));
             }
         }
 
         let take = remaining.min(upstream.read_buf.len());
         let chunk = upstream.read_buf.split_to(take);
-        client_stream.write_all(&chunk).await?;
+        if framing {
+            sink.framing(&chunk).await?;
+        } else {
+            sink.data(&chunk).await?;
+        }
         remaining -= take;
     }
 
@@ -451,7 +957,10 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::parse_response_headers;
+    use super::{
+        Duration, KeepAlive, ProxyError, encoded_headers, parse_response_headers,
+        parse_retry_after, rewrite_connection,
+    };
 
     #[test]
     fn parse_response_headers_accepts_duplicate_content_length() {
@@ -460,11 +969,32 @@ mod tests {
         assert_eq!(info.content_length, Some(5));
     }
 
+    #[test]
+    fn retry_after_is_read_from_5xx_responses_only() {
+        let headers = b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 30\r\n\r\n";
+        let info = parse_response_headers(headers).expect("expected ok");
+        assert_eq!(info.retry_after, Some(Duration::from_secs(30)));
+
+        let headers = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 30\r\n\r\n";
+        let info = parse_response_headers(headers).expect("expected ok");
+        assert_eq!(info.retry_after, None);
+
+        let later = std::time::SystemTime::now() + Duration::from_secs(120);
+        let date = parse_retry_after(&httpdate::fmt_http_date(later)).expect("date");
+        assert!(date > Duration::from_secs(115) && date <= Duration::from_secs(120));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
     #[test]
     fn parse_response_headers_rejects_conflicting_content_length() {
         let headers = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n";
         let err = parse_response_headers(headers).unwrap_err();
         assert!(err.to_string().contains("Conflicting Content-Length"));
+        assert_eq!(ProxyError::of(&err), Some(ProxyError::BadFraming));
     }
 
     #[test]
@@ -474,6 +1004,19 @@ mod tests {
         assert!(err.to_string().contains("Invalid Content-Length"));
     }
 
+    #[test]
+    fn parse_response_headers_rejects_oversized_content_length() {
+        for len in [
+            "99999999999999999999",
+            "18446744073709551615",
+            "9223372036854775808",
+        ] {
+            let headers = format!("HTTP/1.1 200 OK\r\nContent-Length: {len}\r\n\r\n");
+            let err = parse_response_headers(headers.as_bytes()).unwrap_err();
+            assert!(err.to_string().contains("Invalid Content-Length"), "{len}");
+        }
+    }
+
     #[test]
     fn parse_response_headers_detects_chunked_and_connection_tokens() {
         let headers = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, \"chunked\"\r\nConnection: \"close\"\r\n\r\n";
@@ -481,4 +1024,114 @@ mod tests {
         assert!(info.is_chunked);
         assert!(info.connection_close);
     }
+
+    #[test]
+    fn parse_response_headers_reads_status_and_http10() {
+        let headers = b"HTTP/1.0  404 Not Found\r\nconnection: Keep-Alive\r\nContent-Type: Text/Event-Stream; charset=utf-8\r\nContent-Length:  7 \r\n\r\n";
+        let info = parse_response_headers(headers).expect("expected ok");
+        assert_eq!(info.status_code, Some(404));
+        assert!(info.is_http10);
+        assert!(info.connection_keep_alive);
+        assert!(!info.connection_close);
+        assert!(info.is_event_stream);
+        assert_eq!(info.content_length, Some(7));
+
+        let bad = b"HTTP/1.1 200 OK\r\nContent-Length: \xff\r\n\r\n";
+        assert!(parse_response_headers(bad).is_err());
+    }
+
+    #[test]
+    fn parse_response_headers_rejects_transfer_encoding_with_content_length() {
+        for headers in [
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nTransfer-Encoding: gzip\r\n\r\n",
+        ] {
+            let err = parse_response_headers(headers).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("Transfer-Encoding and Content-Length"),
+                "got: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn rewrite_connection_replaces_upstream_connection_headers() {
+        let headers = b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n\r\n";
+        let out = String::from_utf8(rewrite_connection(headers, KeepAlive::Close)).expect("utf8");
+        assert_eq!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n"
+        );
+
+        let open = KeepAlive::Open {
+            timeout_secs: 65,
+            max_requests: 100,
+        };
+        let out = String::from_utf8(rewrite_connection(headers, open)).expect("utf8");
+        assert_eq!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: keep-alive\r\nKeep-Alive: timeout=65, max=100\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn encoded_headers_reframe_and_announce_the_coding() {
+        let headers = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 2000\r\nETag: \"v1\"\r\nVary: Origin\r\n\r\n";
+        let out = String::from_utf8(encoded_headers(headers, "gzip")).expect("utf8");
+        assert_eq!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nETag: W/\"v1\"\r\nVary: Origin, Accept-Encoding\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let out = String::from_utf8(encoded_headers(chunked, "br")).expect("utf8");
+        assert_eq!(
+            out,
+            "HTTP/1.1 200 OK\r\nVary: Accept-Encoding\r\nContent-Encoding: br\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn header_rewrites_keep_non_utf8_values_byte_for_byte() {
+        let headers = b"HTTP/1.1 200 OK\r\nX-Name: caf\xe9\r\nConnection: close\r\nETag: \"\xff\"\r\nVary: Origin\r\n\r\n";
+        let out = rewrite_connection(headers, KeepAlive::Close);
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\nX-Name: caf\xe9\r\nETag: \"\xff\"\r\nVary: Origin\r\nConnection: close\r\n\r\n"
+        );
+
+        let out = encoded_headers(headers, "gzip");
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\nX-Name: caf\xe9\r\nConnection: close\r\nETag: W/\"\xff\"\r\nVary: Origin, Accept-Encoding\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn only_uncompressed_textual_bodies_are_compressed() {
+        let should = |headers: &[u8]| {
+            parse_response_headers(headers)
+                .expect("parse")
+                .should_compress(false, 1024)
+        };
+        assert!(should(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nTransfer-Encoding: chunked\r\n\r\n"
+        ));
+        assert!(!should(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 100\r\n\r\n"
+        ));
+        assert!(!should(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: gzip\r\n\r\n"
+        ));
+        assert!(!should(
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 5000\r\n\r\n"
+        ));
+        assert!(!should(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nCache-Control: public, no-transform\r\n\r\n"
+        ));
+        assert!(!should(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Type: text/plain\r\nContent-Length: 5000\r\n\r\n"
+        ));
+    }
 }
@@ -0,0 +1,330 @@
+//! Raw TCP (L4) proxying for `[stream.*]` listeners: the client connection
+//! is relayed byte for byte to one server of the stream's upstream. The
+//! server is picked like for HTTP (strategy, health, failover on connect
+//! errors); once connected, bytes are copied both ways until each side
+//! has closed or the idle timeout passes. Connections are never pooled.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use migux_config::{MiguxConfig, StreamConfig};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{Duration, Instant, sleep_until};
+use tracing::{debug, error, info};
+
+use super::Proxy;
+use super::error::ProxyError;
+use super::health::health_policy;
+use super::pool::connect_fresh;
+
+/// Bytes read per direction at a time.
+const RELAY_BUF_SIZE: usize = 16 * 1024;
+
+/// Bytes copied by [`relay`], per direction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Relayed {
+    pub(super) to_upstream: u64,
+    pub(super) to_client: u64,
+}
+
+impl Proxy {
+    /// Relay one accepted `[stream.<name>]` connection. Errors when no
+    /// upstream server could be reached; the client is then just closed.
+    pub async fn serve_stream<C>(
+        &self,
+        mut client: C,
+        client_addr: SocketAddr,
+        name: &str,
+        stream_cfg: &StreamConfig,
+        cfg: &MiguxConfig,
+    ) -> anyhow::Result<()>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+    {
+        let upstream_name = stream_cfg.upstream();
+        let upstream_cfg = cfg
+            .upstream
+            .get(upstream_name)
+            .ok_or_else(|| anyhow::anyhow!("Upstream '{}' not found in config", upstream_name))?;
+        let candidate_addrs = self.candidate_addrs(upstream_name, upstream_cfg)?;
+        let policy = health_policy(upstream_cfg);
+        let tls = self.upstream_tls(upstream_name, upstream_cfg)?;
+        let connect_timeout = Duration::from_secs(
+            stream_cfg
+                .proxy_connect_timeout_secs()
+                .unwrap_or(cfg.http.proxy_connect_timeout_secs),
+        );
+        let idle_timeout = stream_cfg
+            .proxy_idle_timeout_secs()
+            .map(Duration::from_secs);
+
+        let mut last_err = None;
+        for upstream_addr in &candidate_addrs {
+            let _in_flight = self.start_request(upstream_addr);
+            let mut upstream = match connect_fresh(
+                upstream_addr,
+                tls.as_ref(),
+                connect_timeout,
+                cfg.global.tcp_fastopen(),
+            )
+            .await
+            {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    error!(
+                        target: "migux::proxy",
+                        stream = %name,
+                        upstream_addr = %upstream_addr,
+                        error = ?e,
+                        "Failed to connect stream upstream"
+                    );
+                    self.record_failure(upstream_name, upstream_addr, &policy);
+                    last_err = Some(e);
+                    continue;
+                }
+            };
+            self.record_success(upstream_name, upstream_addr);
+            info!(
+                target: "migux::proxy",
+                stream = %name,
+                client = %client_addr,
+                upstream_addr = %upstream_addr,
+                "Relaying stream connection"
+            );
+
+            let relayed = relay(&mut client, &mut upstream.stream, idle_timeout).await;
+            match relayed {
+                Ok(bytes) => debug!(
+                    target: "migux::proxy",
+                    stream = %name,
+                    client = %client_addr,
+                    to_upstream = bytes.to_upstream,
+                    to_client = bytes.to_client,
+                    "Stream connection closed"
+                ),
+                Err(e) => debug!(
+                    target: "migux::proxy",
+                    stream = %name,
+                    client = %client_addr,
+                    error = %e,
+                    "Stream connection ended with an error"
+                ),
+            }
+            return Ok(());
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            anyhow::anyhow!("No upstream servers available for stream '{name}'")
+        }))
+    }
+}
+
+/// Copy bytes both ways until both sides have sent EOF. The two directions
+/// run concurrently, so peers that both write before reading can't stall
+/// each other. An EOF from one side is passed on as a write shutdown to the
+/// other, so half-closed protocols keep working. With `idle`, the relay
+/// fails once no bytes were read or written in either direction for that
+/// long.
+pub(super) async fn relay<A, B>(
+    client: &mut A,
+    upstream: &mut B,
+    idle: Option<Duration>,
+) -> anyhow::Result<Relayed>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let (mut client_rd, mut client_wr) = tokio::io::split(client);
+    let (mut upstream_rd, mut upstream_wr) = tokio::io::split(upstream);
+    let last_activity = Activity::new();
+    let copy = async {
+        tokio::try_join!(
+            copy_one_way(&mut client_rd, &mut upstream_wr, &last_activity),
+            copy_one_way(&mut upstream_rd, &mut client_wr, &last_activity),
+        )
+    };
+    let (to_upstream, to_client) = match idle {
+        Some(idle) => tokio::select! {
+            copied = copy => copied?,
+            _ = idle_expired(&last_activity, idle) => {
+                return Err(ProxyError::ReadTimeout
+                    .context("Stream idle timeout (proxy_idle_timeout_secs)"));
+            }
+        },
+        None => copy.await?,
+    };
+    Ok(Relayed {
+        to_upstream,
+        to_client,
+    })
+}
+
+/// Copy `rd` into `wr` until EOF, then shut `wr` down. Every read and every
+/// partial write counts as activity for the idle timeout.
+async fn copy_one_way<R, W>(rd: &mut R, wr: &mut W, last_activity: &Activity) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; RELAY_BUF_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let n = rd.read(&mut buf).await?;
+        if n == 0 {
+            let _ = wr.shutdown().await;
+            return Ok(copied);
+        }
+        last_activity.touch();
+        let mut written = 0;
+        while written < n {
+            let m = wr.write(&buf[written..n]).await?;
+            if m == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            written += m;
+            last_activity.touch();
+        }
+        copied += n as u64;
+    }
+}
+
+/// When bytes last moved through [`relay`], in either direction.
+struct Activity {
+    start: Instant,
+    /// Milliseconds after `start`.
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        self.start + Duration::from_millis(self.last_ms.load(Ordering::Relaxed))
+    }
+}
+
+/// Resolves once `idle` has passed since the last recorded activity.
+async fn idle_expired(last_activity: &Activity, idle: Duration) {
+    loop {
+        let deadline = last_activity.last() + idle;
+        if deadline <= Instant::now() {
+            return;
+        }
+        sleep_until(deadline).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::relay;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{Duration, timeout};
+
+    #[tokio::test]
+    async fn relay_copies_both_ways_and_passes_on_half_close() {
+        let (mut client, mut client_side) = tokio::io::duplex(1024);
+        let (mut upstream_side, mut upstream) = tokio::io::duplex(1024);
+        let task =
+            tokio::spawn(async move { relay(&mut client_side, &mut upstream_side, None).await });
+
+        client.write_all(b"ping").await.expect("write");
+        client.shutdown().await.expect("shutdown");
+        let mut got = Vec::new();
+        upstream.read_to_end(&mut got).await.expect("read");
+        assert_eq!(got, b"ping");
+
+        upstream.write_all(b"pong").await.expect("write");
+        drop(upstream);
+        let mut got = Vec::new();
+        client.read_to_end(&mut got).await.expect("read");
+        assert_eq!(got, b"pong");
+
+        let relayed = task.await.expect("join").expect("relay");
+        assert_eq!((relayed.to_upstream, relayed.to_client), (4, 4));
+    }
+
+    #[tokio::test]
+    async fn relay_gives_up_after_the_idle_timeout() {
+        let (_client, mut client_side) = tokio::io::duplex(1024);
+        let (mut upstream_side, _upstream) = tokio::io::duplex(1024);
+        let err = relay(
+            &mut client_side,
+            &mut upstream_side,
+            Some(Duration::from_millis(50)),
+        )
+        .await
+        .expect_err("idle");
+        assert_eq!(
+            super::ProxyError::of(&err),
+            Some(super::ProxyError::ReadTimeout)
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_does_not_stall_when_both_peers_write_before_reading() {
+        // Each peer sends three socket buffers' worth before reading anything.
+        const LEN: usize = 12 * 1024;
+        let (client, mut client_side) = tokio::io::duplex(4096);
+        let (mut upstream_side, upstream) = tokio::io::duplex(4096);
+        let task =
+            tokio::spawn(async move { relay(&mut client_side, &mut upstream_side, None).await });
+        let peer = |mut io: tokio::io::DuplexStream, byte: u8| async move {
+            io.write_all(&[byte; LEN]).await.expect("write");
+            io.shutdown().await.expect("shutdown");
+            let mut got = Vec::new();
+            io.read_to_end(&mut got).await.expect("read");
+            got
+        };
+
+        let (to_client, to_upstream) = timeout(Duration::from_secs(5), async {
+            tokio::join!(peer(client, b'c'), peer(upstream, b'u'))
+        })
+        .await
+        .expect("relay stalled");
+        assert_eq!(to_client, vec![b'u'; LEN]);
+        assert_eq!(to_upstream, vec![b'c'; LEN]);
+        let relayed = task.await.expect("join").expect("relay");
+        assert_eq!(
+            (relayed.to_upstream, relayed.to_client),
+            (LEN as u64, LEN as u64)
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_idle_timeout_covers_stalled_writes() {
+        // The client keeps sending but the upstream never reads.
+        let (mut client, mut client_side) = tokio::io::duplex(1024);
+        let (mut upstream_side, _upstream) = tokio::io::duplex(1024);
+        let _writer = tokio::spawn(async move {
+            let _ = client.write_all(&[0u8; 64 * 1024]).await;
+        });
+        let err = timeout(
+            Duration::from_secs(5),
+            relay(
+                &mut client_side,
+                &mut upstream_side,
+                Some(Duration::from_millis(50)),
+            ),
+        )
+        .await
+        .expect("relay ignored the idle timeout")
+        .expect_err("idle");
+        assert_eq!(
+            super::ProxyError::of(&err),
+            Some(super::ProxyError::ReadTimeout)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! HTTPS upstreams (`tls = true`): the stream type pooled connections wrap
+//! and the per-upstream `rustls` client config (`tls_verify`,
+//! `tls_ca_path`, `tls_server_name`).
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use anyhow::Context as _;
+use migux_config::UpstreamConfig;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    client::TlsStream,
+    rustls::{
+        self, Certificate, ClientConfig, RootCertStore, ServerName,
+        client::{ServerCertVerified, ServerCertVerifier},
+    },
+};
+use tracing::warn;
+
+/// Where the system root bundle usually lives; `SSL_CERT_FILE` wins.
+const SYSTEM_CA_BUNDLES: [&str; 4] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// A connection to an upstream, plain or TLS.
+pub(super) enum UpstreamStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            UpstreamStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            UpstreamStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            UpstreamStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            UpstreamStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// TLS settings of one upstream, built once and shared by its connections.
+#[derive(Clone)]
+pub(super) struct UpstreamTls {
+    upstream: String,
+    connector: TlsConnector,
+    server_name: Option<String>,
+}
+
+impl UpstreamTls {
+    pub(super) fn new(upstream: &str, cfg: &UpstreamConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            upstream: upstream.to_string(),
+            connector: TlsConnector::from(Arc::new(client_config(cfg)?)),
+            server_name: cfg.tls_server_name().map(str::to_string),
+        })
+    }
+
+    /// Pool key for `addr`: TLS connections carry this upstream's trust
+    /// settings and SNI, so they are only reused within the upstream.
+    pub(super) fn pool_key(&self, addr: &str) -> String {
+        format!("{}/{addr}", self.upstream)
+    }
+
+    /// Handshake over `tcp`, verifying the certificate against
+    /// `tls_server_name` or the host part of `addr`.
+    pub(super) async fn connect(
+        &self,
+        addr: &str,
+        tcp: TcpStream,
+    ) -> anyhow::Result<TlsStream<TcpStream>> {
+        let host = self
+            .server_name
+            .as_deref()
+            .unwrap_or_else(|| addr_host(addr));
+        let name = ServerName::try_from(host)
+            .with_context(|| format!("Invalid TLS server name '{host}' for {addr}"))?;
+        self.connector
+            .connect(name, tcp)
+            .await
+            .with_context(|| format!("TLS handshake with {addr} failed"))
+    }
+}
+
+/// `host` of `host:port` / `[v6]:port`.
+fn addr_host(addr: &str) -> &str {
+    if let Some(rest) = addr.strip_prefix('[')
+        && let Some((host, _)) = rest.split_once(']')
+    {
+        return host;
+    }
+    addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+}
+
+fn client_config(cfg: &UpstreamConfig) -> anyhow::Result<ClientConfig> {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    if !cfg.tls_verify() {
+        return Ok(builder
+            .with_custom_certificate_verifier(Arc::new(NoVerification))
+            .with_no_client_auth());
+    }
+    let roots = match cfg.tls_ca_path() {
+        Some(path) => load_roots(Path::new(path))?,
+        None => system_roots(),
+    };
+    Ok(builder.with_root_certificates(roots).with_no_client_auth())
+}
+
+/// Roots from a PEM bundle; an error when none can be used.
+fn load_roots(path: &Path) -> anyhow::Result<RootCertStore> {
+    let file = File::open(path)
+        .with_context(|| format!("Cannot open tls_ca_path '{}'", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Cannot parse tls_ca_path '{}'", path.display()))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        anyhow::bail!("No usable certificates in tls_ca_path '{}'", path.display());
+    }
+    Ok(roots)
+}
+
+/// The system bundle, or an empty store (every certificate then fails to
+/// verify) when none is found.
+fn system_roots() -> RootCertStore {
+    let env_bundle = std::env::var("SSL_CERT_FILE").ok();
+    let bundle = env_bundle
+        .iter()
+        .map(String::as_str)
+        .chain(SYSTEM_CA_BUNDLES)
+        .map(Path::new)
+        .find(|path| path.is_file());
+    match bundle.map(load_roots) {
+        Some(Ok(roots)) => roots,
+        Some(Err(e)) => {
+            warn!(target: "migux::proxy", error = %e, "Cannot load system CA bundle");
+            RootCertStore::empty()
+        }
+        None => {
+            warn!(
+                target: "migux::proxy",
+                "No system CA bundle found; set tls_ca_path on HTTPS upstreams"
+            );
+            RootCertStore::empty()
+        }
+    }
+}
+
+/// `tls_verify = false`: any certificate is accepted (the handshake
+/// signatures are still checked against it).
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::addr_host;
+
+    #[test]
+    fn server_name_defaults_to_the_address_host() {
+        assert_eq!(addr_host("backend.internal:8443"), "backend.internal");
+        assert_eq!(addr_host("127.0.0.1:443"), "127.0.0.1");
+        assert_eq!(addr_host("[::1]:8443"), "::1");
+        assert_eq!(addr_host("backend"), "backend");
+    }
+}
@@ -1,42 +1,7 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use dashmap::DashMap;
-use migux_config::{UpstreamConfig, UpstreamServers};
-
-/// =======================================================
-/// UPSTREAM CONFIG PARSING / NORMALIZATION
-/// =======================================================
-///
-/// Parse possible `server` formats:
-///
-/// Soporta:
-/// - "127.0.0.1:3000"
-/// - ["127.0.0.1:3000", "127.0.0.1:3001"]
-///
-/// (Esto parece compat con un "raw string" que a veces te llega con [] en texto)
-fn parse_servers_from_one(raw: &str) -> Vec<String> {
-    let trimmed = raw.trim();
-
-    // Caso "array" como texto: ["a","b"]
-    if trimmed.starts_with('[') && trimmed.ends_with(']') {
-        let inner = &trimmed[1..trimmed.len() - 1];
-        inner
-            .split(',')
-            .filter_map(|part| {
-                // limpia comillas y espacios
-                let part = part.trim().trim_matches('"');
-                if part.is_empty() {
-                    None
-                } else {
-                    Some(part.to_string())
-                }
-            })
-            .collect()
-    } else {
-        // Caso normal: un solo server
-        vec![trimmed.to_string()]
-    }
-}
+use migux_config::UpstreamConfig;
 
 /// Normalize `UpstreamConfig` into a Vec<String> of "host:port"
 ///
@@ -47,10 +12,7 @@ fn parse_servers_from_one(raw: &str) -> Vec<String> {
 /// Esta funcion deja SIEMPRE un Vec<String> usable.
 /// Si queda vacio, error.
 pub(super) fn normalize_servers(cfg: &UpstreamConfig) -> anyhow::Result<Vec<String>> {
-    let servers: Vec<String> = match &cfg.server {
-        UpstreamServers::One(s) => parse_servers_from_one(s),
-        UpstreamServers::Many(list) => list.clone(),
-    };
+    let servers = cfg.server.addrs();
 
     if servers.is_empty() {
         anyhow::bail!("Upstream has no configured servers");
@@ -0,0 +1,119 @@
+//! The static serving path, per file size:
+//!
+//! - `memory_hit`: cached response served from the memory cache.
+//! - `miss_disk_read`: cache consulted (memory and disk miss), file read
+//!   from disk and the response built; nothing is stored (TTL 0).
+//! - `uncached`: no cache at all, file read + response build
+//!   (`serve_static_bytes`).
+//!
+//! Location matching at varying location counts is benchmarked in
+//! `migux_core` (`benches/routing.rs`). Run with `cargo bench -p migux_static`.
+
+use std::{path::Path, time::Duration};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use migux_config::{HttpConfig, LocationConfig, ServerConfig};
+use migux_http::keep_alive::KeepAlive;
+use migux_static::{serve_static_bytes, serve_static_cached};
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 3] = [1024, 16 * 1024, 256 * 1024];
+
+const KEEP_ALIVE: KeepAlive = KeepAlive::Open {
+    timeout_secs: 65,
+    max_requests: 1000,
+};
+
+fn location(root: &Path) -> LocationConfig {
+    LocationConfig {
+        server: "main".into(),
+        path: "/".into(),
+        root: Some(root.to_string_lossy().into_owned()),
+        ..Default::default()
+    }
+}
+
+fn cache_config(cache_dir: &Path, ttl_secs: u32) -> HttpConfig {
+    HttpConfig {
+        cache_dir: Some(cache_dir.to_string_lossy().into_owned()),
+        cache_default_ttl_secs: Some(ttl_secs),
+        cache_max_object_bytes: Some(1024 * 1024),
+        ..Default::default()
+    }
+}
+
+async fn serve_cached(http_cfg: &HttpConfig, location: &LocationConfig, path: &str) -> usize {
+    let mut out = Vec::new();
+    serve_static_cached(
+        &mut out,
+        http_cfg,
+        &ServerConfig::default(),
+        location,
+        "GET",
+        "",
+        path,
+        KEEP_ALIVE,
+        None,
+    )
+    .await
+    .expect("serve");
+    out.len()
+}
+
+fn bench_static(c: &mut Criterion) {
+    let rt = Runtime::new().expect("runtime");
+    let root = tempfile::tempdir().expect("tempdir");
+    let cache_dir = tempfile::tempdir().expect("tempdir");
+    let location = location(root.path());
+    let server_cfg = ServerConfig::default();
+    // Separate files per case: the memory cache is global, and a hit left
+    // by `memory_hit` would turn the miss case into a hit.
+    for size in SIZES {
+        for case in ["hit", "miss", "uncached"] {
+            let file = root.path().join(format!("{size}.{case}.bin"));
+            std::fs::write(file, vec![b'x'; size]).expect("write");
+        }
+    }
+
+    let mut group = c.benchmark_group("static");
+    group.measurement_time(Duration::from_secs(3));
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let cached = cache_config(cache_dir.path(), 60);
+        let path = format!("/{size}.hit.bin");
+        rt.block_on(serve_cached(&cached, &location, &path));
+        group.bench_with_input(BenchmarkId::new("memory_hit", size), &path, |b, path| {
+            b.iter(|| rt.block_on(serve_cached(&cached, &location, path)))
+        });
+
+        let uncacheable = cache_config(cache_dir.path(), 0);
+        let path = format!("/{size}.miss.bin");
+        group.bench_with_input(
+            BenchmarkId::new("miss_disk_read", size),
+            &path,
+            |b, path| b.iter(|| rt.block_on(serve_cached(&uncacheable, &location, path))),
+        );
+
+        let path = format!("/{size}.uncached.bin");
+        group.bench_with_input(BenchmarkId::new("uncached", size), &path, |b, path| {
+            b.iter(|| {
+                rt.block_on(serve_static_bytes(
+                    &HttpConfig::default(),
+                    &server_cfg,
+                    &location,
+                    "GET",
+                    "",
+                    path,
+                    KEEP_ALIVE,
+                    None,
+                ))
+                .expect("serve")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_static);
+criterion_main!(benches);
@@ -0,0 +1,504 @@
+//! Read-only `.zip` / `.tar` archives served by `type = "archive"`
+//! locations (experimental).
+//!
+//! An archive is indexed once (entry name → offset, sizes, mtime) and kept
+//! for the life of the process; entry bodies are read, and inflated for
+//! deflated zip entries, on demand, and streamed in chunks to clients. Supported: zip without zip64 or
+//! encryption (stored and deflate entries) and uncompressed ustar/GNU tar
+//! (regular files, names up to the ustar prefix + name limit).
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock},
+    task::{Context, Poll, ready},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flate2::read::DeflateDecoder;
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    sync::mpsc,
+};
+
+/// Largest zip "end of central directory" record plus its trailing comment.
+const ZIP_EOCD_MAX: u64 = 22 + u16::MAX as u64;
+const ZIP_EOCD_SIG: u32 = 0x0605_4b50;
+const ZIP_CENTRAL_SIG: u32 = 0x0201_4b50;
+const ZIP_LOCAL_SIG: u32 = 0x0403_4b50;
+const TAR_BLOCK: u64 = 512;
+/// Most bytes [`Archive::read`] reserves up front; the entry length comes
+/// from the archive, so it is not trusted for the allocation.
+const READ_PREALLOC_MAX: u64 = 1024 * 1024;
+/// Bytes per chunk handed from the blocking reader to [`ArchiveBody`].
+const STREAM_CHUNK: usize = 64 * 1024;
+/// Chunks read ahead of the client.
+const STREAM_CHUNKS_AHEAD: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    Stored,
+    Deflate,
+}
+
+/// One regular file inside an archive.
+#[derive(Clone, Debug)]
+pub(crate) struct ArchiveEntry {
+    /// Offset of the (possibly compressed) data; for zip entries, of the
+    /// local header until it is resolved on first read.
+    offset: u64,
+    compressed_len: u64,
+    pub(crate) len: u64,
+    pub(crate) mtime: SystemTime,
+    compression: Compression,
+    /// Zip CRC-32 of the uncompressed data, checked on read.
+    crc32: Option<u32>,
+    local_header: bool,
+}
+
+/// An indexed archive file.
+#[derive(Debug)]
+pub(crate) struct Archive {
+    path: PathBuf,
+    entries: HashMap<String, ArchiveEntry>,
+}
+
+impl Archive {
+    /// The archive at `path`, indexed on first use and shared afterwards.
+    pub(crate) fn get(path: &str) -> io::Result<Arc<Archive>> {
+        static OPEN: OnceLock<Mutex<HashMap<String, Arc<Archive>>>> = OnceLock::new();
+        let open = OPEN.get_or_init(Default::default);
+        if let Some(archive) = open.lock().expect("archive index lock").get(path) {
+            return Ok(archive.clone());
+        }
+        // Indexed outside the lock; a concurrent first use indexes twice and
+        // the first one stored wins.
+        let archive = Arc::new(Archive::open(Path::new(path))?);
+        Ok(open
+            .lock()
+            .expect("archive index lock")
+            .entry(path.to_string())
+            .or_insert(archive)
+            .clone())
+    }
+
+    /// Index the archive at `path`; `.zip` by extension, tar otherwise.
+    pub(crate) fn open(path: &Path) -> io::Result<Archive> {
+        let mut file = File::open(path)?;
+        let is_zip = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+        let entries = if is_zip {
+            index_zip(&mut file)?
+        } else {
+            index_tar(&mut file)?
+        };
+        Ok(Archive {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn entry(&self, name: &str) -> Option<&ArchiveEntry> {
+        self.entries.get(name)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Read (and inflate) `entry`. Blocking; see [`Archive::read_async`].
+    pub(crate) fn read(&self, entry: &ArchiveEntry) -> io::Result<Vec<u8>> {
+        let mut body = Vec::with_capacity(entry.len.min(READ_PREALLOC_MAX) as usize);
+        self.reader(entry)?.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// A blocking reader over the (inflated) bytes of `entry`. Length and
+    /// CRC are checked when it reaches the end.
+    fn reader(&self, entry: &ArchiveEntry) -> io::Result<EntryReader> {
+        let mut file = File::open(&self.path)?;
+        let mut offset = entry.offset;
+        if entry.local_header {
+            // Name and extra lengths in the local header can differ from the
+            // central directory's, so the data offset is only known here.
+            file.seek(SeekFrom::Start(offset))?;
+            let mut header = [0u8; 30];
+            file.read_exact(&mut header)?;
+            if le_u32(&header, 0) != ZIP_LOCAL_SIG {
+                return Err(invalid("bad zip local header"));
+            }
+            offset += 30 + u64::from(le_u16(&header, 26)) + u64::from(le_u16(&header, 28));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let raw = file.take(entry.compressed_len);
+        let inner: Box<dyn Read + Send> = match entry.compression {
+            Compression::Stored => Box::new(raw.take(entry.len)),
+            Compression::Deflate => Box::new(DeflateDecoder::new(raw).take(entry.len)),
+        };
+        Ok(EntryReader {
+            inner,
+            remaining: entry.len,
+            crc: entry.crc32.map(|expected| (flate2::Crc::new(), expected)),
+        })
+    }
+
+    /// `entry` from byte `start` on, read on the blocking pool a chunk at a
+    /// time. Opening errors are returned here; later read errors end the
+    /// body with an error.
+    pub(crate) async fn stream(
+        self: &Arc<Self>,
+        entry: &ArchiveEntry,
+        start: u64,
+    ) -> io::Result<ArchiveBody> {
+        let archive = self.clone();
+        let entry = entry.clone();
+        let mut reader = tokio::task::spawn_blocking(move || {
+            let mut reader = archive.reader(&entry)?;
+            io::copy(&mut (&mut reader).take(start), &mut io::sink())?;
+            Ok::<_, io::Error>(reader)
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        let (tx, rx) = mpsc::channel(STREAM_CHUNKS_AHEAD);
+        tokio::task::spawn_blocking(move || {
+            loop {
+                let mut chunk = vec![0u8; STREAM_CHUNK];
+                let chunk = match reader.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    }
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // A closed channel means the client went away.
+                if tx.blocking_send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(ArchiveBody {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// [`Archive::read`] on the blocking pool.
+    pub(crate) async fn read_async(self: &Arc<Self>, entry: &ArchiveEntry) -> io::Result<Vec<u8>> {
+        let archive = self.clone();
+        let entry = entry.clone();
+        tokio::task::spawn_blocking(move || archive.read(&entry))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Blocking reader over one entry's bytes; see [`Archive::reader`].
+struct EntryReader {
+    inner: Box<dyn Read + Send>,
+    /// Bytes the entry still owes.
+    remaining: u64,
+    /// Running and expected zip CRC-32.
+    crc: Option<(flate2::Crc, u32)>,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some((crc, _)) = &mut self.crc {
+            crc.update(&buf[..n]);
+        }
+        self.remaining -= n as u64;
+        if n == 0 && !buf.is_empty() {
+            if self.remaining > 0 {
+                return Err(invalid("archive entry is truncated"));
+            }
+            if let Some((crc, expected)) = &self.crc
+                && crc.sum() != *expected
+            {
+                return Err(invalid("archive entry fails its CRC check"));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Async body of an archive entry, fed by [`Archive::stream`].
+pub(crate) struct ArchiveBody {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for ArchiveBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.pos == this.chunk.len() {
+            match ready!(this.rx.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    this.chunk = chunk;
+                    this.pos = 0;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = (this.chunk.len() - this.pos).min(buf.remaining());
+        buf.put_slice(&this.chunk[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Index the archive at `path` ahead of the first request (archive
+/// locations at startup); returns its number of entries.
+pub fn preload_archive(path: &str) -> io::Result<usize> {
+    Archive::get(path).map(|archive| archive.len())
+}
+
+fn index_zip(file: &mut File) -> io::Result<HashMap<String, ArchiveEntry>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(ZIP_EOCD_MAX);
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| le_u32(&tail, pos) == ZIP_EOCD_SIG)
+        .ok_or_else(|| invalid("no zip end of central directory"))?;
+    let count = le_u16(&tail, eocd + 10);
+    let dir_len = le_u32(&tail, eocd + 12);
+    let dir_offset = le_u32(&tail, eocd + 16);
+    if count == u16::MAX || dir_offset == u32::MAX {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+
+    file.seek(SeekFrom::Start(u64::from(dir_offset)))?;
+    let mut dir = vec![0u8; dir_len as usize];
+    file.read_exact(&mut dir)?;
+
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + 46 > dir.len() || le_u32(&dir, pos) != ZIP_CENTRAL_SIG {
+            return Err(invalid("bad zip central directory"));
+        }
+        let flags = le_u16(&dir, pos + 8);
+        let method = le_u16(&dir, pos + 10);
+        let (time, date) = (le_u16(&dir, pos + 12), le_u16(&dir, pos + 14));
+        let crc32 = le_u32(&dir, pos + 16);
+        let compressed_len = le_u32(&dir, pos + 20);
+        let len = le_u32(&dir, pos + 24);
+        let name_len = usize::from(le_u16(&dir, pos + 28));
+        let extra_len = usize::from(le_u16(&dir, pos + 30));
+        let comment_len = usize::from(le_u16(&dir, pos + 32));
+        let local_offset = le_u32(&dir, pos + 42);
+        let name_end = pos + 46 + name_len;
+        let name = dir
+            .get(pos + 46..name_end)
+            .ok_or_else(|| invalid("bad zip central directory"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos = name_end + extra_len + comment_len;
+
+        let compression = match method {
+            0 => Compression::Stored,
+            8 => Compression::Deflate,
+            _ => continue,
+        };
+        // Directories and encrypted entries are not served.
+        if name.ends_with('/') || flags & 1 != 0 {
+            continue;
+        }
+        entries.insert(
+            normalize_name(&name),
+            ArchiveEntry {
+                offset: u64::from(local_offset),
+                compressed_len: u64::from(compressed_len),
+                len: u64::from(len),
+                mtime: dos_time(date, time),
+                compression,
+                crc32: Some(crc32),
+                local_header: true,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn index_tar(file: &mut File) -> io::Result<HashMap<String, ArchiveEntry>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut entries = HashMap::new();
+    let mut offset = 0u64;
+    let mut header = [0u8; TAR_BLOCK as usize];
+    while offset + TAR_BLOCK <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let len = octal(&header[124..136]).ok_or_else(|| invalid("bad tar size"))?;
+        let mtime = octal(&header[136..148]).unwrap_or(0);
+        let data = offset + TAR_BLOCK;
+        if data + len > file_len {
+            return Err(invalid("tar entry runs past the end of the archive"));
+        }
+        if matches!(header[156], b'0' | 0) {
+            let mut name = c_str(&header[0..100]);
+            if &header[257..262] == b"ustar" {
+                let prefix = c_str(&header[345..500]);
+                if !prefix.is_empty() {
+                    name = format!("{prefix}/{name}");
+                }
+            }
+            entries.insert(
+                normalize_name(&name),
+                ArchiveEntry {
+                    offset: data,
+                    compressed_len: len,
+                    len,
+                    mtime: UNIX_EPOCH + Duration::from_secs(mtime),
+                    compression: Compression::Stored,
+                    crc32: None,
+                    local_header: false,
+                },
+            );
+        }
+        offset = data + len.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+    }
+    Ok(entries)
+}
+
+/// Entry names as request paths see them: no leading `./` or `/`.
+fn normalize_name(name: &str) -> String {
+    let name = name.strip_prefix("./").unwrap_or(name);
+    name.trim_start_matches('/').to_string()
+}
+
+fn c_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn octal(field: &[u8]) -> Option<u64> {
+    let digits = c_str(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// MS-DOS date/time (local time in the archive; read as UTC).
+fn dos_time(date: u16, time: u16) -> SystemTime {
+    let year = i64::from(date >> 9) + 1980;
+    let month = i64::from((date >> 5) & 0x0f).max(1);
+    let day = i64::from(date & 0x1f).max(1);
+    let secs = i64::from(time >> 11) * 3600
+        + i64::from((time >> 5) & 0x3f) * 60
+        + i64::from(time & 0x1f) * 2;
+    let unix = days_from_civil(year, month, day) * 86_400 + secs;
+    UNIX_EPOCH + Duration::from_secs(u64::try_from(unix).unwrap_or(0))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn le_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Archive, days_from_civil, dos_time};
+
+    const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+
+    #[test]
+    fn zip_and_tar_entries_read_back() {
+        for name in ["site.zip", "site.tar"] {
+            let archive = Archive::open(&Path::new(TESTDATA).join(name)).expect(name);
+            let index = archive.entry("index.html").expect("index.html");
+            assert_eq!(
+                archive.read(index).expect("read"),
+                b"<h1>archived</h1>\n",
+                "{name}"
+            );
+            let css = archive.entry("assets/app.css").expect("assets/app.css");
+            let body = archive.read(css).expect("read");
+            assert_eq!(body.len() as u64, css.len);
+            assert!(body.starts_with(b"body{"), "{name}");
+            assert!(archive.entry("assets").is_none(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_entries_match_reads_from_any_offset() {
+        use tokio::io::AsyncReadExt;
+
+        for name in ["site.zip", "site.tar"] {
+            let archive =
+                std::sync::Arc::new(Archive::open(&Path::new(TESTDATA).join(name)).expect(name));
+            let css = archive.entry("assets/app.css").expect("assets/app.css");
+            let whole = archive.read(css).expect("read");
+            for start in [0, 5, css.len] {
+                let mut body = Vec::new();
+                archive
+                    .stream(css, start)
+                    .await
+                    .expect("stream")
+                    .read_to_end(&mut body)
+                    .await
+                    .expect("read body");
+                assert_eq!(body, whole[start as usize..], "{name} from {start}");
+            }
+        }
+    }
+
+    #[test]
+    fn dos_timestamps_convert_to_unix_time() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        // 2024-01-02 03:04:06
+        let date = ((2024 - 1980) << 9) | (1 << 5) | 2;
+        let time = (3 << 11) | (4 << 5) | 3;
+        assert_eq!(
+            dos_time(date, time),
+            UNIX_EPOCH + Duration::from_secs(1_704_164_646)
+        );
+    }
+}
@@ -1,33 +1,213 @@
 //! Cache utilities for static responses.
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     hash::{Hash, Hasher},
+    io::{Read, Write},
     path::{Path, PathBuf},
     sync::{
-        Mutex, OnceLock,
-        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
 use migux_config::{HttpConfig, LocationConfig};
-use tokio::{fs, io::AsyncWriteExt, sync::Mutex as AsyncMutex};
-use tracing::debug;
+use migux_http::counter::ShardedCounter;
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard},
+};
+use tracing::{debug, info, warn};
 
 /// In-memory cache entry with expiration.
 struct CacheEntry {
     response: Vec<u8>,
     expires_at: Instant,
+    stale: StaleWindows,
 }
 
 impl CacheEntry {
-    fn new(response: Vec<u8>, expires_at: Instant) -> Self {
+    fn new(response: Vec<u8>, expires_at: Instant, stale: StaleWindows) -> Self {
         Self {
             response,
             expires_at,
+            stale,
+        }
+    }
+
+    fn state_at(&self, now: Instant) -> CacheState {
+        let expired_for = (now > self.expires_at).then(|| now - self.expires_at);
+        self.stale.classify(expired_for)
+    }
+}
+
+/// Freshness of a cached entry relative to its TTL and stale windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheState {
+    /// Within TTL; serve as-is.
+    Fresh,
+    /// Expired but inside `stale-while-revalidate`; serve and refresh in the background.
+    StaleWhileRevalidate,
+    /// Expired but inside `stale-if-error`; serve only when the origin fails.
+    StaleIfError,
+    /// Past every window; the entry must be evicted.
+    Dead,
+}
+
+/// Stale-serving windows stored alongside `expires_at`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct StaleWindows {
+    pub(crate) while_revalidate: Duration,
+    pub(crate) if_error: Duration,
+}
+
+impl StaleWindows {
+    /// Build stale windows from the http cache settings.
+    pub(crate) fn from_config(http_cfg: &HttpConfig) -> Self {
+        Self {
+            while_revalidate: Duration::from_secs(
+                http_cfg.cache_stale_while_revalidate_secs().unwrap_or(0),
+            ),
+            if_error: Duration::from_secs(http_cfg.cache_stale_if_error_secs().unwrap_or(0)),
+        }
+    }
+
+    /// Classify an entry given how long ago it expired (`None` while fresh).
+    pub(crate) fn classify(&self, expired_for: Option<Duration>) -> CacheState {
+        let Some(age) = expired_for else {
+            return CacheState::Fresh;
+        };
+        if age <= self.while_revalidate {
+            CacheState::StaleWhileRevalidate
+        } else if age <= self.if_error {
+            CacheState::StaleIfError
+        } else {
+            CacheState::Dead
+        }
+    }
+}
+
+/// Marks a cache key as being refreshed in the background; released on drop.
+pub(crate) struct RefreshGuard(CacheKey);
+
+impl RefreshGuard {
+    /// Claim `key` for a background refresh, or `None` if one is already running.
+    pub(crate) fn acquire(key: CacheKey) -> Option<Self> {
+        let mut set = REFRESHING
+            .get_or_init(|| Mutex::new(HashSet::new()))
+            .lock()
+            .ok()?;
+        set.insert(key).then_some(Self(key))
+    }
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        if let Some(set) = REFRESHING.get()
+            && let Ok(mut set) = set.lock()
+        {
+            set.remove(&self.0);
+        }
+    }
+}
+
+/// Last cache key served for each route, so a request whose file has since
+/// gone away can still find the entry it was served from.
+static ROUTE_KEYS: OnceLock<Mutex<HashMap<CacheKey, CacheKey>>> = OnceLock::new();
+
+/// Maps a request route (location and its roots, request path, HSTS) to the
+/// cache key of the file it last resolved to. Cache keys follow the file's
+/// size and mtime, which a missing file no longer has.
+pub(crate) struct RouteIndex;
+
+impl RouteIndex {
+    pub(crate) fn route(location: &LocationConfig, req_path: &str, hsts: bool) -> CacheKey {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        location.server.hash(&mut hasher);
+        location.path.hash(&mut hasher);
+        location.roots_or("").hash(&mut hasher);
+        location.archive.hash(&mut hasher);
+        req_path.hash(&mut hasher);
+        hsts.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub(crate) fn remember(route: CacheKey, key: CacheKey) {
+        if let Ok(mut routes) = ROUTE_KEYS.get_or_init(|| Mutex::new(HashMap::new())).lock() {
+            routes.insert(route, key);
         }
     }
+
+    pub(crate) fn last(route: CacheKey) -> Option<CacheKey> {
+        let routes = ROUTE_KEYS.get()?.lock().ok()?;
+        routes.get(&route).copied()
+    }
+}
+
+/// Cache misses being filled: the leader holds the key's lock while it
+/// reads the file, concurrent misses for the key wait for it.
+static FILLING: OnceLock<Mutex<HashMap<CacheKey, Arc<AsyncMutex<()>>>>> = OnceLock::new();
+
+/// Single-flight for cache misses on one key.
+pub(crate) enum MissFill {
+    /// This request reads the file; the key is released when dropped.
+    Leader(FillGuard),
+    /// Another request is reading it; wait with [`MissFill::wait`].
+    Follower(Arc<AsyncMutex<()>>),
+}
+
+impl MissFill {
+    pub(crate) fn claim(key: CacheKey) -> Self {
+        let mut filling = FILLING
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(lock) = filling.get(&key) {
+            return Self::Follower(lock.clone());
+        }
+        let lock = Arc::new(AsyncMutex::new(()));
+        let guard = lock
+            .clone()
+            .try_lock_owned()
+            .expect("a new lock is unlocked");
+        filling.insert(key, lock);
+        Self::Leader(FillGuard { key, _lock: guard })
+    }
+
+    /// Wait up to `limit` for the leader to finish (stored or failed).
+    /// `false` on timeout.
+    pub(crate) async fn wait(lock: Arc<AsyncMutex<()>>, limit: Duration) -> bool {
+        CacheEvent::CoalescedMiss.record();
+        tokio::time::timeout(limit, lock.lock()).await.is_ok()
+    }
+}
+
+/// Held by the request filling a cache miss.
+pub(crate) struct FillGuard {
+    key: CacheKey,
+    _lock: OwnedMutexGuard<()>,
+}
+
+impl Drop for FillGuard {
+    fn drop(&mut self) {
+        // Unlisted before the lock is released (fields drop after this), so
+        // a later miss starts a new fill instead of finding a finished one.
+        if let Some(filling) = FILLING.get() {
+            filling
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&self.key);
+        }
+    }
+}
+
+/// Cached response returned together with its freshness state.
+pub(crate) struct CacheLookup {
+    pub(crate) response: Vec<u8>,
+    pub(crate) state: CacheState,
 }
 
 /// Compact hash key for cache entries.
@@ -42,22 +222,150 @@ pub struct CacheMetrics {
     pub disk_misses: u64,
     pub disk_evictions: u64,
     pub disk_evicted_bytes: u64,
+    /// Bytes the disk cache files take up (compressed with `cache_compress`).
     pub disk_bytes: u64,
+    /// Size of the cached responses themselves.
+    pub disk_response_bytes: u64,
     pub disk_entries: u64,
+    /// Disk writes are paused after repeated failures.
+    pub disk_disabled: bool,
+    pub disk_write_failures: u64,
+    /// Responses served from an expired entry (stale-while-revalidate or
+    /// stale-if-error).
+    pub stale_served: u64,
+    /// Background refreshes of stale entries.
+    pub revalidations: u64,
+    /// Refreshes that found the file unchanged (the static "304").
+    pub revalidation_304: u64,
+    /// Files loaded into the cache by a `HEAD` request.
+    pub warm_fetches: u64,
+    /// Cache misses that waited for a concurrent request reading the same
+    /// file instead of reading it too.
+    pub coalesced_misses: u64,
+}
+
+/// Cache events counted for [`CacheMetrics`] outside the get/put paths.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CacheEvent {
+    StaleServed,
+    Revalidation,
+    Revalidation304,
+    WarmFetch,
+    CoalescedMiss,
+}
+
+impl CacheEvent {
+    pub(crate) fn record(self) {
+        match self {
+            Self::StaleServed => STALE_SERVED.incr(),
+            Self::Revalidation => {
+                REVALIDATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            Self::Revalidation304 => {
+                REVALIDATION_304.fetch_add(1, Ordering::Relaxed);
+            }
+            Self::WarmFetch => {
+                WARM_FETCHES.fetch_add(1, Ordering::Relaxed);
+            }
+            Self::CoalescedMiss => {
+                COALESCED_MISSES.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
 }
 
-static MEMORY_HITS: AtomicU64 = AtomicU64::new(0);
-static MEMORY_MISSES: AtomicU64 = AtomicU64::new(0);
-static DISK_HITS: AtomicU64 = AtomicU64::new(0);
-static DISK_MISSES: AtomicU64 = AtomicU64::new(0);
+/// Hit/miss counters are bumped on every cached request; sharded to avoid
+/// contention between workers.
+static MEMORY_HITS: ShardedCounter = ShardedCounter::new();
+static MEMORY_MISSES: ShardedCounter = ShardedCounter::new();
+static DISK_HITS: ShardedCounter = ShardedCounter::new();
+static DISK_MISSES: ShardedCounter = ShardedCounter::new();
 static DISK_EVICTIONS: AtomicU64 = AtomicU64::new(0);
 static DISK_EVICTED_BYTES: AtomicU64 = AtomicU64::new(0);
+static STALE_SERVED: ShardedCounter = ShardedCounter::new();
+static REVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+static REVALIDATION_304: AtomicU64 = AtomicU64::new(0);
+static WARM_FETCHES: AtomicU64 = AtomicU64::new(0);
+static COALESCED_MISSES: AtomicU64 = AtomicU64::new(0);
 
 /// Global in-memory cache map for static responses.
 static STATIC_CACHE: OnceLock<Mutex<HashMap<CacheKey, CacheEntry>>> = OnceLock::new();
+/// Cache keys with a background refresh in flight.
+static REFRESHING: OnceLock<Mutex<HashSet<CacheKey>>> = OnceLock::new();
 /// Global disk cache index for size/LRU tracking.
 static DISK_CACHE_INDEX: OnceLock<AsyncMutex<DiskCacheIndex>> = OnceLock::new();
 
+/// Trips the disk write circuit for the whole process.
+static DISK_CIRCUIT: DiskCircuit = DiskCircuit::new();
+
+/// Consecutive disk write failures that pause disk caching.
+const DISK_FAILURE_THRESHOLD: u64 = 5;
+/// How long disk caching stays paused before a single write re-probes it.
+const DISK_COOLDOWN_SECS: u64 = 30;
+
+/// Circuit breaker for disk cache writes.
+///
+/// After [`DISK_FAILURE_THRESHOLD`] consecutive failures (unwritable
+/// `cache_dir`, full disk, ...) writes are skipped for
+/// [`DISK_COOLDOWN_SECS`]. Once the cooldown passes, writes are attempted
+/// again: one success closes the circuit, one failure reopens it.
+/// Reads and the memory cache are unaffected.
+struct DiskCircuit {
+    consecutive_failures: AtomicU64,
+    total_failures: AtomicU64,
+    /// Epoch seconds until which writes are skipped; 0 when closed.
+    open_until: AtomicU64,
+    /// Whether the "disabled" warning was logged for the current outage.
+    logged: AtomicBool,
+}
+
+impl DiskCircuit {
+    const fn new() -> Self {
+        Self {
+            consecutive_failures: AtomicU64::new(0),
+            total_failures: AtomicU64::new(0),
+            open_until: AtomicU64::new(0),
+            logged: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a disk write should be attempted at `now`.
+    fn allows_write(&self, now: u64) -> bool {
+        now >= self.open_until.load(Ordering::Relaxed)
+    }
+
+    fn is_open(&self, now: u64) -> bool {
+        !self.allows_write(now)
+    }
+
+    fn record_failure(&self, now: u64) {
+        self.total_failures.fetch_add(1, Ordering::Relaxed);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < DISK_FAILURE_THRESHOLD {
+            return;
+        }
+        self.open_until
+            .store(now.saturating_add(DISK_COOLDOWN_SECS), Ordering::Relaxed);
+        if !self.logged.swap(true, Ordering::Relaxed) {
+            warn!(
+                target: "migux::static_cache",
+                failures,
+                cooldown_secs = DISK_COOLDOWN_SECS,
+                "Disk cache writes keep failing; disabling disk cache"
+            );
+        }
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if self.open_until.swap(0, Ordering::Relaxed) != 0
+            || self.logged.swap(false, Ordering::Relaxed)
+        {
+            info!(target: "migux::static_cache", "Disk cache writes recovered");
+        }
+    }
+}
+
 /// Build a compact cache key from file attributes.
 pub(crate) fn build_cache_key(path: &str, len: u64, mtime_nanos: u128, hsts: bool) -> CacheKey {
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
@@ -77,23 +385,35 @@ fn now_epoch_secs() -> u64 {
 
 /// Snapshot current cache hit/miss counters and disk usage.
 pub async fn cache_metrics_snapshot() -> CacheMetrics {
-    let (disk_bytes, disk_entries) = match DISK_CACHE_INDEX.get() {
+    let (disk_bytes, disk_response_bytes, disk_entries) = match DISK_CACHE_INDEX.get() {
         Some(lock) => {
             let index = lock.lock().await;
-            (index.total_bytes, index.entries.len() as u64)
+            (
+                index.total_bytes,
+                index.response_bytes,
+                index.entries.len() as u64,
+            )
         }
-        None => (0, 0),
+        None => (0, 0, 0),
     };
 
     CacheMetrics {
-        memory_hits: MEMORY_HITS.load(Ordering::Relaxed),
-        memory_misses: MEMORY_MISSES.load(Ordering::Relaxed),
-        disk_hits: DISK_HITS.load(Ordering::Relaxed),
-        disk_misses: DISK_MISSES.load(Ordering::Relaxed),
+        memory_hits: MEMORY_HITS.sum(),
+        memory_misses: MEMORY_MISSES.sum(),
+        disk_hits: DISK_HITS.sum(),
+        disk_misses: DISK_MISSES.sum(),
         disk_evictions: DISK_EVICTIONS.load(Ordering::Relaxed),
         disk_evicted_bytes: DISK_EVICTED_BYTES.load(Ordering::Relaxed),
         disk_bytes,
+        disk_response_bytes,
         disk_entries,
+        disk_disabled: DISK_CIRCUIT.is_open(now_epoch_secs()),
+        disk_write_failures: DISK_CIRCUIT.total_failures.load(Ordering::Relaxed),
+        stale_served: STALE_SERVED.sum(),
+        revalidations: REVALIDATIONS.load(Ordering::Relaxed),
+        revalidation_304: REVALIDATION_304.load(Ordering::Relaxed),
+        warm_fetches: WARM_FETCHES.load(Ordering::Relaxed),
+        coalesced_misses: COALESCED_MISSES.load(Ordering::Relaxed),
     }
 }
 
@@ -105,33 +425,77 @@ impl MemoryCache {
         STATIC_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
     }
 
-    /// Fetch a cached response from memory, honoring expiration.
-    pub(crate) fn get(key: CacheKey) -> Option<Vec<u8>> {
+    /// Fetch a cached response from memory, honoring expiration and stale windows.
+    pub(crate) fn get(key: CacheKey) -> Option<CacheLookup> {
+        Self::get_at(key, Instant::now())
+    }
+
+    fn get_at(key: CacheKey, now: Instant) -> Option<CacheLookup> {
         let mut map = Self::store().lock().ok()?;
-        if let Some(entry) = map.get(&key) {
-            if Instant::now() <= entry.expires_at {
-                MEMORY_HITS.fetch_add(1, Ordering::Relaxed);
-                debug!(
-                    target: "migux::static_cache",
-                    cache_key = %key,
-                    layer = "memory",
-                    "Cache hit"
-                );
-                return Some(entry.response.clone());
+        let state = match map.get(&key) {
+            Some(entry) => entry.state_at(now),
+            None => CacheState::Dead,
+        };
+
+        let entry = match state {
+            CacheState::Dead => {
+                map.remove(&key);
+                MEMORY_MISSES.incr();
+                return None;
             }
+            _ => map.get(&key)?,
+        };
+
+        if state == CacheState::StaleIfError {
+            MEMORY_MISSES.incr();
+        } else {
+            MEMORY_HITS.incr();
+            debug!(
+                target: "migux::static_cache",
+                cache_key = %key,
+                layer = "memory",
+                state = ?state,
+                "Cache hit"
+            );
         }
-        map.remove(&key);
-        MEMORY_MISSES.fetch_add(1, Ordering::Relaxed);
-        None
+
+        Some(CacheLookup {
+            response: entry.response.clone(),
+            state,
+        })
+    }
+
+    /// The fresh response for `key`, without counting a hit or miss.
+    pub(crate) fn fresh(key: CacheKey) -> Option<Vec<u8>> {
+        let map = Self::store().lock().ok()?;
+        let entry = map.get(&key)?;
+        (entry.state_at(Instant::now()) == CacheState::Fresh).then(|| entry.response.clone())
     }
 
-    /// Store a response in memory with a TTL.
-    pub(crate) fn put(key: CacheKey, response: Vec<u8>, ttl: Duration) {
+    /// Store a response in memory with a TTL and stale windows.
+    pub(crate) fn put(key: CacheKey, response: Vec<u8>, ttl: Duration, stale: StaleWindows) {
+        Self::put_at(key, response, ttl, stale, Instant::now());
+    }
+
+    /// Drop the entry for `key`, whatever its state.
+    pub(crate) fn remove(key: CacheKey) {
+        if let Ok(mut map) = Self::store().lock() {
+            map.remove(&key);
+        }
+    }
+
+    pub(crate) fn put_at(
+        key: CacheKey,
+        response: Vec<u8>,
+        ttl: Duration,
+        stale: StaleWindows,
+        now: Instant,
+    ) {
         if ttl.as_secs() == 0 {
             return;
         }
 
-        let entry = CacheEntry::new(response, Instant::now() + ttl);
+        let entry = CacheEntry::new(response, now + ttl, stale);
 
         if let Ok(mut map) = Self::store().lock() {
             map.insert(key, entry);
@@ -139,21 +503,103 @@ impl MemoryCache {
     }
 }
 
+/// How a `.cache` file holds the response (`encoding=` in the meta file).
+/// Meta files without the field predate `cache_compress` and are identity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum DiskEncoding {
+    #[default]
+    Identity,
+    Gzip,
+}
+
+impl DiskEncoding {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "identity" => Some(Self::Identity),
+            "gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+        }
+    }
+}
+
+/// `response` gzipped, or `None` when that does not make it smaller.
+fn gzip_entry(response: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(response).ok()?;
+    let gzipped = encoder.finish().ok()?;
+    (gzipped.len() < response.len()).then_some(gzipped)
+}
+
+/// The response stored in a `.cache` file; `None` when it does not decode.
+fn decode_entry(stored: Vec<u8>, encoding: DiskEncoding) -> Option<Vec<u8>> {
+    match encoding {
+        DiskEncoding::Identity => Some(stored),
+        DiskEncoding::Gzip => {
+            let mut response = Vec::new();
+            GzDecoder::new(stored.as_slice())
+                .read_to_end(&mut response)
+                .ok()?;
+            Some(response)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 struct DiskMetaRecord {
     expires_at: u64,
     last_access: u64,
+    /// Size of the response.
     size: u64,
+    /// Size of the `.cache` file (differs from `size` when gzipped).
+    stored_size: u64,
+    encoding: DiskEncoding,
+    stale_while_revalidate: u64,
+    stale_if_error: u64,
+}
+
+impl DiskMetaRecord {
+    fn stale(&self) -> StaleWindows {
+        StaleWindows {
+            while_revalidate: Duration::from_secs(self.stale_while_revalidate),
+            if_error: Duration::from_secs(self.stale_if_error),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct DiskEntryMeta {
     size: u64,
+    stored_size: u64,
+    encoding: DiskEncoding,
     expires_at: u64,
     last_access: u64,
+    stale: StaleWindows,
     prev: Option<CacheKey>,
     next: Option<CacheKey>,
 }
 
+impl DiskEntryMeta {
+    fn state_at(&self, now: u64) -> CacheState {
+        disk_state(self.expires_at, self.stale, now)
+    }
+}
+
+/// Classify a disk entry from its epoch-seconds expiry.
+fn disk_state(expires_at: u64, stale: StaleWindows, now: u64) -> CacheState {
+    if expires_at == 0 {
+        return CacheState::Dead;
+    }
+    let expired_for = (now > expires_at).then(|| Duration::from_secs(now - expires_at));
+    stale.classify(expired_for)
+}
+
 #[derive(Clone, Copy)]
 struct CacheSettings {
     max_total_bytes: u64,
@@ -170,6 +616,10 @@ impl CacheSettings {
         }
     }
 
+    fn is_inactive(&self, last_access: u64, now: u64) -> bool {
+        self.inactive_secs > 0 && now.saturating_sub(last_access) > self.inactive_secs
+    }
+
     fn exceeds_limits(&self, total_bytes: u64, entries: usize) -> bool {
         if self.max_total_bytes > 0 && total_bytes > self.max_total_bytes {
             return true;
@@ -186,7 +636,10 @@ struct DiskCacheIndex {
     entries: HashMap<CacheKey, DiskEntryMeta>,
     lru_head: Option<CacheKey>,
     lru_tail: Option<CacheKey>,
+    /// Bytes on disk (`stored_size`), what `cache_max_total_bytes` limits.
     total_bytes: u64,
+    /// Bytes of the responses (`size`), before compression.
+    response_bytes: u64,
     loaded: bool,
 }
 
@@ -211,12 +664,20 @@ fn parse_meta_record(meta_str: &str) -> Option<DiskMetaRecord> {
             expires_at,
             last_access: expires_at,
             size: 0,
+            stored_size: 0,
+            encoding: DiskEncoding::Identity,
+            stale_while_revalidate: 0,
+            stale_if_error: 0,
         });
     }
 
     let mut expires_at = None;
     let mut last_access = None;
     let mut size = None;
+    let mut stored_size = None;
+    let mut encoding = DiskEncoding::Identity;
+    let mut stale_while_revalidate = 0;
+    let mut stale_if_error = 0;
     for line in meta_str.lines() {
         let line = line.trim();
         if line.is_empty() {
@@ -235,27 +696,56 @@ fn parse_meta_record(meta_str: &str) -> Option<DiskMetaRecord> {
             "size" => {
                 size = value.parse::<u64>().ok();
             }
+            "stored_size" => {
+                stored_size = value.parse::<u64>().ok();
+            }
+            // An encoding this build cannot read makes the entry unusable.
+            "encoding" => {
+                encoding = DiskEncoding::parse(value)?;
+            }
+            "stale_while_revalidate" => {
+                stale_while_revalidate = value.parse::<u64>().unwrap_or(0);
+            }
+            "stale_if_error" => {
+                stale_if_error = value.parse::<u64>().unwrap_or(0);
+            }
             _ => {}
         }
     }
 
     let expires_at = expires_at?;
     let size = size.unwrap_or(0);
+    let stored_size = stored_size.unwrap_or(match encoding {
+        DiskEncoding::Identity => size,
+        DiskEncoding::Gzip => 0,
+    });
     let last_access = last_access.unwrap_or(expires_at);
     Some(DiskMetaRecord {
         expires_at,
         last_access,
         size,
+        stored_size,
+        encoding,
+        stale_while_revalidate,
+        stale_if_error,
     })
 }
 
 fn format_meta_record(record: &DiskMetaRecord) -> String {
     format!(
-        "expires_at={}\nlast_access={}\nsize={}\n",
-        record.expires_at, record.last_access, record.size
+        "expires_at={}\nlast_access={}\nsize={}\nstored_size={}\nencoding={}\nstale_while_revalidate={}\nstale_if_error={}\n",
+        record.expires_at,
+        record.last_access,
+        record.size,
+        record.stored_size,
+        record.encoding.as_str(),
+        record.stale_while_revalidate,
+        record.stale_if_error
     )
 }
 
+/// Temp file next to `path`: cache writes stay in the cache dir (not
+/// `http.temp_dir`) so the final rename is always atomic.
 fn temp_path_for(path: &Path) -> PathBuf {
     let file_name = path
         .file_name()
@@ -271,7 +761,7 @@ fn temp_path_for(path: &Path) -> PathBuf {
     tmp_path
 }
 
-async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+pub(crate) async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
     let tmp_path = temp_path_for(path);
     let mut file = fs::File::create(&tmp_path).await?;
     file.write_all(data).await?;
@@ -296,6 +786,7 @@ impl DiskCacheIndex {
             lru_head: None,
             lru_tail: None,
             total_bytes: 0,
+            response_bytes: 0,
             loaded: false,
         }
     }
@@ -306,6 +797,7 @@ impl DiskCacheIndex {
         self.lru_head = None;
         self.lru_tail = None;
         self.total_bytes = 0;
+        self.response_bytes = 0;
         self.loaded = false;
     }
 
@@ -322,6 +814,7 @@ impl DiskCacheIndex {
         self.lru_head = None;
         self.lru_tail = None;
         self.total_bytes = 0;
+        self.response_bytes = 0;
 
         let settings = CacheSettings::from(http_cfg);
         let now = now_epoch_secs();
@@ -358,7 +851,7 @@ impl DiskCacheIndex {
                 stale_keys.push(key);
                 continue;
             };
-            if record.expires_at == 0 || now > record.expires_at {
+            if disk_state(record.expires_at, record.stale(), now) == CacheState::Dead {
                 stale_keys.push(key);
                 continue;
             }
@@ -378,10 +871,13 @@ impl DiskCacheIndex {
                 }
             };
 
-            if record.size == 0 {
-                record.size = meta.len();
+            if record.stored_size == 0 {
+                record.stored_size = meta.len();
             }
-            if settings.max_total_bytes > 0 && record.size > settings.max_total_bytes {
+            if record.size == 0 && record.encoding == DiskEncoding::Identity {
+                record.size = record.stored_size;
+            }
+            if settings.max_total_bytes > 0 && record.stored_size > settings.max_total_bytes {
                 stale_keys.push(key);
                 continue;
             }
@@ -390,8 +886,11 @@ impl DiskCacheIndex {
                 key,
                 DiskEntryMeta {
                     size: record.size,
+                    stored_size: record.stored_size,
+                    encoding: record.encoding,
                     expires_at: record.expires_at,
                     last_access: record.last_access,
+                    stale: record.stale(),
                     prev: None,
                     next: None,
                 },
@@ -417,7 +916,8 @@ impl DiskCacheIndex {
         let _ = self.remove(key);
         meta.prev = None;
         meta.next = None;
-        self.total_bytes = self.total_bytes.saturating_add(meta.size);
+        self.total_bytes = self.total_bytes.saturating_add(meta.stored_size);
+        self.response_bytes = self.response_bytes.saturating_add(meta.size);
         self.entries.insert(key, meta);
         self.attach_to_tail(key);
     }
@@ -436,13 +936,17 @@ impl DiskCacheIndex {
             expires_at: entry.expires_at,
             last_access: entry.last_access,
             size: entry.size,
+            stored_size: entry.stored_size,
+            encoding: entry.encoding,
+            stale_while_revalidate: entry.stale.while_revalidate.as_secs(),
+            stale_if_error: entry.stale.if_error.as_secs(),
         })
     }
 
     fn remove(&mut self, key: CacheKey) -> Option<DiskEntryMeta> {
-        let (prev, next, size) = {
+        let (prev, next, size, stored_size) = {
             let entry = self.entries.get(&key)?;
-            (entry.prev, entry.next, entry.size)
+            (entry.prev, entry.next, entry.size, entry.stored_size)
         };
 
         if let Some(prev_key) = prev {
@@ -461,7 +965,8 @@ impl DiskCacheIndex {
             self.lru_tail = prev;
         }
 
-        self.total_bytes = self.total_bytes.saturating_sub(size);
+        self.total_bytes = self.total_bytes.saturating_sub(stored_size);
+        self.response_bytes = self.response_bytes.saturating_sub(size);
         self.entries.remove(&key)
     }
 
@@ -477,7 +982,7 @@ impl DiskCacheIndex {
                 break;
             };
             DISK_EVICTIONS.fetch_add(1, Ordering::Relaxed);
-            DISK_EVICTED_BYTES.fetch_add(meta.size, Ordering::Relaxed);
+            DISK_EVICTED_BYTES.fetch_add(meta.stored_size, Ordering::Relaxed);
             evicted.push(key);
         }
         evicted
@@ -488,10 +993,7 @@ impl DiskCacheIndex {
             return Vec::new();
         }
         let mut evicted = Vec::new();
-        loop {
-            let Some(key) = self.lru_head else {
-                break;
-            };
+        while let Some(key) = self.lru_head {
             let inactive = match self.entries.get(&key) {
                 Some(entry) => now.saturating_sub(entry.last_access) > inactive_secs,
                 None => false,
@@ -501,7 +1003,7 @@ impl DiskCacheIndex {
             }
             if let Some((_, meta)) = self.pop_lru() {
                 DISK_EVICTIONS.fetch_add(1, Ordering::Relaxed);
-                DISK_EVICTED_BYTES.fetch_add(meta.size, Ordering::Relaxed);
+                DISK_EVICTED_BYTES.fetch_add(meta.stored_size, Ordering::Relaxed);
                 evicted.push(key);
             } else {
                 break;
@@ -572,29 +1074,28 @@ impl DiskCache {
         }
     }
 
-    /// Read a cached response from disk if present and not expired.
-    pub(crate) async fn get(&self, http_cfg: &HttpConfig, key: CacheKey) -> Option<Vec<u8>> {
+    /// Read a cached response from disk if present and not past its stale windows.
+    pub(crate) async fn get(&self, http_cfg: &HttpConfig, key: CacheKey) -> Option<CacheLookup> {
         let settings = CacheSettings::from(http_cfg);
         let now = now_epoch_secs();
         let mut meta_record = None;
         let mut expired = false;
+        let state;
+        let encoding;
 
         {
             let mut index = self.lock_index(http_cfg).await;
             if let Some(entry) = index.entries.get(&key) {
-                if entry.expires_at == 0 || now > entry.expires_at {
-                    index.remove(key);
-                    expired = true;
-                } else if settings.inactive_secs > 0
-                    && now.saturating_sub(entry.last_access) > settings.inactive_secs
-                {
+                state = entry.state_at(now);
+                encoding = entry.encoding;
+                if state == CacheState::Dead || settings.is_inactive(entry.last_access, now) {
                     index.remove(key);
                     expired = true;
                 } else {
                     meta_record = index.touch(key, now);
                 }
             } else {
-                DISK_MISSES.fetch_add(1, Ordering::Relaxed);
+                DISK_MISSES.incr();
                 return None;
             }
         }
@@ -603,33 +1104,54 @@ impl DiskCache {
         if expired {
             let _ = fs::remove_file(&data_path).await;
             let _ = fs::remove_file(&meta_path).await;
-            DISK_MISSES.fetch_add(1, Ordering::Relaxed);
+            DISK_MISSES.incr();
             return None;
         }
 
         let data = match fs::read(&data_path).await {
-            Ok(data) => data,
-            Err(_) => {
+            Ok(stored) if encoding == DiskEncoding::Gzip => {
+                tokio::task::spawn_blocking(move || decode_entry(stored, encoding))
+                    .await
+                    .ok()
+                    .flatten()
+            }
+            Ok(stored) => Some(stored),
+            Err(_) => None,
+        };
+        let Some(data) = data else {
+            {
                 let mut index = self.lock_index(http_cfg).await;
                 let _ = index.remove(key);
-                DISK_MISSES.fetch_add(1, Ordering::Relaxed);
-                return None;
             }
+            let _ = fs::remove_file(&data_path).await;
+            let _ = fs::remove_file(&meta_path).await;
+            DISK_MISSES.incr();
+            return None;
         };
 
-        if let Some(record) = meta_record {
+        if let Some(record) = meta_record
+            && DISK_CIRCUIT.allows_write(now)
+        {
             let meta_contents = format_meta_record(&record);
             let _ = write_atomic(&meta_path, meta_contents.as_bytes()).await;
         }
 
-        DISK_HITS.fetch_add(1, Ordering::Relaxed);
-        debug!(
-            target: "migux::static_cache",
-            cache_key = %key,
-            layer = "disk",
-            "Cache hit"
-        );
-        Some(data)
+        if state == CacheState::StaleIfError {
+            DISK_MISSES.incr();
+        } else {
+            DISK_HITS.incr();
+            debug!(
+                target: "migux::static_cache",
+                cache_key = %key,
+                layer = "disk",
+                state = ?state,
+                "Cache hit"
+            );
+        }
+        Some(CacheLookup {
+            response: data,
+            state,
+        })
     }
 
     /// Persist a cached response and its expiration metadata to disk.
@@ -639,39 +1161,77 @@ impl DiskCache {
         key: CacheKey,
         response: &[u8],
         ttl: Duration,
+        stale: StaleWindows,
     ) {
         if ttl.as_secs() == 0 {
             return;
         }
 
-        if fs::create_dir_all(&self.cache_dir).await.is_err() {
+        let now = now_epoch_secs();
+        if !DISK_CIRCUIT.allows_write(now) {
             return;
         }
+        if self.write(http_cfg, key, response, stale, ttl, now).await {
+            DISK_CIRCUIT.record_success();
+        } else {
+            DISK_CIRCUIT.record_failure(now);
+        }
+    }
+
+    /// Write the entry and update the index; `false` on an I/O failure.
+    async fn write(
+        &self,
+        http_cfg: &HttpConfig,
+        key: CacheKey,
+        response: &[u8],
+        stale: StaleWindows,
+        ttl: Duration,
+        now: u64,
+    ) -> bool {
+        if fs::create_dir_all(&self.cache_dir).await.is_err() {
+            return false;
+        }
+
+        let gzipped = if http_cfg.cache_compress() {
+            let owned = response.to_vec();
+            tokio::task::spawn_blocking(move || gzip_entry(&owned))
+                .await
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+        let (stored, encoding) = match gzipped.as_deref() {
+            Some(gzipped) => (gzipped, DiskEncoding::Gzip),
+            None => (response, DiskEncoding::Identity),
+        };
 
         let settings = CacheSettings::from(http_cfg);
-        let size = response.len() as u64;
+        let size = stored.len() as u64;
         if settings.max_total_bytes > 0 && size > settings.max_total_bytes {
-            return;
+            return true;
         }
-
-        let now = now_epoch_secs();
         let record = DiskMetaRecord {
             expires_at: now.saturating_add(ttl.as_secs()),
             last_access: now,
-            size,
+            size: response.len() as u64,
+            stored_size: size,
+            encoding,
+            stale_while_revalidate: stale.while_revalidate.as_secs(),
+            stale_if_error: stale.if_error.as_secs(),
         };
 
         let (data_path, meta_path) = self.cache_paths(key);
-        let data_tmp = match write_temp_file(&data_path, response).await {
+        let data_tmp = match write_temp_file(&data_path, stored).await {
             Ok(path) => path,
-            Err(_) => return,
+            Err(_) => return false,
         };
         let meta_contents = format_meta_record(&record);
         let meta_tmp = match write_temp_file(&meta_path, meta_contents.as_bytes()).await {
             Ok(path) => path,
             Err(_) => {
                 let _ = fs::remove_file(&data_tmp).await;
-                return;
+                return false;
             }
         };
 
@@ -686,14 +1246,14 @@ impl DiskCache {
                     break;
                 };
                 DISK_EVICTIONS.fetch_add(1, Ordering::Relaxed);
-                DISK_EVICTED_BYTES.fetch_add(meta.size, Ordering::Relaxed);
+                DISK_EVICTED_BYTES.fetch_add(meta.stored_size, Ordering::Relaxed);
                 evicted_keys.push(evicted_key);
             }
 
             if settings.exceeds_limits(index.total_bytes + size, index.entries.len() + 1) {
                 let _ = fs::remove_file(&data_tmp).await;
                 let _ = fs::remove_file(&meta_tmp).await;
-                return;
+                return true;
             }
 
             let _ = fs::remove_file(&data_path).await;
@@ -701,20 +1261,23 @@ impl DiskCache {
             if fs::rename(&data_tmp, &data_path).await.is_err() {
                 let _ = fs::remove_file(&data_tmp).await;
                 let _ = fs::remove_file(&meta_tmp).await;
-                return;
+                return false;
             }
             if fs::rename(&meta_tmp, &meta_path).await.is_err() {
                 let _ = fs::remove_file(&data_path).await;
                 let _ = fs::remove_file(&meta_tmp).await;
-                return;
+                return false;
             }
 
             index.insert(
                 key,
                 DiskEntryMeta {
                     size: record.size,
+                    stored_size: record.stored_size,
+                    encoding: record.encoding,
                     expires_at: record.expires_at,
                     last_access: record.last_access,
+                    stale,
                     prev: None,
                     next: None,
                 },
@@ -726,6 +1289,17 @@ impl DiskCache {
             let _ = fs::remove_file(&data_path).await;
             let _ = fs::remove_file(&meta_path).await;
         }
+        true
+    }
+
+    /// Drop the entry for `key` from the index and the cache directory.
+    pub(crate) async fn remove(&self, http_cfg: &HttpConfig, key: CacheKey) {
+        if self.lock_index(http_cfg).await.remove(key).is_none() {
+            return;
+        }
+        let (data_path, meta_path) = self.cache_paths(key);
+        let _ = fs::remove_file(&data_path).await;
+        let _ = fs::remove_file(&meta_path).await;
     }
 
     /// Update access time / LRU for an existing cached entry.
@@ -738,11 +1312,8 @@ impl DiskCache {
         {
             let mut index = self.lock_index(http_cfg).await;
             if let Some(entry) = index.entries.get(&key) {
-                if entry.expires_at == 0 || now > entry.expires_at {
-                    index.remove(key);
-                    expired = true;
-                } else if settings.inactive_secs > 0
-                    && now.saturating_sub(entry.last_access) > settings.inactive_secs
+                if entry.state_at(now) == CacheState::Dead
+                    || settings.is_inactive(entry.last_access, now)
                 {
                     index.remove(key);
                     expired = true;
@@ -761,7 +1332,9 @@ impl DiskCache {
             return;
         }
 
-        if let Some(record) = meta_record {
+        if let Some(record) = meta_record
+            && DISK_CIRCUIT.allows_write(now)
+        {
             let meta_contents = format_meta_record(&record);
             let _ = write_atomic(&meta_path, meta_contents.as_bytes()).await;
         }
@@ -787,12 +1360,42 @@ impl DiskCache {
     }
 }
 
+/// TTL in seconds derived from a `Cache-Control` value.
+///
+/// `s-maxage` wins over `max-age`; `no-store`, `no-cache` and `private`
+/// mean "do not cache" (0). Returns `None` when no directive applies.
+pub(crate) fn cache_control_ttl(value: &str) -> Option<u64> {
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for directive in value.split(',') {
+        let directive = directive.trim();
+        let (name, arg) = match directive.split_once('=') {
+            Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+            None => (directive, None),
+        };
+        if name.eq_ignore_ascii_case("no-store")
+            || name.eq_ignore_ascii_case("no-cache")
+            || name.eq_ignore_ascii_case("private")
+        {
+            return Some(0);
+        }
+        let secs = arg.and_then(|a| a.parse::<u64>().ok());
+        if name.eq_ignore_ascii_case("max-age") {
+            max_age = secs.or(max_age);
+        } else if name.eq_ignore_ascii_case("s-maxage") {
+            s_maxage = secs.or(s_maxage);
+        }
+    }
+    s_maxage.or(max_age)
+}
+
 pub(crate) struct CachePolicy;
 
 impl CachePolicy {
-    /// Decide whether caching is enabled for this location and method.
+    /// Decide whether caching is enabled for this location and method
+    /// (`HEAD` only warms the cache).
     pub(crate) fn enabled(http_cfg: &HttpConfig, location: &LocationConfig, method: &str) -> bool {
-        if method != "GET" {
+        if method != "GET" && method != "HEAD" {
             return false;
         }
         if http_cfg.cache_dir().is_none() {
@@ -801,3 +1404,191 @@ impl CachePolicy {
         !matches!(location.cache(), Some(false))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows(swr: u64, sie: u64) -> StaleWindows {
+        StaleWindows {
+            while_revalidate: Duration::from_secs(swr),
+            if_error: Duration::from_secs(sie),
+        }
+    }
+
+    #[test]
+    fn memory_entry_moves_through_stale_states() {
+        let key = build_cache_key("/tmp/swr-test", 1, 1, false);
+        let start = Instant::now();
+        MemoryCache::put_at(
+            key,
+            b"resp".to_vec(),
+            Duration::from_secs(10),
+            windows(5, 20),
+            start,
+        );
+
+        let state_at =
+            |secs| MemoryCache::get_at(key, start + Duration::from_secs(secs)).map(|l| l.state);
+        assert_eq!(state_at(10), Some(CacheState::Fresh));
+        assert_eq!(state_at(12), Some(CacheState::StaleWhileRevalidate));
+        assert_eq!(state_at(25), Some(CacheState::StaleIfError));
+        assert_eq!(state_at(31), None);
+        // Dead entries are evicted, not resurrected.
+        assert_eq!(state_at(12), None);
+    }
+
+    #[test]
+    fn memory_entry_without_windows_dies_at_expiry() {
+        let key = build_cache_key("/tmp/swr-test-none", 1, 1, false);
+        let start = Instant::now();
+        MemoryCache::put_at(
+            key,
+            b"resp".to_vec(),
+            Duration::from_secs(1),
+            StaleWindows::default(),
+            start,
+        );
+        assert!(MemoryCache::get_at(key, start + Duration::from_secs(2)).is_none());
+    }
+
+    #[test]
+    fn disk_state_uses_epoch_seconds() {
+        let stale = windows(5, 20);
+        assert_eq!(disk_state(100, stale, 100), CacheState::Fresh);
+        assert_eq!(
+            disk_state(100, stale, 105),
+            CacheState::StaleWhileRevalidate
+        );
+        assert_eq!(disk_state(100, stale, 120), CacheState::StaleIfError);
+        assert_eq!(disk_state(100, stale, 121), CacheState::Dead);
+        assert_eq!(disk_state(0, stale, 0), CacheState::Dead);
+    }
+
+    #[test]
+    fn meta_record_round_trips_stale_windows() {
+        let record = DiskMetaRecord {
+            expires_at: 100,
+            last_access: 90,
+            size: 4,
+            stored_size: 24,
+            encoding: DiskEncoding::Gzip,
+            stale_while_revalidate: 5,
+            stale_if_error: 20,
+        };
+        let parsed = parse_meta_record(&format_meta_record(&record)).expect("parse");
+        assert_eq!(parsed, record);
+        assert_eq!(parsed.stale(), windows(5, 20));
+        assert!(parse_meta_record("expires_at=100\nencoding=br\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn compressed_entry_round_trips_through_disk() {
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_compress: true,
+            ..Default::default()
+        };
+        let response = [
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n".as_slice(),
+            "<p>hello</p>".repeat(500).as_bytes(),
+        ]
+        .concat();
+        let key = build_cache_key("/tmp/compressed-entry", 1, 1, false);
+        let cache = DiskCache::new(cache_dir.path());
+        assert!(
+            cache
+                .write(
+                    &http_cfg,
+                    key,
+                    &response,
+                    StaleWindows::default(),
+                    Duration::from_secs(60),
+                    now_epoch_secs(),
+                )
+                .await
+        );
+
+        let (data_path, meta_path) = cache_paths_for(cache_dir.path(), key);
+        let stored = std::fs::read(&data_path).expect("cache file");
+        assert!(stored.starts_with(&[0x1f, 0x8b]), "gzip magic");
+        assert!(stored.len() < response.len());
+        let meta = std::fs::read_to_string(&meta_path).expect("meta file");
+        let record = parse_meta_record(&meta).expect("parse");
+        assert_eq!(record.encoding, DiskEncoding::Gzip);
+        assert_eq!(record.size, response.len() as u64);
+        assert_eq!(record.stored_size, stored.len() as u64);
+
+        let hit = cache.get(&http_cfg, key).await.expect("disk hit");
+        assert_eq!(hit.response, response);
+    }
+
+    #[test]
+    fn cache_control_ttl_reads_directives() {
+        assert_eq!(cache_control_ttl("public, max-age=600"), Some(600));
+        assert_eq!(cache_control_ttl("max-age=600, s-maxage=60"), Some(60));
+        assert_eq!(cache_control_ttl("no-store, max-age=600"), Some(0));
+        assert_eq!(cache_control_ttl("public"), None);
+        assert_eq!(cache_control_ttl("max-age=abc"), None);
+    }
+
+    #[test]
+    fn disk_circuit_trips_and_recovers() {
+        let circuit = DiskCircuit::new();
+        for _ in 0..DISK_FAILURE_THRESHOLD - 1 {
+            circuit.record_failure(100);
+        }
+        assert!(circuit.allows_write(100));
+
+        circuit.record_failure(100);
+        assert!(circuit.is_open(100));
+        assert!(circuit.is_open(100 + DISK_COOLDOWN_SECS - 1));
+
+        // Cooldown over: the probe write fails and the circuit reopens.
+        let probe_at = 100 + DISK_COOLDOWN_SECS;
+        assert!(circuit.allows_write(probe_at));
+        circuit.record_failure(probe_at);
+        assert!(circuit.is_open(probe_at + 1));
+
+        // A successful probe closes it and resets the streak.
+        let probe_at = probe_at + DISK_COOLDOWN_SECS;
+        assert!(circuit.allows_write(probe_at));
+        circuit.record_success();
+        circuit.record_failure(probe_at);
+        assert!(circuit.allows_write(probe_at));
+        assert_eq!(
+            circuit.total_failures.load(Ordering::Relaxed),
+            DISK_FAILURE_THRESHOLD + 2
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_disk_write_is_reported() {
+        let root = tempfile::tempdir().expect("tempdir");
+        // A regular file where the cache directory should be.
+        let blocked = root.path().join("cache");
+        std::fs::write(&blocked, b"").expect("write");
+        let cache = DiskCache::new(&blocked);
+        assert!(
+            !cache
+                .write(
+                    &HttpConfig::default(),
+                    1,
+                    b"resp",
+                    StaleWindows::default(),
+                    Duration::from_secs(60),
+                    now_epoch_secs(),
+                )
+                .await
+        );
+    }
+
+    #[test]
+    fn legacy_meta_record_has_no_stale_windows() {
+        let parsed = parse_meta_record("expires_at=100\nlast_access=90\nsize=4\n").expect("parse");
+        assert_eq!(parsed.stale(), StaleWindows::default());
+        assert_eq!(parsed.encoding, DiskEncoding::Identity);
+        assert_eq!(parsed.stored_size, 4);
+    }
+}
@@ -193,7 +193,8 @@ mod tests {
 
     #[test]
     fn parse_if_modified_since_header() {
-        let headers = "GET / HTTP/1.1\r\nHost: x\r\nIf-Modified-Since: Fri, 15 May 2015 15:34:21 GMT\r\n\r\n";
+        let headers =
+            "GET / HTTP/1.1\r\nHost: x\r\nIf-Modified-Since: Fri, 15 May 2015 15:34:21 GMT\r\n\r\n";
         let t = parse_if_modified_since(headers).expect("expected date");
         let formatted = httpdate::fmt_http_date(t);
         assert_eq!(formatted, "Fri, 15 May 2015 15:34:21 GMT");
@@ -203,23 +204,33 @@ mod tests {
     fn should_304_if_modified_since_same_or_later() {
         let headers = "GET / HTTP/1.1\r\nIf-Modified-Since: Fri, 15 May 2015 15:34:21 GMT\r\n\r\n";
         let file_mtime = httpdate::parse_http_date("Fri, 15 May 2015 15:34:21 GMT").ok();
-        assert!(should_return_not_modified_if_modified_since("GET", headers, file_mtime));
+        assert!(should_return_not_modified_if_modified_since(
+            "GET", headers, file_mtime
+        ));
 
         let file_mtime_earlier = httpdate::parse_http_date("Thu, 14 May 2015 12:00:00 GMT").ok();
-        assert!(should_return_not_modified_if_modified_since("GET", headers, file_mtime_earlier));
+        assert!(should_return_not_modified_if_modified_since(
+            "GET",
+            headers,
+            file_mtime_earlier
+        ));
     }
 
     #[test]
     fn should_not_304_if_modified_since_older() {
         let headers = "GET / HTTP/1.1\r\nIf-Modified-Since: Fri, 15 May 2015 15:34:21 GMT\r\n\r\n";
         let file_mtime = httpdate::parse_http_date("Sat, 16 May 2015 10:00:00 GMT").ok();
-        assert!(!should_return_not_modified_if_modified_since("GET", headers, file_mtime));
+        assert!(!should_return_not_modified_if_modified_since(
+            "GET", headers, file_mtime
+        ));
     }
 
     #[test]
     fn if_modified_since_ignored_for_post() {
         let headers = "POST / HTTP/1.1\r\nIf-Modified-Since: Fri, 15 May 2015 15:34:21 GMT\r\n\r\n";
         let file_mtime = httpdate::parse_http_date("Fri, 15 May 2015 15:34:21 GMT").ok();
-        assert!(!should_return_not_modified_if_modified_since("POST", headers, file_mtime));
+        assert!(!should_return_not_modified_if_modified_since(
+            "POST", headers, file_mtime
+        ));
     }
 }
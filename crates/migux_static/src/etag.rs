@@ -1,5 +1,6 @@
 use std::{fs::Metadata, time::SystemTime, time::UNIX_EPOCH};
 
+#[derive(Clone)]
 pub struct EtagInfo {
     pub value: String,
     pub header: String,
@@ -7,10 +8,12 @@ pub struct EtagInfo {
 }
 
 pub fn weak_etag_size_mtime(metadata: &Metadata) -> EtagInfo {
-    let size = metadata.len();
-    let mtime_nanos = metadata
-        .modified()
-        .ok()
+    weak_etag(metadata.len(), metadata.modified().ok())
+}
+
+/// `W/"{size}-{mtime_nanos}"`, for files and archive entries alike.
+pub fn weak_etag(size: u64, mtime: Option<SystemTime>) -> EtagInfo {
+    let mtime_nanos = mtime
         .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
         .map(|dur| dur.as_nanos())
         .unwrap_or(0);
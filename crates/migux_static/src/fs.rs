@@ -1,9 +1,15 @@
 //! Filesystem/path helpers for static serving.
 
+use migux_config::LocationConfig;
+
 pub(crate) struct PathResolver;
 
 impl PathResolver {
     /// Resolve a request path to a relative file path within the location root.
+    ///
+    /// The location path itself (`/app`, with or without a trailing slash)
+    /// and any path ending in `/` are directory requests and resolve to that
+    /// directory's `index`.
     pub(crate) fn resolve_relative_path(
         req_path: &str,
         location_path: &str,
@@ -15,29 +21,64 @@ impl PathResolver {
             return None;
         }
 
-        if req_path == "/" && location_path == "/" {
-            return Some(index.to_string());
-        }
+        let tail = req_path.strip_prefix(location_path)?;
+        let tail = tail.strip_prefix('/').unwrap_or(tail);
 
-        if req_path == location_path {
-            return Some(index.to_string());
+        if tail.is_empty() || tail.ends_with('/') {
+            Some(format!("{tail}{index}"))
+        } else {
+            Some(tail.to_string())
         }
+    }
+}
 
-        if req_path.starts_with(location_path) {
-            let mut tail = &req_path[location_path.len()..];
-
-            if tail.starts_with('/') {
-                tail = &tail[1..];
-            }
+impl PathResolver {
+    /// Join a resolved relative path onto `root`, refusing anything that could
+    /// escape it (absolute paths or `..` components).
+    pub(crate) fn join_root(root: &str, rel: &str) -> Option<String> {
+        let escapes = std::path::Path::new(rel).components().any(|c| {
+            !matches!(
+                c,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        });
+        if escapes {
+            return None;
+        }
+        Some(format!("{}/{}", root, rel))
+    }
+}
 
-            if tail.is_empty() {
-                Some(index.to_string())
-            } else {
-                Some(tail.to_string())
-            }
-        } else {
-            None
+impl PathResolver {
+    /// Whether a resolved relative path reaches a dotfile or dot-directory
+    /// (`.env`, `.git/HEAD`) that `location` does not serve. Percent-encoded
+    /// dots count. Under one of `allow_dotfile_prefixes` only the part after
+    /// the prefix is checked, so `.well-known/.secret` stays hidden.
+    /// `<file>.httpheaders` sidecars are always hidden.
+    pub(crate) fn is_hidden(location: &LocationConfig, rel: &str) -> bool {
+        let rel = decode_path_for_check(rel);
+        if rel.ends_with(".httpheaders") {
+            return true;
+        }
+        if location.serve_dotfiles() {
+            return false;
         }
+        let rest = location
+            .allow_dotfile_prefixes()
+            .iter()
+            .map(|prefix| prefix.trim_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .find_map(|prefix| {
+                let rest = rel.strip_prefix(prefix)?;
+                if rest.is_empty() {
+                    Some(rest)
+                } else {
+                    rest.strip_prefix('/')
+                }
+            })
+            .unwrap_or(&rel);
+        rest.split('/')
+            .any(|segment| segment.starts_with('.') && segment != ".")
     }
 }
 
@@ -66,20 +107,21 @@ fn decode_path_for_check(path: &str) -> String {
     let mut out = String::with_capacity(bytes.len());
     let mut i = 0;
     while i < bytes.len() {
-        if bytes[i] == b'%' && i + 2 < bytes.len() {
-            if let (Some(h1), Some(h2)) = (from_hex(bytes[i + 1]), from_hex(bytes[i + 2])) {
-                let value = (h1 << 4) | h2;
-                match value {
-                    b'.' | b'/' | b'\\' => out.push(value as char),
-                    _ => {
-                        out.push('%');
-                        out.push(bytes[i + 1] as char);
-                        out.push(bytes[i + 2] as char);
-                    }
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let (Some(h1), Some(h2)) = (from_hex(bytes[i + 1]), from_hex(bytes[i + 2]))
+        {
+            let value = (h1 << 4) | h2;
+            match value {
+                b'.' | b'/' | b'\\' => out.push(value as char),
+                _ => {
+                    out.push('%');
+                    out.push(bytes[i + 1] as char);
+                    out.push(bytes[i + 2] as char);
                 }
-                i += 3;
-                continue;
             }
+            i += 3;
+            continue;
         }
         out.push(bytes[i] as char);
         i += 1;
@@ -95,3 +137,80 @@ fn from_hex(byte: u8) -> Option<u8> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PathResolver;
+    use migux_config::{LocationConfig, StringList};
+
+    fn resolve(req_path: &str, location_path: &str) -> Option<String> {
+        PathResolver::resolve_relative_path(req_path, location_path, "index.html")
+    }
+
+    #[test]
+    fn location_path_with_or_without_slash_serves_the_index() {
+        let index = Some("index.html".to_string());
+        assert_eq!(resolve("/", "/"), index);
+        assert_eq!(resolve("/app", "/app"), index);
+        assert_eq!(resolve("/app/", "/app"), index);
+        assert_eq!(resolve("/app/", "/app/"), index);
+        assert_eq!(resolve("/app?x=1", "/app"), index);
+    }
+
+    #[test]
+    fn trailing_slash_serves_the_subdirectory_index() {
+        assert_eq!(resolve("/docs/", "/").as_deref(), Some("docs/index.html"));
+        assert_eq!(
+            resolve("/app/docs/", "/app").as_deref(),
+            Some("docs/index.html")
+        );
+        assert_eq!(resolve("/app/docs", "/app").as_deref(), Some("docs"));
+        assert_eq!(resolve("/app/a.js", "/app/").as_deref(), Some("a.js"));
+    }
+
+    #[test]
+    fn dotfiles_are_hidden_unless_allowed() {
+        let hidden = |location: &LocationConfig, req_path: &str| {
+            let rel = resolve(req_path, "/").expect("resolves");
+            PathResolver::is_hidden(location, &rel)
+        };
+        let location = LocationConfig::default();
+        assert!(hidden(&location, "/.env"));
+        assert!(hidden(&location, "/.git/HEAD"));
+        assert!(hidden(&location, "/app/.git/config"));
+        assert!(hidden(&location, "/%2egit/config"));
+        assert!(!hidden(&location, "/.well-known/acme-challenge/xyz"));
+        assert!(!hidden(&location, "/docs/a.b.txt"));
+
+        assert!(hidden(&location, "/.well-known/.secret"));
+        assert!(hidden(&location, "/.well-knownx/a"));
+
+        let custom = LocationConfig {
+            allow_dotfile_prefixes: Some(StringList::Many(vec![
+                "/.public/".into(),
+                ".well-known/acme-challenge".into(),
+            ])),
+            ..Default::default()
+        };
+        assert!(!hidden(&custom, "/.public/a.txt"));
+        assert!(!hidden(&custom, "/.well-known/acme-challenge/t"));
+        assert!(hidden(&custom, "/.well-known/security.txt"));
+
+        let open = LocationConfig {
+            serve_dotfiles: Some(true),
+            ..Default::default()
+        };
+        assert!(!hidden(&open, "/.env"));
+
+        assert!(hidden(&location, "/app.js.httpheaders"));
+        assert!(hidden(&location, "/app.js%2ehttpheaders"));
+        assert!(hidden(&open, "/index.html.httpheaders"));
+    }
+
+    #[test]
+    fn paths_outside_the_location_do_not_resolve() {
+        assert_eq!(resolve("/", "/app"), None);
+        assert_eq!(resolve("/ap", "/app"), None);
+        assert_eq!(resolve("/app/../etc/passwd", "/app"), None);
+    }
+}
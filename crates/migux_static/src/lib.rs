@@ -1,14 +1,20 @@
-//! Static file serving and cache utilities.
+//! Static file serving (local roots, archives or pulled from an origin) and cache
+//! utilities.
 //!
 //! Provides a minimal static file handler with optional in-memory and
 //! disk-backed caching, respecting configured TTL and object size limits.
 
+mod archive;
 mod cache;
 mod conditional;
 mod etag;
 mod fs;
+mod origin;
+mod range;
 mod response;
 mod service;
 
+pub use archive::preload_archive;
 pub use cache::{CacheMetrics, cache_metrics_snapshot};
-pub use service::{serve_static, serve_static_bytes, serve_static_cached};
+pub use origin::serve_origin_pull;
+pub use service::{serve_static, serve_static_bytes, serve_static_cached, serve_static_fallback};
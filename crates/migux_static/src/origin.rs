@@ -0,0 +1,395 @@
+//! `origin_pull` locations: a static tree filled on demand from an upstream.
+//!
+//! A request for a file that is missing under the location's (first) root,
+//! or whose stored copy has outlived the origin's `Cache-Control` max-age, is
+//! fetched through the proxy and written there together with a
+//! `<file>.httpheaders` sidecar holding that `Cache-Control`. The file is
+//! then served by the regular static path, so ETags, ranges, conditional
+//! requests and the memory/disk cache all apply.
+//!
+//! Only `200` responses with a positive `max-age`/`s-maxage`, and no larger
+//! than `http.cache_max_object_bytes` when that is set, are stored; anything
+//! else is relayed. Concurrent misses for one file share a single fetch.
+//!
+//! Within `http.cache_stale_while_revalidate_secs` after it expires, a stored
+//! copy is still served while a single background fetch replaces it.
+
+use std::{
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use migux_config::{LocationConfig, MiguxConfig, ServerConfig};
+use migux_http::{keep_alive::KeepAlive, spool};
+use migux_proxy::{FetchedResponse, Proxy};
+use tokio::fs as tokio_fs;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{debug, warn};
+
+use crate::cache::{
+    CacheEvent, CacheKey, CacheState, MissFill, RefreshGuard, StaleWindows, cache_control_ttl,
+    write_atomic,
+};
+use crate::fs::PathResolver;
+use crate::response::ResponseBuilder;
+use crate::service::{COALESCE_WAIT, serve_static_cached, sidecar_header};
+
+/// What to answer after consulting the local copy and the origin.
+enum Pulled {
+    /// Serve the stored file.
+    Local,
+    /// Send this response as-is (origin errors, uncacheable responses).
+    Respond(Vec<u8>),
+}
+
+/// Serve a request on an `origin_pull` location.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_origin_pull<S>(
+    stream: &mut S,
+    proxy: &Arc<Proxy>,
+    cfg: &Arc<MiguxConfig>,
+    server_cfg: &ServerConfig,
+    location: &LocationConfig,
+    method: &str,
+    headers: &str,
+    req_path: &str,
+    keep_alive: KeepAlive,
+    hsts: Option<&str>,
+    client_addr: &SocketAddr,
+) -> anyhow::Result<()>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    let roots = location.roots_or(server_cfg.root());
+    let index = location.index_or(server_cfg.index());
+    let file_path = PathResolver::resolve_relative_path(req_path, &location.path, index)
+        .filter(|rel| !PathResolver::is_hidden(location, rel))
+        .zip(roots.first())
+        .and_then(|(rel, root)| PathResolver::join_root(root, &rel));
+
+    // Unresolvable and hidden paths get the static 404.
+    if let Some(file_path) = file_path {
+        let pulled = pull(
+            proxy,
+            cfg,
+            location,
+            method,
+            headers,
+            req_path,
+            &file_path,
+            keep_alive,
+            hsts,
+            client_addr,
+        )
+        .await;
+        if let Pulled::Respond(resp) = pulled {
+            tracing::Span::current().record("decision", "origin");
+            stream.write_all(&resp).await?;
+            return Ok(());
+        }
+    }
+
+    serve_static_cached(
+        stream, &cfg.http, server_cfg, location, method, headers, req_path, keep_alive, hsts,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn pull(
+    proxy: &Arc<Proxy>,
+    cfg: &Arc<MiguxConfig>,
+    location: &LocationConfig,
+    method: &str,
+    headers: &str,
+    req_path: &str,
+    file_path: &str,
+    keep_alive: KeepAlive,
+    hsts: Option<&str>,
+    client_addr: &SocketAddr,
+) -> Pulled {
+    let local = tokio_fs::metadata(file_path)
+        .await
+        .ok()
+        .filter(|meta| meta.is_file());
+    if let Some(meta) = &local {
+        match expired_for(file_path, meta).await {
+            None => return Pulled::Local,
+            Some(expired)
+                if StaleWindows::from_config(&cfg.http).classify(Some(expired))
+                    == CacheState::StaleWhileRevalidate =>
+            {
+                CacheEvent::StaleServed.record();
+                spawn_refresh(
+                    proxy,
+                    cfg,
+                    location,
+                    req_path,
+                    host_header(headers),
+                    file_path,
+                    client_addr,
+                );
+                return Pulled::Local;
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Single-flight: one request fetches the file, concurrent misses wait
+    // for it and serve what it stored. If it stored nothing (or takes longer
+    // than COALESCE_WAIT) they go to the origin themselves.
+    let _fill = match MissFill::claim(fill_key(file_path)) {
+        MissFill::Leader(guard) => Some(guard),
+        MissFill::Follower(lock) => {
+            if MissFill::wait(lock, COALESCE_WAIT).await
+                && let Ok(meta) = tokio_fs::metadata(file_path).await
+                && meta.is_file()
+                && is_fresh(file_path, &meta).await
+            {
+                return Pulled::Local;
+            }
+            None
+        }
+    };
+
+    let fetched = match proxy
+        .fetch(location, req_path, host_header(headers), cfg, client_addr)
+        .await
+    {
+        Ok(fetched) => fetched,
+        Err(e) if local.is_some() => {
+            warn!(target: "migux::origin_pull", path = %file_path, error = %e, "Origin fetch failed; serving stored copy");
+            return Pulled::Local;
+        }
+        Err(e) => {
+            warn!(target: "migux::origin_pull", path = %file_path, error = %e, "Origin fetch failed");
+            return Pulled::Respond(ResponseBuilder::error(
+                "502 Bad Gateway",
+                keep_alive,
+                location.error_format(),
+            ));
+        }
+    };
+
+    if storable(cfg, &fetched) {
+        match store(&cfg.http.temp_dir(), file_path, &fetched).await {
+            Ok(()) => {
+                debug!(target: "migux::origin_pull", path = %file_path, bytes = fetched.body.len(), "Stored origin response");
+                return Pulled::Local;
+            }
+            Err(e) => {
+                warn!(target: "migux::origin_pull", path = %file_path, error = %e, "Cannot store origin response");
+            }
+        }
+    } else if fetched.status >= 500 && local.is_some() {
+        warn!(target: "migux::origin_pull", path = %file_path, status = fetched.status, "Origin error; serving stored copy");
+        return Pulled::Local;
+    }
+    Pulled::Respond(relay(&fetched, method, keep_alive, hsts))
+}
+
+/// A `200` the origin marked cacheable (positive max-age), within the
+/// configured object size limit.
+fn storable(cfg: &MiguxConfig, fetched: &FetchedResponse) -> bool {
+    let max_obj = cfg.http.cache_max_object_bytes().filter(|v| *v > 0);
+    fetched.status == 200
+        && fetched
+            .header("cache-control")
+            .and_then(cache_control_ttl)
+            .is_some_and(|ttl| ttl > 0)
+        && max_obj.is_none_or(|max| fetched.body.len() as u64 <= max)
+}
+
+/// Single-flight key for fetches of `file_path`; kept apart from the
+/// static cache keys, which hash the file's size and mtime as well.
+fn fill_key(file_path: &str) -> CacheKey {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    "origin_pull".hash(&mut hasher);
+    file_path.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Re-fetch a stored copy in the background (stale-while-revalidate). At
+/// most one refresh per file runs at a time; the copy is only replaced by a
+/// storable response, so on errors it is served until its window runs out.
+fn spawn_refresh(
+    proxy: &Arc<Proxy>,
+    cfg: &Arc<MiguxConfig>,
+    location: &LocationConfig,
+    req_path: &str,
+    host: Option<&str>,
+    file_path: &str,
+    client_addr: &SocketAddr,
+) {
+    let Some(guard) = RefreshGuard::acquire(fill_key(file_path)) else {
+        return;
+    };
+    let (proxy, cfg, location) = (Arc::clone(proxy), Arc::clone(cfg), location.clone());
+    let req_path = req_path.to_string();
+    let host = host.map(str::to_string);
+    let file_path = file_path.to_string();
+    let client_addr = *client_addr;
+
+    tokio::spawn(async move {
+        let _guard = guard;
+        CacheEvent::Revalidation.record();
+        let fetched = match proxy
+            .fetch(&location, &req_path, host.as_deref(), &cfg, &client_addr)
+            .await
+        {
+            Ok(fetched) if storable(&cfg, &fetched) => fetched,
+            Ok(fetched) => {
+                warn!(target: "migux::origin_pull", path = %file_path, status = fetched.status, "Background origin refresh not storable; keeping stored copy");
+                return;
+            }
+            Err(e) => {
+                warn!(target: "migux::origin_pull", path = %file_path, error = %e, "Background origin refresh failed; keeping stored copy");
+                return;
+            }
+        };
+        match store(&cfg.http.temp_dir(), &file_path, &fetched).await {
+            Ok(()) => {
+                debug!(target: "migux::origin_pull", path = %file_path, bytes = fetched.body.len(), "Refreshed stored copy");
+            }
+            Err(e) => {
+                warn!(target: "migux::origin_pull", path = %file_path, error = %e, "Cannot store origin response");
+            }
+        }
+    });
+}
+
+/// Whether a stored copy is within the origin's max-age (from its sidecar).
+/// Without one it is kept until removed.
+async fn is_fresh(file_path: &str, meta: &std::fs::Metadata) -> bool {
+    expired_for(file_path, meta).await.is_none()
+}
+
+/// How long ago a stored copy outlived the origin's max-age; `None` while
+/// it is fresh.
+async fn expired_for(file_path: &str, meta: &std::fs::Metadata) -> Option<Duration> {
+    let sidecar = tokio_fs::read_to_string(format!("{file_path}.httpheaders"))
+        .await
+        .ok()?;
+    let ttl = sidecar_header(&sidecar, "cache-control")
+        .as_deref()
+        .and_then(cache_control_ttl)?;
+    let age = meta
+        .modified()
+        .ok()
+        .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+        .unwrap_or_default();
+    age.checked_sub(Duration::from_secs(ttl))
+}
+
+/// Write the body and its `Cache-Control` sidecar, creating parent
+/// directories as needed. The body is spooled under `temp_dir` and moved
+/// into place, so readers never see a partial file.
+async fn store(temp_dir: &Path, file_path: &str, fetched: &FetchedResponse) -> std::io::Result<()> {
+    let path = Path::new(file_path);
+    if let Some(parent) = path.parent() {
+        tokio_fs::create_dir_all(parent).await?;
+    }
+    let sidecar = format!("{file_path}.httpheaders");
+    let cache_control = fetched.header("cache-control").unwrap_or_default();
+    write_atomic(
+        Path::new(&sidecar),
+        format!("Cache-Control: {cache_control}\n").as_bytes(),
+    )
+    .await?;
+
+    let tmp = spool::temp_file_path(temp_dir, "origin");
+    let spooled = match tokio_fs::write(&tmp, &fetched.body).await {
+        Ok(()) => spool::persist(&tmp, path).await,
+        Err(e) => Err(e),
+    };
+    if spooled.is_err() {
+        let _ = tokio_fs::remove_file(&tmp).await;
+    }
+    spooled
+}
+
+/// The origin's response for the client, without storing it.
+fn relay(
+    fetched: &FetchedResponse,
+    method: &str,
+    keep_alive: KeepAlive,
+    hsts: Option<&str>,
+) -> Vec<u8> {
+    let reason = http::StatusCode::from_u16(fetched.status)
+        .ok()
+        .and_then(|code| code.canonical_reason())
+        .unwrap_or("");
+    let status_line = format!("{} {reason}", fetched.status);
+    let mut extra_headers = Vec::new();
+    for name in ["Cache-Control", "Location", "ETag", "Last-Modified"] {
+        if let Some(value) = fetched.header(name) {
+            extra_headers.push((name, value));
+        }
+    }
+    if let Some(hsts_value) = hsts {
+        extra_headers.push(("Strict-Transport-Security", hsts_value));
+    }
+    let body = (method != "HEAD").then_some(fetched.body.as_slice());
+    ResponseBuilder::build_with_headers(
+        status_line.trim_end(),
+        fetched.header("content-type"),
+        fetched.body.len(),
+        keep_alive,
+        &extra_headers,
+        body,
+    )
+}
+
+fn host_header(headers: &str) -> Option<&str> {
+    headers.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("host")
+            .then(|| value.trim())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_fresh, store};
+    use migux_proxy::FetchedResponse;
+
+    #[tokio::test]
+    async fn stored_copy_expires_with_the_origin_max_age() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let file = dir.path().join("app.js");
+        std::fs::write(&file, "js").expect("write");
+        let file = file.to_string_lossy().into_owned();
+        let meta = std::fs::metadata(&file).expect("metadata");
+        let sidecar = format!("{file}.httpheaders");
+
+        assert!(is_fresh(&file, &meta).await, "no sidecar keeps the copy");
+        std::fs::write(&sidecar, "Cache-Control: public, max-age=60\n").expect("write");
+        assert!(is_fresh(&file, &meta).await);
+        std::fs::write(&sidecar, "Cache-Control: max-age=0\n").expect("write");
+        assert!(!is_fresh(&file, &meta).await);
+    }
+
+    #[tokio::test]
+    async fn stored_body_is_spooled_through_the_temp_dir() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let root = tempfile::tempdir().expect("tempdir");
+        let file = root.path().join("css/app.css");
+        let file = file.to_string_lossy().into_owned();
+        let fetched = FetchedResponse {
+            status: 200,
+            headers: vec![("Cache-Control".into(), "max-age=60".into())],
+            body: b"body {}".to_vec(),
+        };
+
+        store(temp.path(), &file, &fetched).await.expect("store");
+        assert_eq!(std::fs::read(&file).expect("read"), b"body {}");
+        let sidecar = std::fs::read_to_string(format!("{file}.httpheaders")).expect("sidecar");
+        assert_eq!(sidecar, "Cache-Control: max-age=60\n");
+        let leftover = std::fs::read_dir(temp.path()).expect("read_dir").count();
+        assert_eq!(leftover, 0, "spool file left behind");
+    }
+}
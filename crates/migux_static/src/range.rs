@@ -0,0 +1,156 @@
+//! Single byte-range requests (`Range: bytes=...`) for static files.
+//!
+//! Only one range per request is honored; multi-range requests, other units
+//! and malformed values fall back to the full 200 response, as RFC 9110
+//! allows.
+
+/// How to answer a GET with respect to its `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    /// No usable range: send the whole file.
+    Full,
+    /// Send bytes `start..=end` as 206.
+    Partial { start: u64, end: u64 },
+    /// The range starts past the end of the file: 416.
+    Unsatisfiable,
+}
+
+/// Evaluate `Range` (and `If-Range`) against a file of `len` bytes.
+///
+/// `If-Range` only matches the exact `Last-Modified` date: our ETags are weak
+/// and never satisfy the strong comparison it requires.
+pub(crate) fn evaluate_range(
+    method: &str,
+    headers: &str,
+    len: u64,
+    last_modified: Option<&str>,
+) -> ByteRange {
+    if method != "GET" {
+        return ByteRange::Full;
+    }
+    let Some(range) = header_value(headers, "range") else {
+        return ByteRange::Full;
+    };
+    if let Some(if_range) = header_value(headers, "if-range")
+        && Some(if_range) != last_modified
+    {
+        return ByteRange::Full;
+    }
+    parse_byte_range(range, len)
+}
+
+fn header_value<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+fn parse_byte_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    // Suffix range: the last N bytes.
+    if first.is_empty() {
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial {
+                start: len.saturating_sub(n),
+                end: len - 1,
+            },
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return ByteRange::Full,
+        }
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial {
+        start,
+        end: end.min(len - 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteRange, evaluate_range};
+
+    fn range(value: &str, len: u64) -> ByteRange {
+        let headers = format!("GET / HTTP/1.1\r\nRange: {value}\r\n");
+        evaluate_range("GET", &headers, len, None)
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(
+            range("bytes=0-4", 10),
+            ByteRange::Partial { start: 0, end: 4 }
+        );
+        assert_eq!(
+            range("bytes=6-", 10),
+            ByteRange::Partial { start: 6, end: 9 }
+        );
+        assert_eq!(
+            range("bytes=-3", 10),
+            ByteRange::Partial { start: 7, end: 9 }
+        );
+        assert_eq!(
+            range("bytes=5-99", 10),
+            ByteRange::Partial { start: 5, end: 9 }
+        );
+        assert_eq!(
+            range("bytes=-20", 10),
+            ByteRange::Partial { start: 0, end: 9 }
+        );
+    }
+
+    #[test]
+    fn unsupported_or_invalid_ranges_send_full_body() {
+        assert_eq!(range("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(range("items=0-1", 10), ByteRange::Full);
+        assert_eq!(range("bytes=5-2", 10), ByteRange::Full);
+        assert_eq!(range("bytes=x-", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn if_range_must_match_last_modified() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let headers = format!("GET / HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: {date}\r\n");
+        assert_eq!(
+            evaluate_range("GET", &headers, 10, Some(date)),
+            ByteRange::Partial { start: 0, end: 1 }
+        );
+        assert_eq!(evaluate_range("GET", &headers, 10, None), ByteRange::Full);
+        let etag = "GET / HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: W/\"abc\"\r\n";
+        assert_eq!(evaluate_range("GET", etag, 10, Some(date)), ByteRange::Full);
+    }
+}
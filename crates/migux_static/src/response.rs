@@ -1,5 +1,9 @@
 //! HTTP response builders for static file serving.
 
+use migux_config::ErrorFormat;
+use migux_http::keep_alive::KeepAlive;
+use migux_http::responses::json_error_body;
+
 type HeaderPair<'a> = (&'a str, &'a str);
 
 const HTTP_VERSION: &str = "HTTP/1.1";
@@ -7,16 +11,16 @@ const CRLF: &str = "\r\n";
 const HEADER_CONTENT_LENGTH: &str = "Content-Length";
 const HEADER_CONTENT_TYPE: &str = "Content-Type";
 const HEADER_CONNECTION: &str = "Connection";
-const CONNECTION_KEEP_ALIVE: &str = "keep-alive";
-const CONNECTION_CLOSE: &str = "close";
+const HEADER_KEEP_ALIVE: &str = "Keep-Alive";
 const TEXT_PLAIN_UTF8: &str = "text/plain; charset=utf-8";
+const APPLICATION_JSON: &str = "application/json";
 
 /// Metadata required to render the response header section.
 struct ResponseHead<'a> {
     status: &'a str,
     content_type: Option<&'a str>,
     content_length: usize,
-    keep_alive: bool,
+    keep_alive: KeepAlive,
     extra_headers: &'a [HeaderPair<'a>],
 }
 
@@ -26,7 +30,7 @@ impl<'a> ResponseHead<'a> {
         status: &'a str,
         content_type: Option<&'a str>,
         content_length: usize,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         extra_headers: &'a [HeaderPair<'a>],
     ) -> Self {
         Self {
@@ -39,7 +43,7 @@ impl<'a> ResponseHead<'a> {
     }
 
     /// Render the header section into a String.
-    fn to_string(&self) -> String {
+    fn render(&self) -> String {
         let mut headers = String::with_capacity(self.header_len_hint());
         write_status_line(&mut headers, self.status);
         write_header(
@@ -56,8 +60,7 @@ impl<'a> ResponseHead<'a> {
             write_header(&mut headers, name, value);
         }
 
-        let connection = connection_value(self.keep_alive);
-        write_header(&mut headers, HEADER_CONNECTION, connection);
+        write_connection_headers(&mut headers, self.keep_alive);
         headers.push_str(CRLF);
         headers
     }
@@ -76,19 +79,23 @@ impl<'a> ResponseHead<'a> {
             len += name.len() + 2 + value.len() + CRLF.len();
         }
 
-        let connection = connection_value(self.keep_alive);
-        len += HEADER_CONNECTION.len() + 2 + connection.len() + CRLF.len();
+        len += HEADER_CONNECTION.len() + 2 + self.keep_alive.connection().len() + CRLF.len();
+        if self.keep_alive.is_open() {
+            // "timeout=N, max=N"
+            len += HEADER_KEEP_ALIVE.len() + 2 + 32 + CRLF.len();
+        }
         len += CRLF.len();
         len
     }
 }
 
-/// Return the correct Connection header value for the keep-alive setting.
-fn connection_value(keep_alive: bool) -> &'static str {
-    if keep_alive {
-        CONNECTION_KEEP_ALIVE
-    } else {
-        CONNECTION_CLOSE
+/// Append `Connection` and, for an open connection, `Keep-Alive`. These are
+/// always the last headers so `with_connection` can swap them on cached
+/// responses.
+fn write_connection_headers(out: &mut String, keep_alive: KeepAlive) {
+    write_header(out, HEADER_CONNECTION, keep_alive.connection());
+    if let Some(value) = keep_alive.header_value() {
+        write_header(out, HEADER_KEEP_ALIVE, &value);
     }
 }
 
@@ -120,7 +127,7 @@ fn write_header(out: &mut String, name: &str, value: &str) {
 
 /// Combine a rendered header block with an optional body.
 fn write_response(head: ResponseHead<'_>, body: Option<&[u8]>) -> Vec<u8> {
-    let mut out = head.to_string().into_bytes();
+    let mut out = head.render().into_bytes();
     if let Some(body) = body {
         out.extend_from_slice(body);
     }
@@ -136,7 +143,7 @@ impl ResponseBuilder {
         status: &str,
         content_type: Option<&str>,
         content_length: usize,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         extra_headers: &[HeaderPair<'_>],
         body: Option<&[u8]>,
     ) -> Vec<u8> {
@@ -159,7 +166,7 @@ impl ResponseBuilder {
         status: &str,
         content_type: Option<&str>,
         body: &[u8],
-        keep_alive: bool,
+        keep_alive: KeepAlive,
     ) -> Vec<u8> {
         Self::build_with_headers(
             status,
@@ -174,29 +181,61 @@ impl ResponseBuilder {
     /// Build an HTTP response with no body payload.
     pub(crate) fn build_empty(
         status: &str,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         extra_headers: &[HeaderPair<'_>],
     ) -> Vec<u8> {
         let head = ResponseHead::new(status, None, 0, keep_alive, extra_headers);
         write_response(head, None)
     }
 
+    /// Rewrite the trailing `Connection`/`Keep-Alive` headers of a prebuilt
+    /// (e.g. cached) response so they match the keep-alive decision for the
+    /// current request.
+    pub(crate) fn with_connection(mut resp: Vec<u8>, keep_alive: KeepAlive) -> Vec<u8> {
+        let Some(head_end) = resp.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return resp;
+        };
+        let needle = format!("{CRLF}{HEADER_CONNECTION}: ");
+        let Some(start) = resp[..head_end]
+            .windows(needle.len())
+            .rposition(|w| w == needle.as_bytes())
+        else {
+            return resp;
+        };
+        let mut tail = String::from(CRLF);
+        write_connection_headers(&mut tail, keep_alive);
+        tail.truncate(tail.len() - CRLF.len());
+        if resp[start..head_end] != *tail.as_bytes() {
+            resp.splice(start..head_end, tail.bytes());
+        }
+        resp
+    }
+
     /// Build a text/plain response with UTF-8 charset.
-    pub(crate) fn plain_text(status: &str, body: &str, keep_alive: bool) -> Vec<u8> {
+    pub(crate) fn plain_text(status: &str, body: &str, keep_alive: KeepAlive) -> Vec<u8> {
         Self::build(status, Some(TEXT_PLAIN_UTF8), body.as_bytes(), keep_alive)
     }
 
-    /// Build a 404 response with a plain-text body.
-    pub(crate) fn not_found(keep_alive: bool) -> Vec<u8> {
-        Self::plain_text("404 Not Found", "404 Not Found", keep_alive)
+    /// Build an error response in the location's error format.
+    pub(crate) fn error(status: &str, keep_alive: KeepAlive, format: ErrorFormat) -> Vec<u8> {
+        match format {
+            ErrorFormat::Text => Self::plain_text(status, status, keep_alive),
+            ErrorFormat::Json => Self::build(
+                status,
+                Some(APPLICATION_JSON),
+                json_error_body(status).as_bytes(),
+                keep_alive,
+            ),
+        }
     }
 
-    /// Build a 500 response with a plain-text body.
-    pub(crate) fn internal_error(keep_alive: bool) -> Vec<u8> {
-        Self::plain_text(
-            "500 Internal Server Error",
-            "500 Internal Server Error",
-            keep_alive,
-        )
+    /// Build a 404 response.
+    pub(crate) fn not_found(keep_alive: KeepAlive, format: ErrorFormat) -> Vec<u8> {
+        Self::error("404 Not Found", keep_alive, format)
+    }
+
+    /// Build a 500 response.
+    pub(crate) fn internal_error(keep_alive: KeepAlive, format: ErrorFormat) -> Vec<u8> {
+        Self::error("500 Internal Server Error", keep_alive, format)
     }
 }
@@ -1,43 +1,83 @@
 use httpdate::fmt_http_date;
-use mime_guess::mime;
+use std::io::SeekFrom;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::fs as tokio_fs;
-use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
-use migux_config::{HttpConfig, LocationConfig, ServerConfig};
+use migux_config::{
+    AcceptRanges, ErrorFormat, HttpConfig, LocationConfig, LocationType, ServerConfig,
+};
+use migux_http::keep_alive::KeepAlive;
 
+use crate::archive::{Archive, ArchiveEntry};
 use crate::cache::{
-    CacheKey, CachePolicy, DiskCache, MemoryCache, build_cache_key, cache_metrics_snapshot,
+    CacheEvent, CacheKey, CachePolicy, CacheState, DiskCache, MemoryCache, MissFill, RefreshGuard,
+    RouteIndex, StaleWindows, build_cache_key, cache_control_ttl, cache_metrics_snapshot,
+};
+use crate::conditional::{
+    should_return_not_modified, should_return_not_modified_if_modified_since,
+};
+use crate::etag::{
+    EtagInfo, last_modified_header, last_modified_system_time, weak_etag, weak_etag_size_mtime,
 };
-use crate::conditional::{should_return_not_modified, should_return_not_modified_if_modified_since};
-use crate::etag::{EtagInfo, last_modified_header, last_modified_system_time, weak_etag_size_mtime};
 use crate::fs::PathResolver;
+use crate::range::{ByteRange, evaluate_range};
 use crate::response::ResponseBuilder;
 
 struct StaticService<'a> {
     server_cfg: &'a ServerConfig,
     location: &'a LocationConfig,
+    /// How long a streamed body may wait on one chunk write.
+    write_timeout: Duration,
+    /// `charset` parameter for text content types; `None` when `off`.
+    charset: Option<String>,
 }
 
+#[derive(Clone)]
 struct StaticFileInfo {
     content_length: usize,
     etag: EtagInfo,
     last_modified: Option<String>,
     /// File mtime for If-Modified-Since comparison.
     file_mtime: Option<SystemTime>,
+    /// `Cache-Control` from a `.httpheaders` sidecar or the location config.
+    cache_control: Option<String>,
 }
 
+#[derive(Clone)]
 struct ResolvedFile {
     path: String,
     len: u64,
     info: StaticFileInfo,
     content_type: String,
+    /// Location serves byte ranges (`accept_ranges = "bytes"`).
+    accept_ranges: bool,
+    source: FileSource,
+    /// `<file>.httpheaders` not read yet; see [`ResolvedFile::load_sidecar`].
+    sidecar: Option<String>,
+}
+
+/// Where a resolved file's bytes come from.
+#[derive(Clone)]
+enum FileSource {
+    /// `path` on disk.
+    Disk,
+    /// An entry of an archive location's archive; `path` only labels it
+    /// (`<archive>!/<entry>`) for logs and cache keys.
+    Archive(Arc<Archive>, Box<ArchiveEntry>),
 }
 
 const DEFAULT_STREAM_THRESHOLD_BYTES: u64 = 1024 * 1024;
+/// Bytes read from disk and written to the client per streaming step.
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// How long a cache miss waits for a concurrent read of the same file
+/// before reading it itself.
+pub(crate) const COALESCE_WAIT: Duration = Duration::from_secs(5);
 
 enum FileResolution {
-    File(ResolvedFile),
+    File(Box<ResolvedFile>),
     Response(Vec<u8>),
 }
 
@@ -52,6 +92,7 @@ impl StaticFileInfo {
             etag,
             last_modified,
             file_mtime,
+            cache_control: None,
         }
     }
 }
@@ -63,12 +104,40 @@ impl ResolvedFile {
         if let Some(last_modified) = self.info.last_modified.as_deref() {
             headers.push(("Last-Modified", last_modified));
         }
+        if let Some(cache_control) = self.info.cache_control.as_deref() {
+            headers.push(("Cache-Control", cache_control));
+        }
+        if self.accept_ranges {
+            headers.push(("Accept-Ranges", "bytes"));
+        }
         if let Some(hsts_value) = hsts {
             headers.push(("Strict-Transport-Security", hsts_value));
         }
         headers
     }
 
+    /// Take `Cache-Control` from the file's `.httpheaders` sidecar, if it
+    /// has one. Deferred until a response is built from the file, so
+    /// memory cache hits don't touch the filesystem for it.
+    async fn load_sidecar(&mut self) {
+        let Some(sidecar) = self.sidecar.take() else {
+            return;
+        };
+        if let Ok(contents) = tokio_fs::read_to_string(&sidecar).await
+            && let Some(value) = sidecar_header(&contents, "cache-control")
+        {
+            self.info.cache_control = Some(value);
+        }
+    }
+
+    /// The whole body, inflated for compressed archive entries.
+    async fn read(&self) -> std::io::Result<Vec<u8>> {
+        match &self.source {
+            FileSource::Disk => tokio_fs::read(&self.path).await,
+            FileSource::Archive(archive, entry) => archive.read_async(entry).await,
+        }
+    }
+
     fn cache_key(&self, hsts: Option<&str>) -> CacheKey {
         let hsts_flag = hsts.is_some();
         build_cache_key(
@@ -80,13 +149,16 @@ impl ResolvedFile {
     }
 }
 
-fn build_not_modified(info: &StaticFileInfo, keep_alive: bool, hsts: Option<&str>) -> Vec<u8> {
+fn build_not_modified(info: &StaticFileInfo, keep_alive: KeepAlive, hsts: Option<&str>) -> Vec<u8> {
     let date = fmt_http_date(SystemTime::now());
     let mut headers = Vec::new();
     headers.push(("ETag", info.etag.header.as_str()));
     if let Some(last_modified) = info.last_modified.as_deref() {
         headers.push(("Last-Modified", last_modified));
     }
+    if let Some(cache_control) = info.cache_control.as_deref() {
+        headers.push(("Cache-Control", cache_control));
+    }
     if let Some(hsts_value) = hsts {
         headers.push(("Strict-Transport-Security", hsts_value));
     }
@@ -94,39 +166,52 @@ fn build_not_modified(info: &StaticFileInfo, keep_alive: bool, hsts: Option<&str
     ResponseBuilder::build_with_headers("304 Not Modified", None, 0, keep_alive, &headers, None)
 }
 
-fn content_type_for_path(path: &str) -> String {
-    if let Some(ext) = std::path::Path::new(path)
+/// Find a header value in a `.httpheaders` sidecar (`Name: value` per line).
+pub(crate) fn sidecar_header(contents: &str, name: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        let value = value.trim();
+        (key.trim().eq_ignore_ascii_case(name) && !value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Content type for `path`; text types and JSON get `; charset=<charset>`
+/// unless `charset` is `None` (`charset = "off"`).
+fn content_type_for_path(path: &str, charset: Option<&str>) -> String {
+    let essence = std::path::Path::new(path)
         .extension()
         .and_then(|s| s.to_str())
-    {
-        if let Some(ct) = fast_content_type(ext) {
-            return ct.to_string();
+        .and_then(fast_content_type)
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .essence_str()
+                .to_string()
+        });
+    match charset {
+        Some(charset) if essence.starts_with("text/") || essence == "application/json" => {
+            format!("{essence}; charset={charset}")
         }
-    }
-
-    let mime = mime_guess::from_path(path).first_or_octet_stream();
-    if mime.type_() == mime::TEXT {
-        format!("{}; charset=utf-8", mime.essence_str())
-    } else {
-        mime.essence_str().to_string()
+        _ => essence,
     }
 }
 
 fn fast_content_type(ext: &str) -> Option<&'static str> {
     if ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm") {
-        return Some("text/html; charset=utf-8");
+        return Some("text/html");
     }
     if ext.eq_ignore_ascii_case("css") {
-        return Some("text/css; charset=utf-8");
+        return Some("text/css");
     }
     if ext.eq_ignore_ascii_case("js") || ext.eq_ignore_ascii_case("mjs") {
-        return Some("text/javascript; charset=utf-8");
+        return Some("text/javascript");
     }
     if ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("map") {
-        return Some("application/json; charset=utf-8");
+        return Some("application/json");
     }
     if ext.eq_ignore_ascii_case("txt") {
-        return Some("text/plain; charset=utf-8");
+        return Some("text/plain");
     }
     if ext.eq_ignore_ascii_case("svg") {
         return Some("image/svg+xml");
@@ -158,21 +243,200 @@ fn fast_content_type(ext: &str) -> Option<&'static str> {
     None
 }
 
-async fn read_body(path: &str, keep_alive: bool) -> Result<Vec<u8>, Vec<u8>> {
-    match tokio_fs::read(path).await {
+/// `Cache-Control` sent by `force_revalidate` locations.
+const FORCE_REVALIDATE: &str = "max-age=0, must-revalidate";
+
+/// Effective cache TTL.
+///
+/// With `respect_origin_cache_control`, a `max-age` on the file's
+/// `Cache-Control` wins (not under `force_revalidate`, whose header is
+/// meant for clients only). Otherwise: location override, else the
+/// `cache_type_ttls` entry for the file's content type, else the http
+/// default. Either way clamped by `cache_max_ttl_secs`.
+fn cache_ttl_secs(http_cfg: &HttpConfig, location: &LocationConfig, file: &ResolvedFile) -> u64 {
+    let origin_ttl = (location.respect_origin_cache_control() && !location.force_revalidate())
+        .then(|| {
+            file.info
+                .cache_control
+                .as_deref()
+                .and_then(cache_control_ttl)
+        })
+        .flatten();
+    let ttl_secs = origin_ttl.unwrap_or_else(|| {
+        location
+            .cache_ttl_secs()
+            .or_else(|| http_cfg.cache_type_ttl_secs(&file.content_type))
+            .unwrap_or(http_cfg.cache_default_ttl_secs().unwrap_or(0) as u64)
+    });
+    match http_cfg.cache_max_ttl_secs().filter(|v| *v > 0) {
+        Some(max_ttl) => ttl_secs.min(max_ttl),
+        None => ttl_secs,
+    }
+}
+
+/// Re-read a stale entry in the background (stale-while-revalidate).
+///
+/// At most one refresh per cache key runs at a time; on failure (the file
+/// is gone or unreadable) the stale entry is left in place until its
+/// windows run out. A file that changed
+/// since it was cached has a new key, so the old entry is dropped instead
+/// and the next request for the file caches it under the new one.
+fn spawn_refresh(
+    http_cfg: &HttpConfig,
+    file: &ResolvedFile,
+    key: CacheKey,
+    ttl: Duration,
+    keep_alive: KeepAlive,
+    hsts: Option<&str>,
+) {
+    let Some(guard) = RefreshGuard::acquire(key) else {
+        return;
+    };
+    let http_cfg = http_cfg.clone();
+    let file = file.clone();
+    let hsts = hsts.map(str::to_string);
+
+    tokio::spawn(async move {
+        let _guard = guard;
+        CacheEvent::Revalidation.record();
+        // Archives are indexed once, so their entries never change.
+        let unchanged = match file.source {
+            FileSource::Archive(..) => true,
+            FileSource::Disk => match tokio_fs::metadata(&file.path).await {
+                Ok(meta) => {
+                    meta.len() == file.len
+                        && weak_etag_size_mtime(&meta).mtime_nanos == file.info.etag.mtime_nanos
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: "migux::static_cache",
+                        cache_key = %key,
+                        path = %file.path,
+                        error = %e,
+                        "Background cache refresh failed; keeping stale entry"
+                    );
+                    return;
+                }
+            },
+        };
+        if !unchanged {
+            if let Some(cache_dir) = http_cfg.cache_dir() {
+                DiskCache::new(cache_dir).remove(&http_cfg, key).await;
+            }
+            MemoryCache::remove(key);
+            tracing::debug!(
+                target: "migux::static_cache",
+                cache_key = %key,
+                path = %file.path,
+                "File changed since it was cached; dropped stale entry"
+            );
+            return;
+        }
+        CacheEvent::Revalidation304.record();
+        let body = match file.read().await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!(
+                    target: "migux::static_cache",
+                    cache_key = %key,
+                    path = %file.path,
+                    error = %e,
+                    "Background cache refresh failed; keeping stale entry"
+                );
+                return;
+            }
+        };
+
+        let stale = StaleWindows::from_config(&http_cfg);
+        let extra_headers = file.static_headers(hsts.as_deref());
+        let resp = ResponseBuilder::build_with_headers(
+            "200 OK",
+            Some(file.content_type.as_str()),
+            body.len(),
+            keep_alive,
+            &extra_headers,
+            Some(&body),
+        );
+
+        store_cached(&http_cfg, key, resp, ttl, stale).await;
+        tracing::debug!(target: "migux::static_cache", cache_key = %key, "Refreshed stale entry");
+    });
+}
+
+/// Put a response in the memory cache and, with a `cache_dir`, on disk.
+async fn store_cached(
+    http_cfg: &HttpConfig,
+    key: CacheKey,
+    resp: Vec<u8>,
+    ttl: Duration,
+    stale: StaleWindows,
+) {
+    MemoryCache::put(key, resp.clone(), ttl, stale);
+    if let Some(cache_dir) = http_cfg.cache_dir() {
+        DiskCache::new(cache_dir)
+            .put(http_cfg, key, &resp, ttl, stale)
+            .await;
+    }
+}
+
+/// The entry `route` was last served from, while it is still inside its
+/// stale windows. Only with a `stale-if-error` window configured.
+async fn route_stale(http_cfg: &HttpConfig, route: CacheKey) -> Option<(CacheKey, Vec<u8>)> {
+    if StaleWindows::from_config(http_cfg).if_error.is_zero() {
+        return None;
+    }
+    let key = RouteIndex::last(route)?;
+    if let Some(hit) = MemoryCache::get(key) {
+        return Some((key, hit.response));
+    }
+    let cache_dir = http_cfg.cache_dir()?;
+    let hit = DiskCache::new(cache_dir).get(http_cfg, key).await?;
+    Some((key, hit.response))
+}
+
+/// `stale_resp` served in place of an error from the origin.
+fn stale_on_error(
+    stale_resp: Vec<u8>,
+    key: CacheKey,
+    path: &str,
+    keep_alive: KeepAlive,
+) -> Vec<u8> {
+    tracing::warn!(
+        target: "migux::static_cache",
+        cache_key = %key,
+        path = %path,
+        "Serving stale response after read error (stale-if-error)"
+    );
+    CacheEvent::StaleServed.record();
+    ResponseBuilder::with_connection(stale_resp, keep_alive)
+}
+
+async fn read_body(
+    file: &ResolvedFile,
+    keep_alive: KeepAlive,
+    format: ErrorFormat,
+) -> Result<Vec<u8>, Vec<u8>> {
+    match file.read().await {
         Ok(body) => Ok(body),
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            Err(ResponseBuilder::not_found(keep_alive))
+            Err(ResponseBuilder::not_found(keep_alive, format))
         }
-        Err(_) => Err(ResponseBuilder::internal_error(keep_alive)),
+        Err(_) => Err(ResponseBuilder::internal_error(keep_alive, format)),
     }
 }
 
 impl<'a> StaticService<'a> {
-    fn new(server_cfg: &'a ServerConfig, location: &'a LocationConfig) -> Self {
+    /// The write timeout and default charset come from `http_cfg`.
+    fn new(
+        http_cfg: &HttpConfig,
+        server_cfg: &'a ServerConfig,
+        location: &'a LocationConfig,
+    ) -> Self {
         Self {
             server_cfg,
             location,
+            write_timeout: Duration::from_secs(http_cfg.client_write_timeout_secs()),
+            charset: location.charset_or(http_cfg.charset()).map(str::to_string),
         }
     }
 
@@ -182,217 +446,332 @@ impl<'a> StaticService<'a> {
         method: &str,
         headers: &str,
         req_path: &str,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
     ) -> anyhow::Result<()>
     where
         S: AsyncWrite + Unpin + ?Sized,
     {
-        self.serve_uncached(stream, None, method, headers, req_path, keep_alive, hsts)
-            .await
+        self.serve_file(stream, None, method, headers, req_path, keep_alive, hsts)
+            .await?;
+        Ok(())
     }
 
-    async fn serve_uncached<S>(
+    #[allow(clippy::too_many_arguments)]
+    async fn serve_cached<S>(
         &self,
         stream: &mut S,
-        http_cfg: Option<&HttpConfig>,
+        http_cfg: &HttpConfig,
         method: &str,
         headers: &str,
         req_path: &str,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
     ) -> anyhow::Result<()>
     where
         S: AsyncWrite + Unpin + ?Sized,
     {
-        let file = match self.resolve_file(req_path, keep_alive).await? {
-            FileResolution::File(file) => file,
-            FileResolution::Response(resp) => {
-                stream.write_all(&resp).await?;
-                return Ok(());
-            }
-        };
-
-        if let Some(resp) = self.not_modified_response(method, headers, &file, keep_alive, hsts) {
-            stream.write_all(&resp).await?;
-            return Ok(());
-        }
-
-        if method == "HEAD" {
-            let resp = self.head_response(&file, keep_alive, hsts);
-            stream.write_all(&resp).await?;
-            return Ok(());
-        }
-
-        let threshold = http_cfg
-            .map(stream_threshold_bytes)
-            .unwrap_or(DEFAULT_STREAM_THRESHOLD_BYTES);
-        if should_stream_file(file.len, threshold) {
-            self.stream_file_response(stream, &file, keep_alive, hsts)
-                .await?;
-            return Ok(());
-        }
-
-        let body = match read_body(&file.path, keep_alive).await {
-            Ok(body) => body,
-            Err(resp) => {
-                stream.write_all(&resp).await?;
-                return Ok(());
-            }
-        };
-        let resp = self.ok_response(&file, &body, keep_alive, hsts);
-        stream.write_all(&resp).await?;
+        let decision = self
+            .serve_file(
+                stream,
+                Some(http_cfg),
+                method,
+                headers,
+                req_path,
+                keep_alive,
+                hsts,
+            )
+            .await?;
+        // Recorded on the caller's request span (its `decision` field).
+        tracing::Span::current().record("decision", decision);
         Ok(())
     }
 
-    async fn serve_cached<S>(
+    /// The full response as bytes; large files are streamed into the buffer
+    /// exactly as they would be to a client.
+    async fn serve_bytes(
+        &self,
+        method: &str,
+        headers: &str,
+        req_path: &str,
+        keep_alive: KeepAlive,
+        hsts: Option<&str>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.serve_file(&mut out, None, method, headers, req_path, keep_alive, hsts)
+            .await?;
+        Ok(out)
+    }
+
+    /// The one serving routine behind every entry point: resolve the file,
+    /// answer conditional, `HEAD` and `Range` requests, then stream large
+    /// files or send a buffered body, from the cache when `http_cfg` enables
+    /// it for this location and method.
+    ///
+    /// Returns `cache-hit` / `cache-miss` when the cache was consulted,
+    /// `cache-warm` for a `HEAD` that loaded the file into it, and `static`
+    /// otherwise (errors, 304, ranges, streamed files, no cache).
+    #[allow(clippy::too_many_arguments)]
+    async fn serve_file<S>(
         &self,
         stream: &mut S,
-        http_cfg: &HttpConfig,
+        http_cfg: Option<&HttpConfig>,
         method: &str,
         headers: &str,
         req_path: &str,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
-    ) -> anyhow::Result<()>
+    ) -> anyhow::Result<&'static str>
     where
         S: AsyncWrite + Unpin + ?Sized,
     {
-        if !CachePolicy::enabled(http_cfg, self.location, method) {
-            return self
-                .serve_uncached(
-                    stream,
-                    Some(http_cfg),
-                    method,
-                    headers,
-                    req_path,
-                    keep_alive,
-                    hsts,
-                )
-                .await;
-        }
-
-        let file = match self.resolve_file(req_path, keep_alive).await? {
-            FileResolution::File(file) => file,
+        let cache_cfg = http_cfg.filter(|cfg| CachePolicy::enabled(cfg, self.location, method));
+        let mut file = match self.resolve_file(req_path, keep_alive).await? {
+            FileResolution::File(file) => *file,
             FileResolution::Response(resp) => {
+                // A file removed after it was cached is an origin error too.
+                if method == "GET"
+                    && let Some(cfg) = cache_cfg
+                    && let Some((key, stale_resp)) =
+                        route_stale(cfg, self.route(req_path, hsts)).await
+                {
+                    let resp = stale_on_error(stale_resp, key, req_path, keep_alive);
+                    stream.write_all(&resp).await?;
+                    return Ok("cache-miss");
+                }
                 stream.write_all(&resp).await?;
-                return Ok(());
+                return Ok("static");
             }
         };
 
-        if let Some(resp) = self.not_modified_response(method, headers, &file, keep_alive, hsts) {
+        if let Some(resp) = self
+            .not_modified_response(method, headers, &mut file, keep_alive, hsts)
+            .await
+        {
             stream.write_all(&resp).await?;
-            return Ok(());
+            return Ok("static");
         }
 
         if method == "HEAD" {
+            file.load_sidecar().await;
+            let warmed = match cache_cfg {
+                Some(cfg) => {
+                    self.warm_cache(cfg, self.route(req_path, hsts), &file, hsts)
+                        .await
+                }
+                None => false,
+            };
             let resp = self.head_response(&file, keep_alive, hsts);
             stream.write_all(&resp).await?;
-            return Ok(());
+            return Ok(if warmed { "cache-warm" } else { "static" });
+        }
+
+        if self
+            .serve_range(stream, method, headers, &mut file, keep_alive, hsts)
+            .await?
+        {
+            return Ok("static");
         }
 
-        let stream_threshold = stream_threshold_bytes(http_cfg);
-        if should_stream_file(file.len, stream_threshold) {
-            self.stream_file_response(stream, &file, keep_alive, hsts)
+        let threshold = http_cfg
+            .map(stream_threshold_bytes)
+            .unwrap_or(DEFAULT_STREAM_THRESHOLD_BYTES);
+        if should_stream_file(file.len, threshold) {
+            file.load_sidecar().await;
+            self.stream_file_response(stream, &file, None, keep_alive, hsts)
                 .await?;
-            return Ok(());
+            return Ok("static");
         }
 
-        let resp = self
-            .serve_bytes_cached_for_file(http_cfg, method, headers, file, keep_alive, hsts)
-            .await?;
+        let (resp, decision) = match cache_cfg {
+            Some(cfg) => {
+                let route = self.route(req_path, hsts);
+                self.cached_response(cfg, route, &mut file, keep_alive, hsts)
+                    .await?
+            }
+            None => {
+                file.load_sidecar().await;
+                let resp = match read_body(&file, keep_alive, self.location.error_format()).await {
+                    Ok(body) => self.ok_response(&file, &body, keep_alive, hsts),
+                    Err(resp) => resp,
+                };
+                (resp, "static")
+            }
+        };
         stream.write_all(&resp).await?;
-        Ok(())
+        Ok(decision)
     }
 
-    async fn serve_bytes(
+    /// Key of `req_path` in this location for the [`RouteIndex`].
+    fn route(&self, req_path: &str, hsts: Option<&str>) -> CacheKey {
+        RouteIndex::route(self.location, req_path, hsts.is_some())
+    }
+
+    /// Load `file` into the cache for a `HEAD` request when it is cacheable
+    /// and not cached yet, so the cache can be warmed without transferring
+    /// bodies. Returns whether the file was read.
+    async fn warm_cache(
         &self,
-        method: &str,
-        headers: &str,
-        req_path: &str,
-        keep_alive: bool,
+        http_cfg: &HttpConfig,
+        route: CacheKey,
+        file: &ResolvedFile,
         hsts: Option<&str>,
-    ) -> anyhow::Result<Vec<u8>> {
-        let file = match self.resolve_file(req_path, keep_alive).await? {
-            FileResolution::File(file) => file,
-            FileResolution::Response(resp) => return Ok(resp),
-        };
-
-        if let Some(resp) = self.not_modified_response(method, headers, &file, keep_alive, hsts) {
-            return Ok(resp);
+    ) -> bool {
+        let ttl_secs = cache_ttl_secs(http_cfg, self.location, file);
+        let max_obj = http_cfg.cache_max_object_bytes().unwrap_or(0);
+        if ttl_secs == 0
+            || file.len > max_obj
+            || should_stream_file(file.len, stream_threshold_bytes(http_cfg))
+        {
+            return false;
         }
 
-        if method == "HEAD" {
-            return Ok(self.head_response(&file, keep_alive, hsts));
+        let key = file.cache_key(hsts);
+        RouteIndex::remember(route, key);
+        let ttl = Duration::from_secs(ttl_secs);
+        let stale = StaleWindows::from_config(http_cfg);
+        if MemoryCache::get(key).is_some_and(|hit| hit.state == CacheState::Fresh) {
+            return false;
+        }
+        if let Some(cache_dir) = http_cfg.cache_dir()
+            && let Some(hit) = DiskCache::new(cache_dir).get(http_cfg, key).await
+            && hit.state == CacheState::Fresh
+        {
+            MemoryCache::put(key, hit.response, ttl, stale);
+            return false;
         }
 
-        let body = match read_body(&file.path, keep_alive).await {
-            Ok(body) => body,
-            Err(resp) => return Ok(resp),
+        let Ok(body) = file.read().await else {
+            return false;
         };
-
-        Ok(self.ok_response(&file, &body, keep_alive, hsts))
+        let resp = self.ok_response(file, &body, KeepAlive::Close, hsts);
+        store_cached(http_cfg, key, resp, ttl, stale).await;
+        CacheEvent::WarmFetch.record();
+        tracing::debug!(target: "migux::static_cache", cache_key = %key, path = %file.path, "Warmed cache from HEAD");
+        true
     }
 
-    async fn serve_bytes_cached_for_file(
+    /// Full `200` response for `file` from the memory or disk cache, reading
+    /// (and caching) the file on a miss. Stale entries are served within
+    /// their windows. The sidecar is read on anything but a fresh memory
+    /// hit. `route` is pointed at `file`'s key once it is served from, or
+    /// stored in, the cache.
+    async fn cached_response(
         &self,
         http_cfg: &HttpConfig,
-        method: &str,
-        headers: &str,
-        file: ResolvedFile,
-        keep_alive: bool,
+        route: CacheKey,
+        file: &mut ResolvedFile,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
-    ) -> anyhow::Result<Vec<u8>> {
-        if let Some(resp) = self.not_modified_response(method, headers, &file, keep_alive, hsts) {
-            return Ok(resp);
-        }
-
-        if method == "HEAD" {
-            return Ok(self.head_response(&file, keep_alive, hsts));
-        }
-
+    ) -> anyhow::Result<(Vec<u8>, &'static str)> {
         let key = file.cache_key(hsts);
+        let stale = StaleWindows::from_config(http_cfg);
+        let mut stale_fallback = None;
 
-        if let Some(resp) = MemoryCache::get(key) {
-            if let Some(cache_dir) = http_cfg.cache_dir() {
-                DiskCache::new(cache_dir).touch(http_cfg, key).await;
+        if let Some(hit) = MemoryCache::get(key) {
+            match hit.state {
+                CacheState::Fresh | CacheState::StaleWhileRevalidate => {
+                    if let Some(cache_dir) = http_cfg.cache_dir() {
+                        DiskCache::new(cache_dir).touch(http_cfg, key).await;
+                    }
+                    if hit.state == CacheState::StaleWhileRevalidate {
+                        CacheEvent::StaleServed.record();
+                        file.load_sidecar().await;
+                        let ttl =
+                            Duration::from_secs(cache_ttl_secs(http_cfg, self.location, file));
+                        spawn_refresh(http_cfg, file, key, ttl, keep_alive, hsts);
+                    }
+                    RouteIndex::remember(route, key);
+                    return Ok((
+                        ResponseBuilder::with_connection(hit.response, keep_alive),
+                        "cache-hit",
+                    ));
+                }
+                _ => stale_fallback = Some(hit.response),
             }
-            return Ok(resp);
         }
 
-        let max_obj = http_cfg.cache_max_object_bytes().unwrap_or(0);
-        let mut ttl_secs = http_cfg.cache_default_ttl_secs().unwrap_or(0) as u64;
-        if let Some(max_ttl) = http_cfg.cache_max_ttl_secs().filter(|v| *v > 0) {
-            ttl_secs = ttl_secs.min(max_ttl);
-        }
+        file.load_sidecar().await;
+        let file = &*file;
+        let ttl_secs = cache_ttl_secs(http_cfg, self.location, file);
         let ttl = Duration::from_secs(ttl_secs);
+        let max_obj = http_cfg.cache_max_object_bytes().unwrap_or(0);
 
-        if let Some(cache_dir) = http_cfg.cache_dir() {
+        if stale_fallback.is_none()
+            && let Some(cache_dir) = http_cfg.cache_dir()
+        {
             let disk_cache = DiskCache::new(cache_dir);
-            if let Some(resp) = disk_cache.get(http_cfg, key).await {
-                if ttl_secs > 0 {
-                    MemoryCache::put(key, resp.clone(), ttl);
+            if let Some(hit) = disk_cache.get(http_cfg, key).await {
+                match hit.state {
+                    CacheState::Fresh => {
+                        if ttl_secs > 0 {
+                            MemoryCache::put(key, hit.response.clone(), ttl, stale);
+                        }
+                        RouteIndex::remember(route, key);
+                        return Ok((
+                            ResponseBuilder::with_connection(hit.response, keep_alive),
+                            "cache-hit",
+                        ));
+                    }
+                    CacheState::StaleWhileRevalidate => {
+                        CacheEvent::StaleServed.record();
+                        spawn_refresh(http_cfg, file, key, ttl, keep_alive, hsts);
+                        RouteIndex::remember(route, key);
+                        return Ok((
+                            ResponseBuilder::with_connection(hit.response, keep_alive),
+                            "cache-hit",
+                        ));
+                    }
+                    _ => stale_fallback = Some(hit.response),
                 }
-                return Ok(resp);
             }
         }
 
         tracing::debug!(target: "migux::static_cache", cache_key = %key, "Cache miss");
 
-        let body = match read_body(&file.path, keep_alive).await {
+        // Single-flight: one request reads a cacheable file, concurrent
+        // misses wait for it and serve what it stored. A leader that fails,
+        // or takes longer than COALESCE_WAIT, leaves them to read it too.
+        let cacheable = max_obj > 0 && file.len <= max_obj && ttl_secs > 0;
+        let _fill = match cacheable.then(|| MissFill::claim(key)) {
+            Some(MissFill::Leader(guard)) => Some(guard),
+            Some(MissFill::Follower(lock)) => {
+                MissFill::wait(lock, COALESCE_WAIT).await;
+                None
+            }
+            None => None,
+        };
+        // Also covers a fill that finished between the lookups and the claim.
+        if cacheable && let Some(resp) = MemoryCache::fresh(key) {
+            RouteIndex::remember(route, key);
+            return Ok((
+                ResponseBuilder::with_connection(resp, keep_alive),
+                "cache-hit",
+            ));
+        }
+
+        let body = match read_body(file, keep_alive, self.location.error_format()).await {
             Ok(body) => body,
-            Err(resp) => return Ok(resp),
+            Err(resp) => {
+                // The route's previous entry when the file changed (new key)
+                // and can't be read.
+                let stale_hit = match stale_fallback {
+                    Some(stale_resp) => Some((key, stale_resp)),
+                    None => route_stale(http_cfg, route).await,
+                };
+                if let Some((stale_key, stale_resp)) = stale_hit {
+                    let resp = stale_on_error(stale_resp, stale_key, &file.path, keep_alive);
+                    return Ok((resp, "cache-miss"));
+                }
+                return Ok((resp, "cache-miss"));
+            }
         };
 
-        let resp = self.ok_response(&file, &body, keep_alive, hsts);
+        let resp = self.ok_response(file, &body, keep_alive, hsts);
 
         if max_obj > 0 && (body.len() as u64) <= max_obj && ttl_secs > 0 {
-            MemoryCache::put(key, resp.clone(), ttl);
-            if let Some(cache_dir) = http_cfg.cache_dir() {
-                DiskCache::new(cache_dir)
-                    .put(http_cfg, key, &resp, ttl)
-                    .await;
-            }
+            store_cached(http_cfg, key, resp.clone(), ttl, stale).await;
+            RouteIndex::remember(route, key);
             let metrics = cache_metrics_snapshot().await;
             tracing::debug!(
                 target: "migux::static_cache",
@@ -428,78 +807,167 @@ impl<'a> StaticService<'a> {
             );
         }
 
-        Ok(resp)
+        Ok((resp, "cache-miss"))
     }
 
     async fn resolve_file(
         &self,
         req_path: &str,
-        keep_alive: bool,
+        keep_alive: KeepAlive,
     ) -> anyhow::Result<FileResolution> {
-        let root = self.location.root_or(self.server_cfg.root());
+        let roots = self.location.roots_or(self.server_cfg.root());
         let index = self.location.index_or(self.server_cfg.index());
 
-        let rel = PathResolver::resolve_relative_path(req_path, &self.location.path, index);
+        // Hidden dotfiles get the same 404 as missing files, so their
+        // existence is not confirmed.
+        let rel = PathResolver::resolve_relative_path(req_path, &self.location.path, index)
+            .filter(|rel| !PathResolver::is_hidden(self.location, rel));
         let Some(rel) = rel else {
             return Ok(FileResolution::Response(ResponseBuilder::not_found(
                 keep_alive,
+                self.location.error_format(),
             )));
         };
+        if matches!(self.location.r#type, LocationType::Archive) {
+            return Ok(self.resolve_archive_entry(&rel, keep_alive).await);
+        }
 
-        let file_path = format!("{}/{}", root, rel);
-
-        let metadata = match tokio_fs::metadata(&file_path).await {
-            Ok(meta) => meta,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                return Ok(FileResolution::Response(ResponseBuilder::not_found(
-                    keep_alive,
-                )));
-            }
-            Err(_) => {
-                return Ok(FileResolution::Response(ResponseBuilder::internal_error(
-                    keep_alive,
-                )));
+        // Try each root in order; 404 only if none has a regular file.
+        // This also covers index-derived paths: an index name that is a
+        // directory, FIFO or device is skipped before anything opens it
+        // (opening a FIFO would block until a writer shows up).
+        let mut io_error = false;
+        let mut found = None;
+        for root in &roots {
+            let Some(file_path) = PathResolver::join_root(root, &rel) else {
+                continue;
+            };
+            match tokio_fs::metadata(&file_path).await {
+                Ok(meta) if meta.is_file() => {
+                    found = Some((file_path, meta));
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(_) => io_error = true,
             }
-        };
-
-        if !metadata.is_file() {
-            return Ok(FileResolution::Response(ResponseBuilder::not_found(
-                keep_alive,
-            )));
         }
 
-        let info = StaticFileInfo::from_metadata(&metadata);
-        let content_type = content_type_for_path(&file_path);
+        let Some((file_path, metadata)) = found else {
+            let resp = if io_error {
+                ResponseBuilder::internal_error(keep_alive, self.location.error_format())
+            } else {
+                ResponseBuilder::not_found(keep_alive, self.location.error_format())
+            };
+            return Ok(FileResolution::Response(resp));
+        };
+
+        let mut info = StaticFileInfo::from_metadata(&metadata);
+        info.cache_control = self.configured_cache_control();
+        let content_type = content_type_for_path(&file_path, self.charset.as_deref());
         let len = metadata.len();
+        let sidecar = self
+            .respects_sidecar()
+            .then(|| format!("{file_path}.httpheaders"));
 
-        Ok(FileResolution::File(ResolvedFile {
+        Ok(FileResolution::File(Box::new(ResolvedFile {
             path: file_path,
             len,
             info,
             content_type,
+            accept_ranges: self.location.accept_ranges() == AcceptRanges::Bytes,
+            source: FileSource::Disk,
+            sidecar,
+        })))
+    }
+
+    /// Look `rel` up in the location's archive. Entry metadata stands in
+    /// for file metadata; a `<rel>.httpheaders` entry is the sidecar.
+    async fn resolve_archive_entry(&self, rel: &str, keep_alive: KeepAlive) -> FileResolution {
+        let format = self.location.error_format();
+        let archive = match Archive::get(self.location.archive().unwrap_or_default()) {
+            Ok(archive) => archive,
+            Err(e) => {
+                tracing::error!(
+                    target: "migux::static",
+                    archive = ?self.location.archive(),
+                    error = %e,
+                    "Cannot open archive"
+                );
+                return FileResolution::Response(ResponseBuilder::internal_error(
+                    keep_alive, format,
+                ));
+            }
+        };
+        let Some(entry) = archive.entry(rel).cloned() else {
+            return FileResolution::Response(ResponseBuilder::not_found(keep_alive, format));
+        };
+
+        let mut sidecar = None;
+        if self.respects_sidecar()
+            && let Some(sidecar_entry) = archive.entry(&format!("{rel}.httpheaders"))
+            && let Ok(contents) = archive.read_async(sidecar_entry).await
+        {
+            sidecar = sidecar_header(&String::from_utf8_lossy(&contents), "cache-control");
+        }
+        let info = StaticFileInfo {
+            content_length: usize::try_from(entry.len).unwrap_or(usize::MAX),
+            etag: weak_etag(entry.len, Some(entry.mtime)),
+            last_modified: Some(fmt_http_date(entry.mtime)),
+            file_mtime: Some(entry.mtime),
+            cache_control: sidecar.or_else(|| self.configured_cache_control()),
+        };
+        FileResolution::File(Box::new(ResolvedFile {
+            path: format!("{}!/{rel}", archive.path().display()),
+            len: entry.len,
+            info,
+            content_type: content_type_for_path(rel, self.charset.as_deref()),
+            accept_ranges: self.location.accept_ranges() == AcceptRanges::Bytes,
+            source: FileSource::Archive(archive, Box::new(entry)),
+            sidecar: None,
         }))
     }
 
-    fn not_modified_response(
+    /// Whether a file's `<file>.httpheaders` sidecar overrides the
+    /// location's `cache_control` (`respect_origin_cache_control`).
+    fn respects_sidecar(&self) -> bool {
+        self.location.respect_origin_cache_control() && !self.location.force_revalidate()
+    }
+
+    /// `Cache-Control` from the location: `force_revalidate` wins over
+    /// `cache_control`.
+    fn configured_cache_control(&self) -> Option<String> {
+        if self.location.force_revalidate() {
+            return Some(FORCE_REVALIDATE.to_string());
+        }
+        self.location.cache_control().map(str::to_string)
+    }
+
+    async fn not_modified_response(
         &self,
         method: &str,
         headers: &str,
-        file: &ResolvedFile,
-        keep_alive: bool,
+        file: &mut ResolvedFile,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
     ) -> Option<Vec<u8>> {
         // RFC 7232: If-None-Match takes precedence; if present and matching, return 304.
-        if should_return_not_modified(method, headers, &file.info.etag.value) {
-            return Some(build_not_modified(&file.info, keep_alive, hsts));
-        }
         // If-Modified-Since: return 304 when file not modified since the given date.
-        if should_return_not_modified_if_modified_since(method, headers, file.info.file_mtime) {
-            return Some(build_not_modified(&file.info, keep_alive, hsts));
+        let not_modified = should_return_not_modified(method, headers, &file.info.etag.value)
+            || should_return_not_modified_if_modified_since(method, headers, file.info.file_mtime);
+        if !not_modified {
+            return None;
         }
-        None
+        file.load_sidecar().await;
+        Some(build_not_modified(&file.info, keep_alive, hsts))
     }
 
-    fn head_response(&self, file: &ResolvedFile, keep_alive: bool, hsts: Option<&str>) -> Vec<u8> {
+    fn head_response(
+        &self,
+        file: &ResolvedFile,
+        keep_alive: KeepAlive,
+        hsts: Option<&str>,
+    ) -> Vec<u8> {
         let extra_headers = file.static_headers(hsts);
         ResponseBuilder::build_with_headers(
             "200 OK",
@@ -515,7 +983,7 @@ impl<'a> StaticService<'a> {
         &self,
         file: &ResolvedFile,
         body: &[u8],
-        keep_alive: bool,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
     ) -> Vec<u8> {
         let extra_headers = file.static_headers(hsts);
@@ -529,43 +997,186 @@ impl<'a> StaticService<'a> {
         )
     }
 
+    /// Answer a `Range` request (206 or 416); returns `false` when the full
+    /// body should be sent instead.
+    async fn serve_range<S>(
+        &self,
+        stream: &mut S,
+        method: &str,
+        headers: &str,
+        file: &mut ResolvedFile,
+        keep_alive: KeepAlive,
+        hsts: Option<&str>,
+    ) -> anyhow::Result<bool>
+    where
+        S: AsyncWrite + Unpin + ?Sized,
+    {
+        if !file.accept_ranges {
+            return Ok(false);
+        }
+        let range = evaluate_range(
+            method,
+            headers,
+            file.len,
+            file.info.last_modified.as_deref(),
+        );
+        if range != ByteRange::Full {
+            file.load_sidecar().await;
+        }
+        match range {
+            ByteRange::Full => Ok(false),
+            ByteRange::Partial { start, end } => {
+                self.stream_file_response(stream, file, Some((start, end)), keep_alive, hsts)
+                    .await?;
+                Ok(true)
+            }
+            ByteRange::Unsatisfiable => {
+                let content_range = format!("bytes */{}", file.len);
+                let mut extra_headers = file.static_headers(hsts);
+                extra_headers.push(("Content-Range", content_range.as_str()));
+                let resp = ResponseBuilder::build_with_headers(
+                    "416 Range Not Satisfiable",
+                    None,
+                    0,
+                    keep_alive,
+                    &extra_headers,
+                    None,
+                );
+                stream.write_all(&resp).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Stream the file (or the inclusive byte `range` of it, as 206).
     async fn stream_file_response<S>(
         &self,
         stream: &mut S,
         file: &ResolvedFile,
-        keep_alive: bool,
+        range: Option<(u64, u64)>,
+        keep_alive: KeepAlive,
         hsts: Option<&str>,
     ) -> anyhow::Result<()>
     where
         S: AsyncWrite + Unpin + ?Sized,
     {
-        let mut handle = match tokio_fs::File::open(&file.path).await {
-            Ok(file) => file,
+        let start = range.map_or(0, |(start, _)| start);
+        let mut body = match self.open_body(file, start).await {
+            Ok(body) => body,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                let resp = ResponseBuilder::not_found(keep_alive);
+                let resp = ResponseBuilder::not_found(keep_alive, self.location.error_format());
                 stream.write_all(&resp).await?;
                 return Ok(());
             }
             Err(_) => {
-                let resp = ResponseBuilder::internal_error(keep_alive);
+                let resp =
+                    ResponseBuilder::internal_error(keep_alive, self.location.error_format());
                 stream.write_all(&resp).await?;
                 return Ok(());
             }
         };
 
-        let extra_headers = file.static_headers(hsts);
+        let mut extra_headers = file.static_headers(hsts);
+        let content_range;
+        let (status, len) = match range {
+            Some((start, end)) => {
+                content_range = format!("bytes {start}-{end}/{}", file.len);
+                extra_headers.push(("Content-Range", content_range.as_str()));
+                ("206 Partial Content", end - start + 1)
+            }
+            None => ("200 OK", file.len),
+        };
         let head = ResponseBuilder::build_with_headers(
-            "200 OK",
+            status,
             Some(file.content_type.as_str()),
-            file.info.content_length,
+            usize::try_from(len).unwrap_or(usize::MAX),
             keep_alive,
             &extra_headers,
             None,
         );
         stream.write_all(&head).await?;
-        io::copy(&mut handle, stream).await?;
+
+        // Send exactly the advertised Content-Length, even if the file grew.
+        let copied = copy_with_write_timeout(&mut body, stream, len, self.write_timeout)
+            .await
+            .map_err(|e| anyhow::anyhow!("streaming static file '{}': {e}", file.path))?;
+        if copied < len {
+            anyhow::bail!(
+                "static file '{}' shrank while streaming ({copied} of {len} bytes)",
+                file.path,
+            );
+        }
         Ok(())
     }
+
+    /// A reader over `file` positioned at `start`. Archive entries are
+    /// inflated in chunks as the client takes them.
+    async fn open_body(
+        &self,
+        file: &ResolvedFile,
+        start: u64,
+    ) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+        match &file.source {
+            FileSource::Disk => {
+                let mut handle = tokio_fs::File::open(&file.path).await?;
+                handle.seek(SeekFrom::Start(start)).await?;
+                Ok(Box::new(handle))
+            }
+            FileSource::Archive(archive, entry) => {
+                Ok(Box::new(archive.stream(entry, start).await?))
+            }
+        }
+    }
+}
+
+/// Copy up to `len` bytes of `file` to `stream` in bounded chunks, giving
+/// up when the client accepts no bytes at all for `write_timeout`. The
+/// deadline restarts on every partial write, so a slow but moving client
+/// is not cut off.
+async fn copy_with_write_timeout<R, S>(
+    file: &mut R,
+    stream: &mut S,
+    len: u64,
+    write_timeout: Duration,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![
+        0u8;
+        usize::try_from(len)
+            .map_or(STREAM_CHUNK_BYTES, |len| len.min(STREAM_CHUNK_BYTES))
+    ];
+    let mut copied = 0u64;
+    while copied < len {
+        let want = usize::try_from(len - copied).map_or(buf.len(), |rest| rest.min(buf.len()));
+        let n = file.read(&mut buf[..want]).await?;
+        if n == 0 {
+            break;
+        }
+        let mut pending = &buf[..n];
+        while !pending.is_empty() {
+            let written = match tokio::time::timeout(write_timeout, stream.write(pending)).await {
+                Ok(written) => written?,
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "client stalled for {}s after {copied} bytes",
+                            write_timeout.as_secs()
+                        ),
+                    ));
+                }
+            };
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            pending = &pending[written..];
+            copied += written as u64;
+        }
+    }
+    Ok(copied)
 }
 
 fn stream_threshold_bytes(http_cfg: &HttpConfig) -> u64 {
@@ -587,25 +1198,28 @@ fn should_stream_file(len: u64, threshold: u64) -> bool {
 }
 
 /// Serve a static file directly to the client stream.
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_static<S>(
     stream: &mut S,
+    http_cfg: &HttpConfig,
     server_cfg: &ServerConfig,
     location: &LocationConfig,
     method: &str,
     headers: &str,
     req_path: &str,
-    keep_alive: bool,
+    keep_alive: KeepAlive,
     hsts: Option<&str>,
 ) -> anyhow::Result<()>
 where
     S: AsyncWrite + Unpin + ?Sized,
 {
-    StaticService::new(server_cfg, location)
+    StaticService::new(http_cfg, server_cfg, location)
         .serve(stream, method, headers, req_path, keep_alive, hsts)
         .await
 }
 
 /// Serve a static file using cache when enabled.
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_static_cached<S>(
     stream: &mut S,
     http_cfg: &HttpConfig,
@@ -614,30 +1228,1260 @@ pub async fn serve_static_cached<S>(
     method: &str,
     headers: &str,
     req_path: &str,
-    keep_alive: bool,
+    keep_alive: KeepAlive,
     hsts: Option<&str>,
 ) -> anyhow::Result<()>
 where
     S: AsyncWrite + Unpin + ?Sized,
 {
-    StaticService::new(server_cfg, location)
+    StaticService::new(http_cfg, server_cfg, location)
         .serve_cached(
             stream, http_cfg, method, headers, req_path, keep_alive, hsts,
         )
         .await
 }
 
-/// Read a static file and return a full HTTP response.
+/// Serve `path` with `status` in place of a failed response (a proxy
+/// location's `fallback_static`). Returns `false`, having written nothing,
+/// when the file can't be read so the caller can send its own error.
+/// `charset` is the location's (see [`LocationConfig::charset_or`]).
+pub async fn serve_static_fallback<S>(
+    stream: &mut S,
+    path: &str,
+    status: u16,
+    keep_alive: KeepAlive,
+    hsts: Option<&str>,
+    charset: Option<&str>,
+) -> anyhow::Result<bool>
+where
+    S: AsyncWrite + Unpin + ?Sized,
+{
+    let body = match tokio_fs::read(path).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(
+                target: "migux::static",
+                %path,
+                error = %e,
+                "Cannot read fallback_static file"
+            );
+            return Ok(false);
+        }
+    };
+    let reason = http::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|code| code.canonical_reason())
+        .unwrap_or("");
+    let status_line = format!("{status} {reason}");
+    let mut extra_headers = vec![("Cache-Control", "no-store")];
+    if let Some(hsts_value) = hsts {
+        extra_headers.push(("Strict-Transport-Security", hsts_value));
+    }
+    let resp = ResponseBuilder::build_with_headers(
+        status_line.trim_end(),
+        Some(&content_type_for_path(path, charset)),
+        body.len(),
+        keep_alive,
+        &extra_headers,
+        Some(&body),
+    );
+    stream.write_all(&resp).await?;
+    Ok(true)
+}
+
+/// Read a static file and return a full HTTP response.
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_static_bytes(
+    http_cfg: &HttpConfig,
     server_cfg: &ServerConfig,
     location: &LocationConfig,
     method: &str,
     headers: &str,
     req_path: &str,
-    keep_alive: bool,
+    keep_alive: KeepAlive,
     hsts: Option<&str>,
 ) -> anyhow::Result<Vec<u8>> {
-    StaticService::new(server_cfg, location)
+    StaticService::new(http_cfg, server_cfg, location)
         .serve_bytes(method, headers, req_path, keep_alive, hsts)
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FileResolution, ResolvedFile, StaticService, serve_static, serve_static_bytes,
+        serve_static_cached, serve_static_fallback, spawn_refresh, store_cached,
+    };
+    use crate::cache::{
+        CacheKey, CacheState, DiskCache, MemoryCache, StaleWindows, cache_metrics_snapshot,
+    };
+    use migux_config::{
+        AcceptRanges, ErrorFormat, HttpConfig, LocationConfig, LocationType, ServerConfig,
+        StringList,
+    };
+    use migux_http::keep_alive::KeepAlive;
+
+    fn location_with_roots(roots: &[&std::path::Path]) -> LocationConfig {
+        let roots = roots
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        LocationConfig {
+            path: "/".into(),
+            roots: Some(StringList::Many(roots)),
+            ..Default::default()
+        }
+    }
+
+    async fn get(location: &LocationConfig, path: &str) -> String {
+        let resp = serve_static_bytes(
+            &HttpConfig::default(),
+            &ServerConfig::default(),
+            location,
+            "GET",
+            "",
+            path,
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        String::from_utf8_lossy(&resp).into_owned()
+    }
+
+    #[tokio::test]
+    async fn roots_fall_back_to_second_root() {
+        let first = tempfile::tempdir().expect("tempdir");
+        let second = tempfile::tempdir().expect("tempdir");
+        std::fs::write(second.path().join("app.js"), "second").expect("write");
+
+        let location = location_with_roots(&[first.path(), second.path()]);
+        let resp = get(&location, "/app.js").await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "got: {resp}");
+        assert!(resp.ends_with("second"));
+    }
+
+    #[tokio::test]
+    async fn index_directory_falls_through_to_next_root() {
+        let first = tempfile::tempdir().expect("tempdir");
+        let second = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir(first.path().join("index.html")).expect("mkdir");
+
+        let location = location_with_roots(&[first.path()]);
+        let resp = get(&location, "/").await;
+        assert!(resp.starts_with("HTTP/1.1 404"), "got: {resp}");
+
+        std::fs::write(second.path().join("index.html"), "second").expect("write");
+        let location = location_with_roots(&[first.path(), second.path()]);
+        let resp = get(&location, "/").await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "got: {resp}");
+        assert!(resp.ends_with("second"));
+    }
+
+    #[tokio::test]
+    async fn index_special_file_is_a_404() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir(root.path().join("fifo")).expect("mkdir");
+        let status = std::process::Command::new("mkfifo")
+            .arg(root.path().join("fifo/index.html"))
+            .status()
+            .expect("mkfifo");
+        assert!(status.success());
+        std::fs::create_dir(root.path().join("dev")).expect("mkdir");
+        std::os::unix::fs::symlink("/dev/null", root.path().join("dev/index.html"))
+            .expect("symlink");
+
+        let location = location_with_roots(&[root.path()]);
+        for path in ["/fifo/", "/dev/"] {
+            let resp =
+                tokio::time::timeout(std::time::Duration::from_secs(5), get(&location, path))
+                    .await
+                    .expect("special index file blocked the request");
+            assert!(resp.starts_with("HTTP/1.1 404"), "{path}: {resp}");
+        }
+    }
+
+    #[tokio::test]
+    async fn roots_prefer_first_match() {
+        let first = tempfile::tempdir().expect("tempdir");
+        let second = tempfile::tempdir().expect("tempdir");
+        std::fs::write(first.path().join("app.js"), "first").expect("write");
+        std::fs::write(second.path().join("app.js"), "second").expect("write");
+
+        let location = location_with_roots(&[first.path(), second.path()]);
+        assert!(get(&location, "/app.js").await.ends_with("first"));
+    }
+
+    #[tokio::test]
+    async fn roots_404_when_no_root_has_file() {
+        let first = tempfile::tempdir().expect("tempdir");
+        let second = tempfile::tempdir().expect("tempdir");
+        std::fs::write(first.path().join("secret.txt"), "secret").expect("write");
+        let nested = second.path().join("sub");
+        std::fs::create_dir(&nested).expect("mkdir");
+
+        let location = location_with_roots(&[first.path(), &nested]);
+        assert!(
+            get(&location, "/missing.js")
+                .await
+                .starts_with("HTTP/1.1 404")
+        );
+        assert!(
+            get(&location, "/../secret.txt")
+                .await
+                .starts_with("HTTP/1.1 404")
+        );
+    }
+
+    #[tokio::test]
+    async fn bytes_and_stream_callers_get_identical_responses() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("small.txt"), "small body").expect("write");
+        // Above the default stream threshold, so `serve_static` streams it.
+        std::fs::write(root.path().join("large.bin"), vec![b'z'; 1024 * 1024 + 7]).expect("write");
+        let location = location_with_roots(&[root.path()]);
+
+        let etag = {
+            let resp = get(&location, "/small.txt").await;
+            let (head, _) = split_response(resp.as_bytes());
+            head.lines()
+                .find_map(|line| line.strip_prefix("ETag: "))
+                .expect("etag")
+                .to_string()
+        };
+        let cases = [
+            ("GET", "/small.txt", String::new()),
+            ("GET", "/large.bin", String::new()),
+            ("HEAD", "/large.bin", String::new()),
+            ("GET", "/large.bin", "Range: bytes=10-19\r\n".to_string()),
+            ("GET", "/small.txt", format!("If-None-Match: {etag}\r\n")),
+            ("GET", "/missing.txt", String::new()),
+        ];
+        for (method, path, extra) in cases {
+            let headers = format!("{method} {path} HTTP/1.1\r\nHost: example\r\n{extra}");
+            let bytes = serve_static_bytes(
+                &HttpConfig::default(),
+                &ServerConfig::default(),
+                &location,
+                method,
+                &headers,
+                path,
+                KeepAlive::Close,
+                None,
+            )
+            .await
+            .expect("serve bytes");
+            let mut streamed = Vec::new();
+            serve_static(
+                &mut streamed,
+                &HttpConfig::default(),
+                &ServerConfig::default(),
+                &location,
+                method,
+                &headers,
+                path,
+                KeepAlive::Close,
+                None,
+            )
+            .await
+            .expect("serve");
+            assert!(bytes == streamed, "{method} {path} {extra:?} differ");
+        }
+    }
+
+    #[tokio::test]
+    async fn dotfiles_answer_404_unless_enabled() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join(".env"), "SECRET=1").expect("write");
+        std::fs::create_dir_all(root.path().join(".git")).expect("mkdir");
+        std::fs::write(root.path().join(".git/HEAD"), "ref: main").expect("write");
+        let challenge = root.path().join(".well-known/acme-challenge");
+        std::fs::create_dir_all(&challenge).expect("mkdir");
+        std::fs::write(challenge.join("token"), "token").expect("write");
+
+        let location = location_with_roots(&[root.path()]);
+        for path in ["/.env", "/.git/HEAD"] {
+            let resp = get(&location, path).await;
+            assert!(resp.starts_with("HTTP/1.1 404"), "{path}: {resp}");
+        }
+        let resp = get(&location, "/.well-known/acme-challenge/token").await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "got: {resp}");
+        assert!(resp.ends_with("token"));
+
+        let acme_only = LocationConfig {
+            allow_dotfile_prefixes: Some(StringList::One(".well-known/acme-challenge".into())),
+            ..location_with_roots(&[root.path()])
+        };
+        let resp = get(&acme_only, "/.well-known/acme-challenge/token").await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "got: {resp}");
+        assert!(get(&acme_only, "/.env").await.starts_with("HTTP/1.1 404"));
+
+        let open = LocationConfig {
+            serve_dotfiles: Some(true),
+            ..location_with_roots(&[root.path()])
+        };
+        assert!(get(&open, "/.env").await.ends_with("SECRET=1"));
+    }
+
+    fn split_response(resp: &[u8]) -> (String, &[u8]) {
+        let end = resp
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("headers end");
+        (
+            String::from_utf8_lossy(&resp[..end]).into_owned(),
+            &resp[end + 4..],
+        )
+    }
+
+    async fn serve_large(method: &str) -> Vec<u8> {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("big.bin"), vec![b'x'; 100]).expect("write");
+        let location = location_with_roots(&[root.path()]);
+        // Threshold = cache_max_object_bytes, so a 100-byte file is streamed.
+        let http_cfg = HttpConfig {
+            cache_max_object_bytes: Some(16),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        serve_static_cached(
+            &mut out,
+            &http_cfg,
+            &ServerConfig::default(),
+            &location,
+            method,
+            "",
+            "/big.bin",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        out
+    }
+
+    #[tokio::test]
+    async fn head_above_stream_threshold_has_length_and_no_body() {
+        let resp = serve_large("HEAD").await;
+        let (head, body) = split_response(&resp);
+        assert!(head.starts_with("HTTP/1.1 200"), "got: {head}");
+        assert!(head.contains("Content-Length: 100"), "got: {head}");
+        assert!(body.is_empty(), "HEAD sent {} body bytes", body.len());
+    }
+
+    #[tokio::test]
+    async fn get_above_stream_threshold_streams_exact_length() {
+        let resp = serve_large("GET").await;
+        let (head, body) = split_response(&resp);
+        assert!(head.contains("Content-Length: 100"), "got: {head}");
+        assert_eq!(body, vec![b'x'; 100].as_slice());
+    }
+
+    #[tokio::test]
+    async fn stalled_client_aborts_streaming() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("big.bin"), vec![b'x'; 512 * 1024]).expect("write");
+        let location = location_with_roots(&[root.path()]);
+        let http_cfg = HttpConfig {
+            cache_max_object_bytes: Some(16),
+            client_write_timeout_secs: 1,
+            ..Default::default()
+        };
+        // The client end is kept open but never read, so writes stall once
+        // the pipe buffer is full.
+        let (_client, mut server) = tokio::io::duplex(4096);
+
+        let started = std::time::Instant::now();
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            serve_static_cached(
+                &mut server,
+                &http_cfg,
+                &ServerConfig::default(),
+                &location,
+                "GET",
+                "",
+                "/big.bin",
+                KeepAlive::Close,
+                None,
+            ),
+        )
+        .await
+        .expect("transfer was not aborted");
+        let err = result.expect_err("stalled transfer succeeded");
+        assert!(err.to_string().contains("stalled"), "got: {err}");
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn slow_but_steady_client_is_not_cut_off() {
+        use tokio::io::AsyncReadExt;
+
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("big.bin"), vec![b'x'; 48 * 1024]).expect("write");
+        let location = location_with_roots(&[root.path()]);
+        let http_cfg = HttpConfig {
+            cache_max_object_bytes: Some(16),
+            client_write_timeout_secs: 1,
+            ..Default::default()
+        };
+        // The client drains 4 KiB every 100ms: the whole file takes longer
+        // than the write timeout, but no single write waits that long.
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                match client.read(&mut buf).await {
+                    Ok(0) | Err(_) => return received,
+                    Ok(n) => received.extend_from_slice(&buf[..n]),
+                }
+            }
+        });
+
+        serve_static_cached(
+            &mut server,
+            &http_cfg,
+            &ServerConfig::default(),
+            &location,
+            "GET",
+            "",
+            "/big.bin",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("slow transfer was aborted");
+        drop(server);
+        let received = reader.await.expect("reader");
+        assert_eq!(split_response(&received).1.len(), 48 * 1024);
+    }
+
+    fn meta_ttls(cache_dir: &std::path::Path) -> Vec<u64> {
+        let mut ttls: Vec<u64> = std::fs::read_dir(cache_dir)
+            .expect("read cache dir")
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "meta" {
+                    return None;
+                }
+                let meta = std::fs::read_to_string(path).ok()?;
+                let field = |name: &str| {
+                    meta.lines()
+                        .find_map(|l| l.strip_prefix(name)?.parse::<u64>().ok())
+                };
+                Some(field("expires_at=")? - field("last_access=")?)
+            })
+            .collect();
+        ttls.sort_unstable();
+        ttls
+    }
+
+    #[tokio::test]
+    async fn location_cache_ttl_overrides_http_default() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("a.css"), "a").expect("write");
+        std::fs::write(root.path().join("b.css"), "b").expect("write");
+
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(30),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let default_location = location_with_roots(&[root.path()]);
+        let assets_location = LocationConfig {
+            cache_ttl_secs: Some(86_400),
+            ..location_with_roots(&[root.path()])
+        };
+
+        for (location, path) in [(&default_location, "/a.css"), (&assets_location, "/b.css")] {
+            let mut out = Vec::new();
+            serve_static_cached(
+                &mut out,
+                &http_cfg,
+                &ServerConfig::default(),
+                location,
+                "GET",
+                "",
+                path,
+                KeepAlive::Close,
+                None,
+            )
+            .await
+            .expect("serve");
+            assert!(out.starts_with(b"HTTP/1.1 200"));
+        }
+
+        assert_eq!(meta_ttls(cache_dir.path()), vec![30, 86_400]);
+    }
+
+    #[tokio::test]
+    async fn content_type_ttls_override_http_default() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        for name in ["page.html", "logo.png", "style.css", "data.bin"] {
+            std::fs::write(root.path().join(name), name).expect("write");
+        }
+
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(30),
+            cache_type_ttls: [
+                ("text/html", 60),
+                ("image/*", 604_800),
+                ("TEXT/CSS", 86_400),
+            ]
+            .into_iter()
+            .map(|(pattern, ttl)| (pattern.to_string(), ttl))
+            .collect(),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+
+        for path in ["/page.html", "/logo.png", "/style.css", "/data.bin"] {
+            let mut out = Vec::new();
+            serve_static_cached(
+                &mut out,
+                &http_cfg,
+                &ServerConfig::default(),
+                &location,
+                "GET",
+                "",
+                path,
+                KeepAlive::Close,
+                None,
+            )
+            .await
+            .expect("serve");
+            assert!(out.starts_with(b"HTTP/1.1 200"));
+        }
+
+        assert_eq!(meta_ttls(cache_dir.path()), vec![30, 60, 86_400, 604_800]);
+    }
+
+    #[tokio::test]
+    async fn sidecar_max_age_drives_ttl_and_header() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("app.js"), "js").expect("write");
+        std::fs::write(
+            root.path().join("app.js.httpheaders"),
+            "Cache-Control: public, max-age=600\n",
+        )
+        .expect("write");
+
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(30),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = LocationConfig {
+            respect_origin_cache_control: Some(true),
+            cache_control: Some("no-cache".into()),
+            ..location_with_roots(&[root.path()])
+        };
+
+        let mut out = Vec::new();
+        serve_static_cached(
+            &mut out,
+            &http_cfg,
+            &ServerConfig::default(),
+            &location,
+            "GET",
+            "",
+            "/app.js",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+
+        let (head, _) = split_response(&out);
+        assert!(
+            head.contains("Cache-Control: public, max-age=600"),
+            "got: {head}"
+        );
+        assert_eq!(meta_ttls(cache_dir.path()), vec![600]);
+    }
+
+    #[tokio::test]
+    async fn sidecar_max_age_is_clamped_and_the_sidecar_is_hidden() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("app.js"), "js").expect("write");
+        std::fs::write(
+            root.path().join("app.js.httpheaders"),
+            "Cache-Control: public, max-age=600\n",
+        )
+        .expect("write");
+
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_max_ttl_secs: Some(120),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = LocationConfig {
+            respect_origin_cache_control: Some(true),
+            serve_dotfiles: Some(true),
+            ..location_with_roots(&[root.path()])
+        };
+
+        for (path, status) in [
+            ("/app.js", "HTTP/1.1 200"),
+            ("/app.js.httpheaders", "HTTP/1.1 404"),
+        ] {
+            let mut out = Vec::new();
+            serve_static_cached(
+                &mut out,
+                &http_cfg,
+                &ServerConfig::default(),
+                &location,
+                "GET",
+                "",
+                path,
+                KeepAlive::Close,
+                None,
+            )
+            .await
+            .expect("serve");
+            assert!(out.starts_with(status.as_bytes()), "{path}");
+        }
+        assert_eq!(meta_ttls(cache_dir.path()), vec![120]);
+    }
+
+    #[tokio::test]
+    async fn configured_cache_control_is_sent_without_respect_flag() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("a.txt"), "a").expect("write");
+        let location = LocationConfig {
+            cache_control: Some("public, max-age=60".into()),
+            ..location_with_roots(&[root.path()])
+        };
+        assert!(
+            get(&location, "/a.txt")
+                .await
+                .contains("Cache-Control: public, max-age=60\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn force_revalidate_sends_must_revalidate_and_answers_304() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("index.html"), "<p>hi</p>").expect("write");
+        std::fs::write(
+            root.path().join("index.html.httpheaders"),
+            "Cache-Control: public, max-age=600\n",
+        )
+        .expect("write");
+        let location = LocationConfig {
+            force_revalidate: Some(true),
+            respect_origin_cache_control: Some(true),
+            cache_control: Some("public, max-age=60".into()),
+            ..location_with_roots(&[root.path()])
+        };
+
+        let resp = get(&location, "/index.html").await;
+        let (head, body) = split_response(resp.as_bytes());
+        assert!(head.starts_with("HTTP/1.1 200"), "got: {head}");
+        assert!(
+            head.contains("Cache-Control: max-age=0, must-revalidate\r\n"),
+            "got: {head}"
+        );
+        assert!(head.contains("Last-Modified: "), "got: {head}");
+        assert_eq!(body, b"<p>hi</p>");
+        let etag = head
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .expect("etag");
+
+        let headers =
+            format!("GET /index.html HTTP/1.1\r\nHost: example\r\nIf-None-Match: {etag}\r\n");
+        let resp = serve_static_bytes(
+            &HttpConfig::default(),
+            &ServerConfig::default(),
+            &location,
+            "GET",
+            &headers,
+            "/index.html",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        let (head, body) = split_response(&resp);
+        assert!(head.starts_with("HTTP/1.1 304"), "got: {head}");
+        assert!(
+            head.contains("Cache-Control: max-age=0, must-revalidate\r\n"),
+            "got: {head}"
+        );
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cached_response_connection_follows_current_request() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("hit.txt"), "hit").expect("write");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+
+        let open = |timeout_secs, max_requests| KeepAlive::Open {
+            timeout_secs,
+            max_requests,
+        };
+        for (keep_alive, expected) in [
+            (
+                open(65, 1000),
+                "Connection: keep-alive\r\nKeep-Alive: timeout=65, max=1000",
+            ),
+            (
+                open(5, 10),
+                "Connection: keep-alive\r\nKeep-Alive: timeout=5, max=10",
+            ),
+            (KeepAlive::Close, "Connection: close"),
+            (
+                open(65, 1000),
+                "Connection: keep-alive\r\nKeep-Alive: timeout=65, max=1000",
+            ),
+        ] {
+            let mut out = Vec::new();
+            serve_static_cached(
+                &mut out,
+                &http_cfg,
+                &ServerConfig::default(),
+                &location,
+                "GET",
+                "",
+                "/hit.txt",
+                keep_alive,
+                None,
+            )
+            .await
+            .expect("serve");
+            let (head, body) = split_response(&out);
+            assert!(head.ends_with(&format!("\r\n{expected}")), "got: {head}");
+            assert_eq!(body, b"hit");
+        }
+    }
+
+    #[tokio::test]
+    async fn json_error_format_renders_404_as_json() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let location = LocationConfig {
+            error_format: Some(ErrorFormat::Json),
+            ..location_with_roots(&[root.path()])
+        };
+        let resp = get(&location, "/missing.txt").await;
+        let (head, body) = split_response(resp.as_bytes());
+        assert!(head.starts_with("HTTP/1.1 404 Not Found"), "got: {head}");
+        assert!(
+            head.contains("Content-Type: application/json\r\n"),
+            "got: {head}"
+        );
+        assert_eq!(body, br#"{"error":"Not Found","status":404}"#);
+
+        let text = get(&location_with_roots(&[root.path()]), "/missing.txt").await;
+        assert!(text.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(text.ends_with("404 Not Found"));
+    }
+
+    #[tokio::test]
+    async fn text_types_carry_the_configured_charset() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("page.html"), "<p>hi</p>").expect("write");
+        std::fs::write(root.path().join("data.json"), "{}").expect("write");
+        std::fs::write(root.path().join("logo.png"), "png").expect("write");
+
+        let default = location_with_roots(&[root.path()]);
+        let resp = get(&default, "/page.html").await;
+        assert!(
+            resp.contains("Content-Type: text/html; charset=utf-8\r\n"),
+            "got: {resp}"
+        );
+        let resp = get(&default, "/data.json").await;
+        assert!(
+            resp.contains("Content-Type: application/json; charset=utf-8\r\n"),
+            "got: {resp}"
+        );
+        let resp = get(&default, "/logo.png").await;
+        assert!(resp.contains("Content-Type: image/png\r\n"), "got: {resp}");
+
+        let latin1 = LocationConfig {
+            charset: Some("iso-8859-1".into()),
+            ..location_with_roots(&[root.path()])
+        };
+        let resp = get(&latin1, "/page.html").await;
+        assert!(
+            resp.contains("Content-Type: text/html; charset=iso-8859-1\r\n"),
+            "got: {resp}"
+        );
+
+        let off = LocationConfig {
+            charset: Some("off".into()),
+            ..location_with_roots(&[root.path()])
+        };
+        let resp = get(&off, "/page.html").await;
+        assert!(resp.contains("Content-Type: text/html\r\n"), "got: {resp}");
+
+        let mut out = Vec::new();
+        let path = root.path().join("page.html");
+        let served = serve_static_fallback(
+            &mut out,
+            &path.to_string_lossy(),
+            503,
+            KeepAlive::Close,
+            None,
+            Some("windows-1252"),
+        )
+        .await
+        .expect("serve");
+        assert!(served);
+        let out = String::from_utf8_lossy(&out);
+        assert!(
+            out.contains("Content-Type: text/html; charset=windows-1252\r\n"),
+            "got: {out}"
+        );
+    }
+
+    async fn get_range(location: &LocationConfig, range: &str) -> String {
+        let headers = format!("GET /data.txt HTTP/1.1\r\nHost: example\r\nRange: {range}\r\n");
+        let resp = serve_static_bytes(
+            &HttpConfig::default(),
+            &ServerConfig::default(),
+            location,
+            "GET",
+            &headers,
+            "/data.txt",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        String::from_utf8_lossy(&resp).into_owned()
+    }
+
+    #[tokio::test]
+    async fn range_request_returns_partial_content() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("data.txt"), "0123456789").expect("write");
+        let location = location_with_roots(&[root.path()]);
+
+        let resp = get_range(&location, "bytes=2-5").await;
+        assert!(
+            resp.starts_with("HTTP/1.1 206 Partial Content"),
+            "got: {resp}"
+        );
+        assert!(resp.contains("\r\nContent-Range: bytes 2-5/10\r\n"));
+        assert!(resp.contains("\r\nContent-Length: 4\r\n"));
+        assert!(resp.contains("\r\nAccept-Ranges: bytes\r\n"));
+        assert!(resp.ends_with("\r\n\r\n2345"));
+
+        let resp = get_range(&location, "bytes=10-").await;
+        assert!(resp.starts_with("HTTP/1.1 416"), "got: {resp}");
+        assert!(resp.contains("\r\nContent-Range: bytes */10\r\n"));
+    }
+
+    #[tokio::test]
+    async fn accept_ranges_none_ignores_range() {
+        let root = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("data.txt"), "0123456789").expect("write");
+        let mut location = location_with_roots(&[root.path()]);
+        location.accept_ranges = Some(AcceptRanges::Disabled);
+
+        let resp = get_range(&location, "bytes=2-5").await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "got: {resp}");
+        assert!(!resp.contains("Accept-Ranges"));
+        assert!(!resp.contains("Content-Range"));
+        assert!(resp.ends_with("\r\n\r\n0123456789"));
+    }
+
+    async fn resolve_for(location: &LocationConfig, path: &str) -> ResolvedFile {
+        let server = ServerConfig::default();
+        match StaticService::new(&HttpConfig::default(), &server, location)
+            .resolve_file(path, KeepAlive::Close)
+            .await
+            .expect("resolve")
+        {
+            FileResolution::File(file) => *file,
+            FileResolution::Response(_) => panic!("{path} did not resolve to a file"),
+        }
+    }
+
+    async fn cache_key_for(location: &LocationConfig, path: &str) -> CacheKey {
+        resolve_for(location, path).await.cache_key(None)
+    }
+
+    async fn serve_cached(
+        http_cfg: &HttpConfig,
+        location: &LocationConfig,
+        method: &str,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        serve_static_cached(
+            &mut out,
+            http_cfg,
+            &ServerConfig::default(),
+            location,
+            method,
+            "",
+            "/page.html",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        out
+    }
+
+    #[tokio::test]
+    async fn stale_entry_is_served_and_revalidated() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("page.html"), "fresh").expect("write");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            cache_stale_while_revalidate_secs: Some(60),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+        let key = cache_key_for(&location, "/page.html").await;
+        let stale = StaleWindows {
+            while_revalidate: std::time::Duration::from_secs(60),
+            if_error: std::time::Duration::ZERO,
+        };
+        let cached_at = std::time::Instant::now()
+            .checked_sub(std::time::Duration::from_secs(5))
+            .expect("instant");
+        MemoryCache::put_at(
+            key,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nstale".to_vec(),
+            std::time::Duration::from_secs(1),
+            stale,
+            cached_at,
+        );
+
+        let before = cache_metrics_snapshot().await;
+        let out = serve_cached(&http_cfg, &location, "GET").await;
+        assert_eq!(split_response(&out).1, b"stale");
+        let after = cache_metrics_snapshot().await;
+        assert!(after.stale_served > before.stale_served);
+
+        // The background refresh finds the file unchanged and re-stores it.
+        for _ in 0..200 {
+            if MemoryCache::get(key).is_some_and(|hit| hit.state == CacheState::Fresh) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let after = cache_metrics_snapshot().await;
+        assert!(after.revalidations > before.revalidations);
+        assert!(after.revalidation_304 > before.revalidation_304);
+        let out = serve_cached(&http_cfg, &location, "GET").await;
+        assert_eq!(split_response(&out).1, b"fresh");
+    }
+
+    #[tokio::test]
+    async fn refresh_of_a_changed_file_drops_the_stale_entry() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("page.html"), "old").expect("write");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            cache_stale_while_revalidate_secs: Some(60),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+        let file = resolve_for(&location, "/page.html").await;
+        let key = file.cache_key(None);
+        let stale = StaleWindows::from_config(&http_cfg);
+        let ttl = std::time::Duration::from_secs(60);
+        store_cached(
+            &http_cfg,
+            key,
+            b"HTTP/1.1 200 OK\r\n\r\nold".to_vec(),
+            ttl,
+            stale,
+        )
+        .await;
+        assert!(
+            DiskCache::new(cache_dir.path())
+                .get(&http_cfg, key)
+                .await
+                .is_some()
+        );
+
+        std::fs::write(root.path().join("page.html"), "changed").expect("write");
+        spawn_refresh(&http_cfg, &file, key, ttl, KeepAlive::Close, None);
+        for _ in 0..200 {
+            if MemoryCache::get(key).is_none() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        assert!(MemoryCache::get(key).is_none());
+        assert!(
+            DiskCache::new(cache_dir.path())
+                .get(&http_cfg, key)
+                .await
+                .is_none()
+        );
+
+        let out = serve_cached(&http_cfg, &location, "GET").await;
+        assert_eq!(split_response(&out).1, b"changed");
+    }
+
+    #[tokio::test]
+    async fn removed_file_is_served_stale_if_error() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("page.html"), "cached").expect("write");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            cache_stale_if_error_secs: Some(60),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+        let key = cache_key_for(&location, "/page.html").await;
+        let out = serve_cached(&http_cfg, &location, "GET").await;
+        assert_eq!(split_response(&out).1, b"cached");
+
+        // Expired, inside the stale-if-error window only.
+        let stale = StaleWindows {
+            while_revalidate: std::time::Duration::ZERO,
+            if_error: std::time::Duration::from_secs(60),
+        };
+        let cached_at = std::time::Instant::now()
+            .checked_sub(std::time::Duration::from_secs(5))
+            .expect("instant");
+        let hit = MemoryCache::get(key).expect("cached entry");
+        MemoryCache::put_at(
+            key,
+            hit.response,
+            std::time::Duration::from_secs(1),
+            stale,
+            cached_at,
+        );
+        std::fs::remove_file(root.path().join("page.html")).expect("remove");
+
+        let before = cache_metrics_snapshot().await;
+        let out = serve_cached(&http_cfg, &location, "GET").await;
+        let (head, body) = split_response(&out);
+        assert!(head.starts_with("HTTP/1.1 200"), "got: {head}");
+        assert_eq!(body, b"cached");
+        assert!(cache_metrics_snapshot().await.stale_served > before.stale_served);
+
+        // Without a stale-if-error window the missing file is a 404.
+        let no_stale = HttpConfig {
+            cache_stale_if_error_secs: None,
+            ..http_cfg.clone()
+        };
+        let out = serve_cached(&no_stale, &location, "GET").await;
+        assert!(out.starts_with(b"HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn head_warms_the_cache() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(root.path().join("page.html"), "warm me").expect("write");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = location_with_roots(&[root.path()]);
+        let key = cache_key_for(&location, "/page.html").await;
+
+        let before = cache_metrics_snapshot().await;
+        let out = serve_cached(&http_cfg, &location, "HEAD").await;
+        let (head, body) = split_response(&out);
+        assert!(head.contains("Content-Length: 7"), "got: {head}");
+        assert!(body.is_empty());
+        assert!(cache_metrics_snapshot().await.warm_fetches > before.warm_fetches);
+
+        let hit = MemoryCache::get(key).expect("warmed entry");
+        assert_eq!(hit.state, CacheState::Fresh);
+        assert!(hit.response.ends_with(b"warm me"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_misses_read_the_file_once() {
+        const REQUESTS: usize = 16;
+        let root = tempfile::tempdir().expect("tempdir");
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        let body = vec![b'x'; 512 * 1024];
+        std::fs::write(root.path().join("big.bin"), &body).expect("write");
+        let http_cfg = std::sync::Arc::new(HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        });
+        let location = std::sync::Arc::new(location_with_roots(&[root.path()]));
+        let barrier = std::sync::Arc::new(tokio::sync::Barrier::new(REQUESTS));
+
+        let before = cache_metrics_snapshot().await;
+        let tasks: Vec<_> = (0..REQUESTS)
+            .map(|_| {
+                let (http_cfg, location, barrier) =
+                    (http_cfg.clone(), location.clone(), barrier.clone());
+                tokio::spawn(async move {
+                    let server = ServerConfig::default();
+                    let service = StaticService::new(&http_cfg, &server, &location);
+                    let mut out = Vec::new();
+                    barrier.wait().await;
+                    let decision = service
+                        .serve_file(
+                            &mut out,
+                            Some(&http_cfg),
+                            "GET",
+                            "",
+                            "/big.bin",
+                            KeepAlive::Close,
+                            None,
+                        )
+                        .await
+                        .expect("serve");
+                    (decision, out)
+                })
+            })
+            .collect();
+
+        let mut misses = 0;
+        for task in tasks {
+            let (decision, out) = task.await.expect("task");
+            assert_eq!(split_response(&out).1, body.as_slice());
+            misses += usize::from(decision == "cache-miss");
+        }
+        assert_eq!(misses, 1, "exactly one request read the file");
+        let after = cache_metrics_snapshot().await;
+        assert!(after.coalesced_misses > before.coalesced_misses);
+    }
+
+    fn archive_location(archive: &str) -> LocationConfig {
+        LocationConfig {
+            path: "/".into(),
+            r#type: LocationType::Archive,
+            archive: Some(format!("{}/testdata/{archive}", env!("CARGO_MANIFEST_DIR"))),
+            ..Default::default()
+        }
+    }
+
+    async fn get_with(location: &LocationConfig, path: &str, headers: &str) -> String {
+        let headers = format!("GET {path} HTTP/1.1\r\nHost: example\r\n{headers}");
+        let resp = serve_static_bytes(
+            &HttpConfig::default(),
+            &ServerConfig::default(),
+            location,
+            "GET",
+            &headers,
+            path,
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        String::from_utf8_lossy(&resp).into_owned()
+    }
+
+    #[tokio::test]
+    async fn archive_locations_serve_zip_and_tar_entries() {
+        for archive in ["site.zip", "site.tar"] {
+            let location = archive_location(archive);
+
+            let resp = get(&location, "/").await;
+            assert!(resp.starts_with("HTTP/1.1 200"), "{archive}: {resp}");
+            assert!(
+                resp.contains("Content-Type: text/html"),
+                "{archive}: {resp}"
+            );
+            assert!(resp.contains("Last-Modified: Tue, 02 Jan 2024 03:04:06 GMT"));
+            assert!(resp.ends_with("\r\n\r\n<h1>archived</h1>\n"));
+            let etag = resp
+                .lines()
+                .find_map(|line| line.strip_prefix("ETag: "))
+                .expect("etag")
+                .to_string();
+
+            let resp = get_with(
+                &location,
+                "/index.html",
+                &format!("If-None-Match: {etag}\r\n"),
+            )
+            .await;
+            assert!(resp.starts_with("HTTP/1.1 304"), "{archive}: {resp}");
+
+            // Deflated in the zip: ranges apply to the inflated body.
+            let resp = get_with(&location, "/assets/app.css", "Range: bytes=0-13\r\n").await;
+            assert!(resp.starts_with("HTTP/1.1 206"), "{archive}: {resp}");
+            assert!(resp.contains("Content-Type: text/css"), "{archive}: {resp}");
+            assert!(
+                resp.ends_with("\r\n\r\nbody{margin:0}"),
+                "{archive}: {resp}"
+            );
+
+            for missing in ["/missing.html", "/.env", "/assets"] {
+                let resp = get(&location, missing).await;
+                assert!(
+                    resp.starts_with("HTTP/1.1 404"),
+                    "{archive} {missing}: {resp}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_entries_are_cached() {
+        let cache_dir = tempfile::tempdir().expect("tempdir");
+        let http_cfg = HttpConfig {
+            cache_dir: Some(cache_dir.path().to_string_lossy().into_owned()),
+            cache_default_ttl_secs: Some(60),
+            cache_max_object_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let location = LocationConfig {
+            cache: Some(true),
+            ..archive_location("site.zip")
+        };
+        let key = cache_key_for(&location, "/assets/app.css").await;
+
+        let mut out = Vec::new();
+        serve_static_cached(
+            &mut out,
+            &http_cfg,
+            &ServerConfig::default(),
+            &location,
+            "GET",
+            "",
+            "/assets/app.css",
+            KeepAlive::Close,
+            None,
+        )
+        .await
+        .expect("serve");
+        assert!(split_response(&out).1.starts_with(b"body{margin:0}"));
+
+        let hit = MemoryCache::get(key).expect("cached entry");
+        assert_eq!(hit.state, CacheState::Fresh);
+        assert_eq!(split_response(&hit.response).1, split_response(&out).1);
+    }
+}
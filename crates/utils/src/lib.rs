@@ -1,17 +1,180 @@
+use std::fs::{File, OpenOptions};
+
+use migux_config::{GlobalConfig, LogFormat};
+use tracing::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::{EnvFilter, fmt};
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt};
+
+/// Keeps the `error_log` writer thread alive; hold it until shutdown so
+/// buffered lines are flushed.
+#[must_use]
+pub struct TracingGuard {
+    _error_log: Option<WorkerGuard>,
+}
 
-pub fn init_tracing() {
-    let env_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new("info,migux=debug,migux_proxy=debug,migux_http=debug"));
+/// Install the global tracing subscriber: filter from `RUST_LOG`, falling
+/// back to `global.log_level`, and output shaped by `global.log_format`.
+/// Logs go to stdout; unless `error_log` is `off`/`-`, warnings and errors
+/// go to that file too.
+pub fn init_tracing(global: &GlobalConfig) -> TracingGuard {
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let (env_filter, level_warning) = env_filter(rust_log.as_deref(), global.log_level());
+    let (error_log, open_error) = match open_error_log(global.error_log()) {
+        Ok(file) => (file, None),
+        Err(e) => (None, Some(e)),
+    };
+    let (file_writer, guard) = error_log.map(tracing_appender::non_blocking).unzip();
 
+    let mut layers = vec![fmt_layer(global.log_format(), std::io::stdout, true)];
+    if let Some(writer) = file_writer {
+        layers.push(error_log_layer(global.log_format(), writer));
+    }
     tracing_subscriber::registry()
+        .with(layers)
         .with(env_filter)
-        .with(
-            fmt::layer()
-                .compact()
-                .with_target(true)
-                .with_thread_ids(false),
-        )
         .init();
+
+    if let Some(message) = level_warning {
+        warn!(target: "migux::config", "{message}");
+    }
+    if let Some(e) = open_error {
+        warn!(
+            target: "migux::config",
+            path = global.error_log(),
+            error = %e,
+            "Cannot open error_log; logging to stdout only"
+        );
+    }
+
+    TracingGuard { _error_log: guard }
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_target(true)
+        .with_thread_ids(false)
+        .with_ansi(ansi)
+        .with_writer(writer);
+    match format {
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// The `error_log` file only takes warnings and errors, whatever the
+/// global filter lets through to stdout.
+fn error_log_layer<W>(format: LogFormat, writer: W) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt_layer(format, writer, false)
+        .with_filter(LevelFilter::WARN)
+        .boxed()
+}
+
+/// `RUST_LOG` wins when set and valid; otherwise the configured level, or
+/// `info` (with a warning to log once tracing is up) if that doesn't parse.
+fn env_filter(rust_log: Option<&str>, log_level: &str) -> (EnvFilter, Option<String>) {
+    if let Some(filter) = rust_log
+        .filter(|directives| !directives.trim().is_empty())
+        .and_then(|directives| EnvFilter::try_new(directives).ok())
+    {
+        return (filter, None);
+    }
+    match EnvFilter::try_new(log_level) {
+        Ok(filter) => (filter, None),
+        Err(e) => (
+            EnvFilter::new("info"),
+            Some(format!(
+                "Invalid log_level '{log_level}' ({e}); falling back to 'info'"
+            )),
+        ),
+    }
+}
+
+/// `off` and `-` disable the file; anything else is opened for appending.
+fn open_error_log(path: &str) -> std::io::Result<Option<File>> {
+    match path {
+        "off" | "-" => Ok(None),
+        path => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{env_filter, error_log_layer, open_error_log};
+    use migux_config::LogFormat;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn configured_level_applies_without_rust_log() {
+        let level = |rust_log, log_level| env_filter(rust_log, log_level).0.max_level_hint();
+        assert_eq!(level(None, "warn"), Some(LevelFilter::WARN));
+        assert_eq!(level(Some(""), "error"), Some(LevelFilter::ERROR));
+        assert_eq!(level(Some("debug"), "warn"), Some(LevelFilter::DEBUG));
+    }
+
+    #[test]
+    fn invalid_log_level_falls_back_to_info() {
+        let (filter, warning) = env_filter(None, "verbose=loud");
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::INFO));
+        let warning = warning.expect("warning");
+        assert!(warning.contains("verbose=loud"), "got: {warning}");
+
+        let (_, warning) = env_filter(None, "info,migux=debug");
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn error_log_can_be_disabled() {
+        assert!(open_error_log("off").expect("off").is_none());
+        assert!(open_error_log("-").expect("-").is_none());
+        assert!(open_error_log("/nonexistent-dir/error.log").is_err());
+    }
+
+    #[test]
+    fn error_log_only_takes_warnings_and_errors() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let writer = move || SharedBuf(sink.clone());
+        let subscriber = tracing_subscriber::registry()
+            .with(error_log_layer(LogFormat::Compact, writer))
+            .with(LevelFilter::TRACE);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("routine");
+            tracing::warn!("careful");
+            tracing::error!("broken");
+        });
+
+        let out = String::from_utf8(lines.lock().expect("lock").clone()).expect("utf8");
+        assert!(!out.contains("routine"), "got: {out}");
+        assert!(out.contains("careful"), "got: {out}");
+        assert!(out.contains("broken"), "got: {out}");
+    }
+
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use std::env;
 
 use migux_config::MiguxConfig;
-use migux_core::master::Master;
+use migux_core::master::{Master, systemd_listeners};
 use utils::init_tracing;
 
 /// Obtiene la ruta del archivo de configuración:
@@ -9,10 +9,10 @@ use utils::init_tracing;
 /// 2) argumento -c o --config en la línea de comandos
 /// 3) por defecto "migux.conf"
 fn config_path() -> String {
-    if let Ok(path) = env::var("MIGUX_CONFIG") {
-        if !path.is_empty() {
-            return path;
-        }
+    if let Ok(path) = env::var("MIGUX_CONFIG")
+        && !path.is_empty()
+    {
+        return path;
     }
     let args: Vec<String> = env::args().collect();
     let mut i = 1;
@@ -29,10 +29,13 @@ fn config_path() -> String {
     "migux.conf".to_string()
 }
 
+/// Whether `flag` was passed on the command line.
+fn has_flag(flag: &str) -> bool {
+    env::args().skip(1).any(|arg| arg == flag)
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    init_tracing();
-
     let config_path = config_path();
     let cfg = match MiguxConfig::from_file(&config_path) {
         Ok(cfg) => cfg,
@@ -43,6 +46,23 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    // --dump-config: print the effective config as JSON and exit, without
+    // logging or binding anything. --redact hides inline secrets.
+    if has_flag("--dump-config") {
+        let report = cfg.validate();
+        if !report.warnings().is_empty() || report.has_errors() {
+            eprint!("{}", report.format());
+        }
+        println!("{}", cfg.to_json(has_flag("--redact")));
+        if report.has_errors() {
+            return Err(anyhow::anyhow!("invalid configuration"));
+        }
+        return Ok(());
+    }
+
+    // After loading: the default filter and output format come from [global].
+    let _tracing = init_tracing(&cfg.global);
+
     let report = cfg.validate();
     for warning in report.warnings() {
         eprintln!("WARNING: {warning}");
@@ -55,7 +75,8 @@ async fn main() -> anyhow::Result<()> {
         return Err(anyhow::anyhow!("invalid configuration"));
     }
 
-    let master = Master::new(cfg);
+    // Sockets passed by a systemd .socket unit replace binding their address.
+    let master = Master::new(cfg).with_inherited_listeners(systemd_listeners());
     master.run().await?;
 
     Ok(())